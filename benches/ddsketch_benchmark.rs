@@ -12,6 +12,54 @@ fn benchmark_add_values(c: &mut Criterion) {
     });
 }
 
+fn benchmark_add_batch(c: &mut Criterion) {
+    let values: Vec<f64> = (1..=1000).map(|i| i as f64).collect();
+    
+    c.bench_function("add_batch_1000_values", |b| {
+        b.iter(|| {
+            let mut sketch = DDSketch::new(0.02).unwrap();
+            sketch.add_batch(black_box(&values));
+        })
+    });
+}
+
+fn benchmark_add_batch_u64(c: &mut Criterion) {
+    let values: Vec<u64> = (1..=1000u64).map(|i| i * 1_000_003).collect();
+    
+    c.bench_function("add_batch_u64_1000_values", |b| {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        // Build the key table outside the measured loop
+        sketch.add_batch_u64(&values);
+        b.iter(|| {
+            sketch.clear();
+            sketch.add_batch_u64(black_box(&values));
+        })
+    });
+}
+
+fn benchmark_add_quantized(c: &mut Criterion) {
+    // Millisecond timer readings that repeat a handful of values
+    let values: Vec<f64> = (0..1000).map(|i| [3.0, 4.0, 4.0, 5.0, 12.0][i % 5]).collect();
+    
+    c.bench_function("add_1000_quantized_values", |b| {
+        b.iter(|| {
+            let mut sketch = DDSketch::new(0.02).unwrap();
+            for &value in &values {
+                sketch.add(black_box(value));
+            }
+        })
+    });
+    c.bench_function("add_1000_quantized_values_key_cache", |b| {
+        b.iter(|| {
+            let mut sketch = DDSketch::new(0.02).unwrap();
+            sketch.set_key_cache(true);
+            for &value in &values {
+                sketch.add(black_box(value));
+            }
+        })
+    });
+}
+
 fn benchmark_quantile_queries(c: &mut Criterion) {
     let mut sketch = DDSketch::new(0.02).unwrap();
     for i in 1..=10000 {
@@ -39,11 +87,36 @@ fn benchmark_merge(c: &mut Criterion) {
     c.bench_function("merge_sketches", |b| {
         b.iter(|| {
             let mut s1 = sketch1.clone();
-            let s2 = sketch2.clone();
-            s1.merge(&s2).unwrap();
+            s1.merge(black_box(&sketch2)).unwrap();
+        })
+    });
+}
+
+fn benchmark_merge_all(c: &mut Criterion) {
+    let sketches: Vec<DDSketch> = (0..100)
+        .map(|shard| {
+            let mut sketch = DDSketch::new(0.02).unwrap();
+            for i in 1..=1000 {
+                sketch.add((i * (shard + 1)) as f64);
+            }
+            sketch
+        })
+        .collect();
+    
+    c.bench_function("merge_all_100_sketches", |b| {
+        b.iter(|| {
+            let mut merged = DDSketch::new(0.02).unwrap();
+            merged.merge_all(black_box(&sketches)).unwrap();
+        })
+    });
+    
+    c.bench_function("merge_from_100_sketches", |b| {
+        b.iter(|| {
+            let mut merged = DDSketch::new(0.02).unwrap();
+            merged.merge_from(black_box(&sketches).iter()).unwrap();
         })
     });
 }
 
-criterion_group!(benches, benchmark_add_values, benchmark_quantile_queries, benchmark_merge);
+criterion_group!(benches, benchmark_add_values, benchmark_add_batch, benchmark_add_batch_u64, benchmark_add_quantized, benchmark_quantile_queries, benchmark_merge, benchmark_merge_all);
 criterion_main!(benches);
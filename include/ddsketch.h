@@ -0,0 +1,60 @@
+/*
+ * C bindings for ddsketch-rs.
+ *
+ * Build the shared and static libraries with
+ *   cargo build --release --features ffi
+ *
+ * Keep in sync with src/ffi.rs; test_header_matches_bindings checks the two agree.
+ */
+
+#ifndef DDSKETCH_H
+#define DDSKETCH_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status codes */
+#define DDSKETCH_OK 0
+#define DDSKETCH_ERR_NULL_POINTER -1
+#define DDSKETCH_ERR_INVALID_RELATIVE_ACCURACY -2
+#define DDSKETCH_ERR_INVALID_QUANTILE -3
+#define DDSKETCH_ERR_EMPTY_SKETCH -4
+#define DDSKETCH_ERR_INCOMPATIBLE_SKETCHES -5
+#define DDSKETCH_ERR_OTHER -6
+
+/* An opaque sketch, owned by the caller */
+typedef struct DDSketch DDSketch;
+
+/* Create a sketch; returns NULL if the relative accuracy is invalid */
+DDSketch *ddsketch_new(double relative_accuracy);
+
+/* Release a sketch; NULL is ignored */
+void ddsketch_free(DDSketch *sketch);
+
+int ddsketch_add(DDSketch *sketch, double value);
+int ddsketch_add_with_count(DDSketch *sketch, double value, uint64_t count);
+
+/* Merge other into sketch; the two must be distinct */
+int ddsketch_merge(DDSketch *sketch, const DDSketch *other);
+
+/* Write the value at the quantile to out */
+int ddsketch_quantile(const DDSketch *sketch, double quantile, double *out);
+
+uint64_t ddsketch_count(const DDSketch *sketch);
+
+/* Serialize a sketch; release the buffer with ddsketch_bytes_free */
+uint8_t *ddsketch_serialize(const DDSketch *sketch, size_t *len);
+void ddsketch_bytes_free(uint8_t *bytes, size_t len);
+
+/* Deserialize a sketch; returns NULL if the bytes are not a valid encoding */
+DDSketch *ddsketch_deserialize(const uint8_t *bytes, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DDSKETCH_H */
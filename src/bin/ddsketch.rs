@@ -0,0 +1,190 @@
+//! Command-line tool for building and inspecting sketches
+//!
+//! ```text
+//! ddsketch quantiles [--accuracy A] [--quantiles Q,..] [--column N] [--output FILE] [FILE..]
+//! ddsketch merge OUTPUT INPUT..
+//! ddsketch inspect [--quantiles Q,..] FILE
+//! ```
+//!
+//! `quantiles` reads one number per line, or the given column of comma-separated
+//! lines, from the files or standard input. Lines that do not parse as a number,
+//! such as CSV headers, are skipped and counted. Sketch files use the binary format
+//! of the `encoding` module.
+
+use ddsketch_rs::{DDSketch, InputFormat};
+use std::fs;
+use std::io;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: ddsketch quantiles [--accuracy A] [--quantiles Q,..] [--column N] [--output FILE] [FILE..]
+       ddsketch merge OUTPUT INPUT..
+       ddsketch inspect [--quantiles Q,..] FILE";
+
+const DEFAULT_QUANTILES: &[f64] = &[0.5, 0.9, 0.95, 0.99, 0.999];
+
+/// Options shared by the subcommands
+struct Options {
+    accuracy: f64,
+    quantiles: Vec<f64>,
+    column: usize,
+    output: Option<String>,
+    paths: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("ddsketch: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (command, rest) = args.split_first().ok_or(USAGE)?;
+    let options = parse_options(rest)?;
+    
+    match command.as_str() {
+        "quantiles" => quantiles(&options),
+        "merge" => merge(&options),
+        "inspect" => inspect(&options),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("unknown command '{}'\n{}", command, USAGE)),
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        accuracy: 0.01,
+        quantiles: DEFAULT_QUANTILES.to_vec(),
+        column: 0,
+        output: None,
+        paths: Vec::new(),
+    };
+    
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--accuracy" => {
+                options.accuracy = value(arg)?.parse().map_err(|_| "invalid --accuracy")?;
+            }
+            "--quantiles" => options.quantiles = parse_quantiles(value(arg)?)?,
+            "--column" => {
+                options.column = value(arg)?.parse().map_err(|_| "invalid --column")?;
+            }
+            "--output" => options.output = Some(value(arg)?.clone()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+            _ => options.paths.push(arg.clone()),
+        }
+    }
+    
+    Ok(options)
+}
+
+fn parse_quantiles(list: &str) -> Result<Vec<f64>, String> {
+    list.split(',')
+        .map(|quantile| quantile.trim().parse().map_err(|_| format!("invalid quantile '{}'", quantile)))
+        .collect()
+}
+
+fn quantiles(options: &Options) -> Result<(), String> {
+    let mut sketch = DDSketch::new(options.accuracy).map_err(|error| error.to_string())?;
+    let format = InputFormat::Csv { column: options.column };
+    let mut skipped = 0u64;
+    
+    if options.paths.is_empty() {
+        let stats = sketch.ingest_reader(io::stdin().lock(), format).map_err(|error| error.to_string())?;
+        skipped += stats.skipped;
+    }
+    for path in &options.paths {
+        let file = fs::File::open(path).map_err(|error| format!("{}: {}", path, error))?;
+        let stats = sketch.ingest_reader(file, format).map_err(|error| format!("{}: {}", path, error))?;
+        skipped += stats.skipped;
+    }
+    
+    if skipped > 0 {
+        eprintln!("skipped {} lines without a number", skipped);
+    }
+    print_quantiles(&sketch, &options.quantiles)?;
+    
+    if let Some(output) = &options.output {
+        fs::write(output, sketch.encode()).map_err(|error| format!("{}: {}", output, error))?;
+    }
+    Ok(())
+}
+
+fn merge(options: &Options) -> Result<(), String> {
+    let (output, inputs) = options.paths.split_first().ok_or(USAGE)?;
+    if inputs.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    
+    let sketches = inputs.iter().map(|path| read_sketch(path)).collect::<Result<Vec<_>, _>>()?;
+    let mut merged = DDSketch::new(sketches[0].relative_accuracy()).map_err(|error| error.to_string())?;
+    merged.merge_all(&sketches).map_err(|error| error.to_string())?;
+    
+    fs::write(output, merged.encode()).map_err(|error| format!("{}: {}", output, error))?;
+    println!("merged {} sketches with {} values into {}", sketches.len(), merged.count(), output);
+    Ok(())
+}
+
+fn inspect(options: &Options) -> Result<(), String> {
+    let [path] = options.paths.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    
+    let sketch = read_sketch(path)?;
+    println!("relative accuracy\t{}", sketch.relative_accuracy());
+    println!("count\t{}", sketch.count());
+    println!("bins\t{}", sketch.bins().count());
+    if let (Some(min), Some(max)) = (sketch.min(), sketch.max()) {
+        println!("min\t{}", min);
+        println!("max\t{}", max);
+        println!("sum\t{}", sketch.sum());
+    }
+    print_quantiles(&sketch, &options.quantiles)
+}
+
+fn read_sketch(path: &str) -> Result<DDSketch, String> {
+    let bytes = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+    DDSketch::decode(&bytes).map_err(|error| format!("{}: {}", path, error))
+}
+
+fn print_quantiles(sketch: &DDSketch, quantiles: &[f64]) -> Result<(), String> {
+    if sketch.is_empty() {
+        println!("no values");
+        return Ok(());
+    }
+    
+    let values = sketch.get_quantile_values(quantiles).map_err(|error| error.to_string())?;
+    for (quantile, value) in quantiles.iter().zip(values) {
+        println!("p{}\t{}", quantile * 100.0, value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_options() {
+        let args: Vec<String> = ["--accuracy", "0.02", "--quantiles", "0.5, 0.99", "data.csv"]
+            .iter().map(|arg| arg.to_string()).collect();
+        let options = parse_options(&args).unwrap();
+        
+        assert_eq!(options.accuracy, 0.02);
+        assert_eq!(options.quantiles, vec![0.5, 0.99]);
+        assert_eq!(options.paths, vec!["data.csv".to_string()]);
+        assert!(parse_options(&["--column".to_string()]).is_err());
+        assert!(parse_options(&["--bogus".to_string()]).is_err());
+    }
+}
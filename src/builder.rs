@@ -0,0 +1,207 @@
+//! Builder for configuring a DDSketch before use
+//!
+//! The builder gathers the mapping and query options in one place, so a sketch can
+//! be configured without a series of setters after construction.
+
+use crate::{DDSketch, NonFinitePolicy, QuantileInterpolation, Result};
+use crate::mapping::{IndexMapping, LogarithmicMapping, TailBiasedMapping};
+use crate::store::DenseStore;
+
+/// A builder for a `DDSketch` with dense stores
+///
+/// Errors from the mapping are held until `build`, so calls can be chained freely.
+#[derive(Debug, Clone)]
+pub struct DDSketchBuilder<M: IndexMapping = LogarithmicMapping> {
+    mapping: Result<M>,
+    interpolation: QuantileInterpolation,
+    non_finite_policy: NonFinitePolicy,
+    strict: bool,
+    key_cache: bool,
+    min_indexable_value: f64,
+}
+
+impl DDSketchBuilder {
+    /// Create a builder for a sketch with a logarithmic mapping
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    pub fn new(relative_accuracy: f64) -> Self {
+        DDSketchBuilder {
+            mapping: LogarithmicMapping::new(relative_accuracy),
+            interpolation: QuantileInterpolation::default(),
+            non_finite_policy: NonFinitePolicy::default(),
+            strict: false,
+            key_cache: false,
+            min_indexable_value: 0.0,
+        }
+    }
+    
+    /// Use a tail-biased mapping with a finer accuracy from a threshold upwards
+    ///
+    /// The relative accuracy given to the builder applies below the threshold.
+    ///
+    /// # Arguments
+    /// * `tail_accuracy` - The relative accuracy at and above the threshold
+    /// * `threshold` - The positive value where the tail starts
+    pub fn tail_biased(self, tail_accuracy: f64, threshold: f64) -> DDSketchBuilder<TailBiasedMapping> {
+        let mapping = self.mapping.and_then(|mapping| {
+            TailBiasedMapping::new(mapping.relative_accuracy(), tail_accuracy, threshold)
+        });
+        DDSketchBuilder {
+            mapping,
+            interpolation: self.interpolation,
+            non_finite_policy: self.non_finite_policy,
+            strict: self.strict,
+            key_cache: self.key_cache,
+            min_indexable_value: self.min_indexable_value,
+        }
+    }
+}
+
+impl<M: IndexMapping> DDSketchBuilder<M> {
+    /// Use a custom mapping in place of the configured one
+    pub fn mapping<N: IndexMapping>(self, mapping: N) -> DDSketchBuilder<N> {
+        DDSketchBuilder {
+            mapping: Ok(mapping),
+            interpolation: self.interpolation,
+            non_finite_policy: self.non_finite_policy,
+            strict: self.strict,
+            key_cache: self.key_cache,
+            min_indexable_value: self.min_indexable_value,
+        }
+    }
+    
+    /// Set the interpolation mode used by `get_quantile_value`
+    pub fn interpolation(mut self, interpolation: QuantileInterpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+    
+    /// Set the policy applied to NaN and infinite values
+    pub fn non_finite_policy(mut self, policy: NonFinitePolicy) -> Self {
+        self.non_finite_policy = policy;
+        self
+    }
+    
+    /// Set whether the sketch refuses values it cannot represent faithfully
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+    
+    /// Set whether values are keyed through a cache of recently used bins
+    ///
+    /// See `DDSketch::set_key_cache`.
+    pub fn key_cache(mut self, enabled: bool) -> Self {
+        self.key_cache = enabled;
+        self
+    }
+    
+    /// Set the magnitude below which nonzero values are counted as zero
+    ///
+    /// See `DDSketch::set_min_indexable_value`.
+    pub fn min_indexable_value(mut self, min_indexable_value: f64) -> Self {
+        self.min_indexable_value = min_indexable_value;
+        self
+    }
+    
+    /// Build the sketch
+    ///
+    /// # Returns
+    /// The configured empty sketch, the error from creating its mapping, or
+    /// `DDSketchError::InvalidValue` if the minimum indexable value is negative or
+    /// not finite
+    pub fn build(self) -> Result<DDSketch<M, DenseStore>> {
+        let mut sketch = DDSketch::from_parts(self.mapping?, DenseStore::new(), DenseStore::new());
+        sketch.set_quantile_interpolation(self.interpolation);
+        sketch.set_non_finite_policy(self.non_finite_policy);
+        sketch.set_strict(self.strict);
+        sketch.set_key_cache(self.key_cache);
+        sketch.set_min_indexable_value(self.min_indexable_value)?;
+        Ok(sketch)
+    }
+}
+
+impl DDSketch {
+    /// Start configuring a sketch with the given relative accuracy
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    pub fn builder(relative_accuracy: f64) -> DDSketchBuilder {
+        DDSketchBuilder::new(relative_accuracy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DDSketchError;
+    
+    #[test]
+    fn test_builder_options() {
+        let sketch = DDSketch::builder(0.02)
+            .interpolation(QuantileInterpolation::Midpoint)
+            .strict(true)
+            .key_cache(true)
+            .build()
+            .unwrap();
+        assert_eq!(sketch.relative_accuracy(), 0.02);
+        assert_eq!(sketch.quantile_interpolation(), QuantileInterpolation::Midpoint);
+        assert!(sketch.is_strict());
+        assert!(sketch.key_cache().is_some());
+        
+        assert_eq!(DDSketch::builder(1.5).build().unwrap_err(), DDSketchError::InvalidRelativeAccuracy);
+        assert_eq!(
+            DDSketch::builder(0.02).tail_biased(0.05, 100.0).build().unwrap_err(),
+            DDSketchError::InvalidRelativeAccuracy
+        );
+    }
+    
+    #[test]
+    fn test_builder_tail_biased() {
+        let mut sketch = DDSketch::builder(0.05).tail_biased(0.002, 1000.0).build().unwrap();
+        let mut plain = DDSketch::new(0.05).unwrap();
+        for i in 1..=10_000 {
+            let value = i as f64 * 0.15;
+            sketch.add(value);
+            plain.add(value);
+        }
+        
+        let exact = 1485.0;
+        let tail = sketch.get_quantile_value(0.99).unwrap();
+        assert!((tail - exact).abs() / exact <= 0.002, "tail={}", tail);
+        let body = sketch.get_quantile_value(0.1).unwrap();
+        assert!((body - 150.0).abs() / 150.0 <= 0.05, "body={}", body);
+        assert_eq!(sketch.count(), 10_000);
+        assert!(sketch.merge(&DDSketch::builder(0.05).tail_biased(0.002, 1000.0).build().unwrap()).is_ok());
+        assert!(sketch.is_mergeable_with(&plain).is_err());
+    }
+    
+    #[test]
+    fn test_builder_min_indexable_value() {
+        let mut sketch = DDSketch::builder(0.01).min_indexable_value(1e-6).build().unwrap();
+        sketch.add(1e-300);
+        sketch.add(1e-7);
+        sketch.add(-1e-8);
+        sketch.add(5.0);
+        sketch.add_batch(&[2e-7, 3.0]);
+        
+        assert_eq!((sketch.zero_count(), sketch.num_bins()), (4, 2));
+        assert_eq!(sketch.count_at(5e-7), 4);
+        assert_eq!(sketch.min(), Some(-1e-8));
+        assert_eq!(sketch.min_indexable_value(), 1e-6);
+        assert_eq!(sketch.get_quantile_value(0.5).unwrap(), 0.0);
+        
+        // The threshold survives encoding, and the size hint accounts for it
+        let bytes = sketch.encode();
+        assert!(sketch.serialized_size_hint() >= bytes.len());
+        let mut decoded = DDSketch::decode(&bytes).unwrap();
+        assert_eq!(decoded.min_indexable_value(), 1e-6);
+        decoded.add(1e-9);
+        assert_eq!(decoded.zero_count(), 5);
+        
+        assert!(DDSketch::new(0.01).unwrap().min_indexable_value() < 1e-300);
+        assert_eq!(DDSketch::builder(0.01).min_indexable_value(-1.0).build().unwrap_err(), DDSketchError::InvalidValue);
+        assert_eq!(DDSketch::builder(0.01).min_indexable_value(f64::NAN).build().unwrap_err(), DDSketchError::InvalidValue);
+    }
+}
@@ -0,0 +1,246 @@
+//! Checkpoint files
+//!
+//! This module saves sketches to disk and restores them. A checkpoint wraps the
+//! binary encoding of the `encoding` module, which carries its own version and the
+//! relative accuracy of the mapping, in a small checksummed envelope that also
+//! records the full mapping descriptor:
+//!
+//! ```text
+//! magic       4 bytes   "DDCK"
+//! version     u8        2
+//! mapping     u8 + 3 f64  mapping kind, gamma, tail gamma and offset, laid out as
+//!                       the mapping field of the encoding
+//! checksum    u32       CRC-32 (IEEE) of the mapping and the payload
+//! payload     the encoded sketch, to the end of the file
+//! ```
+//!
+//! Loading checks the recorded mapping against the payload and against the mapping
+//! the sketch is restored with, so a checkpoint is never read with the wrong bins.
+//!
+//! Every version of the envelope and the payload that has been written stays
+//! readable by later releases:
+//!
+//! - Version 1 has no mapping field; the mapping is the one the payload describes.
+//! - Version 2 adds the mapping field.
+
+use crate::{DDSketch, DDSketchError, Result, SketchSnapshot};
+use crate::encoding::{read_mapping, write_mapping, MAPPING_FIELD_LEN};
+use crate::mapping::{IndexMapping, LogarithmicMapping};
+use crate::store::Store;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+/// The bytes every checkpoint file starts with
+pub(crate) const MAGIC: &[u8; 4] = b"DDCK";
+
+/// The current version of the checkpoint envelope
+pub const CHECKPOINT_VERSION: u8 = 2;
+
+/// The length of the version 1 envelope before the payload
+const V1_HEADER_LEN: usize = MAGIC.len() + 1 + 4;
+
+/// The length of the envelope before the payload
+const HEADER_LEN: usize = MAGIC.len() + 1 + MAPPING_FIELD_LEN + 4;
+
+impl<M: IndexMapping, S: Store> DDSketch<M, S> {
+    /// Save the sketch to a checkpoint file
+    ///
+    /// The file is written and flushed to disk next to `path` first and then renamed
+    /// over it, so a crash mid-write leaves the previous checkpoint intact.
+    ///
+    /// # Arguments
+    /// * `path` - The file to write
+    ///
+    /// # Returns
+    /// `DDSketchError::Io` if the file cannot be written
+    pub fn save_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        
+        let mut file = File::create(&temporary)?;
+        file.write_all(&checkpoint_bytes(&self.mapping.descriptor(), &self.encode()))?;
+        // The contents must be on disk before the rename can replace the old file
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+impl<M: IndexMapping> DDSketch<M> {
+    /// Load a sketch from a checkpoint file, indexing its bins with a given mapping
+    ///
+    /// # Arguments
+    /// * `mapping` - The mapping the sketch was saved with
+    /// * `path` - The file to read
+    ///
+    /// # Returns
+    /// The sketch, `DDSketchError::Io` if the file cannot be read,
+    /// `DDSketchError::InvalidEncoding` if it is not a checkpoint, is of an unknown
+    /// version, or fails its checksum, or `DDSketchError::IncompatibleSketches` if it
+    /// was saved with a different mapping
+    pub fn load_from_with_mapping(mapping: M, path: impl AsRef<Path>) -> Result<Self> {
+        DDSketch::from_snapshot_with_mapping(mapping, &read_checkpoint(&fs::read(path)?)?)
+    }
+}
+
+impl DDSketch {
+    /// Load a sketch from a checkpoint file written by `save_to`
+    ///
+    /// # Arguments
+    /// * `path` - The file to read
+    ///
+    /// # Returns
+    /// The sketch, `DDSketchError::Io` if the file cannot be read,
+    /// `DDSketchError::InvalidEncoding` if it is not a checkpoint, is of an unknown
+    /// version, or fails its checksum, or `DDSketchError::IncompatibleSketches` if it
+    /// was saved with a mapping other than the logarithmic one; use
+    /// `load_from_with_mapping` for those
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self> {
+        let snapshot = read_checkpoint(&fs::read(path)?)?;
+        DDSketch::from_snapshot_with_mapping(LogarithmicMapping::new(snapshot.relative_accuracy)?, &snapshot)
+    }
+}
+
+/// Wrap an encoded sketch and its mapping in the checkpoint envelope
+fn checkpoint_bytes(descriptor: &crate::MappingDescriptor, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(CHECKPOINT_VERSION);
+    write_mapping(&mut bytes, descriptor);
+    
+    let mut checked = bytes[MAGIC.len() + 1..].to_vec();
+    checked.extend_from_slice(payload);
+    bytes.extend_from_slice(&crc32(&checked).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// Validate a checkpoint and decode the snapshot it holds
+///
+/// # Returns
+/// The snapshot, `DDSketchError::InvalidEncoding` if the envelope is invalid, or
+/// `DDSketchError::IncompatibleSketches` if the mapping it records does not match
+/// the one the payload describes
+pub(crate) fn read_checkpoint(bytes: &[u8]) -> Result<SketchSnapshot> {
+    if bytes.len() < V1_HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(DDSketchError::InvalidEncoding);
+    }
+    
+    let checksum_at = |start: usize| -> Result<u32> {
+        let field = bytes.get(start..start + 4).ok_or(DDSketchError::InvalidEncoding)?;
+        Ok(u32::from_le_bytes(field.try_into().expect("four bytes")))
+    };
+    match bytes[MAGIC.len()] {
+        1 => {
+            let payload = &bytes[V1_HEADER_LEN..];
+            if crc32(payload) != checksum_at(MAGIC.len() + 1)? {
+                return Err(DDSketchError::InvalidEncoding);
+            }
+            SketchSnapshot::decode(payload)
+        }
+        2 => {
+            let mapping_start = MAGIC.len() + 1;
+            let checksum = checksum_at(mapping_start + MAPPING_FIELD_LEN)?;
+            let mapping = &bytes[mapping_start..mapping_start + MAPPING_FIELD_LEN];
+            let payload = &bytes[HEADER_LEN..];
+            let mut checked = mapping.to_vec();
+            checked.extend_from_slice(payload);
+            if crc32(&checked) != checksum {
+                return Err(DDSketchError::InvalidEncoding);
+            }
+            
+            let snapshot = SketchSnapshot::decode(payload)?;
+            read_mapping(mapping)?.check_mergeable(&snapshot.mapping_descriptor()?)?;
+            Ok(snapshot)
+        }
+        _ => Err(DDSketchError::InvalidEncoding),
+    }
+}
+
+/// Compute the CRC-32 (IEEE 802.3) checksum of some bytes
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TailBiasedMapping;
+    
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+    
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let path = std::env::temp_dir().join(format!("ddsketch-checkpoint-{}", std::process::id()));
+        
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        for i in -100..1000 {
+            sketch.add(i as f64 / 3.0);
+        }
+        sketch.save_to(&path).unwrap();
+        assert_eq!(DDSketch::load_from(&path).unwrap().snapshot(), sketch.snapshot());
+        assert!(!std::path::PathBuf::from(format!("{}.tmp", path.display())).exists());
+        
+        // Any flipped bit is caught by the checksum or the envelope checks
+        let bytes = fs::read(&path).unwrap();
+        for position in [0, 4, 5, 10, HEADER_LEN - 1, HEADER_LEN + 10, bytes.len() - 1] {
+            let mut corrupted = bytes.clone();
+            corrupted[position] ^= 0x10;
+            assert_eq!(read_checkpoint(&corrupted), Err(DDSketchError::InvalidEncoding));
+        }
+        
+        // Version 1 envelopes, without the mapping, stay readable
+        let payload = sketch.encode();
+        let mut v1 = MAGIC.to_vec();
+        v1.push(1);
+        v1.extend_from_slice(&crc32(&payload).to_le_bytes());
+        v1.extend_from_slice(&payload);
+        assert_eq!(DDSketch::from_snapshot(&read_checkpoint(&v1).unwrap()).unwrap(), sketch);
+        
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            DDSketch::load_from(&path).unwrap_err(),
+            DDSketchError::Io(std::io::ErrorKind::NotFound)
+        );
+    }
+    
+    #[test]
+    fn test_checkpoint_mapping() {
+        let path = std::env::temp_dir().join(format!("ddsketch-checkpoint-mapping-{}", std::process::id()));
+        let mut sketch = DDSketch::builder(0.05).tail_biased(0.001, 100.0).build().unwrap();
+        for i in 1..1000 {
+            sketch.add(i as f64);
+        }
+        sketch.save_to(&path).unwrap();
+        
+        let mapping = TailBiasedMapping::new(0.05, 0.001, 100.0).unwrap();
+        assert_eq!(DDSketch::load_from_with_mapping(mapping, &path).unwrap(), sketch);
+        assert!(matches!(DDSketch::load_from(&path), Err(DDSketchError::IncompatibleSketches { .. })));
+        let other_tail = TailBiasedMapping::new(0.05, 0.002, 100.0).unwrap();
+        assert!(matches!(
+            DDSketch::load_from_with_mapping(other_tail, &path),
+            Err(DDSketchError::IncompatibleSketches { .. })
+        ));
+        
+        // The envelope's mapping must agree with the payload's
+        let mut mismatched = checkpoint_bytes(&LogarithmicMapping::new(0.05).unwrap().descriptor(), &sketch.encode());
+        assert!(matches!(read_checkpoint(&mismatched), Err(DDSketchError::IncompatibleSketches { .. })));
+        mismatched.truncate(HEADER_LEN - 2);
+        assert_eq!(read_checkpoint(&mismatched), Err(DDSketchError::InvalidEncoding));
+        
+        fs::remove_file(&path).unwrap();
+    }
+}
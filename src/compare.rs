@@ -0,0 +1,245 @@
+//! Distribution comparison
+//!
+//! This module compares the distributions held by two compatible sketches, for
+//! example a canary against its baseline, with a Kolmogorov–Smirnov-style statistic
+//! and the change at a set of quantiles.
+
+use crate::{DDSketch, DDSketchError, Result};
+use crate::mapping::IndexMapping;
+use crate::store::Store;
+
+/// The quantiles compared by `DDSketch::compare`
+pub const COMPARED_QUANTILES: [f64; 6] = [0.5, 0.75, 0.9, 0.95, 0.99, 0.999];
+
+/// The change in value at one quantile between two sketches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantileDelta {
+    /// The quantile compared
+    pub quantile: f64,
+    /// The value at the quantile in the sketch `compare` was called on
+    pub ours: f64,
+    /// The value at the quantile in the other sketch
+    pub theirs: f64,
+}
+
+impl QuantileDelta {
+    /// Get the difference `theirs - ours`
+    pub fn difference(&self) -> f64 {
+        self.theirs - self.ours
+    }
+    
+    /// Get the difference relative to `ours`, or `None` if `ours` is zero
+    pub fn relative_difference(&self) -> Option<f64> {
+        (self.ours != 0.0).then(|| self.difference() / self.ours.abs())
+    }
+}
+
+/// How two distributions differ
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistributionDiff {
+    /// The largest difference between the two CDFs, between 0 and 1
+    pub max_cdf_gap: f64,
+    /// The bin boundary at which the largest CDF difference occurs
+    pub max_cdf_gap_at: f64,
+    /// The change at each quantile in `COMPARED_QUANTILES`
+    pub quantile_deltas: Vec<QuantileDelta>,
+}
+
+impl<M: IndexMapping, S: Store> DDSketch<M, S> {
+    /// Compare the distribution of this sketch with another one
+    ///
+    /// Both sketches share their bins, so their CDFs are compared exactly at every
+    /// bin boundary. The gap is the statistic of a two-sample Kolmogorov–Smirnov
+    /// test on the binned values: it is 0 when the bins hold the same proportions
+    /// and 1 when the two distributions do not overlap.
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to compare with
+    ///
+    /// # Returns
+    /// The differences, `DDSketchError::IncompatibleSketches` if the sketches cannot
+    /// be merged, or `DDSketchError::EmptySketch` if either is empty
+    pub fn compare<N: IndexMapping, T: Store>(&self, other: &DDSketch<N, T>) -> Result<DistributionDiff> {
+        self.check_mergeable(other)?;
+        if self.is_empty() || other.is_empty() {
+            return Err(DDSketchError::EmptySketch);
+        }
+        
+        let (ours_total, theirs_total) = (self.count() as f64, other.count() as f64);
+        let (mut ours, mut theirs) = (self.bins().peekable(), other.bins().peekable());
+        let (mut ours_count, mut theirs_count) = (0u64, 0u64);
+        let (mut max_cdf_gap, mut max_cdf_gap_at) = (0.0, 0.0);
+        
+        // Walk the union of bins in value order; the bins line up, so equal bounds mean the same bin
+        loop {
+            let upper = match (ours.peek(), theirs.peek()) {
+                (Some(a), Some(b)) => a.upper.min(b.upper),
+                (Some(a), None) => a.upper,
+                (None, Some(b)) => b.upper,
+                (None, None) => break,
+            };
+            if let Some(bin) = ours.next_if(|bin| bin.upper == upper) {
+                ours_count += bin.count;
+            }
+            if let Some(bin) = theirs.next_if(|bin| bin.upper == upper) {
+                theirs_count += bin.count;
+            }
+            
+            let gap = (ours_count as f64 / ours_total - theirs_count as f64 / theirs_total).abs();
+            if gap > max_cdf_gap {
+                max_cdf_gap = gap;
+                max_cdf_gap_at = upper;
+            }
+        }
+        
+        let quantile_deltas = self.get_quantile_values(&COMPARED_QUANTILES)?
+            .into_iter()
+            .zip(other.get_quantile_values(&COMPARED_QUANTILES)?)
+            .zip(COMPARED_QUANTILES)
+            .map(|((ours, theirs), quantile)| QuantileDelta { quantile, ours, theirs })
+            .collect();
+        
+        Ok(DistributionDiff {
+            max_cdf_gap,
+            max_cdf_gap_at,
+            quantile_deltas,
+        })
+    }
+    
+    /// Check whether two sketches hold the same distribution to within a relative tolerance
+    ///
+    /// Every quantile is checked, not just a sample: the two sketches' bins are walked
+    /// in rank order, and wherever a quantile falls in one bin of each, the value
+    /// ranges of those bins, narrowed to the recorded min/max, must come within
+    /// `tolerance` of each other relative to their magnitude. Because whole bin
+    /// ranges are compared, sketches of the same values are equal at a tolerance of
+    /// 0 even when their relative accuracies differ, and the result never depends on
+    /// where in a bin a quantile estimate happens to land. Counts are compared as
+    /// proportions, so a sketch equals a scaled copy of itself.
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to compare with
+    /// * `tolerance` - The allowed relative difference between values at each quantile
+    ///
+    /// # Returns
+    /// True if both sketches are empty, or neither is and every quantile agrees
+    pub fn approx_eq<N: IndexMapping, T: Store>(&self, other: &DDSketch<N, T>, tolerance: f64) -> bool {
+        if self.is_empty() || other.is_empty() {
+            return self.is_empty() && other.is_empty();
+        }
+        
+        let close = |(a_lower, a_upper, _): (f64, f64, u64), (b_lower, b_upper, _): (f64, f64, u64)| {
+            // The gap between the ranges, measured between their nearest ends
+            let (near, far) = if a_upper < b_lower { (a_upper, b_lower) } else { (b_upper, a_lower) };
+            far - near <= tolerance * near.abs().max(far.abs())
+        };
+        
+        let (ours, theirs) = (clamped_bins(self), clamped_bins(other));
+        let (ours_total, theirs_total) = (self.count() as u128, other.count() as u128);
+        let (mut i, mut j) = (0, 0);
+        let (mut ours_rank, mut theirs_rank) = (ours[0].2 as u128, theirs[0].2 as u128);
+        loop {
+            if !close(ours[i], theirs[j]) {
+                return false;
+            }
+            
+            // Move past whichever bin ends at the lower quantile, comparing ranks as fractions
+            let (ours_end, theirs_end) = (ours_rank * theirs_total, theirs_rank * ours_total);
+            if ours_end <= theirs_end {
+                i += 1;
+                if i == ours.len() {
+                    return true;
+                }
+                ours_rank += ours[i].2 as u128;
+            }
+            if theirs_end <= ours_end {
+                j += 1;
+                if j == theirs.len() {
+                    return true;
+                }
+                theirs_rank += theirs[j].2 as u128;
+            }
+        }
+    }
+}
+
+/// Collect a sketch's bins as `(lower, upper, count)`, narrowed to its recorded min/max
+fn clamped_bins<M: IndexMapping, S: Store>(sketch: &DDSketch<M, S>) -> Vec<(f64, f64, u64)> {
+    let (min, max) = (sketch.min_value.unwrap_or(f64::MIN), sketch.max_value.unwrap_or(f64::MAX));
+    sketch.bins().map(|bin| (bin.lower.clamp(min, max), bin.upper.clamp(min, max), bin.count)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_compare_detects_shift() {
+        let mut baseline = DDSketch::new(0.01).unwrap();
+        let mut same = DDSketch::new(0.01).unwrap();
+        let mut shifted = DDSketch::new(0.01).unwrap();
+        for i in 1..=1000 {
+            baseline.add(i as f64);
+            same.add_with_count(i as f64, 3);
+            shifted.add(i as f64 * 1.5);
+        }
+        
+        let diff = baseline.compare(&same).unwrap();
+        assert_eq!(diff.max_cdf_gap, 0.0);
+        assert!(diff.quantile_deltas.iter().all(|delta| delta.difference() == 0.0));
+        
+        let diff = baseline.compare(&shifted).unwrap();
+        assert!((diff.max_cdf_gap - 1.0 / 3.0).abs() < 0.01, "{}", diff.max_cdf_gap);
+        assert!((diff.max_cdf_gap_at - 1000.0).abs() <= 1000.0 * 0.01);
+        assert_eq!(diff.quantile_deltas.len(), COMPARED_QUANTILES.len());
+        for delta in &diff.quantile_deltas {
+            assert!((delta.relative_difference().unwrap() - 0.5).abs() < 0.03, "{:?}", delta);
+        }
+    }
+    
+    #[test]
+    fn test_compare_disjoint_and_invalid() {
+        let mut low = DDSketch::new(0.01).unwrap();
+        let mut high = DDSketch::new(0.01).unwrap();
+        low.add(-5.0);
+        low.add(0.0);
+        high.add(10.0);
+        assert_eq!(low.compare(&high).unwrap().max_cdf_gap, 1.0);
+        
+        let empty = DDSketch::new(0.01).unwrap();
+        assert_eq!(low.compare(&empty), Err(DDSketchError::EmptySketch));
+        let coarser = DDSketch::new(0.02).unwrap();
+        assert!(matches!(low.compare(&coarser), Err(DDSketchError::IncompatibleSketches { .. })));
+    }
+    
+    #[test]
+    fn test_approx_eq() {
+        let mut baseline = DDSketch::new(0.01).unwrap();
+        let mut scaled = DDSketch::new(0.01).unwrap();
+        let mut coarse = DDSketch::new(0.05).unwrap();
+        let mut slower = DDSketch::new(0.01).unwrap();
+        for i in -100..=1000 {
+            baseline.add(i as f64);
+            scaled.add_with_count(i as f64, 3);
+            coarse.add(i as f64);
+            slower.add(i as f64 * 1.1);
+        }
+        
+        assert!(baseline.approx_eq(&baseline, 0.0));
+        assert!(baseline.approx_eq(&scaled, 0.0));
+        assert!(baseline.approx_eq(&coarse, 0.0) && coarse.approx_eq(&baseline, 0.0));
+        
+        // A 10% shift is only tolerated by a tolerance of about 10%
+        assert!(!baseline.approx_eq(&slower, 0.05));
+        assert!(baseline.approx_eq(&slower, 0.1));
+        
+        // A single outlier moves only the top quantiles, but they are checked too
+        let mut outlier = baseline.clone();
+        outlier.add(1e6);
+        assert!(!baseline.approx_eq(&outlier, 0.5));
+        
+        let empty = DDSketch::new(0.01).unwrap();
+        assert!(empty.approx_eq(&DDSketch::new(0.02).unwrap(), 0.0));
+        assert!(!empty.approx_eq(&baseline, 1.0));
+    }
+}
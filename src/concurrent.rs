@@ -0,0 +1,328 @@
+//! Concurrent DDSketch
+//!
+//! This module provides a DDSketch variant that many threads can record into
+//! through a shared reference, without wrapping the sketch in a `Mutex`.
+
+use crate::{DDSketch, DDSketchError, Result};
+use crate::mapping::{IndexMapping, LogarithmicMapping};
+use crate::snapshot::SketchSnapshot;
+use crate::store::{AtomicDenseStore, Store};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A DDSketch that supports lock-free ingestion from many threads
+///
+/// Values are recorded into fixed-range atomic stores sized from the expected range
+/// of magnitudes. Values whose magnitude falls outside that range are counted in the
+/// first or last bin, so quantiles outside the range lose their accuracy guarantee.
+///
+/// Queries operate on a snapshot, which is consistent enough for reporting: values
+/// recorded while the snapshot is taken may or may not be included.
+///
+/// NaN and infinite values are handled as under `NonFinitePolicy::CountSeparately`:
+/// they are left out of the distribution and counted in `non_finite_count`.
+#[derive(Debug)]
+pub struct ConcurrentDDSketch {
+    mapping: LogarithmicMapping,
+    store: AtomicDenseStore,
+    negative_store: AtomicDenseStore,
+    zero_count: AtomicU64,
+    min_value: AtomicU64,
+    max_value: AtomicU64,
+    sum: AtomicU64,
+    sum_squares: AtomicU64,
+    non_finite_count: AtomicU64,
+}
+
+impl ConcurrentDDSketch {
+    /// Create a new concurrent sketch
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    /// * `min_magnitude` - The smallest absolute value recorded at full accuracy
+    /// * `max_magnitude` - The largest absolute value recorded at full accuracy
+    ///
+    /// # Returns
+    /// A new ConcurrentDDSketch instance
+    pub fn new(relative_accuracy: f64, min_magnitude: f64, max_magnitude: f64) -> Result<Self> {
+        let mapping = LogarithmicMapping::new(relative_accuracy)?;
+        let min_index = mapping.checked_key(min_magnitude)?;
+        let max_index = mapping.checked_key(max_magnitude)?;
+        
+        if min_index > max_index {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(ConcurrentDDSketch {
+            mapping,
+            store: AtomicDenseStore::new(min_index, max_index),
+            negative_store: AtomicDenseStore::new(min_index, max_index),
+            zero_count: AtomicU64::new(0),
+            min_value: AtomicU64::new(f64::INFINITY.to_bits()),
+            max_value: AtomicU64::new(f64::NEG_INFINITY.to_bits()),
+            sum: AtomicU64::new(0.0f64.to_bits()),
+            sum_squares: AtomicU64::new(0.0f64.to_bits()),
+            non_finite_count: AtomicU64::new(0),
+        })
+    }
+    
+    /// Add a value to the sketch
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&self, value: f64) {
+        self.add_with_count(value, 1);
+    }
+    
+    /// Add a value with a specific count to the sketch
+    ///
+    /// NaN and infinite values are not added, but counted in `non_finite_count`.
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&self, value: f64, count: u64) {
+        if count == 0 {
+            return;
+        }
+        if !value.is_finite() {
+            self.non_finite_count.fetch_add(count, Ordering::Relaxed);
+            return;
+        }
+        
+        if value == 0.0 {
+            self.zero_count.fetch_add(count, Ordering::Relaxed);
+        } else if value > 0.0 {
+            self.store.add_atomic(self.mapping.key(value), count);
+        } else {
+            self.negative_store.add_atomic(self.mapping.key(-value), count);
+        }
+        
+        update_bits(&self.min_value, value, f64::min);
+        update_bits(&self.max_value, value, f64::max);
+        update_bits(&self.sum, value * count as f64, |sum, value| sum + value);
+        update_bits(&self.sum_squares, value * value * count as f64, |sum, value| sum + value);
+    }
+    
+    /// Get the total count of values in the sketch
+    pub fn count(&self) -> u64 {
+        self.store.total_count()
+            + self.zero_count.load(Ordering::Relaxed)
+            + self.negative_store.total_count()
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+    
+    /// Get the number of NaN and infinite values set aside
+    ///
+    /// These values are not part of `count()`.
+    pub fn non_finite_count(&self) -> u64 {
+        self.non_finite_count.load(Ordering::Relaxed)
+    }
+    
+    /// Get the relative accuracy of the sketch
+    pub fn relative_accuracy(&self) -> f64 {
+        self.mapping.relative_accuracy()
+    }
+    
+    /// Take a snapshot of the sketch's contents
+    pub fn snapshot(&self) -> SketchSnapshot {
+        let min_value = f64::from_bits(self.min_value.load(Ordering::Relaxed));
+        let max_value = f64::from_bits(self.max_value.load(Ordering::Relaxed));
+        
+        SketchSnapshot {
+            relative_accuracy: self.mapping.relative_accuracy(),
+            positive_bins: self.store.iter().collect(),
+            negative_bins: self.negative_store.iter().collect(),
+            zero_count: self.zero_count.load(Ordering::Relaxed),
+            min_value: min_value.is_finite().then_some(min_value),
+            max_value: max_value.is_finite().then_some(max_value),
+            sum: f64::from_bits(self.sum.load(Ordering::Relaxed)),
+            sum_squares: f64::from_bits(self.sum_squares.load(Ordering::Relaxed)),
+            min_indexable_value: 0.0,
+            mapping: None,
+        }
+    }
+    
+    /// Move the sketch's contents into a regular DDSketch, leaving it empty
+    ///
+    /// Every counter is swapped with its empty value, so each value recorded
+    /// concurrently is counted by exactly one call to `take`, which suits interval
+    /// exporters. A value recorded during the call may have its count in one interval
+    /// and its contribution to the sum or extremes in the next.
+    pub fn take(&self) -> DDSketch {
+        let positive_bins = self.store.take_atomic();
+        let negative_bins = self.negative_store.take_atomic();
+        let zero_count = self.zero_count.swap(0, Ordering::Relaxed);
+        let min_value = f64::from_bits(self.min_value.swap(f64::INFINITY.to_bits(), Ordering::Relaxed));
+        let max_value = f64::from_bits(self.max_value.swap(f64::NEG_INFINITY.to_bits(), Ordering::Relaxed));
+        let sum = f64::from_bits(self.sum.swap(0.0f64.to_bits(), Ordering::Relaxed));
+        let sum_squares = f64::from_bits(self.sum_squares.swap(0.0f64.to_bits(), Ordering::Relaxed));
+        
+        let snapshot = SketchSnapshot {
+            relative_accuracy: self.mapping.relative_accuracy(),
+            positive_bins,
+            negative_bins,
+            zero_count,
+            min_value: min_value.is_finite().then_some(min_value),
+            max_value: max_value.is_finite().then_some(max_value),
+            sum,
+            sum_squares,
+            min_indexable_value: 0.0,
+            mapping: None,
+        };
+        let mut sketch = DDSketch::from_snapshot(&snapshot).expect("relative accuracy was validated on construction");
+        sketch.non_finite_count = self.non_finite_count.swap(0, Ordering::Relaxed);
+        sketch
+    }
+    
+    /// Copy the sketch's contents into a regular DDSketch
+    pub fn to_sketch(&self) -> DDSketch {
+        let mut sketch = DDSketch::from_snapshot(&self.snapshot())
+            .expect("relative accuracy was validated on construction");
+        sketch.non_finite_count = self.non_finite_count();
+        sketch
+    }
+    
+    /// Get the value at a given quantile
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.to_sketch().get_quantile_value(quantile)
+    }
+}
+
+/// Atomically replace the f64 stored in `bits` with `combine(current, value)`
+fn update_bits(bits: &AtomicU64, value: f64, combine: fn(f64, f64) -> f64) {
+    let _ = bits.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+        let current = f64::from_bits(current);
+        let updated = combine(current, value);
+        (updated != current).then_some(updated.to_bits())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    
+    #[test]
+    fn test_concurrent_ddsketch_creation() {
+        let sketch = ConcurrentDDSketch::new(0.02, 1e-3, 1e6).unwrap();
+        assert_eq!(sketch.relative_accuracy(), 0.02);
+        assert!(sketch.is_empty());
+        
+        assert!(ConcurrentDDSketch::new(0.0, 1e-3, 1e6).is_err());
+        assert!(ConcurrentDDSketch::new(0.02, 0.0, 1e6).is_err());
+        assert!(ConcurrentDDSketch::new(0.02, 1e6, 1e-3).is_err());
+    }
+    
+    #[test]
+    fn test_concurrent_ddsketch_threads() {
+        let sketch = ConcurrentDDSketch::new(0.02, 1e-3, 1e6).unwrap();
+        let mut expected = DDSketch::new(0.02).unwrap();
+        
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in -100..=1000 {
+                        sketch.add(i as f64);
+                    }
+                });
+            }
+        });
+        
+        for i in -100..=1000 {
+            expected.add_with_count(i as f64, 4);
+        }
+        
+        let snapshot = sketch.to_sketch();
+        assert_eq!(sketch.count(), 4 * 1101);
+        assert_eq!(snapshot.min(), Some(-100.0));
+        assert_eq!(snapshot.max(), Some(1000.0));
+        assert_eq!(snapshot.sum(), expected.sum());
+        
+        for q in [0.0, 0.1, 0.5, 0.9, 0.99] {
+            assert_eq!(snapshot.get_quantile_value(q).unwrap(), expected.get_quantile_value(q).unwrap());
+        }
+    }
+    
+    #[test]
+    fn test_concurrent_ddsketch_take() {
+        let sketch = ConcurrentDDSketch::new(0.02, 1e-3, 1e6).unwrap();
+        let mut taken = DDSketch::new(0.02).unwrap();
+        
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..10_000 {
+                        sketch.add((i % 100) as f64 - 10.0);
+                    }
+                });
+            }
+            for _ in 0..50 {
+                taken.merge(&sketch.take()).unwrap();
+            }
+        });
+        taken.merge(&sketch.take()).unwrap();
+        
+        // Every value lands in exactly one interval
+        assert_eq!(taken.count(), 40_000);
+        assert_eq!(taken.zero_count(), 400);
+        assert!(sketch.is_empty());
+        assert!(sketch.take().is_empty());
+        assert_eq!(sketch.to_sketch().min(), None);
+    }
+    
+    #[test]
+    fn test_concurrent_ddsketch_non_finite() {
+        let sketch = ConcurrentDDSketch::new(0.02, 1e-3, 1e6).unwrap();
+        sketch.add(10.0);
+        sketch.add(f64::NAN);
+        sketch.add_with_count(f64::INFINITY, 3);
+        sketch.add(f64::NEG_INFINITY);
+        
+        assert_eq!((sketch.count(), sketch.non_finite_count()), (1, 5));
+        let copy = sketch.to_sketch();
+        assert_eq!((copy.count(), copy.non_finite_count()), (1, 5));
+        assert_eq!((copy.min(), copy.max(), copy.sum()), (Some(10.0), Some(10.0), 10.0));
+        
+        let taken = sketch.take();
+        assert_eq!(taken.non_finite_count(), 5);
+        assert_eq!(sketch.non_finite_count(), 0);
+    }
+    
+    #[test]
+    fn test_atomic_store_total_never_wraps() {
+        let store = AtomicDenseStore::new(0, 63);
+        let taken = AtomicU64::new(0);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..20_000 {
+                        store.add_atomic(i % 64, 1);
+                    }
+                });
+            }
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    for _ in 0..2_000 {
+                        let count: u64 = store.take_atomic().iter().map(|&(_, count)| count).sum();
+                        taken.fetch_add(count, Ordering::Relaxed);
+                        assert!(store.total_count() <= 80_000, "{}", store.total_count());
+                    }
+                });
+            }
+        });
+        
+        let rest: u64 = store.take_atomic().iter().map(|&(_, count)| count).sum();
+        assert_eq!(taken.load(Ordering::Relaxed) + rest, 80_000);
+        assert_eq!(store.total_count(), 0);
+    }
+}
@@ -0,0 +1,291 @@
+//! Sketches conditioned on a second dimension
+//!
+//! This module provides a sketch that buckets each value by a secondary measurement,
+//! such as the payload size of a request, and keeps a sketch of the values in each
+//! bucket, so that quantiles can be asked for a range of that measurement.
+
+use crate::{DDSketch, DDSketchError, IntoSketchValue, Result};
+
+/// A sketch per range of a secondary dimension
+///
+/// The boundaries split the condition's line into buckets: bucket 0 holds conditions
+/// below the first boundary, bucket `i` holds conditions in `[boundaries[i - 1],
+/// boundaries[i])` and the last bucket holds conditions at or above the last boundary.
+///
+/// A range query merges the buckets that overlap the range, so it answers for the
+/// smallest run of whole buckets covering it. Ranges whose ends are boundaries, or
+/// infinite, are answered exactly; for "p99 latency of requests between 1KB and
+/// 10KB", include 1024 and 10240 among the boundaries.
+#[derive(Debug, Clone)]
+pub struct ConditionedSketch {
+    boundaries: Vec<f64>,
+    buckets: Vec<DDSketch>,
+    relative_accuracy: f64,
+}
+
+impl ConditionedSketch {
+    /// Create a new, empty sketch
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy of every bucket's sketch
+    /// * `boundaries` - The bucket boundaries of the condition, in increasing order
+    ///
+    /// # Returns
+    /// A new ConditionedSketch, an error if the relative accuracy is invalid, or
+    /// `DDSketchError::InvalidValue` if a boundary is not finite or the boundaries
+    /// are not strictly increasing
+    pub fn new(relative_accuracy: f64, boundaries: &[f64]) -> Result<Self> {
+        if boundaries.iter().any(|b| !b.is_finite()) || boundaries.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        let empty = DDSketch::new(relative_accuracy)?;
+        Ok(ConditionedSketch {
+            boundaries: boundaries.to_vec(),
+            buckets: vec![empty; boundaries.len() + 1],
+            relative_accuracy,
+        })
+    }
+    
+    /// Get the bucket boundaries of the condition
+    pub fn boundaries(&self) -> &[f64] {
+        &self.boundaries
+    }
+    
+    /// Get the number of buckets, one more than the number of boundaries
+    pub fn num_buckets(&self) -> usize {
+        self.buckets.len()
+    }
+    
+    /// Get the relative accuracy shared by the buckets' sketches
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+    
+    /// Get the sketch of a bucket
+    ///
+    /// # Arguments
+    /// * `index` - The index of the bucket
+    ///
+    /// # Returns
+    /// The bucket's sketch, or None if there is no such bucket
+    pub fn bucket(&self, index: usize) -> Option<&DDSketch> {
+        self.buckets.get(index)
+    }
+    
+    /// Get the range of conditions a bucket holds
+    ///
+    /// # Arguments
+    /// * `index` - The index of the bucket
+    ///
+    /// # Returns
+    /// The bucket's inclusive lower and exclusive upper bound, which are infinite for
+    /// the first and last buckets, or None if there is no such bucket
+    pub fn bucket_range(&self, index: usize) -> Option<(f64, f64)> {
+        if index >= self.buckets.len() {
+            return None;
+        }
+        
+        let lower = if index == 0 { f64::NEG_INFINITY } else { self.boundaries[index - 1] };
+        let upper = self.boundaries.get(index).copied().unwrap_or(f64::INFINITY);
+        Some((lower, upper))
+    }
+    
+    /// Get the index of the bucket holding a condition
+    ///
+    /// # Arguments
+    /// * `condition` - The value of the secondary dimension
+    ///
+    /// # Returns
+    /// The index of the bucket, or None if the condition is NaN
+    pub fn bucket_index(&self, condition: f64) -> Option<usize> {
+        if condition.is_nan() {
+            return None;
+        }
+        Some(self.boundaries.partition_point(|&boundary| boundary <= condition))
+    }
+    
+    /// Add a value to the bucket of its condition
+    ///
+    /// # Arguments
+    /// * `condition` - The value of the secondary dimension; values with a NaN
+    ///   condition belong to no bucket and are not recorded
+    /// * `value` - The value to add
+    pub fn add(&mut self, condition: f64, value: impl IntoSketchValue) {
+        self.add_with_count(condition, value, 1);
+    }
+    
+    /// Add a value with a specific count to the bucket of its condition
+    ///
+    /// # Arguments
+    /// * `condition` - The value of the secondary dimension; values with a NaN
+    ///   condition belong to no bucket and are not recorded
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&mut self, condition: f64, value: impl IntoSketchValue, count: u64) {
+        if let Some(index) = self.bucket_index(condition) {
+            self.buckets[index].add_with_count(value, count);
+        }
+    }
+    
+    /// Get the total count of values across all buckets
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(DDSketch::count).sum()
+    }
+    
+    /// Check if every bucket is empty
+    pub fn is_empty(&self) -> bool {
+        self.buckets.iter().all(DDSketch::is_empty)
+    }
+    
+    /// Get the indices of the buckets overlapping a range of conditions
+    ///
+    /// # Arguments
+    /// * `low` - The inclusive lower end of the range
+    /// * `high` - The exclusive upper end of the range
+    ///
+    /// # Returns
+    /// The indices of the overlapping buckets, or `DDSketchError::InvalidValue` if
+    /// either end is NaN or the range is empty
+    pub fn buckets_in(&self, low: f64, high: f64) -> Result<std::ops::RangeInclusive<usize>> {
+        if low.is_nan() || high.is_nan() || low >= high {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        let first = self.boundaries.partition_point(|&boundary| boundary <= low);
+        let last = self.boundaries.partition_point(|&boundary| boundary < high);
+        Ok(first..=last)
+    }
+    
+    /// Merge the buckets overlapping a range of conditions into one sketch
+    ///
+    /// # Arguments
+    /// * `low` - The inclusive lower end of the range
+    /// * `high` - The exclusive upper end of the range
+    ///
+    /// # Returns
+    /// A sketch of the values in the overlapping buckets, or
+    /// `DDSketchError::InvalidValue` if either end is NaN or the range is empty
+    pub fn range(&self, low: f64, high: f64) -> Result<DDSketch> {
+        let mut merged = DDSketch::new(self.relative_accuracy)?;
+        merged.merge_all(&self.buckets[self.buckets_in(low, high)?])?;
+        Ok(merged)
+    }
+    
+    /// Merge every bucket into one sketch of all values
+    pub fn total(&self) -> DDSketch {
+        self.range(f64::NEG_INFINITY, f64::INFINITY)
+            .expect("the buckets share a relative accuracy")
+    }
+    
+    /// Get the value at a given quantile among the values in a range of conditions
+    ///
+    /// # Arguments
+    /// * `low` - The inclusive lower end of the range
+    /// * `high` - The exclusive upper end of the range
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile across the buckets overlapping the
+    /// range, `DDSketchError::InvalidValue` if the range is invalid, or
+    /// `DDSketchError::EmptySketch` if those buckets are empty
+    pub fn get_quantile_value_in(&self, low: f64, high: f64, quantile: f64) -> Result<f64> {
+        self.range(low, high)?.get_quantile_value(quantile)
+    }
+    
+    /// Merge another sketch into this one, bucket by bucket
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to merge
+    ///
+    /// # Returns
+    /// `DDSketchError::IncompatibleSketches` if the sketches have different accuracies
+    /// or boundaries, in which case the sketch is left unchanged
+    pub fn merge(&mut self, other: &ConditionedSketch) -> Result<()> {
+        if self.boundaries.len() != other.boundaries.len() {
+            return Err(DDSketchError::different_parameter(
+                "number of boundaries",
+                self.boundaries.len() as f64,
+                other.boundaries.len() as f64,
+            ));
+        }
+        if let Some((&ours, &theirs)) = self.boundaries.iter().zip(&other.boundaries).find(|(a, b)| a != b) {
+            return Err(DDSketchError::different_parameter("bucket boundary", ours, theirs));
+        }
+        for (bucket, other_bucket) in self.buckets.iter().zip(&other.buckets) {
+            bucket.check_mergeable(other_bucket)?;
+        }
+        
+        for (bucket, other_bucket) in self.buckets.iter_mut().zip(&other.buckets) {
+            bucket.merge(other_bucket)?;
+        }
+        Ok(())
+    }
+    
+    /// Clear all data from the sketch, keeping its boundaries
+    pub fn clear(&mut self) {
+        for bucket in &mut self.buckets {
+            bucket.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_conditioned_range_queries() {
+        let mut sketch = ConditionedSketch::new(0.01, &[1024.0, 10240.0]).unwrap();
+        assert_eq!(sketch.num_buckets(), 3);
+        for size in 0..20_000 {
+            // Latency grows with payload size
+            sketch.add(size as f64, 1.0 + size as f64 / 100.0);
+        }
+        sketch.add(f64::NAN, 1.0);
+        assert_eq!(sketch.count(), 20_000);
+        assert_eq!(sketch.bucket(1).unwrap().count(), 10240 - 1024);
+        assert_eq!(sketch.bucket_range(0), Some((f64::NEG_INFINITY, 1024.0)));
+        assert_eq!(sketch.bucket_range(2), Some((10240.0, f64::INFINITY)));
+        assert_eq!(sketch.bucket_range(3), None);
+        
+        // Between 1KB and 10KB, the largest latency is 1 + 10239 / 100
+        let p99 = sketch.get_quantile_value_in(1024.0, 10240.0, 0.99).unwrap();
+        let expected = 1.0 + (1024.0 + 0.99 * 9216.0) / 100.0;
+        assert!((p99 - expected).abs() <= expected * 0.02, "{} {}", p99, expected);
+        
+        // Ranges that do not end on boundaries are widened to whole buckets
+        assert_eq!(sketch.buckets_in(2000.0, 3000.0).unwrap(), 1..=1);
+        assert_eq!(sketch.buckets_in(500.0, 10240.0).unwrap(), 0..=1);
+        assert_eq!(sketch.buckets_in(500.0, 10241.0).unwrap(), 0..=2);
+        assert_eq!(sketch.range(f64::NEG_INFINITY, 1024.0).unwrap().count(), 1024);
+        assert_eq!(sketch.total().count(), 20_000);
+        
+        assert_eq!(sketch.range(5.0, 5.0).unwrap_err(), DDSketchError::InvalidValue);
+        assert_eq!(sketch.range(f64::NAN, 5.0).unwrap_err(), DDSketchError::InvalidValue);
+        assert!(ConditionedSketch::new(0.01, &[2.0, 1.0]).is_err());
+        assert!(ConditionedSketch::new(0.01, &[f64::INFINITY]).is_err());
+    }
+    
+    #[test]
+    fn test_conditioned_merge() {
+        let mut a = ConditionedSketch::new(0.01, &[10.0]).unwrap();
+        let mut b = ConditionedSketch::new(0.01, &[10.0]).unwrap();
+        a.add(1.0, 5.0);
+        b.add_with_count(20.0, 50.0, 3);
+        
+        a.merge(&b).unwrap();
+        assert_eq!((a.bucket(0).unwrap().count(), a.bucket(1).unwrap().count()), (1, 3));
+        assert_eq!(a.get_quantile_value_in(10.0, f64::INFINITY, 1.0).unwrap(), 50.0);
+        
+        let other_boundaries = ConditionedSketch::new(0.01, &[20.0]).unwrap();
+        assert!(matches!(a.merge(&other_boundaries), Err(DDSketchError::IncompatibleSketches { .. })));
+        let other_accuracy = ConditionedSketch::new(0.02, &[10.0]).unwrap();
+        assert!(matches!(a.merge(&other_accuracy), Err(DDSketchError::IncompatibleSketches { .. })));
+        assert_eq!(a.count(), 4);
+        
+        a.clear();
+        assert!(a.is_empty());
+        assert_eq!(a.get_quantile_value_in(0.0, 10.0, 0.5), Err(DDSketchError::EmptySketch));
+    }
+}
@@ -0,0 +1,188 @@
+//! Reusable quantile query cursor
+//!
+//! This module provides a cursor that remembers the ordered bins and the position of
+//! its last rank walk, so that workloads alternating a few adds with a quantile query
+//! resume near the previous answer instead of sorting and rescanning every bin.
+
+use crate::{DDSketch, Result};
+use crate::ddsketch::{RankSpan, RankedBin};
+use crate::mapping::IndexMapping;
+use crate::store::Store;
+
+/// A cursor for repeated quantile queries against a single sketch
+///
+/// The cursor caches the sketch's bins in value order together with their cumulative
+/// counts. When the sketch has not changed, a query walks from the bin that answered
+/// the previous query. When values were added to existing bins, the cached order is
+/// reused and only the counts are refreshed; the bins are only re-sorted when new
+/// bins appeared.
+///
+/// A cursor tracks one sketch at a time; call `reset` before using it with another.
+#[derive(Debug, Clone, Default)]
+pub struct QueryCursor {
+    /// The cached bins in value order
+    bins: Vec<RankSpan>,
+    position: usize,
+    generation: Option<u64>,
+}
+
+impl QueryCursor {
+    /// Create a new, empty cursor
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Forget all cached state
+    pub fn reset(&mut self) {
+        self.bins.clear();
+        self.position = 0;
+        self.generation = None;
+    }
+    
+    /// Get the value at a given quantile, resuming from the previous query
+    ///
+    /// # Arguments
+    /// * `sketch` - The sketch to query
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The same estimate as `DDSketch::get_quantile_value`
+    pub fn get_quantile_value<M: IndexMapping, S: Store>(
+        &mut self,
+        sketch: &DDSketch<M, S>,
+        quantile: f64,
+    ) -> Result<f64> {
+        if !sketch.is_empty() && self.generation != Some(sketch.generation) {
+            self.refresh(sketch);
+        }
+        
+        let total_count = sketch.count();
+        sketch.interpolated_quantile(quantile, sketch.interpolation, |rank| self.bin_at_rank(rank, total_count))
+            .map(|(value, _, _)| value)
+    }
+    
+    /// Walk from the previous position towards the bin holding the rank
+    fn bin_at_rank(&mut self, rank: u64, total_count: u64) -> RankSpan {
+        if rank >= total_count {
+            return RankSpan::beyond(total_count);
+        }
+        
+        let mut position = self.position.min(self.bins.len() - 1);
+        while self.bins[position].rank_before > rank {
+            position -= 1;
+        }
+        while self.bins[position].rank_before + self.bins[position].count <= rank {
+            position += 1;
+        }
+        
+        self.position = position;
+        self.bins[position]
+    }
+    
+    /// Bring the cached bins up to date with the sketch
+    fn refresh<M: IndexMapping, S: Store>(&mut self, sketch: &DDSketch<M, S>) {
+        let mut cached_count = 0;
+        for bin in &mut self.bins {
+            bin.count = match bin.bin {
+                RankedBin::Negative(index) => sketch.negative_store.get(index),
+                RankedBin::Zero => sketch.zero_count,
+                RankedBin::Positive(index) => sketch.store.get(index),
+                RankedBin::Beyond => 0,
+            };
+            cached_count += bin.count;
+        }
+        
+        // New bins appeared if the cached ones no longer account for every value
+        if self.bins.is_empty() || cached_count != sketch.count() {
+            self.rebuild(sketch);
+        }
+        
+        let mut rank_before = 0;
+        for bin in &mut self.bins {
+            bin.rank_before = rank_before;
+            rank_before += bin.count;
+        }
+        
+        self.generation = Some(sketch.generation);
+    }
+    
+    /// Collect and sort all bins of the sketch
+    fn rebuild<M: IndexMapping, S: Store>(&mut self, sketch: &DDSketch<M, S>) {
+        let mut negative: Vec<(i32, u64)> = sketch.negative_store.iter().collect();
+        negative.sort_unstable_by_key(|&(index, _)| std::cmp::Reverse(index));
+        
+        let mut positive: Vec<(i32, u64)> = sketch.store.iter().collect();
+        positive.sort_unstable_by_key(|&(index, _)| index);
+        
+        let cached = |bin, count| RankSpan { bin, count, rank_before: 0 };
+        
+        self.bins.clear();
+        self.bins.extend(negative.into_iter().map(|(index, count)| cached(RankedBin::Negative(index), count)));
+        self.bins.push(cached(RankedBin::Zero, sketch.zero_count));
+        self.bins.extend(positive.into_iter().map(|(index, count)| cached(RankedBin::Positive(index), count)));
+        self.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QuantileInterpolation;
+    
+    #[test]
+    fn test_cursor_matches_sketch() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        let mut cursor = QueryCursor::new();
+        
+        assert_eq!(cursor.get_quantile_value(&sketch, 0.5), Err(crate::DDSketchError::EmptySketch));
+        
+        for i in -50..=500 {
+            sketch.add(i as f64);
+        }
+        
+        for &q in &[0.5, 0.51, 0.49, 0.0, 1.0, 0.99, 0.05] {
+            assert_eq!(cursor.get_quantile_value(&sketch, q), sketch.get_quantile_value(q));
+        }
+        assert!(cursor.get_quantile_value(&sketch, 1.5).is_err());
+    }
+    
+    #[test]
+    fn test_cursor_interleaved_adds() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        let mut cursor = QueryCursor::new();
+        
+        for i in 1..=2000 {
+            // Mostly lands in existing bins, occasionally creates new ones
+            sketch.add((i % 97) as f64 - 10.0);
+            if i % 250 == 0 {
+                sketch.add(i as f64 * 100.0);
+            }
+            
+            if i % 7 == 0 {
+                assert_eq!(cursor.get_quantile_value(&sketch, 0.9), sketch.get_quantile_value(0.9));
+                assert_eq!(cursor.get_quantile_value(&sketch, 0.99), sketch.get_quantile_value(0.99));
+            }
+        }
+        
+        sketch.clear();
+        sketch.add(3.0);
+        assert_eq!(cursor.get_quantile_value(&sketch, 0.5), sketch.get_quantile_value(0.5));
+    }
+    
+    #[test]
+    fn test_cursor_interpolation() {
+        let mut sketch = DDSketch::new(0.05).unwrap();
+        let mut cursor = QueryCursor::new();
+        
+        for i in -30..=300 {
+            sketch.add(i as f64);
+        }
+        
+        for mode in [QuantileInterpolation::UpperBound, QuantileInterpolation::Midpoint, QuantileInterpolation::Linear] {
+            sketch.set_quantile_interpolation(mode);
+            for &q in &[0.0, 0.1, 0.42, 0.5, 0.99, 1.0] {
+                assert_eq!(cursor.get_quantile_value(&sketch, q), sketch.get_quantile_value(q));
+            }
+        }
+    }
+}
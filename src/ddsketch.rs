@@ -4,34 +4,278 @@
 //! adding values, computing quantiles, and merging sketches.
 
 use crate::{DDSketchError, Result};
-use crate::mapping::{IndexMapping, LogarithmicMapping};
-use crate::store::{Store, DenseStore, CollapsingStore};
+use crate::mapping::{IndexMapping, IncompatibilityReason, IntegerKeyTable, KeyCache, LogarithmicMapping, MappingDescriptor};
+use crate::store::{Store, DenseStore, CollapsingStore, CollapsePolicy, FixedStore, HybridStore};
+use crate::snapshot::{SketchSnapshot, ColumnarSnapshot};
+use crate::value::IntoSketchValue;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, OnceLock};
 
 /// The main DDSketch data structure
 ///
 /// DDSketch provides approximate quantile estimation with relative error guarantees.
 /// It uses a logarithmic mapping to achieve this while maintaining mergeable sketches.
-pub struct DDSketch {
+///
+/// The sketch is generic over its index mapping and store types so that adding values
+/// is statically dispatched. `DynDDSketch` is available when the mapping and store
+/// need to be chosen at runtime.
+///
+/// # Negative values
+///
+/// Negative values are first class. A negative value `v` is recorded in the negative
+/// store under the index of `|v|`, so the bins are mirrored around zero: the bin
+/// `[γ^k, γ^(k+1))` of the positive store corresponds to `(-γ^(k+1), -γ^k]`. Zero has
+/// its own count. Quantile queries walk the negative bins from the largest magnitude
+/// down, then zero, then the positive bins, so the relative accuracy guarantee holds
+/// for `|v|` on both sides of zero.
+///
+/// Interpolation modes are defined in value order: `LowerBound` returns the end of a
+/// negative bin furthest from zero, and `WithinBin` moves from that end towards zero
+/// as the rank grows. `Linear` is fully symmetric, so a sketch of negated values
+/// answers the mirrored quantiles with negated values.
+///
+/// `min()` and `max()` track the exact extremes across both stores. When a sketch is
+/// assembled from stores or snapshots that carry no extremes, they are derived from
+/// the outermost bins.
+#[derive(Clone)]
+pub struct DDSketch<M: IndexMapping = LogarithmicMapping, S: Store = DenseStore> {
     /// The mapping from values to indices
-    mapping: Box<dyn IndexMapping>,
+    pub(crate) mapping: M,
     
     /// The store containing counts for each index
-    store: Box<dyn Store>,
+    pub(crate) store: S,
     
     /// Count of zero values
-    zero_count: u64,
+    pub(crate) zero_count: u64,
     
     /// Count of negative values (stored as their absolute value)
-    negative_store: Box<dyn Store>,
+    pub(crate) negative_store: S,
     
     /// The minimum value seen
-    min_value: Option<f64>,
+    pub(crate) min_value: Option<f64>,
     
     /// The maximum value seen
-    max_value: Option<f64>,
+    pub(crate) max_value: Option<f64>,
+    
+    /// Whether min/max are the exact extremes rather than bounds derived from bins
+    pub(crate) exact_extremes: bool,
+    
+    /// The sum of all values
+    pub(crate) sum: f64,
+    
+    /// The sum of the squares of all values
+    pub(crate) sum_squares: f64,
+    
+    /// How `get_quantile_value` picks a value within a bin
+    pub(crate) interpolation: QuantileInterpolation,
+    
+    /// How NaN and infinite values are handled
+    pub(crate) non_finite_policy: NonFinitePolicy,
+    
+    /// Count of NaN and infinite values set aside under `NonFinitePolicy::CountSeparately`
+    pub(crate) non_finite_count: u64,
+    
+    /// Whether non-finite and unindexable values are refused instead of handled silently
+    pub(crate) strict: bool,
+    
+    /// Nonzero magnitudes below this are counted in the zero bucket
+    pub(crate) min_indexable_value: f64,
+    
+    /// Whether counts were ever refused because the total count would have overflowed
+    pub(crate) overflowed: bool,
+    
+    /// The bins of recent values, when key caching is enabled
+    pub(crate) key_cache: Option<KeyCache>,
+    
+    /// Incremented whenever the contents change, so cached query state can detect staleness
+    pub(crate) generation: u64,
+    
+    /// Bins in value order with their cumulative counts, built lazily for quantile queries
+    rank_index: OnceLock<Vec<(RankedBin, u64)>>,
+    
+    /// Keys of integer values, built on the first call to `add_batch_u64`
+    integer_keys: OnceLock<Arc<IntegerKeyTable>>,
+}
+
+/// The number of values whose keys are computed together by `add_batch`
+const BATCH_CHUNK_SIZE: usize = 1024;
+
+/// A quantile estimate with the range of values it may stand for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantileEstimate {
+    /// The estimated value at the quantile
+    pub value: f64,
+    /// The lowest value the true quantile can take
+    pub lower_bound: f64,
+    /// The highest value the true quantile can take
+    pub upper_bound: f64,
+}
+
+/// A quantile estimate with the bin that answered it
+///
+/// Useful when investigating a suspicious percentile: it shows which bin held the
+/// quantile's rank and how many values it was estimated from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantileBin {
+    /// The estimated value at the quantile
+    pub value: f64,
+    /// The index of the bin, or `None` for the zero bin; negative values are indexed
+    /// by their absolute value, as in `SketchSnapshot`
+    pub bin_index: Option<i32>,
+    /// The number of values in the bin
+    pub bin_count: u64,
+    /// The lowest value covered by the bin
+    pub bin_lower: f64,
+    /// The highest value covered by the bin
+    pub bin_upper: f64,
+}
+
+/// A bin of the sketch with the range of values it covers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bin {
+    /// The lowest value covered by the bin
+    pub lower: f64,
+    /// The highest value covered by the bin
+    pub upper: f64,
+    /// The number of values in the bin
+    pub count: u64,
+}
+
+/// The headline statistics of a sketch
+///
+/// Every field except `count` is `None` for an empty sketch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SketchSummary {
+    /// The number of values in the sketch
+    pub count: u64,
+    /// The minimum value
+    pub min: Option<f64>,
+    /// The maximum value
+    pub max: Option<f64>,
+    /// The median
+    pub p50: Option<f64>,
+    /// The 90th percentile
+    pub p90: Option<f64>,
+    /// The 99th percentile
+    pub p99: Option<f64>,
+}
+
+impl fmt::Display for SketchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "count={}", self.count)?;
+        let fields = [
+            ("min", self.min),
+            ("max", self.max),
+            ("p50", self.p50),
+            ("p90", self.p90),
+            ("p99", self.p99),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                write!(f, " {}={}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A plain-data report of a sketch's statistics at chosen quantiles
+///
+/// With the `serde` feature, reports serialize as structured data, so the same report
+/// can be logged, returned from an API, and compared with an earlier one.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SketchReport {
+    /// The number of values in the sketch
+    pub count: u64,
+    /// The sum of the values
+    pub sum: f64,
+    /// The minimum value, or `None` if the sketch is empty
+    pub min: Option<f64>,
+    /// The maximum value, or `None` if the sketch is empty
+    pub max: Option<f64>,
+    /// The requested quantiles, in the order they were requested
+    pub quantiles: Vec<ReportedQuantile>,
+}
+
+/// The value of a sketch at one quantile of a `SketchReport`
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReportedQuantile {
+    /// The quantile (between 0 and 1)
+    pub quantile: f64,
+    /// The estimated value, or `None` if the sketch is empty or the quantile is out of range
+    pub value: Option<f64>,
+}
+
+/// How to pick a value within the bin holding a quantile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuantileInterpolation {
+    /// The lowest value covered by the bin
+    #[default]
+    LowerBound,
+    /// The highest value covered by the bin
+    UpperBound,
+    /// The value with equal relative distance to both ends of the bin, as returned by
+    /// the reference implementations
+    Midpoint,
+    /// Interpolate linearly between the midpoints of the bins holding the two ranks
+    /// surrounding the quantile, using the exact min/max for the first and last ranks
+    Linear,
+    /// Interpolate linearly between the ends of the bin holding the quantile, by the
+    /// position of the quantile's rank among the ranks the bin holds
+    ///
+    /// Quantiles that fall in the same bin get distinct values, which smooths the
+    /// quantile curves of coarse sketches, at the cost of the error bound of the
+    /// `LowerBound` and `UpperBound` modes.
+    WithinBin,
+}
+
+/// How a sketch handles NaN and infinite values
+///
+/// Non-finite values have no bin, so they are never part of the distribution; the
+/// policy decides whether they are dropped, counted on the side, or rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonFinitePolicy {
+    /// Drop non-finite values without a trace
+    Ignore,
+    /// Refuse non-finite values; `try_add` reports `DDSketchError::NonFiniteValue`
+    Error,
+    /// Drop non-finite values but count them in `non_finite_count`
+    #[default]
+    CountSeparately,
+}
+
+/// The bin holding a given rank
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum RankedBin {
+    Negative(i32),
+    Zero,
+    Positive(i32),
+    /// The rank is at or beyond the total count
+    Beyond,
+}
+
+/// A bin found by a rank walk, with the ranks it holds
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RankSpan {
+    pub(crate) bin: RankedBin,
+    pub(crate) count: u64,
+    /// The total count of all bins before this one
+    pub(crate) rank_before: u64,
+}
+
+impl RankSpan {
+    /// The span of the ranks at or beyond the total count
+    pub(crate) fn beyond(total_count: u64) -> Self {
+        RankSpan { bin: RankedBin::Beyond, count: 0, rank_before: total_count }
+    }
 }
 
+/// A DDSketch whose mapping and stores are type-erased trait objects
+pub type DynDDSketch = DDSketch<Box<dyn IndexMapping>, Box<dyn Store>>;
+
 impl DDSketch {
     /// Create a new DDSketch with the given relative accuracy
     ///
@@ -43,353 +287,4485 @@ impl DDSketch {
     pub fn new(relative_accuracy: f64) -> Result<Self> {
         let mapping = LogarithmicMapping::new(relative_accuracy)?;
         
-        Ok(DDSketch {
-            mapping: Box::new(mapping),
-            store: Box::new(DenseStore::new()),
-            zero_count: 0,
-            negative_store: Box::new(DenseStore::new()),
-            min_value: None,
-            max_value: None,
-        })
+        Ok(DDSketch::from_parts(mapping, DenseStore::new(), DenseStore::new()))
     }
     
-    /// Create a new DDSketch with the given relative accuracy and maximum number of bins
+    /// Create a new DDSketch with room for a number of bins
+    ///
+    /// Pre-sizing avoids rehashing while a sketch fills up to a known size. The room
+    /// is split evenly between the positive and negative stores; a sketch of positive
+    /// values only uses half of it.
     ///
     /// # Arguments
     /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
-    /// * `max_num_bins` - The maximum number of bins to maintain
+    /// * `expected_bins` - The number of bins to make room for
     ///
     /// # Returns
-    /// A new DDSketch instance with collapsing stores
-    pub fn with_max_bins(relative_accuracy: f64, max_num_bins: usize) -> Result<Self> {
+    /// A new DDSketch instance or an error if the parameters are invalid
+    pub fn with_capacity(relative_accuracy: f64, expected_bins: usize) -> Result<Self> {
         let mapping = LogarithmicMapping::new(relative_accuracy)?;
+        let per_store = expected_bins.div_ceil(2);
         
-        Ok(DDSketch {
-            mapping: Box::new(mapping),
-            store: Box::new(CollapsingStore::new(max_num_bins)),
-            zero_count: 0,
-            negative_store: Box::new(CollapsingStore::new(max_num_bins)),
-            min_value: None,
-            max_value: None,
-        })
+        Ok(DDSketch::from_parts(
+            mapping,
+            DenseStore::with_capacity(per_store),
+            DenseStore::with_capacity(per_store),
+        ))
     }
     
-    /// Add a value to the sketch
+    /// Rebuild a sketch from a snapshot
     ///
     /// # Arguments
-    /// * `value` - The value to add
-    pub fn add(&mut self, value: f64) {
-        self.add_with_count(value, 1);
+    /// * `snapshot` - The snapshot to restore
+    ///
+    /// # Returns
+    /// A new DDSketch with the snapshot's contents, an error if the snapshot's
+    /// relative accuracy is invalid, `DDSketchError::IncompatibleSketches` if it was
+    /// taken with a mapping other than the logarithmic one, or
+    /// `DDSketchError::CountOverflow` if its total count exceeds `u64::MAX`
+    pub fn from_snapshot(snapshot: &SketchSnapshot) -> Result<Self> {
+        DDSketch::from_snapshot_with_mapping(LogarithmicMapping::new(snapshot.relative_accuracy)?, snapshot)
     }
     
-    /// Add a value with a specific count to the sketch
+    
+    /// Build a sketch from pre-aggregated bins, such as those of a legacy histogram
+    ///
+    /// Each bin's representative value is mapped to its index once and its whole count
+    /// is added there, so large counts cost no more than small ones.
     ///
     /// # Arguments
-    /// * `value` - The value to add
-    /// * `count` - The number of times to add the value
-    pub fn add_with_count(&mut self, value: f64, count: u64) {
-        if count == 0 {
-            return;
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    /// * `bins` - Pairs of a representative value and the number of values it stands for
+    ///
+    /// # Returns
+    /// A new DDSketch holding the bins, or an error if the relative accuracy is invalid,
+    /// a value is NaN or infinite (`DDSketchError::NonFiniteValue`), or a value cannot be
+    /// indexed by the mapping (`DDSketchError::ValueOutOfRange`)
+    pub fn from_bins(relative_accuracy: f64, bins: impl IntoIterator<Item = (f64, u64)>) -> Result<Self> {
+        let mut sketch = DDSketch::new(relative_accuracy)?;
+        sketch.strict = true;
+        for (value, count) in bins {
+            sketch.try_add_with_count(value, count)?;
         }
+        sketch.strict = false;
         
-        // Update min/max
-        self.min_value = Some(self.min_value.map_or(value, |min| min.min(value)));
-        self.max_value = Some(self.max_value.map_or(value, |max| max.max(value)));
+        Ok(sketch)
+    }
+}
+
+impl<M: IndexMapping> DDSketch<M> {
+    /// Rebuild a sketch from a snapshot, indexing its bins with a given mapping
+    ///
+    /// This restores sketches with mappings other than the default logarithmic one,
+    /// such as a `TailBiasedMapping` rebuilt from the snapshot's descriptor.
+    ///
+    /// # Arguments
+    /// * `mapping` - The mapping the snapshot's bins were indexed with
+    /// * `snapshot` - The snapshot to restore
+    ///
+    /// # Returns
+    /// A new DDSketch with the snapshot's contents, an error if the snapshot's
+    /// relative accuracy is invalid, `DDSketchError::IncompatibleSketches` if the
+    /// snapshot was taken with a different mapping, or `DDSketchError::CountOverflow`
+    /// if its total count exceeds `u64::MAX`
+    pub fn from_snapshot_with_mapping(mapping: M, snapshot: &SketchSnapshot) -> Result<Self> {
+        let mut sketch = DDSketch::from_parts(mapping, DenseStore::new(), DenseStore::new());
+        sketch.mapping.descriptor().check_mergeable(&snapshot.mapping_descriptor()?)?;
+        snapshot.positive_bins.iter()
+            .chain(&snapshot.negative_bins)
+            .try_fold(snapshot.zero_count, |total, &(_, count)| total.checked_add(count))
+            .ok_or(DDSketchError::CountOverflow)?;
         
-        if value == 0.0 {
-            self.zero_count += count;
-        } else if value > 0.0 {
-            if let Ok(index) = self.mapping.key(value) {
-                self.store.add(index, count);
-            }
-        } else {
-            // Handle negative values by storing their absolute value
-            if let Ok(index) = self.mapping.key(-value) {
-                self.negative_store.add(index, count);
+        for &(index, count) in &snapshot.positive_bins {
+            sketch.store.add(index, count);
+        }
+        
+        for &(index, count) in &snapshot.negative_bins {
+            sketch.negative_store.add(index, count);
+        }
+        
+        sketch.set_min_indexable_value(snapshot.min_indexable_value)?;
+        sketch.zero_count = snapshot.zero_count;
+        sketch.min_value = snapshot.min_value;
+        sketch.max_value = snapshot.max_value;
+        sketch.sum = snapshot.sum;
+        sketch.sum_squares = snapshot.sum_squares;
+        sketch.fill_missing_min_max();
+        
+        Ok(sketch)
+    }
+}
+
+impl DDSketch<LogarithmicMapping, CollapsingStore> {
+    /// Create a new DDSketch with the given relative accuracy and maximum number of bins
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    /// * `max_num_bins` - The maximum number of bins to maintain
+    /// * `policy` - Which bins lose precision when the budget is exceeded
+    ///
+    /// # Returns
+    /// A new DDSketch instance with collapsing stores
+    pub fn with_max_bins(
+        relative_accuracy: f64,
+        max_num_bins: usize,
+        policy: CollapsePolicy,
+    ) -> Result<Self> {
+        let mapping = LogarithmicMapping::new(relative_accuracy)?;
+        
+        // The negative store holds absolute values, so its low end is the one nearest
+        // to zero; mirror the policy to keep collapsing on the same tail of the data.
+        let negative_policy = match policy {
+            CollapsePolicy::CollapseLowest => CollapsePolicy::CollapseHighest,
+            CollapsePolicy::CollapseHighest => CollapsePolicy::CollapseLowest,
+            CollapsePolicy::Error => CollapsePolicy::Error,
+        };
+        
+        Ok(DDSketch::from_parts(
+            mapping,
+            CollapsingStore::with_policy(max_num_bins, policy),
+            CollapsingStore::with_policy(max_num_bins, negative_policy),
+        ))
+    }
+    
+    /// Create a new DDSketch that fits in a memory budget
+    ///
+    /// Picks the largest number of bins per store for which `memory_size_bytes()` is
+    /// estimated to stay within `max_bytes` however the sketch is filled and queried,
+    /// including the cached rank index, and collapses the lowest bins beyond it, so the
+    /// high quantiles keep their accuracy guarantee. Merging may allocate more while it
+    /// runs, but releases it before returning.
+    ///
+    /// The estimate follows the standard library's current hash table growth policy,
+    /// which is not part of its API; the budget is a target rather than a hard limit
+    /// should that policy change.
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    /// * `max_bytes` - The most memory the sketch may use
+    ///
+    /// # Returns
+    /// A new DDSketch instance with collapsing stores, an error if the relative
+    /// accuracy is invalid, or `DDSketchError::InvalidValue` if the budget cannot hold
+    /// even one bin per store
+    pub fn with_memory_budget(relative_accuracy: f64, max_bytes: usize) -> Result<Self> {
+        LogarithmicMapping::new(relative_accuracy)?;
+        
+        // The footprint follows from the bin count alone, so the search needs no stores
+        let fits = |max_num_bins: usize| {
+            // Both stores full, plus the zero bin
+            let rank_index = (2 * max_num_bins + 1) * std::mem::size_of::<(RankedBin, u64)>();
+            std::mem::size_of::<Self>() - 2 * std::mem::size_of::<CollapsingStore>()
+                + 2 * CollapsingStore::memory_size_for(max_num_bins)
+                + rank_index
+                <= max_bytes
+        };
+        if !fits(1) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        // Every bin takes more than a byte, which bounds the search
+        let (mut low, mut high) = (1, max_bytes);
+        while low < high {
+            let middle = low + (high - low).div_ceil(2);
+            if fits(middle) {
+                low = middle;
+            } else {
+                high = middle - 1;
             }
         }
+        Self::with_max_bins(relative_accuracy, low, CollapsePolicy::CollapseLowest)
+    }
+}
+
+impl DDSketch<LogarithmicMapping, HybridStore> {
+    /// Create a new DDSketch whose stores start sparse and become dense as they grow
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    ///
+    /// # Returns
+    /// A new DDSketch instance with hybrid stores
+    pub fn with_hybrid_stores(relative_accuracy: f64) -> Result<Self> {
+        Ok(DDSketch::from_parts(
+            LogarithmicMapping::new(relative_accuracy)?,
+            HybridStore::new(),
+            HybridStore::new(),
+        ))
+    }
+}
+
+impl<const N: usize> DDSketch<LogarithmicMapping, FixedStore<N>> {
+    /// Create a new DDSketch whose stores hold `N` bins each inline
+    ///
+    /// Adding values never allocates. Once the values span more than `N` bins, the
+    /// lowest magnitudes lose their accuracy guarantee.
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    ///
+    /// # Returns
+    /// A new DDSketch instance with fixed-capacity stores
+    pub fn with_fixed_stores(relative_accuracy: f64) -> Result<Self> {
+        Ok(DDSketch::from_parts(
+            LogarithmicMapping::new(relative_accuracy)?,
+            FixedStore::new(),
+            FixedStore::new(),
+        ))
+    }
+}
+
+impl<M: IndexMapping> DDSketch<M, CollapsingStore> {
+    /// Check whether either store has collapsed bins to stay within its budget
+    pub fn is_collapsed(&self) -> bool {
+        self.store.is_collapsed() || self.negative_store.is_collapsed()
     }
     
-    /// Get the total count of values in the sketch
-    pub fn count(&self) -> u64 {
-        self.store.total_count() + self.zero_count + self.negative_store.total_count()
+    /// Get the total number of collapses across both stores
+    pub fn collapse_count(&self) -> u64 {
+        self.store.collapse_count() + self.negative_store.collapse_count()
     }
     
-    /// Check if the sketch is empty
-    pub fn is_empty(&self) -> bool {
-        self.count() == 0
+    /// Get the value ranges that have lost their accuracy guarantee, in ascending order
+    ///
+    /// There is at most one range for negative values and one for positive values.
+    pub fn degraded_value_ranges(&self) -> Vec<(f64, f64)> {
+        let negative = self.negative_store.collapsed_index_range().map(|(low, high)| {
+            (-self.mapping.upper_bound(high), -self.mapping.lower_bound(low))
+        });
+        let positive = self.store.collapsed_index_range().map(|(low, high)| {
+            (self.mapping.lower_bound(low), self.mapping.upper_bound(high))
+        });
+        
+        negative.into_iter().chain(positive).collect()
+    }
+}
+
+impl<M: IndexMapping, S: Store> DDSketch<M, S> {
+    /// Create a new DDSketch from a mapping and a pair of empty stores
+    ///
+    /// # Arguments
+    /// * `mapping` - The mapping from values to indices
+    /// * `store` - The store for positive values
+    /// * `negative_store` - The store for the absolute values of negative values
+    ///
+    /// # Returns
+    /// A new DDSketch instance
+    pub fn from_parts(mapping: M, store: S, negative_store: S) -> Self {
+        let mut sketch = DDSketch {
+            mapping,
+            store,
+            zero_count: 0,
+            negative_store,
+            min_value: None,
+            max_value: None,
+            exact_extremes: true,
+            sum: 0.0,
+            sum_squares: 0.0,
+            interpolation: QuantileInterpolation::default(),
+            non_finite_policy: NonFinitePolicy::default(),
+            non_finite_count: 0,
+            strict: false,
+            min_indexable_value: 0.0,
+            overflowed: false,
+            key_cache: None,
+            generation: 0,
+            rank_index: OnceLock::new(),
+            integer_keys: OnceLock::new(),
+        };
+        sketch.fill_missing_min_max();
+        sketch
     }
     
-    /// Get the minimum value in the sketch
-    pub fn min(&self) -> Option<f64> {
-        self.min_value
+    /// Convert the sketch into one with type-erased mapping and stores
+    pub fn into_dyn(self) -> DynDDSketch
+    where
+        M: 'static,
+        S: 'static,
+    {
+        DDSketch {
+            mapping: Box::new(self.mapping),
+            store: Box::new(self.store),
+            zero_count: self.zero_count,
+            negative_store: Box::new(self.negative_store),
+            min_value: self.min_value,
+            max_value: self.max_value,
+            exact_extremes: self.exact_extremes,
+            sum: self.sum,
+            sum_squares: self.sum_squares,
+            interpolation: self.interpolation,
+            non_finite_policy: self.non_finite_policy,
+            non_finite_count: self.non_finite_count,
+            strict: self.strict,
+            min_indexable_value: self.min_indexable_value,
+            overflowed: self.overflowed,
+            key_cache: self.key_cache,
+            generation: self.generation,
+            rank_index: self.rank_index,
+            integer_keys: self.integer_keys,
+        }
     }
     
-    /// Get the maximum value in the sketch
-    pub fn max(&self) -> Option<f64> {
-        self.max_value
+    /// Add a value to the sketch
+    ///
+    /// Any `IntoSketchValue` type is accepted, so integer and `f32` inputs need no casts.
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        self.add_with_count(value, 1);
     }
     
-    /// Get the relative accuracy of the sketch
-    pub fn relative_accuracy(&self) -> f64 {
-        self.mapping.relative_accuracy()
+    /// Add a value with a specific count to the sketch
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    ///
+    /// Values refused by a store with `CollapsePolicy::Error` are dropped; use
+    /// `try_add_with_count` to observe the rejection.
+    pub fn add_with_count(&mut self, value: impl IntoSketchValue, count: u64) {
+        let _ = self.try_add_with_count(value, count);
     }
     
-    /// Get the value at a given quantile
+    /// Add a value to the sketch, reporting values the stores refuse to hold
     ///
     /// # Arguments
-    /// * `quantile` - The quantile to query (between 0 and 1)
+    /// * `value` - The value to add
+    pub fn try_add(&mut self, value: impl IntoSketchValue) -> Result<()> {
+        self.try_add_with_count(value, 1)
+    }
+    
+    /// Add a value with a specific count to the sketch, reporting values the stores refuse to hold
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
     ///
     /// # Returns
-    /// The estimated value at the given quantile
-    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
-        if quantile < 0.0 || quantile > 1.0 {
-            return Err(DDSketchError::InvalidQuantile);
+    /// `DDSketchError::BinLimitExceeded` if a store with `CollapsePolicy::Error` has no room
+    /// for the value, `DDSketchError::NonFiniteValue` if the value is NaN or infinite
+    /// under `NonFinitePolicy::Error` or in strict mode, or `DDSketchError::ValueOutOfRange`
+    /// if the value cannot be indexed in strict mode, or `DDSketchError::CountOverflow`
+    /// if the total count would exceed `u64::MAX`. In every case the sketch's contents
+    /// are left unchanged.
+    pub fn try_add_with_count(&mut self, value: impl IntoSketchValue, count: u64) -> Result<()> {
+        let value = value.into_sketch_value();
+        if count == 0 {
+            return Ok(());
         }
         
-        if self.is_empty() {
-            return Err(DDSketchError::EmptySketch);
+        if !value.is_finite() {
+            if self.strict {
+                return Err(DDSketchError::NonFiniteValue);
+            }
+            return self.add_non_finite(count);
+        }
+        if self.strict && !self.is_indexable(value) {
+            return Err(DDSketchError::ValueOutOfRange);
         }
+        self.reserve_count(count)?;
         
-        let total_count = self.count();
-        let rank = (quantile * total_count as f64) as u64;
+        if self.counts_as_zero(value) {
+            self.zero_count += count;
+        } else if value > 0.0 {
+            let key = self.key(value);
+            self.store.try_add(key, count)?;
+        } else {
+            // Handle negative values by storing their absolute value
+            let key = self.key(-value);
+            self.negative_store.try_add(key, count)?;
+        }
         
-        // Find the value at the given rank
-        let mut current_rank = 0u64;
+        self.record_value(value, count);
+        self.mark_modified();
         
-        // Check negative values first (in reverse order)
-        if !self.negative_store.is_empty() {
-            let mut negative_indices: Vec<i32> = self.negative_store.iter().map(|(i, _)| i).collect();
-            negative_indices.sort_by(|a, b| b.cmp(a)); // Reverse order for negative values
-            
-            for index in negative_indices {
-                let count = self.negative_store.get(index);
-                if current_rank + count > rank {
-                    return Ok(-self.mapping.value(index));
-                }
-                current_rank += count;
+        Ok(())
+    }
+    
+    /// Add a batch of values to the sketch
+    ///
+    /// Keys are computed for a whole chunk of values at a time and values that share a
+    /// bin are coalesced, so each bin is touched once per chunk. The result is the same
+    /// as adding each value with `add`, so in strict mode values that would be refused
+    /// are dropped.
+    ///
+    /// # Arguments
+    /// * `values` - The values to add
+    pub fn add_batch(&mut self, values: &[f64]) {
+        if self.count().checked_add(values.len() as u64).is_none() {
+            // Add one at a time so the values that still fit are kept
+            for &value in values {
+                self.add(value);
             }
+            return;
         }
         
-        // Check zero values
-        if current_rank + self.zero_count > rank {
-            return Ok(0.0);
-        }
-        current_rank += self.zero_count;
+        let chunk_capacity = values.len().min(BATCH_CHUNK_SIZE);
+        let mut positive = Vec::with_capacity(chunk_capacity);
+        let mut negative = Vec::with_capacity(chunk_capacity);
         
-        // Check positive values
-        if !self.store.is_empty() {
-            let mut positive_indices: Vec<i32> = self.store.iter().map(|(i, _)| i).collect();
-            positive_indices.sort();
-            
-            for index in positive_indices {
-                let count = self.store.get(index);
-                if current_rank + count > rank {
-                    return Ok(self.mapping.value(index));
+        for chunk in values.chunks(BATCH_CHUNK_SIZE) {
+            positive.clear();
+            negative.clear();
+            // Only values bound for a store are keyed, so zero and non-finite values
+            // never reach the mapping
+            for &value in chunk {
+                if self.strict && !(value.is_finite() && self.is_indexable(value)) {
+                    continue;
+                } else if !value.is_finite() {
+                    let _ = self.add_non_finite(1);
+                } else if self.counts_as_zero(value) {
+                    self.zero_count += 1;
+                    self.record_value(value, 1);
+                } else if value > 0.0 {
+                    positive.push((self.key(value), value));
+                } else {
+                    negative.push((self.key(-value), value));
                 }
-                current_rank += count;
             }
+            
+            self.add_coalesced(&mut positive, false);
+            self.add_coalesced(&mut negative, true);
         }
         
-        // Should not reach here if counts are correct
-        self.max_value.ok_or(DDSketchError::EmptySketch)
+        self.mark_modified();
     }
     
-    /// Get values for multiple quantiles
-    ///
-    /// # Arguments
-    /// * `quantiles` - A slice of quantiles to query
+    /// Add a batch of integer values to the sketch
     ///
-    /// # Returns
-    /// A vector of estimated values for the given quantiles
-    pub fn get_quantile_values(&self, quantiles: &[f64]) -> Result<Vec<f64>> {
-        quantiles.iter()
-            .map(|&q| self.get_quantile_value(q))
-            .collect()
-    }
-    
-    /// Merge another sketch into this one
+    /// This is the integer counterpart of `add_batch` for inputs such as nanosecond
+    /// durations or byte counts. Keys come from an `IntegerKeyTable` built on the first
+    /// call and kept with the sketch and its clones, so no value the table covers is
+    /// converted to `f64` for keying or passed through a logarithm. The result is the
+    /// same as adding each value with `add`.
     ///
     /// # Arguments
-    /// * `other` - The other sketch to merge
-    ///
-    /// # Returns
-    /// An error if the sketches are incompatible
-    pub fn merge(&mut self, other: &DDSketch) -> Result<()> {
-        // Check compatibility
-        if (self.mapping.relative_accuracy() - other.mapping.relative_accuracy()).abs() > 1e-10 {
-            return Err(DDSketchError::IncompatibleSketches);
+    /// * `values` - The values to add
+    pub fn add_batch_u64(&mut self, values: &[u64]) {
+        if self.count().checked_add(values.len() as u64).is_none() {
+            // Add one at a time so the values that still fit are kept
+            for &value in values {
+                self.add(value);
+            }
+            return;
         }
         
-        // Merge stores
-        self.store.merge(other.store.as_ref());
-        self.negative_store.merge(other.negative_store.as_ref());
-        self.zero_count += other.zero_count;
-        
-        // Update min/max
-        if let Some(other_min) = other.min_value {
-            self.min_value = Some(self.min_value.map_or(other_min, |min| min.min(other_min)));
-        }
+        let table = Arc::clone(self.integer_keys.get_or_init(|| Arc::new(IntegerKeyTable::new(&self.mapping))));
+        let (min_indexable, max_indexable) = (self.mapping.min_indexable_value(), self.mapping.max_indexable_value());
+        let mut positive = Vec::with_capacity(values.len().min(BATCH_CHUNK_SIZE));
         
-        if let Some(other_max) = other.max_value {
-            self.max_value = Some(self.max_value.map_or(other_max, |max| max.max(other_max)));
+        for chunk in values.chunks(BATCH_CHUNK_SIZE) {
+            positive.clear();
+            for &value in chunk {
+                let magnitude = value as f64;
+                if self.counts_as_zero(magnitude) {
+                    self.zero_count += 1;
+                    self.record_value(magnitude, 1);
+                } else if !self.strict || (magnitude >= min_indexable && magnitude <= max_indexable) {
+                    let key = table.key(value).unwrap_or_else(|| self.key(magnitude));
+                    positive.push((key, magnitude));
+                }
+            }
+            self.add_coalesced(&mut positive, false);
         }
         
-        Ok(())
+        self.mark_modified();
     }
     
-    /// Clear all data from the sketch
-    pub fn clear(&mut self) {
+    /// Add pre-counted values to the sketch, such as the rows of an aggregated histogram
+    ///
+    /// Each value is mapped to its index once and its whole count is added there, and
+    /// pairs that share a bin are coalesced, so each bin is touched once per chunk of
+    /// pairs however large the counts are. The result is the same as calling
+    /// `add_with_count` for each pair.
+    ///
+    /// # Arguments
+    /// * `pairs` - Pairs of a value and the number of times to add it
+    pub fn add_counts(&mut self, pairs: impl IntoIterator<Item = (f64, u64)>) {
+        let mut chunk = Vec::with_capacity(BATCH_CHUNK_SIZE);
+        let mut positive = Vec::new();
+        let mut negative = Vec::new();
+        
+        for pair in pairs {
+            chunk.push(pair);
+            if chunk.len() == BATCH_CHUNK_SIZE {
+                self.add_counts_chunk(&chunk, &mut positive, &mut negative);
+                chunk.clear();
+            }
+        }
+        self.add_counts_chunk(&chunk, &mut positive, &mut negative);
+        
+        self.mark_modified();
+    }
+    
+    /// Add one chunk of pre-counted values, reusing the buffers of keyed values
+    fn add_counts_chunk(
+        &mut self,
+        chunk: &[(f64, u64)],
+        positive: &mut Vec<(i32, f64, u64)>,
+        negative: &mut Vec<(i32, f64, u64)>,
+    ) {
+        let chunk_count = chunk.iter().try_fold(0u64, |total, &(_, count)| total.checked_add(count));
+        if chunk_count.and_then(|count| self.count().checked_add(count)).is_none() {
+            // Add one pair at a time so the values that still fit are kept
+            for &(value, count) in chunk {
+                self.add_with_count(value, count);
+            }
+            return;
+        }
+        
+        positive.clear();
+        negative.clear();
+        for &(value, count) in chunk {
+            if count == 0 || (self.strict && !(value.is_finite() && self.is_indexable(value))) {
+                continue;
+            } else if !value.is_finite() {
+                let _ = self.add_non_finite(count);
+            } else if self.counts_as_zero(value) {
+                self.zero_count += count;
+                self.record_value(value, count);
+            } else if value > 0.0 {
+                positive.push((self.key(value), value, count));
+            } else {
+                negative.push((self.key(-value), value, count));
+            }
+        }
+        
+        self.add_coalesced_counts(positive, false);
+        self.add_coalesced_counts(negative, true);
+    }
+    
+    /// Map a positive, finite magnitude to its index, through the key cache if enabled
+    fn key(&mut self, magnitude: f64) -> i32 {
+        match &mut self.key_cache {
+            Some(cache) => self.mapping.key_cached(magnitude, cache),
+            None => self.mapping.key(magnitude),
+        }
+    }
+    
+    /// Check whether a finite value is counted as zero or has an index of its own
+    pub(crate) fn is_indexable(&self, value: f64) -> bool {
+        let magnitude = value.abs();
+        self.counts_as_zero(value)
+            || (magnitude >= self.mapping.min_indexable_value() && magnitude <= self.mapping.max_indexable_value())
+    }
+    
+    /// Check whether a finite value belongs in the zero bucket
+    fn counts_as_zero(&self, value: f64) -> bool {
+        value == 0.0 || value.abs() < self.min_indexable_value
+    }
+    
+    /// Check that `count` more values fit in the total count, recording an overflow if not
+    pub(crate) fn reserve_count(&mut self, count: u64) -> Result<()> {
+        if self.count().checked_add(count).is_none() {
+            self.overflowed = true;
+            return Err(DDSketchError::CountOverflow);
+        }
+        Ok(())
+    }
+    
+    /// Apply the non-finite policy to a NaN or infinite value
+    fn add_non_finite(&mut self, count: u64) -> Result<()> {
+        match self.non_finite_policy {
+            NonFinitePolicy::Ignore => Ok(()),
+            NonFinitePolicy::Error => Err(DDSketchError::NonFiniteValue),
+            NonFinitePolicy::CountSeparately => {
+                self.non_finite_count = self.non_finite_count.saturating_add(count);
+                Ok(())
+            }
+        }
+    }
+    
+    /// Add keyed values to a store, touching each distinct key once
+    fn add_coalesced(&mut self, keyed_values: &mut [(i32, f64)], negative: bool) {
+        keyed_values.sort_unstable_by_key(|&(key, _)| key);
+        
+        for group in keyed_values.chunk_by(|a, b| a.0 == b.0) {
+            let store = if negative { &mut self.negative_store } else { &mut self.store };
+            if store.try_add(group[0].0, group.len() as u64).is_ok() {
+                for &(_, value) in group {
+                    self.record_value(value, 1);
+                }
+            }
+        }
+    }
+    
+    /// Add keyed, pre-counted values to a store, touching each distinct key once
+    fn add_coalesced_counts(&mut self, keyed_values: &mut [(i32, f64, u64)], negative: bool) {
+        keyed_values.sort_unstable_by_key(|&(key, _, _)| key);
+        
+        for group in keyed_values.chunk_by(|a, b| a.0 == b.0) {
+            let store = if negative { &mut self.negative_store } else { &mut self.store };
+            let count = group.iter().map(|&(_, _, count)| count).sum();
+            if store.try_add(group[0].0, count).is_ok() {
+                for &(_, value, count) in group {
+                    self.record_value(value, count);
+                }
+            }
+        }
+    }
+    
+    /// Update min/max and moments for values accepted by the stores
+    fn record_value(&mut self, value: f64, count: u64) {
+        // Update min/max
+        self.min_value = Some(self.min_value.map_or(value, |min| min.min(value)));
+        self.max_value = Some(self.max_value.map_or(value, |max| max.max(value)));
+        
+        // Update moments
+        self.sum += value * count as f64;
+        self.sum_squares += value * value * count as f64;
+    }
+    
+    /// Get the total count of values in the sketch
+    pub fn count(&self) -> u64 {
+        self.store.total_count() + self.zero_count + self.negative_store.total_count()
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+    
+    /// Get the count of zero values, which are tracked exactly
+    pub fn zero_count(&self) -> u64 {
+        self.zero_count
+    }
+    
+    /// Get the count in the bin that holds a value
+    ///
+    /// Every value within the relative accuracy of `value` shares its bin, so this
+    /// counts the values indistinguishable from it, such as requests that hit exactly
+    /// a timeout. Zero, and any magnitude below `min_indexable_value`, is looked up in
+    /// the zero bucket. Under a collapsing store, values whose bin
+    /// was collapsed are counted in the bin they were folded into.
+    ///
+    /// # Arguments
+    /// * `value` - The value to look up
+    ///
+    /// # Returns
+    /// The count of the bin holding `value`, or 0 if `value` is NaN or infinite
+    pub fn count_at(&self, value: f64) -> u64 {
+        if !value.is_finite() {
+            0
+        } else if self.counts_as_zero(value) {
+            self.zero_count
+        } else if value > 0.0 {
+            self.store.get(self.mapping.key(value))
+        } else {
+            self.negative_store.get(self.mapping.key(-value))
+        }
+    }
+    
+    /// Get the count of positive values, excluding those counted as zero
+    pub fn positive_count(&self) -> u64 {
+        self.store.total_count()
+    }
+    
+    /// Get the count of negative values, excluding those counted as zero
+    pub fn negative_count(&self) -> u64 {
+        self.negative_store.total_count()
+    }
+    
+    /// Get the smallest positive value in the sketch
+    ///
+    /// This is the minimum when the sketch holds no zero or negative values, and
+    /// otherwise is estimated from the lowest positive bin, within the relative
+    /// accuracy.
+    ///
+    /// # Returns
+    /// The smallest positive value, or `None` if the sketch holds no positive values
+    pub fn min_positive(&self) -> Option<f64> {
+        let index = self.store.min_index()?;
+        match (self.min_value, self.max_value) {
+            (Some(min), _) if min > 0.0 => Some(min),
+            (_, Some(max)) => {
+                let value = relative_midpoint(self.mapping.lower_bound(index), self.mapping.upper_bound(index));
+                Some(value.min(max))
+            }
+            _ => None,
+        }
+    }
+    
+    /// Get the negative value closest to zero in the sketch
+    ///
+    /// This is the maximum when the sketch holds no zero or positive values, and
+    /// otherwise is estimated from the negative bin closest to zero, within the
+    /// relative accuracy.
+    ///
+    /// # Returns
+    /// The largest negative value, or `None` if the sketch holds no negative values
+    pub fn max_negative(&self) -> Option<f64> {
+        let index = self.negative_store.min_index()?;
+        match (self.min_value, self.max_value) {
+            (_, Some(max)) if max < 0.0 => Some(max),
+            (Some(min), _) => {
+                let value = -relative_midpoint(self.mapping.lower_bound(index), self.mapping.upper_bound(index));
+                Some(value.max(min))
+            }
+            _ => None,
+        }
+    }
+    
+    /// Get the number of non-empty bins across both stores
+    pub fn num_bins(&self) -> usize {
+        self.store.num_bins() + self.negative_store.num_bins()
+    }
+    
+    /// Estimate the memory used by the sketch in bytes
+    ///
+    /// Includes the stores and the cached rank index, but not heap data owned by the
+    /// mapping.
+    pub fn memory_size_bytes(&self) -> usize {
+        let rank_index = self.rank_index.get()
+            .map_or(0, |rank_index| rank_index.capacity() * std::mem::size_of::<(RankedBin, u64)>());
+        
+        std::mem::size_of::<Self>() - 2 * std::mem::size_of::<S>()
+            + self.store.memory_size_bytes()
+            + self.negative_store.memory_size_bytes()
+            + rank_index
+    }
+    
+    /// Get the minimum value in the sketch
+    pub fn min(&self) -> Option<f64> {
+        self.min_value
+    }
+    
+    /// Get the maximum value in the sketch
+    pub fn max(&self) -> Option<f64> {
+        self.max_value
+    }
+    
+    /// Get the count, extremes, and median, p90 and p99 of the sketch
+    pub fn summary(&self) -> SketchSummary {
+        let quantile = |q| self.get_quantile_value(q).ok();
+        
+        SketchSummary {
+            count: self.count(),
+            min: self.min_value,
+            max: self.max_value,
+            p50: quantile(0.5),
+            p90: quantile(0.9),
+            p99: quantile(0.99),
+        }
+    }
+    
+    /// Get the count, sum, extremes, and values at some quantiles of the sketch
+    ///
+    /// # Arguments
+    /// * `quantiles` - The quantiles to report (between 0 and 1)
+    ///
+    /// # Returns
+    /// A report holding a value for each quantile, which is `None` if the sketch is
+    /// empty or the quantile is out of range
+    pub fn report(&self, quantiles: &[f64]) -> SketchReport {
+        SketchReport {
+            count: self.count(),
+            sum: self.sum,
+            min: self.min_value,
+            max: self.max_value,
+            quantiles: quantiles.iter()
+                .map(|&quantile| ReportedQuantile {
+                    quantile,
+                    value: self.get_quantile_value(quantile).ok(),
+                })
+                .collect(),
+        }
+    }
+    
+    /// Get the exact sum of all values added to the sketch
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+    
+    /// Get the exact mean of all values added to the sketch
+    ///
+    /// # Returns
+    /// The mean, or `None` if the sketch is empty
+    pub fn mean(&self) -> Option<f64> {
+        let count = self.count();
+        (count > 0).then(|| self.sum / count as f64)
+    }
+    
+    /// Get the population standard deviation of all values added to the sketch
+    ///
+    /// # Returns
+    /// The standard deviation, or `None` if the sketch is empty
+    pub fn stddev(&self) -> Option<f64> {
+        let count = self.count() as f64;
+        let mean = self.mean()?;
+        
+        // Rounding can make the variance slightly negative for near-constant data
+        let variance = (self.sum_squares / count - mean * mean).max(0.0);
+        Some(variance.sqrt())
+    }
+    
+    /// Get the relative accuracy of the sketch
+    pub fn relative_accuracy(&self) -> f64 {
+        self.mapping.relative_accuracy()
+    }
+    
+    /// Get the parameters of the sketch's mapping from values to bins
+    pub fn mapping(&self) -> MappingDescriptor {
+        self.mapping.descriptor()
+    }
+    
+    /// Check whether another sketch can be merged into this one bin by bin
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to check
+    ///
+    /// # Returns
+    /// The reason the sketches' bins do not line up, if they do not
+    pub fn is_mergeable_with<N: IndexMapping, T: Store>(
+        &self,
+        other: &DDSketch<N, T>,
+    ) -> std::result::Result<(), IncompatibilityReason> {
+        self.mapping.descriptor().check_compatible(&other.mapping.descriptor())
+    }
+    
+    /// Check that another sketch can be merged bin by bin, reporting why it cannot
+    pub(crate) fn check_mergeable<N: IndexMapping, T: Store>(&self, other: &DDSketch<N, T>) -> Result<()> {
+        self.mapping.descriptor().check_mergeable(&other.mapping.descriptor())
+    }
+    
+    /// Get the mapping's descriptor if it is not the logarithmic mapping of the
+    /// relative accuracy, which is all the encodings otherwise record
+    pub(crate) fn custom_mapping(&self) -> Option<MappingDescriptor> {
+        let descriptor = self.mapping.descriptor();
+        let logarithmic = LogarithmicMapping::new(self.mapping.relative_accuracy()).ok()?.descriptor();
+        match logarithmic.check_compatible(&descriptor) {
+            Ok(()) => None,
+            Err(_) => Some(descriptor),
+        }
+    }
+    
+    /// Get the value at a given quantile
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.get_quantile_value_with(quantile, self.interpolation)
+    }
+    
+    /// Get the value at a given quantile using a specific interpolation mode
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    /// * `interpolation` - How to pick a value within the bins holding the quantile
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value_with(
+        &self,
+        quantile: f64,
+        interpolation: QuantileInterpolation,
+    ) -> Result<f64> {
+        self.interpolated_quantile(quantile, interpolation, |rank| self.bin_at_rank(rank))
+            .map(|(value, _, _)| value)
+    }
+    
+    /// Get the value at a given quantile together with the range it may be off by
+    ///
+    /// The bounds are the value range covered by the bins that answered the query,
+    /// narrowed to the recorded min/max.
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile with its bounds
+    pub fn get_quantile_with_bounds(&self, quantile: f64) -> Result<QuantileEstimate> {
+        let (value, lower_bound, upper_bound) =
+            self.interpolated_quantile(quantile, self.interpolation, |rank| self.bin_at_rank(rank))?;
+        
+        Ok(QuantileEstimate {
+            value,
+            lower_bound,
+            upper_bound,
+        })
+    }
+    
+    /// Get the value at a given quantile together with the bin holding its rank
+    ///
+    /// With linear interpolation the value may be interpolated towards a neighboring
+    /// bin; the bin reported is always the one holding rank `quantile × count`.
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value with its bin, `DDSketchError::InvalidQuantile` if the
+    /// quantile is out of bounds, or `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn get_quantile_bin(&self, quantile: f64) -> Result<QuantileBin> {
+        let value = self.get_quantile_value(quantile)?;
+        let rank = ((quantile * self.count() as f64) as u64).min(self.count() - 1);
+        let bin = self.bin_at_rank(rank).bin;
+        let (bin_lower, bin_upper) = self.bin_bounds(bin);
+        let (bin_index, bin_count) = match bin {
+            RankedBin::Negative(index) => (Some(index), self.negative_store.get(index)),
+            RankedBin::Positive(index) => (Some(index), self.store.get(index)),
+            RankedBin::Zero | RankedBin::Beyond => (None, self.zero_count),
+        };
+        
+        Ok(QuantileBin {
+            value,
+            bin_index,
+            bin_count,
+            bin_lower,
+            bin_upper,
+        })
+    }
+    
+    /// Get the largest relative error of any quantile value, as the sketch is configured
+    ///
+    /// This depends on the interpolation mode: returning either end of a bin, or any
+    /// point within it, can be off by up to `γ - 1` relative to the true value, while
+    /// the midpoint and linear modes are off by at most `(γ - 1) / (γ + 1)`. It assumes no bins have been
+    /// collapsed; use `observed_error_bound_at` for the bound that actually applies.
+    pub fn guaranteed_relative_error(&self) -> f64 {
+        let gamma = self.mapping.descriptor().gamma;
+        match self.interpolation {
+            QuantileInterpolation::LowerBound
+            | QuantileInterpolation::UpperBound
+            | QuantileInterpolation::WithinBin => gamma - 1.0,
+            QuantileInterpolation::Midpoint | QuantileInterpolation::Linear => (gamma - 1.0) / (gamma + 1.0),
+        }
+    }
+    
+    /// Get the largest relative error the value at a given quantile can have
+    ///
+    /// The bound covers the bins that answer the query, widened to the whole collapsed
+    /// range if one of them holds collapsed counts, and narrowed to the recorded
+    /// min/max. Answers from the zero bin or pinned to the min/max are exact. When
+    /// the possible values straddle zero, no relative bound exists and the result is
+    /// infinite.
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The relative error bound of `get_quantile_value(quantile)`
+    pub fn observed_error_bound_at(&self, quantile: f64) -> Result<f64> {
+        let estimate = self.get_quantile_with_bounds(quantile)?;
+        let (mut lower, mut upper) = (estimate.lower_bound, estimate.upper_bound);
+        
+        let collapsed = [
+            self.negative_store.collapsed_index_range()
+                .map(|(low, high)| (-self.mapping.upper_bound(high), -self.mapping.lower_bound(low))),
+            self.store.collapsed_index_range()
+                .map(|(low, high)| (self.mapping.lower_bound(low), self.mapping.upper_bound(high))),
+        ];
+        for (collapsed_lower, collapsed_upper) in collapsed.into_iter().flatten() {
+            if lower < collapsed_upper && collapsed_lower < upper {
+                lower = lower.min(collapsed_lower);
+                upper = upper.max(collapsed_upper);
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_value, self.max_value) {
+            lower = lower.clamp(min, max);
+            upper = upper.clamp(min, max);
+        }
+        
+        let value = estimate.value;
+        if lower == upper {
+            return Ok(0.0);
+        }
+        if lower <= 0.0 && upper >= 0.0 {
+            return Ok(f64::INFINITY);
+        }
+        Ok(((value - lower) / lower).abs().max(((upper - value) / upper).abs()))
+    }
+    
+    /// Estimate the mean of the values between two quantiles
+    ///
+    /// Each bin contributes its midpoint weighted by how many of its values fall between
+    /// the two ranks, so the bins at the edges are counted partially.
+    ///
+    /// # Arguments
+    /// * `lower_quantile` - The quantile below which values are discarded
+    /// * `upper_quantile` - The quantile above which values are discarded
+    ///
+    /// # Returns
+    /// The estimated trimmed mean, or an error if the quantiles are not ordered within [0, 1]
+    pub fn trimmed_mean(&self, lower_quantile: f64, upper_quantile: f64) -> Result<f64> {
+        if !(0.0..=1.0).contains(&lower_quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: lower_quantile });
+        }
+        if !(0.0..=1.0).contains(&upper_quantile) || lower_quantile >= upper_quantile {
+            return Err(DDSketchError::InvalidQuantile { got: upper_quantile });
+        }
+        
+        let (min, max) = match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) if !self.is_empty() => (min, max),
+            _ => return Err(DDSketchError::EmptySketch),
+        };
+        
+        let total_count = self.count() as f64;
+        let (lower_rank, upper_rank) = (lower_quantile * total_count, upper_quantile * total_count);
+        
+        let mut weighted_sum = 0.0;
+        let mut total_weight = 0.0;
+        let mut rank_before = 0.0f64;
+        
+        for &(bin, cumulative_count) in self.rank_index() {
+            let rank_after = cumulative_count as f64;
+            let weight = rank_after.min(upper_rank) - rank_before.max(lower_rank);
+            
+            if weight > 0.0 {
+                let (lower, upper) = self.bin_bounds(bin);
+                weighted_sum += weight * relative_midpoint(lower.clamp(min, max), upper.clamp(min, max));
+                total_weight += weight;
+            }
+            
+            if rank_after >= upper_rank {
+                break;
+            }
+            rank_before = rank_after;
+        }
+        
+        Ok(weighted_sum / total_weight)
+    }
+    
+    /// Get the spread of the middle half of the values, `p75 - p25`
+    ///
+    /// # Returns
+    /// The interquartile range, or an error if the sketch is empty
+    pub fn interquartile_range(&self) -> Result<f64> {
+        Ok(self.get_quantile_value(0.75)? - self.get_quantile_value(0.25)?)
+    }
+    
+    /// Estimate the median of the absolute deviations from the median
+    ///
+    /// Each bin is represented by its midpoint, narrowed to the recorded min/max, and
+    /// the distances `|midpoint - median|` are ranked with the bins' counts. The result
+    /// carries the sketch's relative error in the values being compared, so it is
+    /// least precise when the spread is small relative to the median.
+    ///
+    /// # Returns
+    /// The estimated median absolute deviation, or an error if the sketch is empty
+    pub fn median_absolute_deviation(&self) -> Result<f64> {
+        let median = self.get_quantile_value(0.5)?;
+        let (min, max) = (self.min_value.unwrap_or(median), self.max_value.unwrap_or(median));
+        
+        let mut deviations: Vec<(f64, u64)> = self.rank_index().iter()
+            .scan(0, |rank_before, &(bin, cumulative_count)| {
+                let count = cumulative_count - *rank_before;
+                *rank_before = cumulative_count;
+                let (lower, upper) = self.bin_bounds(bin);
+                let midpoint = relative_midpoint(lower.clamp(min, max), upper.clamp(min, max));
+                Some(((midpoint - median).abs(), count))
+            })
+            .collect();
+        deviations.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        
+        let rank = self.count() / 2;
+        let mut cumulative_count = 0;
+        for (deviation, count) in deviations {
+            cumulative_count += count;
+            if cumulative_count > rank {
+                return Ok(deviation);
+            }
+        }
+        Err(DDSketchError::EmptySketch)
+    }
+    
+    /// Estimate the mode, the value where the data is densest
+    ///
+    /// The density of a bin is its count divided by the width of its value range, so
+    /// the result is the relative midpoint of the densest bin, narrowed to the recorded
+    /// min/max. Unlike the median this finds the highest peak of a multi-modal
+    /// distribution. Zero values have no width; they are the mode when the zero bucket
+    /// holds more values than any other bin. Ties go to the lowest value.
+    ///
+    /// # Returns
+    /// The estimated mode, or `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn mode(&self) -> Result<f64> {
+        self.mode_smoothed(0)
+    }
+    
+    /// Estimate the mode, averaging the density over neighbouring bins
+    ///
+    /// Each bin is scored by the counts of the bins up to `neighbors` indices away on
+    /// either side, divided by the width of that window, which keeps a single noisy bin
+    /// from winning over a broad peak. See `mode` for how the result is chosen.
+    ///
+    /// # Arguments
+    /// * `neighbors` - How many bins on each side to include, where 0 uses the bin alone
+    ///
+    /// # Returns
+    /// The estimated mode, or `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn mode_smoothed(&self, neighbors: u32) -> Result<f64> {
+        let (min, max) = match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Err(DDSketchError::EmptySketch),
+        };
+        
+        let mut best: Option<(f64, f64)> = None;
+        let mut consider = |density: f64, value: f64| {
+            if best.is_none_or(|(best_density, best_value)| {
+                density > best_density || (density == best_density && value < best_value)
+            }) {
+                best = Some((density, value));
+            }
+        };
+        
+        let mut densest_count = 0;
+        for (sign, store) in [(-1.0, &self.negative_store), (1.0, &self.store)] {
+            let bins = store.to_vec();
+            for &(index, count) in &bins {
+                densest_count = densest_count.max(count);
+                let first = index.saturating_sub(neighbors as i32);
+                let last = index.saturating_add(neighbors as i32);
+                let start = bins.partition_point(|&(other, _)| other < first);
+                let end = bins.partition_point(|&(other, _)| other <= last);
+                let window_count: u64 = bins[start..end].iter().map(|&(_, count)| count).sum();
+                let width = self.mapping.upper_bound(last) - self.mapping.lower_bound(first);
+                
+                let (lower, upper) = (self.mapping.lower_bound(index), self.mapping.upper_bound(index));
+                let value = (sign * relative_midpoint(lower, upper)).clamp(min, max);
+                consider(window_count as f64 / width, value);
+            }
+        }
+        
+        if self.zero_count > 0 && self.zero_count > densest_count {
+            return Ok(0.0);
+        }
+        best.map(|(_, value)| value).ok_or(DDSketchError::EmptySketch)
+    }
+    
+    /// Get the interpolation mode used by `get_quantile_value`
+    pub fn quantile_interpolation(&self) -> QuantileInterpolation {
+        self.interpolation
+    }
+    
+    /// Set the interpolation mode used by `get_quantile_value`
+    pub fn set_quantile_interpolation(&mut self, interpolation: QuantileInterpolation) {
+        self.interpolation = interpolation;
+    }
+    
+    /// Get the policy applied to NaN and infinite values
+    pub fn non_finite_policy(&self) -> NonFinitePolicy {
+        self.non_finite_policy
+    }
+    
+    /// Set the policy applied to NaN and infinite values
+    pub fn set_non_finite_policy(&mut self, policy: NonFinitePolicy) {
+        self.non_finite_policy = policy;
+    }
+    
+    /// Check whether the sketch is in strict mode
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+    
+    /// Set whether the sketch refuses values it cannot represent faithfully
+    ///
+    /// In strict mode, `try_add` reports NaN and infinite values as
+    /// `DDSketchError::NonFiniteValue` whatever the non-finite policy, and nonzero
+    /// values whose magnitude is outside `[min_indexable_value, max_indexable_value]`
+    /// as `DDSketchError::ValueOutOfRange`. Otherwise such values follow the
+    /// non-finite policy or share the mapping's extreme bins.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    
+    /// Count nonzero values whose magnitude is below a threshold in the zero bucket
+    ///
+    /// Without a threshold, tiny values are given the far negative indices of their
+    /// magnitude, so a single stray value such as `1e-300` stretches a dense store
+    /// across hundreds of thousands of bins. As in the reference implementations,
+    /// values below the threshold are counted as zero instead, while the min, max and
+    /// sum still see their exact values. Values already in the sketch keep their bins,
+    /// so set this before adding values.
+    ///
+    /// # Arguments
+    /// * `min_indexable_value` - The threshold, or 0 to only count zero as zero
+    ///
+    /// # Returns
+    /// `DDSketchError::InvalidValue` if the threshold is negative or not finite
+    pub fn set_min_indexable_value(&mut self, min_indexable_value: f64) -> Result<()> {
+        if !(min_indexable_value.is_finite() && min_indexable_value >= 0.0) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        self.min_indexable_value = min_indexable_value;
+        Ok(())
+    }
+    
+    /// Set whether values are keyed through a cache of recently used bins
+    ///
+    /// Each added value is first looked up among the last few bins values fell into,
+    /// which skips the logarithm for inputs that repeat the same or nearby values, such
+    /// as quantized timers. A miss costs more than computing the key directly, so the
+    /// cache only pays off for such inputs. The sketch's contents are the same either way.
+    pub fn set_key_cache(&mut self, enabled: bool) {
+        if enabled != self.key_cache.is_some() {
+            self.key_cache = enabled.then(KeyCache::new);
+        }
+    }
+    
+    /// Get the key cache, if enabled, for its hit and miss counts
+    pub fn key_cache(&self) -> Option<&KeyCache> {
+        self.key_cache.as_ref()
+    }
+    
+    /// Get the smallest magnitude of a nonzero value with an index of its own
+    ///
+    /// This is the larger of the mapping's limit and the threshold set with
+    /// `set_min_indexable_value`.
+    pub fn min_indexable_value(&self) -> f64 {
+        self.mapping.min_indexable_value().max(self.min_indexable_value)
+    }
+    
+    /// Get the largest magnitude of a value with an index of its own
+    pub fn max_indexable_value(&self) -> f64 {
+        self.mapping.max_indexable_value()
+    }
+    
+    /// Get the number of NaN and infinite values counted under
+    /// `NonFinitePolicy::CountSeparately`
+    ///
+    /// These values are not part of `count()`.
+    pub fn non_finite_count(&self) -> u64 {
+        self.non_finite_count
+    }
+    
+    /// Check whether values were ever refused because the total count would have
+    /// exceeded `u64::MAX`
+    ///
+    /// Counts never wrap around: adds and merges that would overflow the total are
+    /// refused with `DDSketchError::CountOverflow`, or dropped by the infallible
+    /// methods, and this flag records that it happened until the sketch is cleared.
+    pub fn has_overflowed(&self) -> bool {
+        self.overflowed
+    }
+    
+    /// Estimate a quantile given a way to find the bin holding each rank
+    ///
+    /// # Returns
+    /// The estimate and the bounds of the bins it was derived from, all narrowed to
+    /// the recorded min/max
+    pub(crate) fn interpolated_quantile(
+        &self,
+        quantile: f64,
+        interpolation: QuantileInterpolation,
+        mut bin_at_rank: impl FnMut(u64) -> RankSpan,
+    ) -> Result<(f64, f64, f64)> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        
+        let (min, max) = match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) if !self.is_empty() => (min, max),
+            _ => return Err(DDSketchError::EmptySketch),
+        };
+        let bounds = |bin| {
+            let (lower, upper) = self.bin_bounds(bin);
+            (lower.clamp(min, max), upper.clamp(min, max))
+        };
+        
+        let total_count = self.count();
+        let (value, lower_bound, upper_bound) = match interpolation {
+            QuantileInterpolation::Linear => {
+                // Interpolate between the two ranks surrounding the quantile, using the
+                // exact min/max for the extreme ranks
+                let last_rank = total_count - 1;
+                let mut rank_value = |rank: u64| {
+                    let (lower, upper) = bounds(bin_at_rank(rank).bin);
+                    let value = match rank {
+                        0 => min,
+                        rank if rank == last_rank => max,
+                        _ => relative_midpoint(lower, upper),
+                    };
+                    (value, lower, upper)
+                };
+                
+                let rank = quantile * last_rank as f64;
+                let (low, lower, _) = rank_value(rank.floor() as u64);
+                let (high, _, upper) = rank_value(rank.ceil() as u64);
+                (low + (rank - rank.floor()) * (high - low), lower, upper)
+            }
+            QuantileInterpolation::WithinBin => {
+                // Place the quantile's fractional rank within the ranks of its bin, so
+                // the value runs from the bin's lower end to its upper end
+                let rank = quantile * total_count as f64;
+                let span = bin_at_rank((rank as u64).min(total_count - 1));
+                let (lower, upper) = bounds(span.bin);
+                let fraction = ((rank - span.rank_before as f64) / span.count.max(1) as f64).clamp(0.0, 1.0);
+                (lower + fraction * (upper - lower), lower, upper)
+            }
+            _ => {
+                let (lower, upper) = bounds(bin_at_rank((quantile * total_count as f64) as u64).bin);
+                let value = match interpolation {
+                    QuantileInterpolation::LowerBound => lower,
+                    QuantileInterpolation::UpperBound => upper,
+                    _ => relative_midpoint(lower, upper),
+                };
+                (value, lower, upper)
+            }
+        };
+        
+        Ok((value.clamp(min, max), lower_bound, upper_bound))
+    }
+    
+    /// Get the range of values covered by a bin
+    pub(crate) fn bin_bounds(&self, bin: RankedBin) -> (f64, f64) {
+        match bin {
+            RankedBin::Negative(index) => {
+                (-self.mapping.upper_bound(index), -self.mapping.lower_bound(index))
+            }
+            RankedBin::Zero => (0.0, 0.0),
+            RankedBin::Positive(index) => {
+                (self.mapping.lower_bound(index), self.mapping.upper_bound(index))
+            }
+            RankedBin::Beyond => {
+                let max = self.max_value.unwrap_or(0.0);
+                (max, max)
+            }
+        }
+    }
+    
+    /// Find the bin holding the value of the given rank, walking bins in value order
+    ///
+    /// The cumulative counts are built on the first query after a modification and
+    /// reused until the next one, so repeated queries take O(log n).
+    fn bin_at_rank(&self, rank: u64) -> RankSpan {
+        // Stores that keep their bins in order answer one-off queries without the index
+        if self.rank_index.get().is_none()
+            && self.store.has_ordered_ranks()
+            && self.negative_store.has_ordered_ranks()
+        {
+            return self.bin_at_rank_in_stores(rank);
+        }
+        let rank_index = self.rank_index();
+        
+        // Find the first bin whose cumulative count exceeds the rank
+        let position = rank_index.partition_point(|&(_, cumulative_count)| cumulative_count <= rank);
+        span_in_rank_index(rank_index, position)
+    }
+    
+    /// Find the bin holding a rank by asking the stores, without building the rank index
+    fn bin_at_rank_in_stores(&self, rank: u64) -> RankSpan {
+        // Negative values come first, in reverse index order
+        let negative_count = self.negative_store.total_count();
+        if rank < negative_count {
+            return self.negative_store.key_span_at_rank(negative_count - 1 - rank)
+                .map_or(RankSpan::beyond(self.count()), |(index, count, rank_below)| RankSpan {
+                    bin: RankedBin::Negative(index),
+                    count,
+                    rank_before: negative_count - rank_below - count,
+                });
+        }
+        
+        let zero_rank = rank - negative_count;
+        if zero_rank < self.zero_count {
+            return RankSpan { bin: RankedBin::Zero, count: self.zero_count, rank_before: negative_count };
+        }
+        let rank_before = negative_count + self.zero_count;
+        self.store.key_span_at_rank(rank - rank_before)
+            .map_or(RankSpan::beyond(self.count()), |(index, count, rank_below)| RankSpan {
+                bin: RankedBin::Positive(index),
+                count,
+                rank_before: rank_before + rank_below,
+            })
+    }
+    
+    /// Get the non-empty bins in value order with their cumulative counts, building them if needed
+    pub(crate) fn rank_index(&self) -> &[(RankedBin, u64)] {
+        self.rank_index.get_or_init(|| self.build_rank_index())
+    }
+    
+    /// Collect all non-empty bins in value order with their cumulative counts
+    fn build_rank_index(&self) -> Vec<(RankedBin, u64)> {
+        let mut rank_index = Vec::with_capacity(self.num_bins() + 1);
+        let mut cumulative_count = 0u64;
+        let mut push = |bin, count| {
+            if count > 0 {
+                cumulative_count += count;
+                rank_index.push((bin, cumulative_count));
+            }
+        };
+        
+        // Negative values come first, in reverse index order
+        for (index, count) in self.negative_store.iter_descending() {
+            push(RankedBin::Negative(index), count);
+        }
+        
+        push(RankedBin::Zero, self.zero_count);
+        
+        for (index, count) in self.store.iter_ascending() {
+            push(RankedBin::Positive(index), count);
+        }
+        
+        rank_index
+    }
+    
+    /// Record that the contents changed, invalidating cached query state
+    pub(crate) fn mark_modified(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.rank_index.take();
+    }
+    
+    /// Get the estimated number of values less than or equal to a given value
+    ///
+    /// Values that share a bin with `value` are counted as being at or below it, so the
+    /// estimate can overcount by at most the count of that bin. Values at or beyond the
+    /// recorded min/max are answered exactly.
+    ///
+    /// # Arguments
+    /// * `value` - The value to compare against
+    ///
+    /// # Returns
+    /// The estimated count of values at or below `value`
+    pub fn rank(&self, value: f64) -> u64 {
+        match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) if value >= min => {
+                if value >= max {
+                    return self.count();
+                }
+            }
+            _ => return 0,
+        }
+        
+        if value < 0.0 {
+            // Negative values are at or below `value` when their absolute value is at least |value|
+            let key = self.mapping.key(-value);
+            return self.negative_store.iter()
+                .filter(|&(index, _)| index >= key)
+                .map(|(_, count)| count)
+                .sum();
+        }
+        
+        let mut rank = self.negative_store.total_count() + self.zero_count;
+        if value > 0.0 {
+            let key = self.mapping.key(value);
+            rank += self.store.iter()
+                .filter(|&(index, _)| index <= key)
+                .map(|(_, count)| count)
+                .sum::<u64>();
+        }
+        rank
+    }
+    
+    /// Get the estimated fraction of values less than or equal to a given value
+    ///
+    /// This is the inverse of `get_quantile_value`; see `rank` for how values near
+    /// `value` are counted.
+    ///
+    /// # Arguments
+    /// * `value` - The value to compare against
+    ///
+    /// # Returns
+    /// The estimated fraction (between 0 and 1) of values at or below `value`
+    pub fn cdf(&self, value: f64) -> Result<f64> {
+        if self.is_empty() {
+            return Err(DDSketchError::EmptySketch);
+        }
+        
+        Ok(self.rank(value) as f64 / self.count() as f64)
+    }
+    
+    /// Get the estimated number of values strictly below a given value
+    ///
+    /// The bin containing `value` contributes the fraction of its value range that lies
+    /// below `value`, assuming its values are spread uniformly. The outermost bins are
+    /// narrowed to the recorded min/max.
+    ///
+    /// # Arguments
+    /// * `value` - The value to compare against
+    pub fn count_below(&self, value: f64) -> f64 {
+        let (min, max) = match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return 0.0,
+        };
+        
+        if value.is_nan() || value <= min {
+            return 0.0;
+        }
+        if value > max {
+            return self.count() as f64;
+        }
+        
+        let fraction_below = |lower: f64, upper: f64| {
+            let (lower, upper) = (lower.max(min), upper.min(max));
+            if value <= lower {
+                0.0
+            } else if value >= upper {
+                1.0
+            } else {
+                (value - lower) / (upper - lower)
+            }
+        };
+        
+        let negative: f64 = self.negative_store.iter()
+            .map(|(index, count)| {
+                let lower = -self.mapping.upper_bound(index);
+                let upper = -self.mapping.lower_bound(index);
+                count as f64 * fraction_below(lower, upper)
+            })
+            .sum();
+        
+        let zero = self.zero_count as f64 * fraction_below(0.0, 0.0);
+        
+        let positive: f64 = self.store.iter()
+            .map(|(index, count)| {
+                let lower = self.mapping.lower_bound(index);
+                let upper = self.mapping.upper_bound(index);
+                count as f64 * fraction_below(lower, upper)
+            })
+            .sum();
+        
+        negative + zero + positive
+    }
+    
+    /// Get the estimated number of values strictly above a given value
+    ///
+    /// See `count_below` for how the bin containing `value` is split.
+    ///
+    /// # Arguments
+    /// * `value` - The value to compare against
+    pub fn count_above(&self, value: f64) -> f64 {
+        if value.is_nan() {
+            return 0.0;
+        }
+        
+        self.count() as f64 - self.count_below(value)
+    }
+    
+    /// Get the estimated number of values between two values
+    ///
+    /// See `count_below` for how the bins containing the endpoints are split.
+    ///
+    /// # Arguments
+    /// * `lower` - The lower end of the range
+    /// * `upper` - The upper end of the range
+    ///
+    /// # Returns
+    /// The estimated count, or 0 if `lower` is not below `upper`
+    pub fn count_between(&self, lower: f64, upper: f64) -> f64 {
+        if lower.is_nan() || upper.is_nan() || lower >= upper {
+            return 0.0;
+        }
+        
+        self.count_below(upper) - self.count_below(lower)
+    }
+    
+    /// Redistribute the values into fixed histogram buckets
+    ///
+    /// For boundaries `b₀ < b₁ < … < bₙ₋₁` the result holds `n + 1` counts: values below
+    /// `b₀`, values in each `[bᵢ₋₁, bᵢ)`, and values at or above `bₙ₋₁`. Bins straddling a
+    /// boundary are split as in `count_below`. Counts are rounded cumulatively, so they
+    /// always add up to `count()`.
+    ///
+    /// # Arguments
+    /// * `boundaries` - The bucket boundaries in strictly ascending order
+    ///
+    /// # Returns
+    /// The bucket counts, or `DDSketchError::InvalidValue` if the boundaries are not
+    /// strictly ascending or one is NaN
+    pub fn to_fixed_buckets(&self, boundaries: &[f64]) -> Result<Vec<u64>> {
+        if boundaries.iter().any(|boundary| boundary.is_nan())
+            || boundaries.windows(2).any(|pair| pair[0] >= pair[1])
+        {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        let mut buckets = Vec::with_capacity(boundaries.len() + 1);
+        let mut previous = 0;
+        for &boundary in boundaries {
+            let cumulative = (self.count_below(boundary).round() as u64).clamp(previous, self.count());
+            buckets.push(cumulative - previous);
+            previous = cumulative;
+        }
+        buckets.push(self.count() - previous);
+        
+        Ok(buckets)
+    }
+    
+    /// Get the value at a given quantile of only the values inside a range
+    ///
+    /// This answers questions such as "p95 of the requests slower than 100 ms". As in
+    /// `count_below`, the bins are narrowed to the recorded min/max and those straddling
+    /// an end of the range contribute the fraction of their value range inside it,
+    /// assuming their values are spread uniformly. The result is interpolated linearly
+    /// within the bin holding the quantile, so it always lies inside the range.
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    /// * `range` - The values to consider, including `start` and excluding `end`
+    ///
+    /// # Returns
+    /// The estimated value, `DDSketchError::InvalidQuantile` if the quantile is out of
+    /// bounds, `DDSketchError::InvalidValue` if the range is empty or has a NaN end, or
+    /// `DDSketchError::EmptySketch` if no values fall inside the range
+    pub fn quantile_within(&self, quantile: f64, range: std::ops::Range<f64>) -> Result<f64> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        if range.start.is_nan() || range.end.is_nan() || range.start >= range.end {
+            return Err(DDSketchError::InvalidValue);
+        }
+        let (min, max) = match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Err(DDSketchError::EmptySketch),
+        };
+        
+        // The part of each bin inside the range, with the count it is estimated to hold
+        let parts: Vec<(f64, f64, f64)> = self.bins()
+            .filter_map(|bin| {
+                let (lower, upper) = (bin.lower.max(min), bin.upper.min(max));
+                let (start, end) = (lower.max(range.start), upper.min(range.end));
+                if lower == upper {
+                    let inside = range.contains(&lower);
+                    return inside.then_some((lower, lower, bin.count as f64));
+                }
+                (start < end).then(|| (start, end, bin.count as f64 * (end - start) / (upper - lower)))
+            })
+            .collect();
+        
+        let total: f64 = parts.iter().map(|&(_, _, weight)| weight).sum();
+        if total <= 0.0 {
+            return Err(DDSketchError::EmptySketch);
+        }
+        
+        let target = quantile * total;
+        let mut cumulative = 0.0;
+        for &(start, end, weight) in &parts {
+            if cumulative + weight >= target {
+                let fraction = if weight > 0.0 { (target - cumulative) / weight } else { 0.0 };
+                return Ok(start + fraction.clamp(0.0, 1.0) * (end - start));
+            }
+            cumulative += weight;
+        }
+        Ok(parts.last().map_or(range.start, |&(_, end, _)| end))
+    }
+    
+    /// Get values for multiple quantiles
+    ///
+    /// # Arguments
+    /// * `quantiles` - A slice of quantiles to query
+    ///
+    /// # Returns
+    /// A vector of estimated values for the given quantiles
+    pub fn get_quantile_values(&self, quantiles: &[f64]) -> Result<Vec<f64>> {
+        // Answer the quantiles in ascending order so one walk over the bins serves them all
+        let mut order: Vec<usize> = (0..quantiles.len()).collect();
+        order.sort_unstable_by(|&a, &b| quantiles[a].total_cmp(&quantiles[b]));
+        
+        let mut walker = RankWalker::new(self.rank_index());
+        let mut values = vec![0.0; quantiles.len()];
+        
+        for position in order {
+            let (value, _, _) = self.interpolated_quantile(
+                quantiles[position],
+                self.interpolation,
+                |rank| walker.bin_at_rank(rank),
+            )?;
+            values[position] = value;
+        }
+        
+        Ok(values)
+    }
+    
+    /// Get the quantile function at evenly spaced quantiles
+    ///
+    /// The quantiles `0, 1/steps, …, 1` are answered in one walk over the bins, which
+    /// makes this the cheap way to render a CDF chart or export a piecewise quantile
+    /// function.
+    ///
+    /// # Arguments
+    /// * `steps` - The number of intervals between quantile 0 and quantile 1
+    ///
+    /// # Returns
+    /// `steps + 1` pairs of a quantile and its estimated value, in ascending order,
+    /// `DDSketchError::InvalidValue` if `steps` is zero, or
+    /// `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn quantile_curve(&self, steps: usize) -> Result<Vec<(f64, f64)>> {
+        if steps == 0 {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        let mut walker = RankWalker::new(self.rank_index());
+        (0..=steps)
+            .map(|step| {
+                let quantile = step as f64 / steps as f64;
+                let (value, _, _) = self.interpolated_quantile(
+                    quantile,
+                    self.interpolation,
+                    |rank| walker.bin_at_rank(rank),
+                )?;
+                Ok((quantile, value))
+            })
+            .collect()
+    }
+    
+    /// Get the value at a given percentile
+    ///
+    /// # Arguments
+    /// * `percentile` - The percentile to query (between 0 and 100)
+    ///
+    /// # Returns
+    /// The estimated value, `DDSketchError::InvalidQuantile` if the percentile is out of
+    /// range, or `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn percentile(&self, percentile: f64) -> Result<f64> {
+        if !(0.0..=100.0).contains(&percentile) {
+            return Err(DDSketchError::InvalidQuantile { got: percentile / 100.0 });
+        }
+        self.get_quantile_value(percentile / 100.0)
+    }
+    
+    /// Get the median, or `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn p50(&self) -> Result<f64> {
+        self.get_quantile_value(0.5)
+    }
+    
+    /// Get the 75th percentile, or `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn p75(&self) -> Result<f64> {
+        self.get_quantile_value(0.75)
+    }
+    
+    /// Get the 90th percentile, or `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn p90(&self) -> Result<f64> {
+        self.get_quantile_value(0.9)
+    }
+    
+    /// Get the 95th percentile, or `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn p95(&self) -> Result<f64> {
+        self.get_quantile_value(0.95)
+    }
+    
+    /// Get the 99th percentile, or `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn p99(&self) -> Result<f64> {
+        self.get_quantile_value(0.99)
+    }
+    
+    /// Get the 99.9th percentile, or `DDSketchError::EmptySketch` if the sketch is empty
+    pub fn p999(&self) -> Result<f64> {
+        self.get_quantile_value(0.999)
+    }
+    
+    /// Merge another sketch into this one
+    ///
+    /// # Arguments
+    /// * `other` - The other sketch to merge
+    ///
+    /// # Returns
+    /// An error if the sketches are incompatible, or if a store with
+    /// `CollapsePolicy::Error` cannot hold the other sketch's bins; either way the
+    /// sketch is left unchanged
+    pub fn merge<N: IndexMapping, T: Store>(&mut self, other: &DDSketch<N, T>) -> Result<()> {
+        self.check_mergeable(other)?;
+        self.reserve_count(other.count())?;
+        // Both stores must accept their bins before either changes
+        self.negative_store.check_add(&mut other.negative_store.iter())?;
+        self.store.check_add(&mut other.store.iter())?;
+        
+        // Merge stores
+        self.mark_modified();
+        self.negative_store.try_merge(&other.negative_store)?;
+        self.store.try_merge(&other.store)?;
+        self.merge_summary(other);
+        self.fill_missing_min_max();
+        
+        Ok(())
+    }
+    
+    /// Merge many sketches into this one in a single pass
+    ///
+    /// All sketches are checked for compatibility before anything is merged, so on
+    /// error the sketch is left unchanged. Bins are accumulated in a pre-sized scratch
+    /// store and folded into this sketch's stores once, so a collapsing store runs a
+    /// single collapse pass instead of one per sketch.
+    ///
+    /// # Arguments
+    /// * `sketches` - The sketches to merge
+    ///
+    /// # Returns
+    /// An error if any sketch is incompatible, or if a store with `CollapsePolicy::Error`
+    /// cannot hold the merged bins
+    pub fn merge_all<'a, N, T>(&mut self, sketches: impl IntoIterator<Item = &'a DDSketch<N, T>>) -> Result<()>
+    where
+        N: IndexMapping + 'a,
+        T: Store + 'a,
+    {
+        let sketches: Vec<&DDSketch<N, T>> = sketches.into_iter().collect();
+        for other in &sketches {
+            self.check_mergeable(*other)?;
+        }
+        let total = sketches.iter().try_fold(0u64, |total, other| total.checked_add(other.count()));
+        self.reserve_count(total.unwrap_or(u64::MAX))?;
+        
+        // Sketches typically share most of their bins, so the largest one is a good size estimate
+        let capacity = |bins: fn(&DDSketch<N, T>) -> &T| {
+            sketches.iter().map(|other| bins(other).iter().count()).max().unwrap_or(0)
+        };
+        let mut negative = DenseStore::with_capacity(capacity(|other| &other.negative_store));
+        let mut positive = DenseStore::with_capacity(capacity(|other| &other.store));
+        for other in &sketches {
+            negative.merge(&other.negative_store);
+            positive.merge(&other.store);
+        }
+        // Both stores must accept the merged bins before either changes
+        self.negative_store.check_add(&mut negative.iter())?;
+        self.store.check_add(&mut positive.iter())?;
+        
+        self.mark_modified();
+        self.negative_store.reserve(negative.iter().count());
+        self.store.reserve(positive.iter().count());
+        self.negative_store.try_merge(&negative)?;
+        self.store.try_merge(&positive)?;
+        for other in &sketches {
+            self.merge_summary(other);
+        }
+        self.fill_missing_min_max();
+        
+        Ok(())
+    }
+    
+    /// Merge a stream of sketches into this one without buffering them
+    ///
+    /// Each sketch is checked once and its bins are added straight into this sketch's
+    /// stores, so nothing is cloned or collected and no scratch store is allocated.
+    /// Unlike `merge_all`, sketches are merged as they arrive: on error, the sketches
+    /// before the failing one remain merged, and nothing of the failing one is.
+    ///
+    /// # Arguments
+    /// * `sketches` - The sketches to merge
+    ///
+    /// # Returns
+    /// `DDSketchError::IncompatibleSketches` at the first sketch that cannot be merged,
+    /// or an error if a store with `CollapsePolicy::Error` cannot hold its bins
+    pub fn merge_from<'a, N, T>(&mut self, sketches: impl Iterator<Item = &'a DDSketch<N, T>>) -> Result<()>
+    where
+        N: IndexMapping + 'a,
+        T: Store + 'a,
+    {
+        let mut result = Ok(());
+        for other in sketches {
+            // Every check runs before the sketch's bins touch either store
+            let checked = self.check_mergeable(other)
+                .and_then(|_| self.reserve_count(other.count()))
+                .and_then(|_| self.negative_store.check_add(&mut other.negative_store.iter()))
+                .and_then(|_| self.store.check_add(&mut other.store.iter()));
+            if let Err(error) = checked {
+                result = Err(error);
+                break;
+            }
+            
+            self.mark_modified();
+            self.negative_store.merge(&other.negative_store);
+            self.store.merge(&other.store);
+            self.merge_summary(other);
+        }
+        self.fill_missing_min_max();
+        
+        result
+    }
+    
+    /// Merge sketches into a single new sketch
+    ///
+    /// This is the fallible form of summing sketches with `Iterator::sum`. The result
+    /// takes its configuration from the first sketch.
+    ///
+    /// # Arguments
+    /// * `sketches` - The sketches to merge
+    ///
+    /// # Returns
+    /// The merged sketch, `DDSketchError::EmptySketch` if there are no sketches, or
+    /// an error if any sketch cannot be merged into the first
+    pub fn try_sum(sketches: impl IntoIterator<Item = Self>) -> Result<Self> {
+        let mut sketches = sketches.into_iter();
+        let mut total = sketches.next().ok_or(DDSketchError::EmptySketch)?;
+        for sketch in sketches {
+            total.merge(&sketch)?;
+        }
+        Ok(total)
+    }
+    
+    /// Merge another sketch with a different relative accuracy into this one
+    ///
+    /// Each of the other sketch's bins is re-indexed into this sketch's mapping through
+    /// the bin's midpoint. A value estimated from the result is within a relative error
+    /// of `α + β + αβ`, where `α` and `β` are the two relative accuracies, so call this on
+    /// the coarser sketch: merging a 1% sketch into a 2% one yields at most about 3%.
+    /// Counts, extremes and moments are merged exactly.
+    ///
+    /// # Arguments
+    /// * `other` - The other sketch to merge
+    ///
+    /// # Returns
+    /// An error if a store with `CollapsePolicy::Error` cannot hold the re-indexed bins,
+    /// in which case the sketch is left unchanged
+    pub fn merge_rebin<N: IndexMapping, T: Store>(&mut self, other: &DDSketch<N, T>) -> Result<()> {
+        if self.is_mergeable_with(other).is_ok() {
+            return self.merge(other);
+        }
+        self.reserve_count(other.count())?;
+        
+        let rebin = |store: &T| {
+            let mut rebinned = DenseStore::new();
+            for (index, count) in store.iter() {
+                let lower = other.mapping.lower_bound(index);
+                let upper = other.mapping.upper_bound(index);
+                rebinned.add(self.mapping.key(relative_midpoint(lower, upper)), count);
+            }
+            rebinned
+        };
+        let negative = rebin(&other.negative_store);
+        let positive = rebin(&other.store);
+        // Both stores must accept the re-indexed bins before either changes
+        self.negative_store.check_add(&mut negative.iter())?;
+        self.store.check_add(&mut positive.iter())?;
+        
+        self.mark_modified();
+        self.negative_store.try_merge(&negative)?;
+        self.store.try_merge(&positive)?;
+        self.merge_summary(other);
+        self.fill_missing_min_max();
+        
+        Ok(())
+    }
+    
+    /// Re-bin the sketch into a new sketch with a coarser relative accuracy
+    ///
+    /// Useful to downsample sketches before archiving them: a coarser mapping needs
+    /// fewer bins and encodes smaller. Values estimated from the result are within the
+    /// `α + β + αβ` bound of `merge_rebin`. The interpolation mode and non-finite policy
+    /// carry over.
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The new relative accuracy, no finer than the current one
+    ///
+    /// # Returns
+    /// The re-binned sketch, or `DDSketchError::InvalidRelativeAccuracy` if the new
+    /// accuracy is invalid or finer than the current one
+    pub fn with_relative_accuracy(&self, relative_accuracy: f64) -> Result<DDSketch> {
+        if relative_accuracy < self.mapping.relative_accuracy() - 1e-10 {
+            return Err(DDSketchError::InvalidRelativeAccuracy);
+        }
+        
+        let mut sketch = DDSketch::new(relative_accuracy)?;
+        sketch.interpolation = self.interpolation;
+        sketch.non_finite_policy = self.non_finite_policy;
+        sketch.strict = self.strict;
+        sketch.min_indexable_value = self.min_indexable_value;
+        sketch.key_cache = self.key_cache.as_ref().map(|_| KeyCache::new());
+        sketch.overflowed = self.overflowed;
+        sketch.merge_rebin(self)?;
+        
+        Ok(sketch)
+    }
+    
+    /// Merge the zero count, extremes and moments of another sketch into this one
+    fn merge_summary<N: IndexMapping, T: Store>(&mut self, other: &DDSketch<N, T>) {
+        self.zero_count += other.zero_count;
+        self.non_finite_count = self.non_finite_count.saturating_add(other.non_finite_count);
+        self.sum += other.sum;
+        self.sum_squares += other.sum_squares;
+        
+        // Update min/max
+        if let Some(other_min) = other.min_value {
+            self.min_value = Some(self.min_value.map_or(other_min, |min| min.min(other_min)));
+        }
+        
+        if let Some(other_max) = other.max_value {
+            self.max_value = Some(self.max_value.map_or(other_max, |max| max.max(other_max)));
+        }
+        self.exact_extremes &= other.exact_extremes;
+    }
+    
+    /// Remove the values of another sketch from this one
+    ///
+    /// Counts are subtracted bin by bin, saturating at zero, so `cumulative_now` minus
+    /// `cumulative_earlier` yields the values recorded in between. The exact extremes
+    /// of the remaining values are unknown, so `min()`/`max()` are narrowed to the
+    /// outermost remaining bins and `has_exact_extremes()` becomes false.
+    ///
+    /// # Arguments
+    /// * `other` - The sketch whose values to remove
+    ///
+    /// # Returns
+    /// An error if the sketches are incompatible
+    pub fn subtract<N: IndexMapping, T: Store>(&mut self, other: &DDSketch<N, T>) -> Result<()> {
+        self.check_mergeable(other)?;
+        
+        self.mark_modified();
+        self.negative_store.subtract(&other.negative_store);
+        self.store.subtract(&other.store);
+        self.zero_count = self.zero_count.saturating_sub(other.zero_count);
+        self.non_finite_count = self.non_finite_count.saturating_sub(other.non_finite_count);
+        
+        if self.is_empty() {
+            self.min_value = None;
+            self.max_value = None;
+            self.exact_extremes = true;
+            self.sum = 0.0;
+            self.sum_squares = 0.0;
+            return Ok(());
+        }
+        
+        self.sum -= other.sum;
+        self.sum_squares -= other.sum_squares;
+        
+        // Narrow the previous extremes to the bins that still hold values
+        let (previous_min, previous_max) = (self.min_value.take(), self.max_value.take());
+        self.fill_missing_min_max();
+        if let (Some(previous), Some(min)) = (previous_min, self.min_value) {
+            self.min_value = Some(min.max(previous));
+        }
+        if let (Some(previous), Some(max)) = (previous_max, self.max_value) {
+            self.max_value = Some(max.min(previous));
+        }
+        
+        Ok(())
+    }
+    
+    /// Drop the values below a threshold
+    ///
+    /// Bins that lie entirely below `value` are removed. The bin containing `value` is
+    /// kept whole, and `min()` is clamped to `value`, since the bins cannot tell which
+    /// of its values fall on either side. The sum is reduced by the estimated values of
+    /// the removed bins, and `has_exact_extremes()` becomes false if anything was removed.
+    ///
+    /// # Arguments
+    /// * `value` - The lowest value to keep
+    ///
+    /// # Returns
+    /// The number of values removed, or `DDSketchError::InvalidValue` if `value` is NaN
+    pub fn retain_above(&mut self, value: f64) -> Result<u64> {
+        if value.is_nan() {
+            return Err(DDSketchError::InvalidValue);
+        }
+        Ok(self.retain_between(value, f64::INFINITY))
+    }
+    
+    /// Drop the values above a threshold
+    ///
+    /// This mirrors `retain_above`: bins entirely above `value` are removed and `max()`
+    /// is clamped to `value`.
+    ///
+    /// # Arguments
+    /// * `value` - The highest value to keep
+    ///
+    /// # Returns
+    /// The number of values removed, or `DDSketchError::InvalidValue` if `value` is NaN
+    pub fn retain_below(&mut self, value: f64) -> Result<u64> {
+        if value.is_nan() {
+            return Err(DDSketchError::InvalidValue);
+        }
+        Ok(self.retain_between(f64::NEG_INFINITY, value))
+    }
+    
+    /// Remove the bins that lie entirely outside `[low, high]`, returning the count removed
+    fn retain_between(&mut self, low: f64, high: f64) -> u64 {
+        // Positive bins cover [lower, upper) and negative bins (-upper, -lower]
+        let positive: Vec<(i32, u64)> = self.store.iter()
+            .filter(|&(index, _)| {
+                self.mapping.upper_bound(index) <= low || self.mapping.lower_bound(index) > high
+            })
+            .collect();
+        let negative: Vec<(i32, u64)> = self.negative_store.iter()
+            .filter(|&(index, _)| {
+                -self.mapping.lower_bound(index) < low || -self.mapping.upper_bound(index) >= high
+            })
+            .collect();
+        let drop_zeros = !(low..=high).contains(&0.0) && self.zero_count > 0;
+        if positive.is_empty() && negative.is_empty() && !drop_zeros {
+            let (min, max) = (self.min_value.map(|v| v.max(low)), self.max_value.map(|v| v.min(high)));
+            if (min, max) != (self.min_value, self.max_value) {
+                (self.min_value, self.max_value) = (min, max);
+                self.exact_extremes = false;
+                self.mark_modified();
+            }
+            return 0;
+        }
+        
+        self.mark_modified();
+        let mut removed = 0;
+        for (sign, store, bins) in [(1.0, &mut self.store, positive), (-1.0, &mut self.negative_store, negative)] {
+            for (index, count) in bins {
+                let estimate = sign * relative_midpoint(self.mapping.lower_bound(index), self.mapping.upper_bound(index));
+                let count = store.remove(index, count);
+                self.sum -= estimate * count as f64;
+                self.sum_squares -= estimate * estimate * count as f64;
+                removed += count;
+            }
+        }
+        if drop_zeros {
+            removed += std::mem::take(&mut self.zero_count);
+        }
+        
+        if self.is_empty() {
+            self.min_value = None;
+            self.max_value = None;
+            self.exact_extremes = true;
+            self.sum = 0.0;
+            self.sum_squares = 0.0;
+            return removed;
+        }
+        
+        // Narrow the previous extremes to the remaining bins and the kept range
+        let (previous_min, previous_max) = (self.min_value.take(), self.max_value.take());
+        self.fill_missing_min_max();
+        self.exact_extremes = false;
+        if let Some(min) = self.min_value {
+            self.min_value = Some(previous_min.map_or(min, |previous| min.max(previous)).max(low));
+        }
+        if let Some(max) = self.max_value {
+            self.max_value = Some(previous_max.map_or(max, |previous| max.min(previous)).min(high));
+        }
+        removed
+    }
+    
+    /// Remove sparse bins, moving their counts to the nearest remaining bin
+    ///
+    /// Bins holding fewer than `min_count` values are removed and their counts added to
+    /// the closest bin of the same sign that holds at least `min_count`; if no bin of a
+    /// sign qualifies, that sign's fullest bin is kept. The total count, sum, and
+    /// extremes are unchanged, so this shrinks long-lived sketches polluted by one-off
+    /// outliers without losing values.
+    ///
+    /// Moved values are reported at their new bin, so their quantile estimates are off
+    /// by up to the distance between the two bins rather than the relative accuracy:
+    /// a move across `k` bins adds a relative error of up to `γ^k - 1`, where `γ` is
+    /// the mapping's gamma, `1 + α` for the logarithmic mapping.
+    ///
+    /// # Arguments
+    /// * `min_count` - The fewest values a bin must hold to be kept
+    ///
+    /// # Returns
+    /// The number of bins removed
+    pub fn prune(&mut self, min_count: u64) -> usize {
+        let removed = Self::prune_store(&mut self.store, min_count)
+            + Self::prune_store(&mut self.negative_store, min_count);
+        if removed > 0 {
+            self.mark_modified();
+        }
+        removed
+    }
+    
+    /// Move the counts of a store's bins below `min_count` to their nearest kept bin
+    fn prune_store(store: &mut S, min_count: u64) -> usize {
+        let bins = store.to_vec();
+        let mut kept: Vec<i32> = bins.iter()
+            .filter(|&&(_, count)| count >= min_count)
+            .map(|&(index, _)| index)
+            .collect();
+        if kept.len() == bins.len() {
+            return 0;
+        }
+        if kept.is_empty() {
+            let fullest = bins.iter().max_by_key(|&&(_, count)| count).expect("a bin is below min_count");
+            kept.push(fullest.0);
+        }
+        
+        let mut removed = 0;
+        for &(index, count) in &bins {
+            let position = kept.partition_point(|&kept| kept < index);
+            if kept.get(position) == Some(&index) {
+                continue;
+            }
+            let below = position.checked_sub(1).map(|position| kept[position]);
+            let above = kept.get(position).copied();
+            let target = match (below, above) {
+                (Some(below), Some(above)) if (above as i64 - index as i64) < (index as i64 - below as i64) => above,
+                (Some(below), _) => below,
+                (None, above) => above.expect("at least one bin is kept"),
+            };
+            let count = store.remove(index, count);
+            store.add(target, count);
+            removed += 1;
+        }
+        removed
+    }
+    
+    /// Check whether `min()` and `max()` are the exact extremes of the recorded values
+    ///
+    /// They are bounds derived from the bins after `subtract`, or when the sketch was
+    /// built from stores or snapshots without extremes.
+    pub fn has_exact_extremes(&self) -> bool {
+        self.exact_extremes
+    }
+    
+    /// Derive missing min/max from the outermost non-empty bins of both stores
+    pub(crate) fn fill_missing_min_max(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        
+        if self.min_value.is_none() || self.max_value.is_none() {
+            self.exact_extremes = false;
+        }
+        
+        if self.min_value.is_none() {
+            self.min_value = self.negative_store.max_index()
+                .map(|index| -self.mapping.upper_bound(index))
+                .or((self.zero_count > 0).then_some(0.0))
+                .or(self.store.min_index().map(|index| self.mapping.value(index)));
+        }
+        
+        if self.max_value.is_none() {
+            self.max_value = self.store.max_index()
+                .map(|index| self.mapping.upper_bound(index))
+                .or((self.zero_count > 0).then_some(0.0))
+                .or(self.negative_store.min_index().map(|index| -self.mapping.value(index)));
+        }
+    }
+    
+    /// Multiply every value in the sketch by a constant
+    ///
+    /// Under the logarithmic mapping a multiplication is a shift of the bin indices, so
+    /// this converts units, such as nanoseconds to milliseconds, without re-ingesting
+    /// data. Each bin is moved to the bin holding its scaled midpoint; unless the factor
+    /// is a power of gamma, this can add up to one more bin of error, so estimates are
+    /// accurate to within about twice the relative accuracy. A negative factor swaps the
+    /// positive and negative values, and a factor of zero moves every value to zero.
+    ///
+    /// # Arguments
+    /// * `factor` - The finite factor to multiply values by
+    ///
+    /// # Returns
+    /// `DDSketchError::InvalidValue` if the factor is not finite or a scaled bin falls
+    /// outside the range of the mapping, in which case the sketch is left unchanged
+    pub fn scale(&mut self, factor: f64) -> Result<()> {
+        if !factor.is_finite() {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        if factor == 0.0 {
+            self.zero_count += self.store.total_count() + self.negative_store.total_count();
+            self.store.clear();
+            self.negative_store.clear();
+            if !self.is_empty() {
+                self.min_value = Some(0.0);
+                self.max_value = Some(0.0);
+            }
+            self.sum = 0.0;
+            self.sum_squares = 0.0;
+            self.mark_modified();
+            return Ok(());
+        }
+        
+        let magnitude = factor.abs();
+        let rescale = |store: &S| -> Result<Vec<(i32, u64)>> {
+            store.iter()
+                .map(|(index, count)| {
+                    let lower = self.mapping.lower_bound(index);
+                    let upper = self.mapping.upper_bound(index);
+                    let index = self.mapping.checked_key(relative_midpoint(lower, upper) * magnitude)?;
+                    if index < self.mapping.min_possible_index() || index > self.mapping.max_possible_index() {
+                        return Err(DDSketchError::InvalidValue);
+                    }
+                    Ok((index, count))
+                })
+                .collect()
+        };
+        let mut positive = rescale(&self.store)?;
+        let mut negative = rescale(&self.negative_store)?;
+        if factor < 0.0 {
+            std::mem::swap(&mut positive, &mut negative);
+        }
+        
+        self.store.clear();
+        self.negative_store.clear();
+        for (index, count) in positive {
+            self.store.add(index, count);
+        }
+        for (index, count) in negative {
+            self.negative_store.add(index, count);
+        }
+        
+        let (min, max) = (self.min_value.map(|v| v * factor), self.max_value.map(|v| v * factor));
+        (self.min_value, self.max_value) = if factor < 0.0 { (max, min) } else { (min, max) };
+        self.sum *= factor;
+        self.sum_squares *= factor * factor;
+        self.mark_modified();
+        
+        Ok(())
+    }
+    
+    /// Release memory the sketch no longer needs
+    ///
+    /// Stores keep the room they grew into, so a long-lived sketch holds on to its
+    /// peak allocation after bursty ingestion or `clear()`. This shrinks the stores to
+    /// their current bins and drops the cached rank index, which is rebuilt on the
+    /// next rank query.
+    pub fn shrink_to_fit(&mut self) {
+        self.store.shrink_to_fit();
+        self.negative_store.shrink_to_fit();
+        self.rank_index.take();
+    }
+    
+    /// Clear all data from the sketch
+    ///
+    /// The stores keep their allocations for reuse; call `shrink_to_fit` afterwards
+    /// to release them.
+    pub fn clear(&mut self) {
         self.store.clear();
         self.negative_store.clear();
         self.zero_count = 0;
+        self.non_finite_count = 0;
+        self.overflowed = false;
         self.min_value = None;
         self.max_value = None;
+        self.exact_extremes = true;
+        self.sum = 0.0;
+        self.sum_squares = 0.0;
+        self.mark_modified();
+    }
+    
+    /// Iterate over the non-empty bins of the sketch in ascending value order
+    ///
+    /// Negative bins come first, followed by a zero-width bin for zero values and then
+    /// the positive bins. Each bin reports the full value range its index covers.
+    pub fn bins(&self) -> impl Iterator<Item = Bin> + '_ {
+        let negative = self.negative_store.iter_descending()
+            .map(|(index, count)| Bin {
+                lower: -self.mapping.upper_bound(index),
+                upper: -self.mapping.lower_bound(index),
+                count,
+            });
+        
+        let zero = (self.zero_count > 0).then_some(Bin {
+            lower: 0.0,
+            upper: 0.0,
+            count: self.zero_count,
+        });
+        
+        let positive = self.store.iter_ascending()
+            .map(|(index, count)| Bin {
+                lower: self.mapping.lower_bound(index),
+                upper: self.mapping.upper_bound(index),
+                count,
+            });
+        
+        negative.chain(zero).chain(positive)
+    }
+    
+    /// Move the sketch's bins out as plain (value, count) pairs in ascending value order
+    ///
+    /// Covers the negative bins, the zero bucket and the positive bins, with each value
+    /// being the estimate `ordered_iter` reports for the bin. The result owns its data,
+    /// so it can be sent to another thread or stored without keeping the sketch or
+    /// its stores alive.
+    pub fn into_bins(self) -> Vec<(f64, u64)> {
+        self.ordered_iter().map(|(value, count, _)| (value, count)).collect()
+    }
+    
+    /// Iterate over the non-empty bins in ascending value order with cumulative counts
+    ///
+    /// Yields `(value, count, cumulative_count)` for the negative bins, the zero
+    /// bucket and the positive bins, where `value` is the estimate `get_quantile_value`
+    /// returns for ranks in the bin (its relative midpoint under
+    /// `QuantileInterpolation::Linear` and `WithinBin`) and `cumulative_count` includes the bin. This
+    /// reuses the cumulative counts cached for quantile queries.
+    pub fn ordered_iter(&self) -> impl Iterator<Item = (f64, u64, u64)> + '_ {
+        let (min, max) = (self.min_value.unwrap_or(f64::NEG_INFINITY), self.max_value.unwrap_or(f64::INFINITY));
+        let mut previous = 0;
+        
+        self.rank_index().iter().map(move |&(bin, cumulative_count)| {
+            let (lower, upper) = self.bin_bounds(bin);
+            let (lower, upper) = (lower.clamp(min, max), upper.clamp(min, max));
+            let value = match self.interpolation {
+                QuantileInterpolation::LowerBound => lower,
+                QuantileInterpolation::UpperBound => upper,
+                _ => relative_midpoint(lower, upper),
+            };
+            let count = cumulative_count - previous;
+            previous = cumulative_count;
+            (value, count, cumulative_count)
+        })
+    }
+    
+    /// Take a snapshot of the sketch's contents
+    ///
+    /// # Returns
+    /// A plain-data copy of the sketch with bins sorted by index
+    pub fn snapshot(&self) -> SketchSnapshot {
+        SketchSnapshot {
+            relative_accuracy: self.mapping.relative_accuracy(),
+            positive_bins: self.store.to_vec(),
+            negative_bins: self.negative_store.to_vec(),
+            zero_count: self.zero_count,
+            min_value: self.min_value,
+            max_value: self.max_value,
+            sum: self.sum,
+            sum_squares: self.sum_squares,
+            min_indexable_value: self.min_indexable_value,
+            mapping: self.custom_mapping(),
+        }
+    }
+    
+    /// Take a struct-of-arrays snapshot of the sketch's contents
+    pub fn columnar_snapshot(&self) -> ColumnarSnapshot {
+        ColumnarSnapshot::from(self.snapshot())
+    }
+}
+
+impl<M: IndexMapping + Clone, S: Store + Clone> DDSketch<M, S> {
+    /// Return the sketch's contents and reset it, keeping its configuration
+    ///
+    /// Exporting with `clone()` followed by `clear()` loses any value recorded by
+    /// another holder of the sketch in between; this does both under the one mutable
+    /// borrow, so each value is returned by exactly one `take`. The stores keep their
+    /// allocations for the next interval. `ShardedDDSketch::flush` and
+    /// `GlobalSketch::flush` do the same for shared sketches.
+    pub fn take(&mut self) -> Self {
+        let taken = self.clone();
+        self.clear();
+        taken
+    }
+    
+    /// Approximate the distribution of `X + Y` for independent `X` and `Y`
+    ///
+    /// Every pair of bins contributes the sum of their midpoints, weighted by the
+    /// product of their counts, so the result holds `count() × other.count()` values
+    /// and its quantiles are those of the sums of all pairs. The min, max, sum and sum
+    /// of squares are derived exactly. This takes time proportional to the product of
+    /// the numbers of bins.
+    ///
+    /// When both sketches hold values of one sign, each midpoint is within `α` of its
+    /// values, so their sums are too, and binning the sums adds another `α`: quantiles
+    /// are within about `2α` relative error. Sums of values of opposite signs cancel,
+    /// and their error is only bounded relative to the larger magnitude.
+    ///
+    /// # Arguments
+    /// * `other` - The sketch of the distribution to add
+    ///
+    /// # Returns
+    /// A sketch with this sketch's mapping and configuration, an error if the sketches
+    /// are incompatible, `DDSketchError::EmptySketch` if either is empty, or
+    /// `DDSketchError::CountOverflow` if the product of the counts exceeds `u64::MAX`
+    pub fn convolve<N: IndexMapping, T: Store>(&self, other: &DDSketch<N, T>) -> Result<Self> {
+        self.check_mergeable(other)?;
+        if self.is_empty() || other.is_empty() {
+            return Err(DDSketchError::EmptySketch);
+        }
+        let (count, other_count) = (self.count(), other.count());
+        count.checked_mul(other_count).ok_or(DDSketchError::CountOverflow)?;
+        
+        let mut result = self.clone();
+        result.clear();
+        let other_bins: Vec<(f64, u64)> = other.bins()
+            .map(|bin| (relative_midpoint(bin.lower, bin.upper), bin.count))
+            .collect();
+        for bin in self.bins() {
+            let (value, count) = (relative_midpoint(bin.lower, bin.upper), bin.count);
+            for &(other_value, other_count) in &other_bins {
+                result.try_add_with_count(value + other_value, count * other_count)?;
+            }
+        }
+        
+        let (count, other_count) = (count as f64, other_count as f64);
+        result.min_value = Some(self.min_value.unwrap_or(0.0) + other.min_value.unwrap_or(0.0));
+        result.max_value = Some(self.max_value.unwrap_or(0.0) + other.max_value.unwrap_or(0.0));
+        result.exact_extremes = self.exact_extremes && other.exact_extremes;
+        result.sum = self.sum * other_count + other.sum * count;
+        result.sum_squares = self.sum_squares * other_count
+            + other.sum_squares * count
+            + 2.0 * self.sum * other.sum;
+        Ok(result)
+    }
+    
+    /// Partition the sketch into `n` sketches whose merge equals it
+    ///
+    /// Every bin, the zero count, and the non-finite count are divided evenly, and the
+    /// remainders go one apiece to the shards in turn, continuing from bin to bin, so
+    /// the shard counts differ by at most one and the split is deterministic. The sum
+    /// and sum of squares are divided in proportion to each shard's count. Each
+    /// shard's extremes are its outermost bins narrowed to the original extremes, so
+    /// the shards together still hold the exact `min()` and `max()`, but
+    /// `has_exact_extremes()` is false for each shard.
+    ///
+    /// # Arguments
+    /// * `n` - The number of sketches to split into
+    ///
+    /// # Returns
+    /// `n` sketches with this sketch's mapping and configuration, some of which may be
+    /// empty if the sketch holds fewer than `n` values
+    ///
+    /// # Panics
+    /// If `n` is zero
+    pub fn split(&self, n: usize) -> Vec<Self> {
+        assert!(n > 0, "cannot split a sketch into zero parts");
+        
+        let mut empty = self.clone();
+        empty.clear();
+        let mut shards = vec![empty; n];
+        
+        // The shard that receives the next remainder
+        let mut next = 0;
+        let mut divide = |count: u64, give: &mut dyn FnMut(&mut Self, u64)| {
+            let (base, remainder) = (count / n as u64, (count % n as u64) as usize);
+            for (position, shard) in shards.iter_mut().enumerate() {
+                let extra = (position + n - next) % n < remainder;
+                let count = base + extra as u64;
+                if count > 0 {
+                    give(shard, count);
+                }
+            }
+            next = (next + remainder) % n;
+        };
+        
+        for (index, count) in self.negative_store.iter_ascending() {
+            divide(count, &mut |shard, count| shard.negative_store.add(index, count));
+        }
+        divide(self.zero_count, &mut |shard, count| shard.zero_count += count);
+        for (index, count) in self.store.iter_ascending() {
+            divide(count, &mut |shard, count| shard.store.add(index, count));
+        }
+        divide(self.non_finite_count, &mut |shard, count| shard.non_finite_count += count);
+        
+        let total = self.count();
+        for shard in &mut shards {
+            if shard.is_empty() {
+                continue;
+            }
+            
+            let share = shard.count() as f64 / total as f64;
+            shard.sum = self.sum * share;
+            shard.sum_squares = self.sum_squares * share;
+            shard.fill_missing_min_max();
+            if let (Some(min), Some(original)) = (shard.min_value, self.min_value) {
+                shard.min_value = Some(min.max(original));
+            }
+            if let (Some(max), Some(original)) = (shard.max_value, self.max_value) {
+                shard.max_value = Some(max.min(original));
+            }
+        }
+        shards
+    }
+}
+
+/// A position in a rank index that moves incrementally between nearby ranks
+///
+/// Queries for ranks in ascending order walk the index once in total.
+struct RankWalker<'a> {
+    rank_index: &'a [(RankedBin, u64)],
+    position: usize,
+}
+
+impl<'a> RankWalker<'a> {
+    fn new(rank_index: &'a [(RankedBin, u64)]) -> Self {
+        RankWalker { rank_index, position: 0 }
+    }
+    
+    /// Find the bin holding the rank, starting from the previously found bin
+    fn bin_at_rank(&mut self, rank: u64) -> RankSpan {
+        while self.position > 0 && self.rank_index[self.position - 1].1 > rank {
+            self.position -= 1;
+        }
+        while self.position < self.rank_index.len() && self.rank_index[self.position].1 <= rank {
+            self.position += 1;
+        }
+        
+        span_in_rank_index(self.rank_index, self.position)
+    }
+}
+
+/// Get the ranks held by the bin at a position of a rank index
+fn span_in_rank_index(rank_index: &[(RankedBin, u64)], position: usize) -> RankSpan {
+    let rank_before = position.checked_sub(1).map_or(0, |previous| rank_index[previous].1);
+    match rank_index.get(position) {
+        Some(&(bin, cumulative_count)) => RankSpan { bin, count: cumulative_count - rank_before, rank_before },
+        None => RankSpan::beyond(rank_before),
+    }
+}
+
+/// Get the value with equal relative distance to both ends of a same-signed range
+pub(crate) fn relative_midpoint(lower: f64, upper: f64) -> f64 {
+    if lower == upper {
+        return lower;
+    }
+    2.0 * lower * upper / (lower + upper)
+}
+
+impl<M: IndexMapping, S: Store> fmt::Debug for DDSketch<M, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DDSketch")
+            .field("relative_accuracy", &self.mapping.relative_accuracy())
+            .field("count", &self.count())
+            .field("min_value", &self.min_value)
+            .field("max_value", &self.max_value)
+            .field("sum", &self.sum)
+            .finish()
+    }
+}
+
+/// Formats the sketch's summary on one line, e.g. `count=3 min=1 max=3 p50=2 p90=3 p99=3`
+impl<M: IndexMapping, S: Store> fmt::Display for DDSketch<M, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.summary().fmt(f)
+    }
+}
+
+/// The parts of a sketch that define its content, in a canonical form
+///
+/// The mapping is described by its normalized descriptor's kind and parameters.
+/// Floats are compared by their bits and empty bins are dropped, so two sketches
+/// compare equal exactly when their canonical forms do.
+type CanonicalForm = ((u8, [u64; 3]), Vec<(i32, u64)>, Vec<(i32, u64)>, u64, Option<u64>, Option<u64>);
+
+impl<M: IndexMapping, S: Store> DDSketch<M, S> {
+    fn canonical_form(&self) -> CanonicalForm {
+        let non_empty = |store: &S| -> Vec<(i32, u64)> {
+            store.iter_ascending().filter(|&(_, count)| count > 0).collect()
+        };
+        
+        let descriptor = self.mapping.descriptor().normalized();
+        let parameters = [descriptor.gamma, descriptor.tail_gamma, descriptor.offset].map(f64::to_bits);
+        
+        (
+            (descriptor.kind as u8, parameters),
+            non_empty(&self.store),
+            non_empty(&self.negative_store),
+            self.zero_count,
+            self.min_value.map(f64::to_bits),
+            self.max_value.map(f64::to_bits),
+        )
+    }
+}
+
+/// Sketches are equal when they have the same mapping, bins, zero count and extremes
+///
+/// The store type, interpolation mode, and moments are not compared, so a sketch
+/// equals its clone regardless of how it was configured to answer queries.
+impl<M: IndexMapping, S: Store> PartialEq for DDSketch<M, S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical_form() == other.canonical_form()
+    }
+}
+
+impl<M: IndexMapping, S: Store> Eq for DDSketch<M, S> {}
+
+impl<M: IndexMapping, S: Store> Hash for DDSketch<M, S> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical_form().hash(state);
+    }
+}
+
+/// Merges another sketch into this one
+///
+/// # Panics
+/// If the sketches cannot be merged, as `merge` would report; use `merge` to handle
+/// incompatible sketches
+impl<M: IndexMapping, S: Store, N: IndexMapping, T: Store> std::ops::AddAssign<&DDSketch<N, T>> for DDSketch<M, S> {
+    fn add_assign(&mut self, other: &DDSketch<N, T>) {
+        if let Err(error) = self.merge(other) {
+            panic!("cannot merge sketches: {}", error);
+        }
+    }
+}
+
+/// Merges two sketches, with the same panics as `+=`
+///
+/// The operator traits are not imported in this module, since bringing `Add` into
+/// scope would shadow the inherent `add` for values passed by value.
+impl<M: IndexMapping, S: Store, N: IndexMapping, T: Store> std::ops::Add<&DDSketch<N, T>> for DDSketch<M, S> {
+    type Output = Self;
+    
+    fn add(mut self, other: &DDSketch<N, T>) -> Self {
+        self += other;
+        self
+    }
+}
+
+/// Merges two sketches, with the same panics as `+=`
+impl<M: IndexMapping, S: Store> std::ops::Add for DDSketch<M, S> {
+    type Output = Self;
+    
+    fn add(self, other: Self) -> Self {
+        self + &other
+    }
+}
+
+/// Merges sketches, taking the configuration from the first
+///
+/// # Panics
+/// If there are no sketches, since the result would have no relative accuracy, or
+/// if the sketches cannot be merged; use `DDSketch::try_sum` to handle either case
+impl<M: IndexMapping, S: Store> std::iter::Sum for DDSketch<M, S> {
+    fn sum<I: Iterator<Item = Self>>(sketches: I) -> Self {
+        match DDSketch::try_sum(sketches) {
+            Ok(total) => total,
+            Err(DDSketchError::EmptySketch) => panic!("cannot sum an empty iterator of sketches"),
+            Err(error) => panic!("cannot merge sketches: {}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_ddsketch_creation() {
+        let sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.relative_accuracy(), 0.02);
+        assert!(sketch.is_empty());
+        assert_eq!(sketch.count(), 0);
+        
+        // Invalid relative accuracy
+        assert!(DDSketch::new(0.0).is_err());
+        assert!(DDSketch::new(1.0).is_err());
+    }
+    
+    #[test]
+    fn test_ddsketch_add_values() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        
+        sketch.add(1.0);
+        sketch.add(2.0);
+        sketch.add(3.0);
+        
+        assert_eq!(sketch.count(), 3);
+        assert_eq!(sketch.min(), Some(1.0));
+        assert_eq!(sketch.max(), Some(3.0));
+        
+        // Test adding zero and negative values
+        sketch.add(0.0);
+        sketch.add(-1.0);
+        
+        assert_eq!(sketch.count(), 5);
+        assert_eq!(sketch.min(), Some(-1.0));
+        assert_eq!(sketch.max(), Some(3.0));
+    }
+    
+    #[test]
+    fn test_ddsketch_quantiles() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        
+        // Add values 1 through 100
+        for i in 1..=100 {
+            sketch.add(i as f64);
+        }
+        
+        let median = sketch.get_quantile_value(0.5).unwrap();
+        let p90 = sketch.get_quantile_value(0.9).unwrap();
+        let p99 = sketch.get_quantile_value(0.99).unwrap();
+        
+        // Check that quantiles are reasonable
+        assert!(median > 40.0 && median < 60.0);
+        assert!(p90 > 80.0 && p90 < 95.0);
+        assert!(p99 > 95.0 && p99 <= 100.0);
+        
+        // Test multiple quantiles
+        let quantiles = vec![0.25, 0.5, 0.75, 0.95];
+        let values = sketch.get_quantile_values(&quantiles).unwrap();
+        assert_eq!(values.len(), 4);
+        
+        // Values should be increasing
+        for i in 1..values.len() {
+            assert!(values[i] >= values[i-1]);
+        }
+    }
+    
+    #[test]
+    fn test_ddsketch_merge() {
+        let mut sketch1 = DDSketch::new(0.02).unwrap();
+        let mut sketch2 = DDSketch::new(0.02).unwrap();
+        
+        // Add different values to each sketch
+        for i in 1..=50 {
+            sketch1.add(i as f64);
+        }
+        
+        for i in 51..=100 {
+            sketch2.add(i as f64);
+        }
+        
+        let count1 = sketch1.count();
+        let count2 = sketch2.count();
+        
+        sketch1.merge(&sketch2).unwrap();
+        
+        assert_eq!(sketch1.count(), count1 + count2);
+        assert_eq!(sketch1.min(), Some(1.0));
+        assert_eq!(sketch1.max(), Some(100.0));
+        
+        // Test incompatible sketches
+        let mut sketch3 = DDSketch::new(0.01).unwrap();
+        sketch3.add(1.0);
+        
+        assert!(sketch1.merge(&sketch3).is_err());
+    }
+    
+    #[test]
+    fn test_ddsketch_edge_cases() {
+        let sketch = DDSketch::new(0.02).unwrap();
+        
+        // Empty sketch
+        assert!(sketch.get_quantile_value(0.5).is_err());
+        
+        // Invalid quantiles
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        sketch.add(1.0);
+        
+        assert!(sketch.get_quantile_value(-0.1).is_err());
+        assert!(sketch.get_quantile_value(1.1).is_err());
+    }
+    
+    #[test]
+    fn test_ddsketch_rank_and_cdf() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.rank(1.0), 0);
+        assert!(sketch.cdf(1.0).is_err());
+        
+        for i in -100..=100 {
+            sketch.add(i as f64);
+        }
+        
+        assert_eq!(sketch.rank(-101.0), 0);
+        assert_eq!(sketch.rank(f64::NAN), 0);
+        assert_eq!(sketch.rank(100.0), 201);
+        assert_eq!(sketch.rank(0.0), 101);
+        assert_eq!(sketch.rank(-0.5), 100);
+        
+        // Within one bin of the exact answer
+        let rank = sketch.rank(50.0);
+        assert!((151..=153).contains(&rank));
+        let rank = sketch.rank(-50.0);
+        assert!((51..=53).contains(&rank));
+        
+        let cdf = sketch.cdf(90.0).unwrap();
+        assert!((cdf - 191.0 / 201.0).abs() < 0.02);
+        assert_eq!(sketch.cdf(1000.0).unwrap(), 1.0);
+    }
+    
+    #[test]
+    fn test_ddsketch_quantile_bounds() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert!(sketch.get_quantile_with_bounds(0.5).is_err());
+        
+        for i in -100..=1000 {
+            sketch.add(i as f64);
+        }
+        
+        for &q in &[0.0, 0.01, 0.05, 0.0909, 0.5, 0.9, 0.99] {
+            let estimate = sketch.get_quantile_with_bounds(q).unwrap();
+            let exact = -100.0 + (q * 1101.0f64).floor();
+            
+            assert_eq!(estimate.value, sketch.get_quantile_value(q).unwrap());
+            assert!(estimate.lower_bound <= estimate.value && estimate.value <= estimate.upper_bound);
+            assert!(estimate.lower_bound <= exact && exact <= estimate.upper_bound);
+            assert!(estimate.upper_bound - estimate.lower_bound <= 0.03 * exact.abs().max(1.0));
+        }
+        
+        let estimate = sketch.get_quantile_with_bounds(1.0).unwrap();
+        assert_eq!(estimate, QuantileEstimate { value: 1000.0, lower_bound: 1000.0, upper_bound: 1000.0 });
+    }
+    
+    #[test]
+    fn test_ddsketch_quantile_interpolation() {
+        let mut sketch = DDSketch::new(0.05).unwrap();
+        
+        for i in -100..=100 {
+            sketch.add(i as f64);
+        }
+        sketch.add_with_count(50.0, 50);
+        
+        for &q in &[0.01, 0.25, 0.5, 0.75, 0.9] {
+            let lower = sketch.get_quantile_value_with(q, QuantileInterpolation::LowerBound).unwrap();
+            let upper = sketch.get_quantile_value_with(q, QuantileInterpolation::UpperBound).unwrap();
+            let midpoint = sketch.get_quantile_value_with(q, QuantileInterpolation::Midpoint).unwrap();
+            let linear = sketch.get_quantile_value_with(q, QuantileInterpolation::Linear).unwrap();
+            
+            assert!(lower <= midpoint && midpoint <= upper);
+            assert!((linear - midpoint).abs() <= 0.1 * midpoint.abs().max(1.0));
+            assert_eq!(lower, sketch.get_quantile_value(q).unwrap());
+        }
+        
+        // Bounds are narrowed to the recorded range
+        assert_eq!(sketch.get_quantile_value_with(0.0, QuantileInterpolation::LowerBound).unwrap(), -100.0);
+        assert_eq!(sketch.get_quantile_value_with(0.999, QuantileInterpolation::UpperBound).unwrap(), 100.0);
+        assert_eq!(sketch.get_quantile_value_with(0.0, QuantileInterpolation::Linear).unwrap(), -100.0);
+        assert_eq!(sketch.get_quantile_value_with(1.0, QuantileInterpolation::Linear).unwrap(), 100.0);
+        
+        sketch.set_quantile_interpolation(QuantileInterpolation::UpperBound);
+        assert_eq!(sketch.quantile_interpolation(), QuantileInterpolation::UpperBound);
+        assert_eq!(
+            sketch.get_quantile_value(0.5).unwrap(),
+            sketch.get_quantile_value_with(0.5, QuantileInterpolation::UpperBound).unwrap()
+        );
+        
+        let estimate = sketch.get_quantile_with_bounds(0.5).unwrap();
+        assert_eq!(estimate.value, estimate.upper_bound);
+    }
+    
+    #[test]
+    fn test_ddsketch_quantile_values_single_pass() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.get_quantile_values(&[]).unwrap(), Vec::<f64>::new());
+        assert!(sketch.get_quantile_values(&[0.5]).is_err());
+        
+        for i in -500..=2000 {
+            sketch.add((i as f64) * 0.37);
+        }
+        
+        let quantiles = [0.99, 0.5, 0.0, 1.0, 0.25, 0.5, 0.0004, 0.9996, 0.75];
+        for mode in [QuantileInterpolation::LowerBound, QuantileInterpolation::Midpoint, QuantileInterpolation::Linear] {
+            sketch.set_quantile_interpolation(mode);
+            let values = sketch.get_quantile_values(&quantiles).unwrap();
+            
+            for (&q, &value) in quantiles.iter().zip(&values) {
+                assert_eq!(value, sketch.get_quantile_value(q).unwrap());
+            }
+        }
+        
+        assert_eq!(sketch.get_quantile_values(&[0.5, 1.5]), Err(DDSketchError::InvalidQuantile { got: 1.5 }));
+        assert!(matches!(sketch.get_quantile_values(&[f64::NAN]), Err(DDSketchError::InvalidQuantile { .. })));
     }
-}
-
-impl fmt::Debug for DDSketch {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("DDSketch")
-            .field("relative_accuracy", &self.mapping.relative_accuracy())
-            .field("count", &self.count())
-            .field("min_value", &self.min_value)
-            .field("max_value", &self.max_value)
-            .finish()
+    
+    #[test]
+    fn test_ddsketch_trimmed_mean() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.trimmed_mean(0.1, 0.9), Err(DDSketchError::EmptySketch));
+        
+        for i in 1..=1000 {
+            sketch.add(i as f64);
+        }
+        
+        let trimmed = sketch.trimmed_mean(0.1, 0.9).unwrap();
+        assert!((trimmed - 500.5).abs() / 500.5 < 0.01);
+        
+        let full = sketch.trimmed_mean(0.0, 1.0).unwrap();
+        assert!((full - sketch.mean().unwrap()).abs() / 500.5 < 0.01);
+        
+        // Outliers above the upper quantile have no influence
+        sketch.add_with_count(1e9, 5);
+        let robust = sketch.trimmed_mean(0.05, 0.95).unwrap();
+        assert!(robust < 600.0);
+        
+        assert_eq!(sketch.trimmed_mean(0.9, 0.1), Err(DDSketchError::InvalidQuantile { got: 0.1 }));
+        assert_eq!(sketch.trimmed_mean(0.5, 0.5), Err(DDSketchError::InvalidQuantile { got: 0.5 }));
+        assert_eq!(sketch.trimmed_mean(-0.1, 0.5), Err(DDSketchError::InvalidQuantile { got: -0.1 }));
+    }
+    
+    #[test]
+    fn test_ddsketch_spread_measures() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        assert_eq!(sketch.interquartile_range(), Err(DDSketchError::EmptySketch));
+        assert_eq!(sketch.median_absolute_deviation(), Err(DDSketchError::EmptySketch));
+        
+        for i in 1..=1000 {
+            sketch.add(i as f64);
+        }
+        assert!((sketch.interquartile_range().unwrap() - 500.0).abs() <= 751.0 * 0.02);
+        assert!((sketch.median_absolute_deviation().unwrap() - 250.0).abs() <= 10.0);
+        
+        // A single outlier moves neither measure
+        sketch.add(1e9);
+        assert!((sketch.median_absolute_deviation().unwrap() - 250.0).abs() <= 10.0);
+        
+        let mut constant = DDSketch::new(0.01).unwrap();
+        constant.add_with_count(42.0, 10);
+        assert_eq!(constant.interquartile_range().unwrap(), 0.0);
+        assert_eq!(constant.median_absolute_deviation().unwrap(), 0.0);
+    }
+    
+    #[test]
+    fn test_ddsketch_rank_index_invalidation() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        
+        for i in 1..=100 {
+            sketch.add(i as f64);
+        }
+        assert_eq!(sketch.get_quantile_value(1.0).unwrap(), 100.0);
+        assert!(sketch.rank_index.get().is_some());
+        
+        // Adding, merging, and clearing all invalidate the cached counts
+        sketch.add(-1000.0);
+        assert!(sketch.rank_index.get().is_none());
+        assert_eq!(sketch.get_quantile_value(0.0).unwrap(), -1000.0);
+        
+        let mut other = DDSketch::new(0.02).unwrap();
+        other.add(5000.0);
+        sketch.merge(&other).unwrap();
+        assert!(sketch.get_quantile_value(0.999).unwrap() > 4900.0);
+        
+        sketch.clear();
+        assert!(sketch.get_quantile_value(0.5).is_err());
+        sketch.add(3.0);
+        assert_eq!(sketch.get_quantile_value(0.5).unwrap(), 3.0);
+    }
+    
+    #[test]
+    fn test_ddsketch_bins() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.bins().count(), 0);
+        
+        for i in -50..=50 {
+            sketch.add(i as f64 * 1.5);
+        }
+        
+        let bins: Vec<Bin> = sketch.bins().collect();
+        assert_eq!(bins.iter().map(|bin| bin.count).sum::<u64>(), sketch.count());
+        assert!(bins.contains(&Bin { lower: 0.0, upper: 0.0, count: 1 }));
+        
+        for bin in &bins {
+            assert!(bin.lower <= bin.upper);
+        }
+        for pair in bins.windows(2) {
+            assert!(pair[0].upper <= pair[1].lower);
+        }
+        
+        assert!(bins[0].lower <= -75.0 && -75.0 <= bins[0].upper);
+        assert!(bins[bins.len() - 1].lower <= 75.0 && 75.0 <= bins[bins.len() - 1].upper);
+    }
+    
+    #[test]
+    fn test_ddsketch_range_counts() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.count_below(1.0), 0.0);
+        
+        for i in 1..=1000 {
+            sketch.add(i as f64);
+            sketch.add(-(i as f64));
+        }
+        sketch.add(0.0);
+        
+        assert_eq!(sketch.count_below(-1000.0), 0.0);
+        assert_eq!(sketch.count_below(1000.5), 2001.0);
+        assert_eq!(sketch.count_below(f64::NAN), 0.0);
+        assert_eq!(sketch.count_below(0.0), 1000.0);
+        assert_eq!(sketch.count_below(0.5), 1001.0);
+        
+        assert!((sketch.count_below(500.5) - 1501.0).abs() < 2.0);
+        assert!((sketch.count_below(-500.5) - 500.0).abs() < 2.0);
+        assert!((sketch.count_above(900.5) - 100.0).abs() < 2.0);
+        assert!((sketch.count_between(100.5, 200.5) - 100.0).abs() < 2.0);
+        assert!((sketch.count_between(-200.5, 200.5) - 401.0).abs() < 2.0);
+        assert_eq!(sketch.count_between(5.0, 1.0), 0.0);
+    }
+    
+    #[test]
+    fn test_ddsketch_negative_values_accuracy() {
+        let relative_accuracy = 0.01;
+        let mut sketch = DDSketch::new(relative_accuracy).unwrap();
+        let mut values: Vec<f64> = (1..=2000).map(|i| -(i as f64).powf(1.5)).collect();
+        
+        for &value in &values {
+            sketch.add(value);
+        }
+        values.sort_by(f64::total_cmp);
+        
+        for q in [0.0, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0] {
+            let exact = values[((q * values.len() as f64) as usize).min(values.len() - 1)];
+            for mode in [QuantileInterpolation::LowerBound, QuantileInterpolation::Midpoint] {
+                let estimate = sketch.get_quantile_value_with(q, mode).unwrap();
+                assert!(
+                    (estimate - exact).abs() <= relative_accuracy * exact.abs() + 1e-9,
+                    "q={} mode={:?}: estimate {} exact {}", q, mode, estimate, exact
+                );
+            }
+        }
+    }
+    
+    #[test]
+    fn test_ddsketch_negative_values_mirrored() {
+        let mut positive = DDSketch::new(0.02).unwrap();
+        let mut negative = DDSketch::new(0.02).unwrap();
+        
+        for i in 1..=997 {
+            let value = (i as f64).sqrt() * 3.7;
+            positive.add(value);
+            negative.add(-value);
+        }
+        
+        for q in [0.0, 0.05, 0.3, 0.5, 0.77, 0.95, 1.0] {
+            let mirrored = -positive.get_quantile_value_with(1.0 - q, QuantileInterpolation::Linear).unwrap();
+            let estimate = negative.get_quantile_value_with(q, QuantileInterpolation::Linear).unwrap();
+            assert!((estimate - mirrored).abs() <= 1e-9 * mirrored.abs(), "q={}", q);
+        }
+        
+        // Both ranks include the bin holding the value itself
+        let value = 500f64.sqrt() * 3.7;
+        let shared_bin = positive.store.get(positive.mapping.key(value));
+        assert_eq!(negative.rank(-value) + positive.rank(value), positive.count() + shared_bin);
+    }
+    
+    #[test]
+    fn test_ddsketch_min_max_derived_from_stores() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        for value in [-40.0, -3.0, 7.0, 250.0] {
+            sketch.add(value);
+        }
+        
+        let mut snapshot = sketch.snapshot();
+        snapshot.min_value = None;
+        snapshot.max_value = None;
+        
+        let restored = DDSketch::from_snapshot(&snapshot).unwrap();
+        let (min, max) = (restored.min().unwrap(), restored.max().unwrap());
+        assert!((-40.0 * 1.02..=-40.0).contains(&min));
+        assert!((250.0..=250.0 * 1.02).contains(&max));
+        assert!(restored.get_quantile_value(0.5).is_ok());
+        
+        // Merging into an empty sketch keeps the exact extremes of both sides
+        let mut merged = DDSketch::new(0.02).unwrap();
+        merged.merge(&sketch).unwrap();
+        assert_eq!((merged.min(), merged.max()), (Some(-40.0), Some(250.0)));
+    }
+    
+    #[test]
+    fn test_ddsketch_merge_all() {
+        let sketches: Vec<DDSketch> = (0..20)
+            .map(|shard| {
+                let mut sketch = DDSketch::new(0.02).unwrap();
+                for i in 0..100 {
+                    sketch.add((shard * 100 + i) as f64 - 300.0);
+                }
+                sketch
+            })
+            .collect();
+        
+        let mut pairwise = DDSketch::new(0.02).unwrap();
+        for sketch in &sketches {
+            pairwise.merge(sketch).unwrap();
+        }
+        
+        let mut merged = DDSketch::new(0.02).unwrap();
+        merged.merge_all(&sketches).unwrap();
+        assert_eq!(merged.snapshot(), pairwise.snapshot());
+        
+        // An incompatible sketch anywhere leaves the destination untouched
+        let odd_one = DDSketch::new(0.05).unwrap();
+        // Empty sketches in the mix do not make the extremes approximate
+        let mut with_empty = DDSketch::new(0.02).unwrap();
+        let empty = DDSketch::new(0.02).unwrap();
+        with_empty.merge_all(std::iter::once(&empty).chain(&sketches)).unwrap();
+        assert_eq!(with_empty.min(), Some(-300.0));
+        assert!(with_empty.has_exact_extremes());
+        
+        let mut untouched = DDSketch::new(0.02).unwrap();
+        let mixed: Vec<&DDSketch> = sketches.iter().chain(std::iter::once(&odd_one)).collect();
+        assert!(matches!(untouched.merge_all(mixed), Err(DDSketchError::IncompatibleSketches { .. })));
+        assert!(untouched.is_empty());
+    }
+    
+    #[test]
+    fn test_ddsketch_operators() {
+        let shards: Vec<DDSketch> = (0..4)
+            .map(|shard| {
+                let mut sketch = DDSketch::new(0.02).unwrap();
+                for i in 0..100 {
+                    sketch.add((shard * 100 + i + 1) as f64);
+                }
+                sketch
+            })
+            .collect();
+        let mut expected = DDSketch::new(0.02).unwrap();
+        expected.merge_all(&shards).unwrap();
+        
+        let total: DDSketch = shards.clone().into_iter().sum();
+        assert_eq!(total, expected);
+        assert_eq!(DDSketch::try_sum(shards.clone()).unwrap(), expected);
+        assert_eq!(shards[0].clone() + &shards[1] + shards[2].clone() + &shards[3], expected);
+        
+        let mut accumulated = DDSketch::new(0.02).unwrap();
+        for shard in &shards {
+            accumulated += shard;
+        }
+        assert_eq!(accumulated, expected);
+        
+        assert_eq!(DDSketch::try_sum(Vec::<DDSketch>::new()), Err(DDSketchError::EmptySketch));
+        let incompatible = vec![DDSketch::new(0.02).unwrap(), DDSketch::new(0.05).unwrap()];
+        assert!(matches!(DDSketch::try_sum(incompatible.clone()), Err(DDSketchError::IncompatibleSketches { .. })));
+        let result = std::panic::catch_unwind(|| incompatible.into_iter().sum::<DDSketch>());
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_ddsketch_is_mergeable_with() {
+        let sketch = DDSketch::new(0.01).unwrap();
+        let dynamic = DynDDSketch::from_parts(
+            Box::new(LogarithmicMapping::new(0.01).unwrap()),
+            Box::new(DenseStore::new()),
+            Box::new(DenseStore::new()),
+        );
+        assert_eq!(sketch.is_mergeable_with(&dynamic), Ok(()));
+        assert_eq!(sketch.mapping(), dynamic.mapping());
+        
+        let coarser = DDSketch::new(0.02).unwrap();
+        assert_eq!(
+            sketch.is_mergeable_with(&coarser),
+            Err(IncompatibilityReason::DifferentGamma {
+                ours: sketch.mapping().gamma,
+                theirs: coarser.mapping().gamma,
+            })
+        );
+    }
+    
+    #[test]
+    fn test_ddsketch_merge_rebin() {
+        let mut coarse = DDSketch::new(0.02).unwrap();
+        let mut fine = DDSketch::new(0.01).unwrap();
+        let mut values = Vec::new();
+        
+        for i in 1..=1000 {
+            let value = (i as f64).powf(1.3) - 200.0;
+            if i % 2 == 0 {
+                coarse.add(value);
+            } else {
+                fine.add(value);
+            }
+            values.push(value);
+        }
+        values.sort_by(f64::total_cmp);
+        
+        assert!(matches!(coarse.clone().merge(&fine), Err(DDSketchError::IncompatibleSketches { .. })));
+        coarse.merge_rebin(&fine).unwrap();
+        
+        assert_eq!(coarse.count(), 1000);
+        assert_eq!(coarse.min(), Some(values[0]));
+        assert_eq!(coarse.max(), Some(values[999]));
+        
+        let bound = 0.02 + 0.01 + 0.02 * 0.01;
+        for q in [0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            let exact = values[(q * values.len() as f64) as usize];
+            let estimate = coarse.get_quantile_value_with(q, QuantileInterpolation::Midpoint).unwrap();
+            assert!((estimate - exact).abs() <= bound * exact.abs() + 1e-9, "q={}", q);
+        }
+        
+        // Equal accuracies fall back to a plain merge
+        let mut same = DDSketch::new(0.02).unwrap();
+        same.merge_rebin(&coarse).unwrap();
+        assert_eq!(same.snapshot(), coarse.snapshot());
+    }
+    
+    #[test]
+    fn test_ddsketch_subtract() {
+        let mut earlier = DDSketch::new(0.02).unwrap();
+        for i in 1..=100 {
+            earlier.add(i as f64);
+        }
+        
+        let mut now = earlier.clone();
+        for i in 500..=600 {
+            now.add(i as f64);
+        }
+        now.add(-3.0);
+        
+        let mut interval = now.clone();
+        interval.subtract(&earlier).unwrap();
+        
+        assert_eq!(interval.count(), 102);
+        assert!(!interval.has_exact_extremes());
+        assert_eq!(interval.min(), Some(-3.0));
+        assert_eq!(interval.max(), Some(600.0));
+        assert_eq!(interval.sum(), (500..=600).sum::<i32>() as f64 - 3.0);
+        
+        let median = interval.get_quantile_value(0.5).unwrap();
+        assert!((median - 549.0).abs() <= 549.0 * 0.02);
+        
+        // Subtracting more than was recorded saturates at zero
+        interval.subtract(&now).unwrap();
+        assert!(interval.is_empty());
+        assert!(interval.has_exact_extremes());
+        assert_eq!(interval.min(), None);
+        
+        assert!(interval.subtract(&DDSketch::new(0.05).unwrap()).is_err());
+    }
+    
+    #[test]
+    fn test_ddsketch_non_finite_policy() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.non_finite_policy(), NonFinitePolicy::CountSeparately);
+        
+        sketch.add(1.0);
+        sketch.add(f64::NAN);
+        sketch.add_with_count(f64::INFINITY, 2);
+        sketch.add_batch(&[f64::NEG_INFINITY, 2.0]);
+        
+        assert_eq!(sketch.count(), 2);
+        assert_eq!(sketch.non_finite_count(), 4);
+        assert_eq!(sketch.min(), Some(1.0));
+        assert_eq!(sketch.max(), Some(2.0));
+        assert_eq!(sketch.sum(), 3.0);
+        
+        sketch.set_non_finite_policy(NonFinitePolicy::Error);
+        assert_eq!(sketch.try_add(f64::NAN), Err(DDSketchError::NonFiniteValue));
+        assert_eq!(sketch.non_finite_count(), 4);
+        
+        sketch.set_non_finite_policy(NonFinitePolicy::Ignore);
+        assert_eq!(sketch.try_add(f64::NAN), Ok(()));
+        assert_eq!(sketch.non_finite_count(), 4);
+        assert_eq!(sketch.count(), 2);
+        
+        sketch.clear();
+        assert_eq!(sketch.non_finite_count(), 0);
+    }
+    
+    #[test]
+    fn test_ddsketch_strict_mode() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        assert!(!sketch.is_strict());
+        assert_eq!(sketch.try_add(f64::NAN), Ok(()));
+        assert_eq!(sketch.try_add(1e-310), Ok(()));
+        
+        sketch.set_strict(true);
+        assert_eq!(sketch.try_add(f64::INFINITY), Err(DDSketchError::NonFiniteValue));
+        assert_eq!(sketch.try_add(1e-310), Err(DDSketchError::ValueOutOfRange));
+        assert_eq!(sketch.try_add(-1e-310), Err(DDSketchError::ValueOutOfRange));
+        assert_eq!(sketch.try_add(0.0), Ok(()));
+        assert_eq!(sketch.try_add(-2.5), Ok(()));
+        sketch.add_batch(&[f64::NAN, 1e-310, 3.0]);
+        assert_eq!(sketch.count(), 4);
+        assert_eq!(sketch.non_finite_count(), 1);
+        
+        // A fine mapping cannot index large values
+        let mut fine = DDSketch::new(1e-9).unwrap();
+        fine.set_strict(true);
+        assert!(fine.max_indexable_value() < 10.0);
+        assert_eq!(fine.try_add(100.0), Err(DDSketchError::ValueOutOfRange));
+        assert_eq!(fine.try_add(5.0), Ok(()));
+        assert!(fine.with_relative_accuracy(1e-6).unwrap().is_strict());
+    }
+    
+    #[test]
+    fn test_ddsketch_with_relative_accuracy() {
+        let mut sketch = DDSketch::new(0.005).unwrap();
+        for i in -100..=10000 {
+            sketch.add(i as f64);
+        }
+        
+        let coarse = sketch.with_relative_accuracy(0.05).unwrap();
+        assert_eq!(coarse.relative_accuracy(), 0.05);
+        assert_eq!(coarse.count(), sketch.count());
+        assert_eq!((coarse.min(), coarse.max()), (sketch.min(), sketch.max()));
+        assert_eq!(coarse.sum(), sketch.sum());
+        assert!(coarse.num_bins() * 5 < sketch.num_bins());
+        assert!(coarse.encode().len() < sketch.encode().len());
+        
+        for q in [0.001, 0.25, 0.5, 0.9, 0.999] {
+            let fine = sketch.get_quantile_value(q).unwrap();
+            let estimate = coarse.get_quantile_value(q).unwrap();
+            assert!((estimate - fine).abs() <= fine.abs() * 0.06, "q={}", q);
+        }
+        
+        assert_eq!(sketch.with_relative_accuracy(0.001).unwrap_err(), DDSketchError::InvalidRelativeAccuracy);
+        assert_eq!(sketch.with_relative_accuracy(0.005).unwrap(), sketch);
+    }
+    
+    #[test]
+    fn test_ddsketch_scale() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        let mut reference = DDSketch::new(0.01).unwrap();
+        for i in 1..=1000 {
+            sketch.add(i as f64 * 1e6);
+            reference.add(i as f64);
+        }
+        sketch.add(-5e6);
+        reference.add(-5.0);
+        
+        // Nanoseconds to milliseconds
+        sketch.scale(1e-6).unwrap();
+        assert_eq!(sketch.count(), 1001);
+        assert_eq!(sketch.min(), Some(-5.0));
+        assert_eq!(sketch.max(), Some(1000.0));
+        for q in [0.0, 0.1, 0.5, 0.9, 0.99] {
+            let expected = reference.get_quantile_value(q).unwrap();
+            let estimate = sketch.get_quantile_value(q).unwrap();
+            assert!((estimate - expected).abs() <= expected.abs() * 0.02, "q={}", q);
+        }
+        
+        sketch.scale(-2.0).unwrap();
+        assert_eq!(sketch.min(), Some(-2000.0));
+        assert_eq!(sketch.max(), Some(10.0));
+        assert!((sketch.get_quantile_value(0.0).unwrap() + 2000.0).abs() <= 2000.0 * 0.02);
+        
+        assert_eq!(sketch.scale(f64::NAN), Err(DDSketchError::InvalidValue));
+        assert_eq!(sketch.scale(1e306), Err(DDSketchError::InvalidValue));
+        assert_eq!(sketch.max(), Some(10.0));
+        
+        sketch.scale(0.0).unwrap();
+        assert_eq!(sketch.count(), 1001);
+        assert_eq!(sketch.get_quantile_value(0.5).unwrap(), 0.0);
+        assert_eq!(sketch.max(), Some(0.0));
+    }
+    
+    #[test]
+    fn test_ddsketch_summary_and_display() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.summary().p50, None);
+        assert_eq!(sketch.to_string(), "count=0");
+        
+        for i in 1..=100 {
+            sketch.add(i as f64);
+        }
+        
+        let summary = sketch.summary();
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.min, Some(1.0));
+        assert_eq!(summary.max, Some(100.0));
+        for (estimate, expected) in [(summary.p50, 50.0), (summary.p90, 90.0), (summary.p99, 99.0)] {
+            assert!((estimate.unwrap() - expected).abs() <= expected * 0.02);
+        }
+        
+        let line = sketch.to_string();
+        assert!(line.starts_with("count=100 min=1 max=100 p50="));
+        assert!(line.contains(" p90=") && line.contains(" p99="));
+    }
+    
+    #[test]
+    fn test_ddsketch_clone_preserves_store_configuration() {
+        let mut original = DDSketch::with_max_bins(0.02, 10, CollapsePolicy::CollapseHighest).unwrap();
+        original.set_quantile_interpolation(QuantileInterpolation::Linear);
+        let mut clone = original.clone();
+        
+        for i in 1..=1000 {
+            original.add(i as f64);
+            clone.add(i as f64);
+        }
+        
+        assert_eq!(clone.store.max_num_bins(), 10);
+        assert_eq!(clone.num_bins(), original.num_bins());
+        assert_eq!(clone.collapse_count(), original.collapse_count());
+        assert_eq!(clone.quantile_interpolation(), QuantileInterpolation::Linear);
+        assert_eq!(clone.get_quantile_value(0.5).unwrap(), original.get_quantile_value(0.5).unwrap());
+    }
+    
+    #[test]
+    fn test_ddsketch_equality_and_hash() {
+        use std::collections::hash_map::DefaultHasher;
+        
+        let hash = |sketch: &DDSketch| {
+            let mut hasher = DefaultHasher::new();
+            sketch.hash(&mut hasher);
+            hasher.finish()
+        };
+        
+        let mut a = DDSketch::new(0.02).unwrap();
+        let mut b = DDSketch::new(0.02).unwrap();
+        for value in [-3.0, 0.0, 1.5, 40.0] {
+            a.add(value);
+        }
+        for value in [40.0, 1.5, 0.0, -3.0] {
+            b.add(value);
+        }
+        assert_eq!(a, b);
+        
+        assert_eq!(hash(&a), hash(&b));
+        
+        b.add(40.0);
+        assert_ne!(a, b);
+        assert_ne!(DDSketch::new(0.02).unwrap(), DDSketch::new(0.05).unwrap());
+        
+        // Emptied bins do not affect equality
+        let mut c = a.clone();
+        c.add(7.0);
+        let mut seven = DDSketch::new(0.02).unwrap();
+        seven.add(7.0);
+        c.subtract(&seven).unwrap();
+        c.min_value = a.min_value;
+        c.max_value = a.max_value;
+        assert_eq!(a, c);
+    }
+    
+    #[test]
+    fn test_ddsketch_with_hybrid_stores() {
+        let mut hybrid = DDSketch::with_hybrid_stores(0.02).unwrap();
+        let mut dense = DDSketch::new(0.02).unwrap();
+        for i in -50..500 {
+            hybrid.add(i as f64 * 1.7);
+            dense.add(i as f64 * 1.7);
+        }
+        
+        assert!(hybrid.store.is_dense());
+        assert_eq!(hybrid.snapshot(), dense.snapshot());
+        assert_eq!(hybrid.get_quantile_value(0.9).unwrap(), dense.get_quantile_value(0.9).unwrap());
+    }
+    
+    #[test]
+    fn test_ddsketch_error_diagnostics() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.observed_error_bound_at(0.5), Err(DDSketchError::EmptySketch));
+        for i in 1..=1000 {
+            sketch.add(i as f64);
+        }
+        
+        assert!((sketch.guaranteed_relative_error() - 0.02).abs() < 1e-12);
+        sketch.set_quantile_interpolation(QuantileInterpolation::Midpoint);
+        assert!((sketch.guaranteed_relative_error() - 0.02 / 2.02).abs() < 1e-12);
+        for q in [0.1, 0.5, 0.99] {
+            let bound = sketch.observed_error_bound_at(q).unwrap();
+            assert!(bound > 0.0 && bound <= sketch.guaranteed_relative_error() + 1e-12);
+            
+            let value = sketch.get_quantile_value(q).unwrap();
+            let actual = (q * 1000.0).floor() + 1.0;
+            assert!((value - actual).abs() / actual <= bound + 1e-12);
+        }
+        assert_eq!(sketch.observed_error_bound_at(1.0).unwrap(), 0.0);
+        
+        // Collapsed bins lose the guarantee for the low quantiles only
+        let mut collapsed = DDSketch::with_max_bins(0.02, 50, CollapsePolicy::CollapseLowest).unwrap();
+        for i in 1..=1000 {
+            collapsed.add(i as f64);
+        }
+        assert!(collapsed.observed_error_bound_at(0.01).unwrap() > 0.5);
+        assert!(collapsed.observed_error_bound_at(0.99).unwrap() <= collapsed.guaranteed_relative_error() + 1e-12);
+        
+        let mut mixed = DDSketch::new(0.02).unwrap();
+        mixed.add(-1.0);
+        mixed.add(0.0);
+        mixed.add(1.0);
+        assert_eq!(mixed.observed_error_bound_at(0.4).unwrap(), 0.0);
+        mixed.set_quantile_interpolation(QuantileInterpolation::Linear);
+        assert_eq!(mixed.observed_error_bound_at(0.25).unwrap(), f64::INFINITY);
+    }
+    
+    #[test]
+    fn test_ddsketch_with_fixed_stores() {
+        let mut sketch = DDSketch::<LogarithmicMapping, FixedStore<128>>::with_fixed_stores(0.02).unwrap();
+        for i in 1..=10_000 {
+            sketch.add(i as f64);
+            sketch.add(-(i as f64));
+        }
+        
+        assert_eq!(sketch.count(), 20_000);
+        let p99 = sketch.get_quantile_value(0.995).unwrap();
+        assert!((p99 - 9900.0).abs() <= 9900.0 * 0.02 + 1.0);
+        
+        // Only the smallest magnitudes, just below and above zero, were folded
+        assert!(sketch.observed_error_bound_at(0.49).unwrap() > 0.5);
+        assert!(sketch.observed_error_bound_at(0.51).unwrap() > 0.5);
+        assert!(sketch.observed_error_bound_at(0.75).unwrap() <= 0.02 + 1e-12);
+    }
+    
+    #[test]
+    fn test_ddsketch_collapse_telemetry() {
+        let mut sketch = DDSketch::with_max_bins(0.02, 50, CollapsePolicy::CollapseLowest).unwrap();
+        for i in 1..=50 {
+            sketch.add(i as f64 * 10.0);
+        }
+        assert!(!sketch.is_collapsed());
+        assert!(sketch.degraded_value_ranges().is_empty());
+        
+        for i in 1..=1000 {
+            sketch.add(i as f64);
+        }
+        assert!(sketch.is_collapsed());
+        assert!(sketch.collapse_count() > 0);
+        
+        // Only the low end has lost accuracy; the top quantiles are unaffected
+        let ranges = sketch.degraded_value_ranges();
+        assert_eq!(ranges.len(), 1);
+        assert!(ranges[0].0 <= 1.0 && ranges[0].1 < 500.0);
+        
+        sketch.clear();
+        assert!(!sketch.is_collapsed());
+    }
+    
+    #[test]
+    fn test_ddsketch_memory_introspection() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        let empty_size = sketch.memory_size_bytes();
+        assert_eq!(sketch.num_bins(), 0);
+        
+        for i in -100..=100 {
+            sketch.add(i as f64);
+        }
+        assert_eq!(sketch.num_bins(), sketch.bins().filter(|bin| bin.lower != 0.0 || bin.upper != 0.0).count());
+        assert!(sketch.memory_size_bytes() > empty_size);
+        
+        // The rank index built by a quantile query is accounted for
+        let before_query = sketch.memory_size_bytes();
+        sketch.get_quantile_value(0.5).unwrap();
+        assert!(sketch.memory_size_bytes() > before_query);
+    }
+    
+    #[test]
+    fn test_ddsketch_generic_input() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        
+        sketch.add(3u64);
+        sketch.add(-2i64);
+        sketch.add(1.5f32);
+        sketch.add_with_count(10u32, 2);
+        sketch.try_add(0.25).unwrap();
+        
+        assert_eq!(sketch.count(), 6);
+        assert_eq!(sketch.min(), Some(-2.0));
+        assert_eq!(sketch.max(), Some(10.0));
+        assert_eq!(sketch.sum(), 22.75);
+    }
+    
+    #[test]
+    fn test_ddsketch_add_batch() {
+        let values: Vec<f64> = (0..5000).map(|i| ((i * 7919) % 2001) as f64 - 500.0).collect();
+        
+        let mut batched = DDSketch::new(0.02).unwrap();
+        let mut sequential = DDSketch::new(0.02).unwrap();
+        
+        batched.add_batch(&values);
+        for &value in &values {
+            sequential.add(value);
+        }
+        
+        assert_eq!(batched.snapshot(), sequential.snapshot());
+        
+        // Rejected values do not leak into min/max or moments
+        let mut strict = DDSketch::with_max_bins(0.02, 1, CollapsePolicy::Error).unwrap();
+        strict.add_batch(&[5.0, 5.01, 0.0, 1000.0]);
+        assert_eq!(strict.count(), 3);
+        assert_eq!(strict.max(), Some(5.01));
+        assert_eq!(strict.sum(), 10.01);
+        
+        // Zero and non-finite values are never keyed
+        let special = [0.0, -0.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 3.0];
+        let mut cached = DDSketch::new(0.02).unwrap();
+        cached.set_key_cache(true);
+        cached.add_batch(&special);
+        let cache = cached.key_cache().unwrap();
+        assert_eq!(cache.hits() + cache.misses(), 1);
+        assert_eq!((cached.count(), cached.zero_count(), cached.non_finite_count()), (3, 2, 3));
+        
+        let mut uncached = DDSketch::new(0.02).unwrap();
+        uncached.add_batch(&special);
+        assert_eq!(uncached.snapshot(), cached.snapshot());
+    }
+    
+    #[test]
+    fn test_ddsketch_moments() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.sum(), 0.0);
+        assert_eq!(sketch.mean(), None);
+        assert_eq!(sketch.stddev(), None);
+        
+        sketch.add(2.0);
+        sketch.add_with_count(4.0, 3);
+        sketch.add(-2.0);
+        
+        assert_eq!(sketch.sum(), 12.0);
+        assert_eq!(sketch.mean(), Some(2.4));
+        
+        let mut other = DDSketch::new(0.02).unwrap();
+        other.add(7.0);
+        other.add(9.0);
+        sketch.merge(&other).unwrap();
+        
+        // Values: -2, 2, 4, 4, 4, 7, 9
+        assert_eq!(sketch.sum(), 28.0);
+        assert_eq!(sketch.mean(), Some(4.0));
+        let expected_stddev = (74.0f64 / 7.0).sqrt();
+        assert!((sketch.stddev().unwrap() - expected_stddev).abs() < 1e-12);
+        
+        sketch.clear();
+        assert_eq!(sketch.sum(), 0.0);
+        assert_eq!(sketch.mean(), None);
     }
-}
-
-impl Clone for DDSketch {
-    fn clone(&self) -> Self {
-        // Note: This is a simplified clone that creates a new sketch with the same parameters
-        // In a real implementation, you might want to implement Clone for the trait objects
-        let mut cloned = DDSketch::new(self.mapping.relative_accuracy()).unwrap();
+    
+    #[test]
+    fn test_ddsketch_collapse_policy() {
+        let mut lowest = DDSketch::with_max_bins(0.02, 50, CollapsePolicy::CollapseLowest).unwrap();
+        let mut exact = DDSketch::new(0.02).unwrap();
         
-        // Copy the data by iterating through the stores
-        for (index, count) in self.store.iter() {
-            cloned.store.add(index, count);
+        for i in 1..=1000 {
+            lowest.add(i as f64);
+            exact.add(i as f64);
         }
         
-        for (index, count) in self.negative_store.iter() {
-            cloned.negative_store.add(index, count);
+        // The upper tail is untouched when the lowest bins collapse
+        assert_eq!(lowest.count(), 1000);
+        assert_eq!(lowest.get_quantile_value(0.99).unwrap(), exact.get_quantile_value(0.99).unwrap());
+        
+        let mut strict = DDSketch::with_max_bins(0.02, 2, CollapsePolicy::Error).unwrap();
+        assert!(strict.try_add(1.0).is_ok());
+        assert!(strict.try_add(-1.0).is_ok());
+        assert!(strict.try_add(100.0).is_ok());
+        assert_eq!(strict.try_add(1000.0), Err(DDSketchError::BinLimitExceeded));
+        assert_eq!(strict.count(), 3);
+        assert_eq!(strict.max(), Some(100.0));
+    }
+    
+    #[test]
+    fn test_ddsketch_static_and_dynamic_dispatch() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DDSketch>();
+        assert_send_sync::<DDSketch<LogarithmicMapping, CollapsingStore>>();
+        
+        let mut collapsing = DDSketch::with_max_bins(0.02, 10, CollapsePolicy::CollapseLowest).unwrap();
+        for i in 1..=1000 {
+            collapsing.add(i as f64);
         }
         
-        cloned.zero_count = self.zero_count;
-        cloned.min_value = self.min_value;
-        cloned.max_value = self.max_value;
+        // Cloning keeps the collapsing store and its configuration
+        let mut cloned = collapsing.clone();
+        cloned.add(5000.0);
+        assert!(cloned.store.iter().count() <= 10);
+        assert_eq!(cloned.count(), 1001);
         
-        cloned
+        let mut dynamic: DynDDSketch = collapsing.into_dyn();
+        dynamic.add(2000.0);
+        assert_eq!(dynamic.count(), 1001);
+        assert!(dynamic.store.iter().count() <= 10);
+        
+        let mut dense = DDSketch::new(0.02).unwrap();
+        dense.merge(&dynamic).unwrap();
+        assert_eq!(dense.count(), 1001);
+        assert_eq!(dense.max(), Some(2000.0));
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
     
     #[test]
-    fn test_ddsketch_creation() {
-        let sketch = DDSketch::new(0.02).unwrap();
-        assert_eq!(sketch.relative_accuracy(), 0.02);
-        assert!(sketch.is_empty());
-        assert_eq!(sketch.count(), 0);
+    fn test_ddsketch_snapshot_round_trip() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
         
-        // Invalid relative accuracy
-        assert!(DDSketch::new(0.0).is_err());
-        assert!(DDSketch::new(1.0).is_err());
+        for i in -20..=50 {
+            sketch.add(i as f64);
+        }
+        
+        let snapshot = sketch.snapshot();
+        assert_eq!(snapshot.count(), sketch.count());
+        assert!(snapshot.positive_bins.windows(2).all(|w| w[0].0 < w[1].0));
+        
+        let columnar = sketch.columnar_snapshot();
+        let restored = DDSketch::from_snapshot(&SketchSnapshot::from(columnar)).unwrap();
+        
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.get_quantile_value(0.5).unwrap(), sketch.get_quantile_value(0.5).unwrap());
     }
     
     #[test]
-    fn test_ddsketch_add_values() {
+    fn test_ddsketch_split() {
         let mut sketch = DDSketch::new(0.02).unwrap();
+        for i in -50i32..=200 {
+            sketch.add_with_count(i as f64 * 1.3, (i.unsigned_abs() % 7) as u64 + 1);
+        }
+        sketch.add_with_count(0.0, 10);
         
-        sketch.add(1.0);
-        sketch.add(2.0);
-        sketch.add(3.0);
+        let shards = sketch.split(3);
+        assert_eq!(shards.len(), 3);
+        let counts: Vec<u64> = shards.iter().map(|shard| shard.count()).collect();
+        assert!(counts.iter().max().unwrap() - counts.iter().min().unwrap() <= 1, "{:?}", counts);
+        assert!(shards.iter().all(|shard| !shard.has_exact_extremes()));
         
-        assert_eq!(sketch.count(), 3);
-        assert_eq!(sketch.min(), Some(1.0));
-        assert_eq!(sketch.max(), Some(3.0));
+        let merged: DDSketch = shards.iter().cloned().sum();
+        assert_eq!(merged, sketch);
+        assert!((merged.sum() - sketch.sum()).abs() < 1e-6 * sketch.sum().abs());
+        assert_eq!(sketch.split(2), sketch.split(2));
         
-        // Test adding zero and negative values
+        // More shards than values leaves the extra shards empty
+        let mut single = DDSketch::new(0.02).unwrap();
+        single.add(5.0);
+        let shards = single.split(4);
+        assert_eq!(shards.iter().filter(|shard| shard.is_empty()).count(), 3);
+        assert_eq!(shards.iter().cloned().sum::<DDSketch>(), single);
+    }
+    
+    #[test]
+    fn test_ddsketch_from_bins() {
+        let bins = [(-2.5, 3), (0.0, 2), (1.0, 10), (250.0, 1_000_000_000)];
+        let sketch = DDSketch::from_bins(0.01, bins).unwrap();
+        
+        let mut expected = DDSketch::new(0.01).unwrap();
+        for (value, count) in bins {
+            expected.add_with_count(value, count);
+        }
+        assert_eq!(sketch, expected);
+        assert_eq!(sketch.count(), 1_000_000_015);
+        assert!(!sketch.is_strict());
+        
+        assert_eq!(DDSketch::from_bins(0.01, [(1.0, 1), (f64::NAN, 1)]), Err(DDSketchError::NonFiniteValue));
+        assert_eq!(DDSketch::from_bins(0.01, [(1e-320, 1)]), Err(DDSketchError::ValueOutOfRange));
+        assert_eq!(DDSketch::from_bins(0.0, []), Err(DDSketchError::InvalidRelativeAccuracy));
+    }
+    
+    #[test]
+    fn test_ddsketch_merge_from() {
+        let sketches: Vec<DDSketch> = (0..10)
+            .map(|shard| {
+                let mut sketch = DDSketch::new(0.02).unwrap();
+                for i in 0..100 {
+                    sketch.add((shard * 100 + i) as f64 - 300.0);
+                }
+                sketch
+            })
+            .collect();
+        
+        let mut merged = DDSketch::new(0.02).unwrap();
+        merged.merge_all(&sketches).unwrap();
+        let mut streamed = DDSketch::new(0.02).unwrap();
+        streamed.merge_from(sketches.iter()).unwrap();
+        assert_eq!(streamed.snapshot(), merged.snapshot());
+        assert!(streamed.has_exact_extremes());
+        
+        // Sketches before an incompatible one stay merged
+        let odd_one = DDSketch::new(0.05).unwrap();
+        let mut partial = DDSketch::new(0.02).unwrap();
+        let result = partial.merge_from(sketches[..2].iter().chain(std::iter::once(&odd_one)).chain(&sketches[2..]));
+        assert!(matches!(result, Err(DDSketchError::IncompatibleSketches { .. })));
+        assert_eq!(partial.count(), 200);
+        assert_eq!(partial.max(), Some(-101.0));
+    }
+    
+    #[test]
+    fn test_ddsketch_count_overflow() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        sketch.add_with_count(5.0, u64::MAX - 1);
+        assert!(!sketch.has_overflowed());
+        
+        assert_eq!(sketch.try_add_with_count(-5.0, 2), Err(DDSketchError::CountOverflow));
+        assert!(sketch.has_overflowed());
+        assert_eq!(sketch.count(), u64::MAX - 1);
+        assert_eq!(sketch.min(), Some(5.0));
+        
+        // The last value that fits is kept; the rest are dropped
+        sketch.add_batch(&[0.0, 1.0, 2.0]);
+        assert_eq!(sketch.count(), u64::MAX);
         sketch.add(0.0);
-        sketch.add(-1.0);
+        assert_eq!(sketch.count(), u64::MAX);
         
-        assert_eq!(sketch.count(), 5);
-        assert_eq!(sketch.min(), Some(-1.0));
-        assert_eq!(sketch.max(), Some(3.0));
+        let mut other = DDSketch::new(0.02).unwrap();
+        other.add(1.0);
+        assert_eq!(other.merge(&sketch), Err(DDSketchError::CountOverflow));
+        assert_eq!(other.merge_all([&sketch, &sketch]), Err(DDSketchError::CountOverflow));
+        assert_eq!(other.count(), 1);
+        assert!(other.has_overflowed());
+        
+        sketch.clear();
+        assert!(!sketch.has_overflowed());
+        
+        let mut snapshot = sketch.snapshot();
+        snapshot.positive_bins = vec![(1, u64::MAX), (2, 1)];
+        assert_eq!(DDSketch::from_snapshot(&snapshot), Err(DDSketchError::CountOverflow));
     }
     
     #[test]
-    fn test_ddsketch_quantiles() {
+    fn test_ddsketch_report() {
         let mut sketch = DDSketch::new(0.02).unwrap();
+        for value in [1.0, 2.0, 3.0, 4.0] {
+            sketch.add(value);
+        }
         
-        // Add values 1 through 100
+        let report = sketch.report(&[0.0, 1.0, 1.5]);
+        assert_eq!((report.count, report.sum, report.min, report.max), (4, 10.0, Some(1.0), Some(4.0)));
+        assert_eq!(report.quantiles[0], ReportedQuantile { quantile: 0.0, value: Some(1.0) });
+        assert_eq!(report.quantiles[1].value, Some(4.0));
+        assert_eq!(report.quantiles[2].value, None);
+        
+        let empty = DDSketch::new(0.02).unwrap().report(&[0.5]);
+        assert_eq!((empty.count, empty.min), (0, None));
+        assert_eq!(empty.quantiles[0].value, None);
+    }
+    
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_ddsketch_report_serde() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        sketch.add(8.0);
+        let report = sketch.report(&[0.5]);
+        
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(json, r#"{"count":1,"sum":8.0,"min":8.0,"max":8.0,"quantiles":[{"quantile":0.5,"value":8.0}]}"#);
+        assert_eq!(serde_json::from_str::<SketchReport>(&json).unwrap(), report);
+    }
+    
+    #[test]
+    fn test_ddsketch_percentiles() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        for i in 1..=1000 {
+            sketch.add(i as f64);
+        }
+        
+        let accessors = [
+            (sketch.p50(), 0.5),
+            (sketch.p75(), 0.75),
+            (sketch.p90(), 0.9),
+            (sketch.p95(), 0.95),
+            (sketch.p99(), 0.99),
+            (sketch.p999(), 0.999),
+        ];
+        for (value, quantile) in accessors {
+            assert_eq!(value, sketch.get_quantile_value(quantile));
+            assert_eq!(sketch.percentile(quantile * 100.0), value);
+        }
+        assert_eq!(sketch.percentile(100.0), Ok(1000.0));
+        assert_eq!(sketch.percentile(100.5), Err(DDSketchError::InvalidQuantile { got: 1.005 }));
+        assert_eq!(sketch.percentile(-1.0), Err(DDSketchError::InvalidQuantile { got: -0.01 }));
+        
+        let empty = DDSketch::new(0.01).unwrap();
+        assert_eq!(empty.p99(), Err(DDSketchError::EmptySketch));
+        assert_eq!(empty.percentile(50.0), Err(DDSketchError::EmptySketch));
+    }
+    
+    #[test]
+    fn test_ddsketch_ordered_iter() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        sketch.add_with_count(-10.0, 2);
+        sketch.add(-1.0);
+        sketch.add_with_count(0.0, 3);
+        sketch.add(5.0);
+        sketch.add_with_count(100.0, 4);
+        
+        let bins: Vec<(f64, u64, u64)> = sketch.ordered_iter().collect();
+        assert_eq!(bins.iter().map(|&(_, count, _)| count).collect::<Vec<_>>(), vec![2, 1, 3, 1, 4]);
+        assert_eq!(bins.iter().map(|&(_, _, cumulative)| cumulative).collect::<Vec<_>>(), vec![2, 3, 6, 7, 11]);
+        assert!(bins.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        
+        // Each value is the quantile estimate for the ranks in its bin
+        for &(value, _, cumulative) in &bins {
+            let rank = (cumulative - 1) as f64 / sketch.count() as f64;
+            assert_eq!(value, sketch.get_quantile_value(rank).unwrap());
+        }
+        assert_eq!(bins[0].0, -10.0);
+        assert_eq!(DDSketch::new(0.02).unwrap().ordered_iter().count(), 0);
+        
+        let owned = std::thread::spawn(move || sketch.into_bins()).join().unwrap();
+        let expected: Vec<(f64, u64)> = bins.iter().map(|&(value, count, _)| (value, count)).collect();
+        assert_eq!(owned, expected);
+    }
+    
+    #[test]
+    fn test_retain_above_and_below() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
         for i in 1..=100 {
             sketch.add(i as f64);
         }
+        sketch.add_with_count(-5.0, 3);
+        sketch.add_with_count(0.0, 2);
+        sketch.add(1000.0);
         
+        assert_eq!(sketch.retain_above(0.0).unwrap(), 3);
+        assert_eq!(sketch.count(), 103);
+        assert_eq!(sketch.min(), Some(0.0));
+        assert!(!sketch.has_exact_extremes());
+        
+        assert_eq!(sketch.retain_above(10.0).unwrap(), 11);
+        assert_eq!(sketch.count(), 92);
+        assert_eq!(sketch.min(), Some(10.0));
+        assert_eq!(sketch.retain_below(100.0).unwrap(), 1);
+        assert_eq!(sketch.max(), Some(100.0));
+        assert_eq!(sketch.count(), 91);
+        let expected_sum: f64 = (10..=100).map(|i| i as f64).sum();
+        assert!((sketch.sum() - expected_sum).abs() / expected_sum < 0.01, "sum={}", sketch.sum());
         let median = sketch.get_quantile_value(0.5).unwrap();
-        let p90 = sketch.get_quantile_value(0.9).unwrap();
-        let p99 = sketch.get_quantile_value(0.99).unwrap();
+        assert!((median - 55.0).abs() / 55.0 <= 0.01, "median={}", median);
         
-        // Check that quantiles are reasonable
-        assert!(median > 40.0 && median < 60.0);
-        assert!(p90 > 80.0 && p90 < 95.0);
-        assert!(p99 > 95.0 && p99 <= 100.0);
+        // A threshold inside a bin keeps the bin and clamps the extreme
+        assert_eq!(sketch.retain_above(10.01).unwrap(), 0);
+        assert_eq!(sketch.min(), Some(10.01));
         
-        // Test multiple quantiles
-        let quantiles = vec![0.25, 0.5, 0.75, 0.95];
-        let values = sketch.get_quantile_values(&quantiles).unwrap();
-        assert_eq!(values.len(), 4);
+        assert_eq!(sketch.retain_below(-1.0).unwrap(), 91);
+        assert!(sketch.is_empty());
+        assert_eq!(sketch.min(), None);
+        assert_eq!(sketch.sum(), 0.0);
+        assert_eq!(sketch.retain_above(f64::NAN), Err(DDSketchError::InvalidValue));
+    }
+    
+    #[test]
+    fn test_quantile_within() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        for i in 1..=1000 {
+            sketch.add(i as f64);
+        }
+        sketch.add_with_count(-20.0, 50);
         
-        // Values should be increasing
-        for i in 1..values.len() {
-            assert!(values[i] >= values[i-1]);
+        // p95 of the values in [100, 1000] is about 955
+        let p95 = sketch.quantile_within(0.95, 100.0..f64::INFINITY).unwrap();
+        assert!((p95 - 955.0).abs() / 955.0 <= 0.01, "p95={}", p95);
+        let median = sketch.quantile_within(0.5, 200.0..400.0).unwrap();
+        assert!((median - 300.0).abs() / 300.0 <= 0.01, "median={}", median);
+        
+        let low = sketch.quantile_within(0.0, 500.5..600.0).unwrap();
+        assert!((500.5..600.0).contains(&low), "low={}", low);
+        let negative = sketch.quantile_within(0.5, -100.0..0.0).unwrap();
+        assert!((negative + 20.0).abs() / 20.0 <= 0.01, "negative={}", negative);
+        
+        assert_eq!(sketch.quantile_within(0.5, 2000.0..3000.0), Err(DDSketchError::EmptySketch));
+        assert_eq!(sketch.quantile_within(0.5, 10.0..10.0), Err(DDSketchError::InvalidValue));
+        assert_eq!(sketch.quantile_within(1.5, 0.0..10.0), Err(DDSketchError::InvalidQuantile { got: 1.5 }));
+        assert_eq!(
+            DDSketch::new(0.01).unwrap().quantile_within(0.5, 0.0..1.0),
+            Err(DDSketchError::EmptySketch)
+        );
+    }
+    
+    #[test]
+    fn test_mode() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        assert_eq!(sketch.mode(), Err(DDSketchError::EmptySketch));
+        
+        // A tight peak at 20 and a broad one around 300 holding more values
+        for i in 0..200 {
+            sketch.add(20.0 + (i % 5) as f64 * 0.01);
+        }
+        for i in 0..600 {
+            sketch.add(100.0 + i as f64);
+        }
+        let mode = sketch.mode().unwrap();
+        assert!((mode - 20.0).abs() / 20.0 <= 0.01, "mode={}", mode);
+        assert!(sketch.get_quantile_value(0.5).unwrap() > 100.0);
+        
+        // A lone spike at 500 is denser than any single bin of the broad peak at 200-220
+        let mut noisy = DDSketch::new(0.01).unwrap();
+        noisy.add_with_count(500.0, 300);
+        for i in 0..400 {
+            noisy.add(200.0 + i as f64 * 0.05);
         }
+        assert!((noisy.mode().unwrap() - 500.0).abs() / 500.0 <= 0.01);
+        let smoothed = noisy.mode_smoothed(3).unwrap();
+        assert!((200.0..=220.0).contains(&smoothed), "smoothed={}", smoothed);
+        
+        noisy.add_with_count(0.0, 100);
+        assert!(noisy.mode().unwrap() != 0.0);
+        noisy.add_with_count(0.0, 1000);
+        assert_eq!(noisy.mode().unwrap(), 0.0);
+        
+        let mut negative = DDSketch::new(0.01).unwrap();
+        negative.add_with_count(-3.0, 10);
+        negative.add(-1000.0);
+        assert!((negative.mode().unwrap() + 3.0).abs() / 3.0 <= 0.01);
     }
     
     #[test]
-    fn test_ddsketch_merge() {
-        let mut sketch1 = DDSketch::new(0.02).unwrap();
-        let mut sketch2 = DDSketch::new(0.02).unwrap();
+    fn test_incompatible_merge_reports_context() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        let coarse = DDSketch::new(0.02).unwrap();
+        let error = sketch.merge(&coarse).unwrap_err();
         
-        // Add different values to each sketch
-        for i in 1..=50 {
-            sketch1.add(i as f64);
+        let DDSketchError::IncompatibleSketches { self_gamma, other_gamma, reason } = error.clone() else {
+            panic!("unexpected error {:?}", error);
+        };
+        assert!((self_gamma.unwrap() - 1.01).abs() < 1e-12);
+        assert!((other_gamma.unwrap() - 1.02).abs() < 1e-12);
+        assert!(matches!(reason, IncompatibilityReason::DifferentGamma { .. }));
+        assert!(error.to_string().starts_with("Sketches are incompatible for merging: gammas differ"));
+        
+        let error = sketch.get_quantile_value(1.25).unwrap_err();
+        assert_eq!(error, DDSketchError::InvalidQuantile { got: 1.25 });
+        assert_eq!(error.to_string(), "Quantile must be between 0 and 1, got 1.25");
+    }
+    
+    #[test]
+    fn test_key_cache_matches_uncached() {
+        let mut cached = DDSketch::new(0.01).unwrap();
+        cached.set_key_cache(true);
+        let mut plain = DDSketch::new(0.01).unwrap();
+        
+        let values: Vec<f64> = (0..5000).map(|i| [12.0, 12.0, 13.0, -4.0, 0.0, (i % 97) as f64 * 1.5][i % 6]).collect();
+        for &value in &values[..2500] {
+            cached.add(value);
+            plain.add(value);
         }
+        cached.add_batch(&values[2500..]);
+        plain.add_batch(&values[2500..]);
+        cached.add_batch(&[f64::NAN, f64::INFINITY, 1e-320]);
+        plain.add_batch(&[f64::NAN, f64::INFINITY, 1e-320]);
         
-        for i in 51..=100 {
-            sketch2.add(i as f64);
+        assert_eq!(cached, plain);
+        let cache = cached.key_cache().unwrap();
+        assert!(cache.hits() > cache.misses(), "hits={} misses={}", cache.hits(), cache.misses());
+        assert!(plain.key_cache().is_none());
+        
+        let rebinned = cached.with_relative_accuracy(0.05).unwrap();
+        assert_eq!(rebinned.key_cache().map(KeyCache::hits), Some(0));
+        cached.set_key_cache(false);
+        assert!(cached.key_cache().is_none());
+    }
+    
+    #[test]
+    fn test_add_batch_u64() {
+        let values: Vec<u64> = (0..20_000u64).map(|i| i * i * 7919 % 1_000_000_007).chain([0, 1, u64::MAX]).collect();
+        let mut integers = DDSketch::new(0.01).unwrap();
+        integers.add_batch_u64(&values[..10_000]);
+        let mut clone = integers.clone();
+        integers.add_batch_u64(&values[10_000..]);
+        clone.add_batch_u64(&values[10_000..]);
+        
+        let mut floats = DDSketch::new(0.01).unwrap();
+        floats.add_batch(&values.iter().map(|&value| value as f64).collect::<Vec<_>>());
+        assert_eq!(integers, floats);
+        assert_eq!(clone, floats);
+        assert_eq!(integers.sum(), floats.sum());
+        
+        let mut strict = DDSketch::builder(0.01).strict(true).build().unwrap();
+        strict.add_batch_u64(&[0, 5, 500]);
+        assert_eq!(strict.count(), 3);
+        
+        // Values beyond the table of a fine mapping are keyed with the mapping
+        let mut fine = DDSketch::new(0.0001).unwrap();
+        fine.add_batch_u64(&values);
+        let mut fine_floats = DDSketch::new(0.0001).unwrap();
+        fine_floats.add_batch(&values.iter().map(|&value| value as f64).collect::<Vec<_>>());
+        assert_eq!(fine, fine_floats);
+    }
+    
+    #[test]
+    fn test_prune() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        sketch.add_with_count(100.0, 50);
+        sketch.add_with_count(200.0, 40);
+        sketch.add(101.5);
+        sketch.add(190.0);
+        sketch.add(-3.0);
+        sketch.add(0.0);
+        let (count, sum, min, max) = (sketch.count(), sketch.sum(), sketch.min(), sketch.max());
+        
+        assert_eq!(sketch.prune(1), 0);
+        assert_eq!(sketch.prune(10), 2);
+        assert_eq!(sketch.num_bins(), 3);
+        assert_eq!((sketch.count(), sketch.sum(), sketch.min(), sketch.max()), (count, sum, min, max));
+        
+        // The outliers were folded into their nearest surviving bins
+        assert!((sketch.count_below(150.0) - 53.0).abs() < 1e-9);
+        assert_eq!(sketch.count_above(150.0), 41.0);
+        assert_eq!(sketch.negative_store.get(sketch.mapping.key(3.0)), 1);
+        
+        let mut empty = DDSketch::new(0.01).unwrap();
+        assert_eq!(empty.prune(10), 0);
+    }
+    
+    #[test]
+    fn test_quantile_curve() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        assert_eq!(sketch.quantile_curve(10), Err(DDSketchError::EmptySketch));
+        for i in 1..=1000 {
+            sketch.add(i as f64);
         }
+        assert_eq!(sketch.quantile_curve(0), Err(DDSketchError::InvalidValue));
         
-        let count1 = sketch1.count();
-        let count2 = sketch2.count();
+        let curve = sketch.quantile_curve(100).unwrap();
+        assert_eq!(curve.len(), 101);
+        assert_eq!(curve[0], (0.0, 1.0));
+        assert_eq!(curve[100], (1.0, 1000.0));
+        for &(quantile, value) in &curve {
+            assert_eq!(value, sketch.get_quantile_value(quantile).unwrap());
+        }
+        assert!(curve.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    }
+    
+    #[test]
+    fn test_to_fixed_buckets() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        for i in 1..=1000 {
+            sketch.add(i as f64);
+        }
+        sketch.add(-5.0);
         
-        sketch1.merge(&sketch2).unwrap();
+        let buckets = sketch.to_fixed_buckets(&[0.0, 250.0, 500.0, 750.0]).unwrap();
+        assert_eq!(buckets.iter().sum::<u64>(), sketch.count());
+        assert_eq!(buckets[0], 1);
+        for &bucket in &buckets[1..] {
+            assert!((245..=255).contains(&bucket), "buckets={:?}", buckets);
+        }
         
-        assert_eq!(sketch1.count(), count1 + count2);
-        assert_eq!(sketch1.min(), Some(1.0));
-        assert_eq!(sketch1.max(), Some(100.0));
+        assert_eq!(sketch.to_fixed_buckets(&[]).unwrap(), vec![1001]);
+        assert_eq!(sketch.to_fixed_buckets(&[5.0, 1.0]), Err(DDSketchError::InvalidValue));
+        assert_eq!(sketch.to_fixed_buckets(&[f64::NAN]), Err(DDSketchError::InvalidValue));
+        assert_eq!(DDSketch::new(0.01).unwrap().to_fixed_buckets(&[1.0]).unwrap(), vec![0, 0]);
+    }
+    
+    #[test]
+    fn test_get_quantile_bin() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        assert_eq!(sketch.get_quantile_bin(0.5), Err(DDSketchError::EmptySketch));
+        sketch.add_with_count(10.0, 30);
+        sketch.add_with_count(0.0, 20);
+        sketch.add_with_count(-4.0, 10);
+        sketch.add_with_count(1000.0, 40);
         
-        // Test incompatible sketches
-        let mut sketch3 = DDSketch::new(0.01).unwrap();
-        sketch3.add(1.0);
+        let bin = sketch.get_quantile_bin(0.99).unwrap();
+        assert_eq!(bin.value, sketch.get_quantile_value(0.99).unwrap());
+        assert_eq!(bin.bin_index, Some(sketch.mapping.key(1000.0)));
+        assert_eq!(bin.bin_count, 40);
+        assert!(bin.bin_lower <= 1000.0 && 1000.0 < bin.bin_upper);
+        assert_eq!(sketch.get_quantile_bin(1.0).unwrap().bin_count, 40);
         
-        assert!(sketch1.merge(&sketch3).is_err());
+        let zero = sketch.get_quantile_bin(0.15).unwrap();
+        assert_eq!((zero.bin_index, zero.bin_count, zero.bin_lower, zero.bin_upper), (None, 20, 0.0, 0.0));
+        
+        let negative = sketch.get_quantile_bin(0.0).unwrap();
+        assert_eq!(negative.bin_index, Some(sketch.mapping.key(4.0)));
+        assert_eq!(negative.bin_count, 10);
+        assert!(negative.bin_lower <= -4.0 && -4.0 <= negative.bin_upper);
+        assert!(sketch.get_quantile_bin(1.5).is_err());
     }
     
     #[test]
-    fn test_ddsketch_edge_cases() {
-        let sketch = DDSketch::new(0.02).unwrap();
+    fn test_capacity_management() {
+        let empty = DDSketch::new(0.01).unwrap().memory_size_bytes();
+        let mut sketch = DDSketch::with_capacity(0.01, 2000).unwrap();
+        assert!(sketch.memory_size_bytes() > empty + 2000 * 12);
+        assert!(DDSketch::with_capacity(0.0, 10).is_err());
         
-        // Empty sketch
-        assert!(sketch.get_quantile_value(0.5).is_err());
+        for i in 1..=5000 {
+            sketch.add(i as f64);
+        }
+        let peak = sketch.memory_size_bytes();
+        let p99 = sketch.p99().unwrap();
+        sketch.shrink_to_fit();
+        assert!(sketch.memory_size_bytes() <= peak);
+        assert_eq!(sketch.p99().unwrap(), p99);
         
-        // Invalid quantiles
+        sketch.clear();
+        assert!(sketch.memory_size_bytes() > empty);
+        sketch.shrink_to_fit();
+        assert_eq!(sketch.memory_size_bytes(), empty);
+        sketch.add(1.0);
+        assert_eq!(sketch.count(), 1);
+    }
+    
+    #[test]
+    fn test_ordered_stores_answer_ranks() {
+        let mut dense = DDSketch::new(0.01).unwrap();
+        let mut hybrid = DDSketch::with_hybrid_stores(0.01).unwrap();
+        for i in -200..=800 {
+            dense.add(i as f64 * 1.5);
+            hybrid.add(i as f64 * 1.5);
+        }
+        
+        for quantile in [0.0, 0.05, 0.2, 0.201, 0.5, 0.99, 1.0] {
+            let expected = dense.get_quantile_value(quantile).unwrap();
+            assert_eq!(hybrid.get_quantile_value(quantile).unwrap(), expected);
+            assert!(hybrid.rank_index.get().is_none());
+        }
+        assert_eq!(hybrid.get_quantile_bin(0.2).unwrap(), dense.get_quantile_bin(0.2).unwrap());
+    }
+    
+    #[test]
+    fn test_convolve() {
+        let mut x = DDSketch::new(0.01).unwrap();
+        let mut y = DDSketch::new(0.01).unwrap();
+        for i in 1..=100 {
+            x.add(i as f64);
+            y.add(1000.0 + 10.0 * i as f64);
+        }
+        
+        let sum = x.convolve(&y).unwrap();
+        assert_eq!(sum.count(), 10_000);
+        assert_eq!((sum.min(), sum.max()), (Some(1011.0), Some(2100.0)));
+        assert!((sum.mean().unwrap() - (50.5 + 1505.0)).abs() < 1e-6);
+        
+        // Compare against the exact distribution of all pairwise sums
+        let mut exact: Vec<f64> = (1..=100)
+            .flat_map(|i| (1..=100).map(move |j| i as f64 + 1000.0 + 10.0 * j as f64))
+            .collect();
+        exact.sort_by(f64::total_cmp);
+        for quantile in [0.01, 0.25, 0.5, 0.75, 0.99] {
+            let expected = exact[(quantile * (exact.len() - 1) as f64) as usize];
+            let estimate = sum.get_quantile_value(quantile).unwrap();
+            assert!((estimate - expected).abs() <= 0.021 * expected, "q={} {} vs {}", quantile, estimate, expected);
+        }
+        
+        let mut constant = DDSketch::new(0.01).unwrap();
+        constant.add(0.0);
+        assert_eq!(x.convolve(&constant).unwrap().p50().unwrap(), x.p50().unwrap());
+        assert_eq!(x.convolve(&DDSketch::new(0.01).unwrap()).unwrap_err(), DDSketchError::EmptySketch);
+        assert!(x.convolve(&DDSketch::new(0.05).unwrap()).is_err());
+    }
+    
+    #[test]
+    fn test_memory_budget() {
+        assert_eq!(DDSketch::with_memory_budget(0.01, 64).unwrap_err(), DDSketchError::InvalidValue);
+        assert!(DDSketch::with_memory_budget(2.0, 1 << 20).is_err());
+        
+        let mut rng = 0x2545_f491_4f6c_dd1du64;
+        for budget in [1 << 10, 4 << 10, 64 << 10] {
+            let mut sketch = DDSketch::with_memory_budget(0.01, budget).unwrap();
+            let mut other = DDSketch::with_memory_budget(0.01, budget).unwrap();
+            for _ in 0..20_000 {
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                let value = f64::from_bits((rng >> 12) | 0x3ff0_0000_0000_0000) * 1e6 - 1.5e6;
+                sketch.add(value);
+                other.add(-value * 3.0);
+            }
+            sketch.p99().unwrap();
+            assert!(sketch.memory_size_bytes() <= budget, "{} > {}", sketch.memory_size_bytes(), budget);
+            assert!(sketch.memory_size_bytes() > budget / 3, "{} <= {}", sketch.memory_size_bytes(), budget / 3);
+            
+            sketch.merge(&other).unwrap();
+            sketch.p50().unwrap();
+            assert!(sketch.memory_size_bytes() <= budget, "{} > {}", sketch.memory_size_bytes(), budget);
+            assert_eq!(sketch.count(), 40_000);
+        }
+    }
+    
+    #[test]
+    fn test_ddsketch_count_at() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        sketch.add_with_count(30.0, 7);
+        sketch.add(29.0);
+        sketch.add_with_count(0.0, 2);
+        sketch.add_with_count(-5.0, 3);
+        sketch.add(f64::INFINITY);
+        
+        assert_eq!(sketch.count_at(30.0), 7);
+        let bin = sketch.bins().find(|bin| bin.count == 7).unwrap();
+        assert_eq!(sketch.count_at((bin.lower + bin.upper) / 2.0), 7);
+        assert_eq!(sketch.count_at(29.0), 1);
+        assert_eq!(sketch.count_at(31.0), 0);
+        assert_eq!(sketch.count_at(-5.0), 3);
+        assert_eq!(sketch.count_at(5.0), 0);
+        assert_eq!((sketch.count_at(0.0), sketch.count_at(-0.0), sketch.zero_count()), (2, 2, 2));
+        assert_eq!((sketch.count_at(f64::INFINITY), sketch.count_at(f64::NAN)), (0, 0));
+    }
+    
+    #[test]
+    fn test_ddsketch_take() {
         let mut sketch = DDSketch::new(0.02).unwrap();
+        sketch.set_non_finite_policy(NonFinitePolicy::CountSeparately);
         sketch.add(1.0);
+        sketch.add(f64::NAN);
         
-        assert!(sketch.get_quantile_value(-0.1).is_err());
-        assert!(sketch.get_quantile_value(1.1).is_err());
+        let taken = sketch.take();
+        assert_eq!((taken.count(), taken.non_finite_count(), taken.max()), (1, 1, Some(1.0)));
+        assert!(sketch.is_empty());
+        assert_eq!(sketch.non_finite_count(), 0);
+        assert_eq!(sketch.non_finite_policy(), NonFinitePolicy::CountSeparately);
+        
+        sketch.add(2.0);
+        assert_eq!(sketch.take().max(), Some(2.0));
+        assert_eq!(taken.count(), 1);
+    }
+    
+    #[test]
+    fn test_ddsketch_signed_statistics() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        assert_eq!((sketch.min_positive(), sketch.max_negative()), (None, None));
+        
+        sketch.add_with_count(-0.5, 3);
+        sketch.add(-20.0);
+        assert_eq!((sketch.negative_count(), sketch.positive_count()), (4, 0));
+        assert_eq!((sketch.min_positive(), sketch.max_negative()), (None, Some(-0.5)));
+        
+        sketch.add_with_count(0.0, 2);
+        sketch.add_with_count(3.0, 5);
+        sketch.add(100.0);
+        assert_eq!((sketch.negative_count(), sketch.positive_count()), (4, 6));
+        assert_eq!(sketch.negative_count() + sketch.zero_count() + sketch.positive_count(), sketch.count());
+        
+        let min_positive = sketch.min_positive().unwrap();
+        assert!((min_positive - 3.0).abs() <= 3.0 * 0.01, "{}", min_positive);
+        let max_negative = sketch.max_negative().unwrap();
+        assert!((max_negative + 0.5).abs() <= 0.5 * 0.01, "{}", max_negative);
+        
+        // Estimates never fall outside the observed extremes
+        let mut single = DDSketch::new(0.05).unwrap();
+        single.add(-1.0);
+        single.add(7.0);
+        assert!(single.min_positive().unwrap() <= 7.0);
+        assert!(single.max_negative().unwrap() >= -1.0);
+    }
+    
+    #[test]
+    fn test_within_bin_interpolation() {
+        // A coarse sketch puts all of these values in a handful of bins
+        let mut sketch = DDSketch::new(0.2).unwrap();
+        for value in 100..200 {
+            sketch.add(value as f64);
+        }
+        sketch.add(-150.0);
+        sketch.set_quantile_interpolation(QuantileInterpolation::WithinBin);
+        
+        let curve = sketch.quantile_curve(100).unwrap();
+        let values: Vec<f64> = curve.iter().map(|&(_, value)| value).collect();
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", values);
+        assert_eq!((values[0], values[100]), (-150.0, 199.0));
+        
+        // Neighboring quantiles within one bin get distinct values, unlike with the midpoint
+        let distinct = |values: &[f64]| values.windows(2).filter(|pair| pair[0] != pair[1]).count();
+        let midpoints: Vec<f64> = (0..=100)
+            .map(|step| sketch.get_quantile_value_with(step as f64 / 100.0, QuantileInterpolation::Midpoint).unwrap())
+            .collect();
+        assert!(distinct(&values) > 4 * distinct(&midpoints), "{:?}", values);
+        
+        let bound = sketch.guaranteed_relative_error();
+        for (quantile, value) in curve.into_iter().skip(2) {
+            let expected = 100.0 + (quantile * 101.0 - 1.0).clamp(0.0, 99.0);
+            assert!((value - expected).abs() <= expected * bound, "{} {} {}", quantile, value, expected);
+        }
+        
+        // The rank index, the stores and a cursor all place ranks the same way
+        let mut fixed = DDSketch::<LogarithmicMapping, FixedStore<64>>::with_fixed_stores(0.2).unwrap();
+        fixed.merge(&sketch).unwrap();
+        fixed.set_quantile_interpolation(QuantileInterpolation::WithinBin);
+        let mut cursor = crate::QueryCursor::new();
+        for step in 0..=20 {
+            let quantile = step as f64 / 20.0;
+            let expected = sketch.get_quantile_value(quantile).unwrap();
+            assert_eq!(fixed.get_quantile_value(quantile).unwrap(), expected);
+            assert_eq!(cursor.get_quantile_value(&sketch, quantile).unwrap(), expected);
+        }
+    }
+    
+    #[test]
+    fn test_ddsketch_add_counts() {
+        let pairs: Vec<(f64, u64)> = (0..3000u64)
+            .map(|i| (((i * 7919) % 2001) as f64 - 500.0, 1 + i % 7 * 1_000_000))
+            .chain([(f64::NAN, 4), (12.0, 0)])
+            .collect();
+        
+        let mut counted = DDSketch::new(0.02).unwrap();
+        let mut sequential = DDSketch::new(0.02).unwrap();
+        counted.add_counts(pairs.iter().copied());
+        for &(value, count) in &pairs {
+            sequential.add_with_count(value, count);
+        }
+        
+        assert_eq!(counted.snapshot(), sequential.snapshot());
+        assert_eq!(counted.count(), sequential.count());
+        assert_eq!(counted.non_finite_count(), 4);
+        assert_eq!(counted.get_quantile_value(0.5).unwrap(), sequential.get_quantile_value(0.5).unwrap());
+        
+        // Counts that would overflow the total are dropped, keeping the rest
+        let mut full = DDSketch::new(0.02).unwrap();
+        full.add_counts([(1.0, u64::MAX - 1), (2.0, 5), (3.0, 1)]);
+        assert_eq!(full.count(), u64::MAX);
+        assert_eq!(full.max(), Some(3.0));
+        assert!(full.has_overflowed());
+        
+        // Pairs refused by the store do not leak into min/max or moments
+        let mut strict = DDSketch::with_max_bins(0.02, 1, CollapsePolicy::Error).unwrap();
+        strict.add_counts([(5.0, 2), (5.01, 3), (0.0, 1), (1000.0, 4)]);
+        assert_eq!(strict.count(), 6);
+        assert_eq!(strict.max(), Some(5.01));
+    }
+    
+    #[test]
+    fn test_failed_merges_leave_sketch_unchanged() {
+        let mut strict = DDSketch::with_max_bins(0.01, 8, CollapsePolicy::Error).unwrap();
+        for value in [-3.0, -2.0, 1.0, 2.0] {
+            strict.add(value);
+        }
+        let before = strict.snapshot();
+        
+        // The negative bin fits but the positive ones do not
+        let mut other = DDSketch::with_max_bins(0.01, 64, CollapsePolicy::Error).unwrap();
+        other.add(-4.0);
+        for i in 10..30 {
+            other.add(i as f64);
+        }
+        
+        assert_eq!(strict.merge(&other), Err(DDSketchError::BinLimitExceeded));
+        assert_eq!(strict.snapshot(), before);
+        assert_eq!(strict.merge_all([&other]), Err(DDSketchError::BinLimitExceeded));
+        assert_eq!(strict.snapshot(), before);
+        assert_eq!(strict.count(), 4);
+        
+        // Sketches before the failing one stay merged, and nothing of it is
+        let mut fitting = DDSketch::with_max_bins(0.01, 64, CollapsePolicy::Error).unwrap();
+        fitting.add(1.0);
+        fitting.add(-2.0);
+        assert_eq!(strict.merge_from([&fitting, &other].into_iter()), Err(DDSketchError::BinLimitExceeded));
+        let mut expected = DDSketch::from_snapshot(&before).unwrap();
+        expected.add(1.0);
+        expected.add(-2.0);
+        assert_eq!(strict.snapshot(), expected.snapshot());
+        assert_eq!((strict.count(), strict.min(), strict.max()), (6, Some(-3.0), Some(2.0)));
+        
+        // Re-binned merges are checked the same way
+        let before = strict.snapshot();
+        let mut coarse = DDSketch::new(0.05).unwrap();
+        coarse.add(-4.0);
+        for i in 10..30 {
+            coarse.add(i as f64 * 10.0);
+        }
+        assert_eq!(strict.merge_rebin(&coarse), Err(DDSketchError::BinLimitExceeded));
+        assert_eq!(strict.snapshot(), before);
     }
 }
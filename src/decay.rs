@@ -0,0 +1,178 @@
+//! Exponentially decayed sketches
+//!
+//! This module provides a sketch in which older observations fade out smoothly,
+//! for "recent" percentiles in long-running processes without window boundaries.
+
+use crate::{DDSketchError, Result, WeightedDDSketch};
+use std::time::{Duration, Instant};
+
+/// Bins whose weight decays below this are dropped to keep memory bounded
+const MIN_DECAYED_WEIGHT: f64 = 1e-9;
+
+/// A sketch whose weights halve every half-life
+///
+/// Values are recorded with a weight of one into a `WeightedDDSketch`. Every
+/// `decay_interval`, all weights are multiplied by the decay accumulated since the
+/// previous decay, so a value recorded one half-life ago counts half as much as a
+/// value recorded now. Bins that have faded to a negligible weight are dropped.
+#[derive(Debug, Clone)]
+pub struct ExpDecayDDSketch {
+    sketch: WeightedDDSketch,
+    half_life: Duration,
+    decay_interval: Duration,
+    last_decay: Instant,
+}
+
+impl ExpDecayDDSketch {
+    /// Create a new decaying sketch
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    /// * `half_life` - The age at which a value's weight has halved
+    /// * `decay_interval` - How often the decay is applied; shorter is smoother but
+    ///   costs a pass over the bins each time
+    ///
+    /// # Returns
+    /// A new ExpDecayDDSketch, or an error if `half_life` is zero
+    pub fn new(relative_accuracy: f64, half_life: Duration, decay_interval: Duration) -> Result<Self> {
+        if half_life.is_zero() {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(ExpDecayDDSketch {
+            sketch: WeightedDDSketch::new(relative_accuracy)?,
+            half_life,
+            decay_interval,
+            last_decay: Instant::now(),
+        })
+    }
+    
+    /// Get the half-life of the decay
+    pub fn half_life(&self) -> Duration {
+        self.half_life
+    }
+    
+    /// Get the underlying weighted sketch, as of the last decay
+    pub fn sketch(&self) -> &WeightedDDSketch {
+        &self.sketch
+    }
+    
+    /// Add a value to the sketch, applying any decay that is due first
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: f64) {
+        self.add_weighted(value, 1.0);
+    }
+    
+    /// Add a weighted value to the sketch, applying any decay that is due first
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `weight` - The weight of the value at the time it is added
+    pub fn add_weighted(&mut self, value: f64, weight: f64) {
+        self.advance_to(Instant::now());
+        self.sketch.add_weighted(value, weight);
+    }
+    
+    /// Apply the decay accumulated up to `now`, if a decay interval has passed
+    pub fn advance_to(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_decay);
+        if elapsed < self.decay_interval || elapsed.is_zero() {
+            return;
+        }
+        
+        self.decay_by(0.5f64.powf(elapsed.as_secs_f64() / self.half_life.as_secs_f64()));
+        self.last_decay = now;
+    }
+    
+    /// Multiply all weights by a factor immediately
+    ///
+    /// # Arguments
+    /// * `factor` - The factor to multiply weights by (between 0 and 1)
+    pub fn decay_by(&mut self, factor: f64) {
+        self.sketch.scale_weights(factor, MIN_DECAYED_WEIGHT);
+    }
+    
+    /// Get the decayed total weight of the sketch
+    pub fn total_weight(&self) -> f64 {
+        self.sketch.total_weight()
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.sketch.is_empty()
+    }
+    
+    /// Get the value at a given quantile of the decayed weights
+    ///
+    /// Decaying all weights by the same factor does not move the quantiles, so queries
+    /// do not need to apply pending decay.
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.sketch.get_quantile_value(quantile)
+    }
+    
+    /// Clear all data from the sketch
+    pub fn clear(&mut self) {
+        self.sketch.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_exp_decay_fades_old_values() {
+        let half_life = Duration::from_secs(60);
+        let mut sketch = ExpDecayDDSketch::new(0.01, half_life, Duration::from_secs(1)).unwrap();
+        assert!(ExpDecayDDSketch::new(0.01, Duration::ZERO, Duration::from_secs(1)).is_err());
+        
+        for _ in 0..100 {
+            sketch.sketch.add(10.0);
+        }
+        let start = sketch.last_decay;
+        
+        // After three half-lives the old values weigh 12.5, less than 50 new ones
+        sketch.advance_to(start + half_life * 3);
+        assert!((sketch.total_weight() - 12.5).abs() < 1e-9);
+        for _ in 0..50 {
+            sketch.sketch.add(1000.0);
+        }
+        
+        let median = sketch.get_quantile_value(0.5).unwrap();
+        assert!((median - 1000.0).abs() <= 1000.0 * 0.01);
+        
+        // Decay within the interval is deferred
+        sketch.advance_to(start + half_life * 3 + Duration::from_millis(500));
+        assert!((sketch.total_weight() - 62.5).abs() < 1e-9);
+    }
+    
+    #[test]
+    fn test_exp_decay_drops_faded_bins() {
+        let mut sketch = ExpDecayDDSketch::new(0.01, Duration::from_secs(1), Duration::ZERO).unwrap();
+        sketch.add(5.0);
+        
+        sketch.decay_by(1e-12);
+        assert!(sketch.is_empty());
+        assert!(sketch.get_quantile_value(0.5).is_err());
+        
+        // Extremes whose bins faded away move to the bins that remain
+        sketch.add_weighted(1e6, 1.0);
+        sketch.add_weighted(-3.0, 1.0);
+        sketch.add_weighted(5.0, 1e10);
+        sketch.decay_by(1e-10);
+        assert!((sketch.total_weight() - 1.0).abs() < 1e-6);
+        let max = sketch.sketch().max().unwrap();
+        assert!((5.0..=5.0 * 1.02).contains(&max), "{}", max);
+        assert!(sketch.sketch().min().unwrap() >= 5.0 / 1.02);
+        assert_eq!(sketch.get_quantile_value(1.0).unwrap(), max);
+        assert!(sketch.get_quantile_value(0.0).unwrap() <= 5.0 * 1.02);
+    }
+}
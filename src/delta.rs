@@ -0,0 +1,260 @@
+//! Incremental deltas between snapshots
+//!
+//! This module encodes only what changed in a sketch since a snapshot, so that a
+//! receiver holding that snapshot can bring its copy up to date without a full
+//! transfer. Few bins change per interval in a long-lived sketch, so deltas are
+//! usually a small fraction of the full encoding.
+//!
+//! ```text
+//! magic       4 bytes   "DDSD"
+//! version     u8        1
+//! accuracy    f64       relative accuracy
+//! base        u32       CRC-32 of the encoded base snapshot
+//! flags       u8        bit 0: min present, bit 1: max present
+//! min, max    f64       each only when present
+//! sum         f64
+//! sum_squares f64
+//! zero_count  varint
+//! positive    changed bins as in the encoding module, with their new counts
+//! negative    same layout as positive
+//! ```
+//!
+//! Counts are absolute, so a bin that emptied is sent with a count of zero.
+
+use crate::{DDSketch, DDSketchError, Result, SketchSnapshot};
+use crate::checkpoint::crc32;
+use crate::encoding::{write_bins, write_varint, Reader};
+use crate::mapping::{IncompatibilityReason, IndexMapping};
+use crate::store::Store;
+
+/// The bytes every delta starts with
+const MAGIC: &[u8; 4] = b"DDSD";
+
+/// The current version of the delta format
+pub const DELTA_VERSION: u8 = 1;
+
+const FLAG_MIN: u8 = 1;
+const FLAG_MAX: u8 = 1 << 1;
+
+/// The encoded changes of a sketch since a snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaBytes(Vec<u8>);
+
+impl DeltaBytes {
+    /// Get the encoded bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+    
+    /// Convert into the encoded bytes
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+    
+    /// Get the length of the encoding in bytes
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    
+    /// Check if the encoding is empty, which a valid delta never is
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl AsRef<[u8]> for DeltaBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<M: IndexMapping, S: Store> DDSketch<M, S> {
+    /// Encode the changes in this sketch since a snapshot of it was taken
+    ///
+    /// # Arguments
+    /// * `base` - The snapshot the receiver holds
+    ///
+    /// # Returns
+    /// The delta, or `DDSketchError::IncompatibleSketches` if the snapshot has a
+    /// different relative accuracy
+    pub fn delta_since(&self, base: &SketchSnapshot) -> Result<DeltaBytes> {
+        self.mapping.descriptor().check_mergeable(&base.mapping_descriptor()?)?;
+        
+        let current = self.snapshot();
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(DELTA_VERSION);
+        bytes.extend_from_slice(&current.relative_accuracy.to_le_bytes());
+        bytes.extend_from_slice(&crc32(&base.encode()).to_le_bytes());
+        
+        let flags = if current.min_value.is_some() { FLAG_MIN } else { 0 }
+            | if current.max_value.is_some() { FLAG_MAX } else { 0 };
+        bytes.push(flags);
+        for value in current.min_value.iter().chain(&current.max_value) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        
+        bytes.extend_from_slice(&current.sum.to_le_bytes());
+        bytes.extend_from_slice(&current.sum_squares.to_le_bytes());
+        write_varint(&mut bytes, current.zero_count);
+        write_bins(&mut bytes, &changed_bins(&base.positive_bins, &current.positive_bins));
+        write_bins(&mut bytes, &changed_bins(&base.negative_bins, &current.negative_bins));
+        
+        Ok(DeltaBytes(bytes))
+    }
+    
+    /// Apply a delta produced by `delta_since`, bringing this sketch up to date
+    ///
+    /// The sketch must hold exactly the contents of the delta's base snapshot.
+    ///
+    /// # Arguments
+    /// * `bytes` - The encoded delta
+    ///
+    /// # Returns
+    /// `DDSketchError::InvalidEncoding` if the bytes are not a valid delta, or
+    /// `DDSketchError::IncompatibleSketches` if the sketch does not match the base,
+    /// in either case leaving the sketch unchanged
+    pub fn apply_delta(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC || reader.byte()? != DELTA_VERSION {
+            return Err(DDSketchError::InvalidEncoding);
+        }
+        
+        let relative_accuracy = reader.f64()?;
+        let base_checksum = u32::from_le_bytes(reader.take(4)?.try_into().expect("took four bytes"));
+        let flags = reader.byte()?;
+        let min_value = if flags & FLAG_MIN != 0 { Some(reader.f64()?) } else { None };
+        let max_value = if flags & FLAG_MAX != 0 { Some(reader.f64()?) } else { None };
+        let sum = reader.f64()?;
+        let sum_squares = reader.f64()?;
+        let zero_count = reader.varint()?;
+        let positive_bins = reader.bins()?;
+        let negative_bins = reader.bins()?;
+        if !reader.bytes.is_empty() {
+            return Err(DDSketchError::InvalidEncoding);
+        }
+        
+        if (self.relative_accuracy() - relative_accuracy).abs() > 1e-10 {
+            return Err(DDSketchError::different_accuracy(self.relative_accuracy(), relative_accuracy));
+        }
+        if crc32(&self.encode()) != base_checksum {
+            let gamma = Some(self.mapping.descriptor().gamma);
+            return Err(DDSketchError::IncompatibleSketches {
+                self_gamma: gamma,
+                other_gamma: gamma,
+                reason: IncompatibilityReason::DifferentBase,
+            });
+        }
+        
+        set_bins(&mut self.store, &positive_bins);
+        set_bins(&mut self.negative_store, &negative_bins);
+        self.zero_count = zero_count;
+        self.min_value = min_value;
+        self.max_value = max_value;
+        self.sum = sum;
+        self.sum_squares = sum_squares;
+        self.mark_modified();
+        
+        Ok(())
+    }
+}
+
+/// Get the bins of `current` whose counts differ from `base`, both sorted by index
+///
+/// Bins missing from `current` are reported with a count of zero.
+fn changed_bins(base: &[(i32, u64)], current: &[(i32, u64)]) -> Vec<(i32, u64)> {
+    let mut changed = Vec::new();
+    let (mut base, mut current) = (base.iter().peekable(), current.iter().peekable());
+    
+    loop {
+        match (base.peek(), current.peek()) {
+            (Some(&&(base_index, base_count)), Some(&&(index, count))) => {
+                if base_index < index {
+                    changed.push((base_index, 0));
+                    base.next();
+                } else if index < base_index {
+                    changed.push((index, count));
+                    current.next();
+                } else {
+                    if count != base_count {
+                        changed.push((index, count));
+                    }
+                    base.next();
+                    current.next();
+                }
+            }
+            (Some(&&(base_index, _)), None) => {
+                changed.push((base_index, 0));
+                base.next();
+            }
+            (None, Some(&&bin)) => {
+                changed.push(bin);
+                current.next();
+            }
+            (None, None) => return changed,
+        }
+    }
+}
+
+/// Set the counts of some bins of a store
+fn set_bins<S: Store>(store: &mut S, bins: &[(i32, u64)]) {
+    for &(index, count) in bins {
+        let existing = store.get(index);
+        if count > existing {
+            store.add(index, count - existing);
+        } else {
+            store.remove(index, existing - count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_delta_round_trip() {
+        let mut sender = DDSketch::new(0.01).unwrap();
+        for i in 1..=5000 {
+            sender.add(i as f64);
+        }
+        let base = sender.snapshot();
+        let mut receiver = DDSketch::from_snapshot(&base).unwrap();
+        
+        sender.add(2500.0);
+        sender.add(-7.0);
+        sender.add(0.0);
+        let mut removed = DDSketch::new(0.01).unwrap();
+        removed.add(1.0);
+        sender.subtract(&removed).unwrap();
+        
+        let delta = sender.delta_since(&base).unwrap();
+        assert!(delta.len() * 10 < sender.encode().len());
+        
+        receiver.apply_delta(delta.as_bytes()).unwrap();
+        assert_eq!(receiver.snapshot(), sender.snapshot());
+        
+        // The receiver no longer matches the base, so the same delta is refused
+        assert!(matches!(
+            receiver.apply_delta(delta.as_bytes()),
+            Err(DDSketchError::IncompatibleSketches { reason: IncompatibilityReason::DifferentBase, .. })
+        ));
+    }
+    
+    #[test]
+    fn test_delta_rejects_malformed_input() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        let base = sketch.snapshot();
+        sketch.add(3.0);
+        let delta = sketch.delta_since(&base).unwrap().into_bytes();
+        
+        let mut receiver = DDSketch::new(0.02).unwrap();
+        for len in 0..delta.len() {
+            assert_eq!(receiver.apply_delta(&delta[..len]), Err(DDSketchError::InvalidEncoding));
+        }
+        assert!(receiver.is_empty());
+        
+        let other = DDSketch::new(0.05).unwrap();
+        assert!(matches!(other.delta_since(&base), Err(DDSketchError::IncompatibleSketches { .. })));
+    }
+}
@@ -0,0 +1,132 @@
+//! DogStatsD emission
+//!
+//! This module renders sketches as DogStatsD distribution packets, so that their
+//! contents reach the Datadog agent as one weighted sample per bin rather than one
+//! sample per recorded value. Sending the packets is left to the caller.
+
+use crate::{DDSketch, DDSketchError, Result};
+use crate::ddsketch::relative_midpoint;
+use crate::mapping::IndexMapping;
+use crate::store::Store;
+use std::fmt::Write;
+
+/// The largest packet the agent accepts over UDP by default
+pub const UDP_MAX_PACKET_SIZE: usize = 1432;
+
+/// The largest packet the agent accepts over a Unix domain socket by default
+pub const UDS_MAX_PACKET_SIZE: usize = 8192;
+
+impl<M: IndexMapping, S: Store> DDSketch<M, S> {
+    /// Render the sketch as DogStatsD distribution packets
+    ///
+    /// Each non-empty bin becomes one `name:value|d|@rate|#tags` line, with the bin's
+    /// relative midpoint, clamped to the extremes, as its value and `1/count` as its
+    /// sample rate, which the agent scales back up to the bin's count. Lines are packed
+    /// into newline-separated packets of at most `max_packet_size` bytes. NaN and
+    /// infinite values are not sent.
+    ///
+    /// # Arguments
+    /// * `name` - The metric name
+    /// * `tags` - The tags attached to every line, as key-value pairs
+    /// * `max_packet_size` - The largest packet to produce, such as `UDP_MAX_PACKET_SIZE`
+    ///
+    /// # Returns
+    /// The packets, empty if the sketch is, or `DDSketchError::InvalidValue` if the name
+    /// or a tag contains a character reserved by the protocol or a single line does not
+    /// fit in a packet
+    pub fn to_dogstatsd_packets(&self, name: &str, tags: &[(&str, &str)], max_packet_size: usize) -> Result<Vec<String>> {
+        if name.is_empty() || name.contains([':', '|', '@', '\n']) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        if tags.iter().any(|(key, value)| format!("{}{}", key, value).contains([',', '|', '#', '\n'])) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        let suffix = if tags.is_empty() {
+            String::new()
+        } else {
+            let tags: Vec<String> = tags.iter().map(|(key, value)| format!("{}:{}", key, value)).collect();
+            format!("|#{}", tags.join(","))
+        };
+        let (min, max) = (self.min().unwrap_or(f64::NEG_INFINITY), self.max().unwrap_or(f64::INFINITY));
+        
+        let mut packets = Vec::new();
+        let mut packet = String::new();
+        let mut line = String::new();
+        for bin in self.bins() {
+            let value = if bin.lower < 0.0 {
+                -relative_midpoint(-bin.upper, -bin.lower)
+            } else {
+                relative_midpoint(bin.lower, bin.upper)
+            };
+            
+            line.clear();
+            let _ = write!(line, "{}:{}|d", name, value.clamp(min, max));
+            if bin.count > 1 {
+                let _ = write!(line, "|@{}", 1.0 / bin.count as f64);
+            }
+            line.push_str(&suffix);
+            
+            if line.len() > max_packet_size {
+                return Err(DDSketchError::InvalidValue);
+            }
+            if !packet.is_empty() && packet.len() + 1 + line.len() > max_packet_size {
+                packets.push(std::mem::take(&mut packet));
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+        if !packet.is_empty() {
+            packets.push(packet);
+        }
+        
+        Ok(packets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_dogstatsd_packets() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        sketch.add(-3.0);
+        sketch.add_with_count(0.0, 2);
+        sketch.add_with_count(5.0, 4);
+        
+        let packets = sketch.to_dogstatsd_packets("latency", &[("env", "prod")], UDP_MAX_PACKET_SIZE).unwrap();
+        assert_eq!(packets.len(), 1);
+        let lines: Vec<&str> = packets[0].lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "latency:-3|d|#env:prod");
+        assert_eq!(lines[1], "latency:0|d|@0.5|#env:prod");
+        assert_eq!(lines[2], "latency:5|d|@0.25|#env:prod");
+        
+        assert!(DDSketch::new(0.02).unwrap().to_dogstatsd_packets("x", &[], UDP_MAX_PACKET_SIZE).unwrap().is_empty());
+        assert_eq!(sketch.to_dogstatsd_packets("a|b", &[], UDP_MAX_PACKET_SIZE), Err(DDSketchError::InvalidValue));
+        assert_eq!(sketch.to_dogstatsd_packets("x", &[("k", "a,b")], UDP_MAX_PACKET_SIZE), Err(DDSketchError::InvalidValue));
+        assert_eq!(sketch.to_dogstatsd_packets("latency", &[], 8), Err(DDSketchError::InvalidValue));
+    }
+    
+    #[test]
+    fn test_dogstatsd_packet_chunking() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        for i in 1..=2000 {
+            sketch.add_with_count(i as f64, 3);
+        }
+        
+        let packets = sketch.to_dogstatsd_packets("size", &[], UDP_MAX_PACKET_SIZE).unwrap();
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|packet| packet.len() <= UDP_MAX_PACKET_SIZE));
+        
+        // The sample rates add back up to the sketch's count
+        let total: f64 = packets.iter()
+            .flat_map(|packet| packet.lines())
+            .map(|line| line.split("|@").nth(1).map_or(1.0, |rate| 1.0 / rate.parse::<f64>().unwrap()))
+            .sum();
+        assert!((total - sketch.count() as f64).abs() < 1e-6);
+    }
+}
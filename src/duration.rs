@@ -0,0 +1,255 @@
+//! Latency sketches
+//!
+//! This module provides a DDSketch wrapper that records `Duration`s in a configured
+//! unit, so that callers never convert latencies to and from raw floats by hand.
+
+use crate::{DDSketch, DDSketchError, Result};
+use crate::mapping::{IndexMapping, LogarithmicMapping};
+use crate::store::{DenseStore, Store};
+use std::time::Duration;
+
+/// The unit durations are recorded in
+///
+/// The unit only affects the values stored in the sketch; the relative accuracy
+/// guarantee is the same for every unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DurationUnit {
+    /// Record durations as seconds
+    Seconds,
+    /// Record durations as milliseconds
+    Milliseconds,
+    /// Record durations as microseconds
+    Microseconds,
+    /// Record durations as nanoseconds
+    #[default]
+    Nanoseconds,
+}
+
+impl DurationUnit {
+    /// Get the number of units in one second
+    fn per_second(self) -> f64 {
+        match self {
+            DurationUnit::Seconds => 1.0,
+            DurationUnit::Milliseconds => 1e3,
+            DurationUnit::Microseconds => 1e6,
+            DurationUnit::Nanoseconds => 1e9,
+        }
+    }
+    
+    /// Convert a duration to a value in this unit
+    pub fn to_value(self, duration: Duration) -> f64 {
+        match self {
+            DurationUnit::Nanoseconds => duration.as_nanos() as f64,
+            _ => duration.as_secs_f64() * self.per_second(),
+        }
+    }
+    
+    /// Convert a value in this unit back to a duration
+    ///
+    /// # Returns
+    /// The duration, or an error if the value is negative, NaN or too large
+    pub fn to_duration(self, value: f64) -> Result<Duration> {
+        Duration::try_from_secs_f64(value / self.per_second())
+            .map_err(|_| DDSketchError::InvalidValue)
+    }
+}
+
+/// A DDSketch of durations recorded in a fixed unit
+#[derive(Debug, Clone)]
+pub struct DurationSketch<M: IndexMapping = LogarithmicMapping, S: Store = DenseStore> {
+    sketch: DDSketch<M, S>,
+    unit: DurationUnit,
+}
+
+impl DurationSketch {
+    /// Create a new duration sketch
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    /// * `unit` - The unit durations are recorded in
+    ///
+    /// # Returns
+    /// A new DurationSketch instance
+    pub fn new(relative_accuracy: f64, unit: DurationUnit) -> Result<Self> {
+        Ok(DurationSketch {
+            sketch: DDSketch::new(relative_accuracy)?,
+            unit,
+        })
+    }
+}
+
+impl<M: IndexMapping, S: Store> DurationSketch<M, S> {
+    /// Wrap an existing sketch whose values are in the given unit
+    pub fn from_sketch(sketch: DDSketch<M, S>, unit: DurationUnit) -> Self {
+        DurationSketch { sketch, unit }
+    }
+    
+    /// Get the unit durations are recorded in
+    pub fn unit(&self) -> DurationUnit {
+        self.unit
+    }
+    
+    /// Get the underlying sketch, whose values are in `unit()`
+    pub fn sketch(&self) -> &DDSketch<M, S> {
+        &self.sketch
+    }
+    
+    /// Unwrap the underlying sketch, whose values are in `unit()`
+    pub fn into_sketch(self) -> DDSketch<M, S> {
+        self.sketch
+    }
+    
+    /// Add a duration to the sketch
+    ///
+    /// # Arguments
+    /// * `duration` - The duration to add
+    pub fn add_duration(&mut self, duration: Duration) {
+        self.sketch.add(self.unit.to_value(duration));
+    }
+    
+    /// Add a duration with a specific count to the sketch
+    ///
+    /// # Arguments
+    /// * `duration` - The duration to add
+    /// * `count` - The number of times to add the duration
+    pub fn add_duration_with_count(&mut self, duration: Duration, count: u64) {
+        self.sketch.add_with_count(self.unit.to_value(duration), count);
+    }
+    
+    /// Get the total count of durations in the sketch
+    pub fn count(&self) -> u64 {
+        self.sketch.count()
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.sketch.is_empty()
+    }
+    
+    /// Get the duration at a given quantile
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated duration at the given quantile
+    pub fn quantile_duration(&self, quantile: f64) -> Result<Duration> {
+        self.unit.to_duration(self.sketch.get_quantile_value(quantile)?)
+    }
+    
+    /// Get the shortest duration recorded
+    pub fn min_duration(&self) -> Option<Duration> {
+        self.sketch.min().and_then(|min| self.unit.to_duration(min).ok())
+    }
+    
+    /// Get the longest duration recorded
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.sketch.max().and_then(|max| self.unit.to_duration(max).ok())
+    }
+    
+    /// Merge another duration sketch into this one
+    ///
+    /// # Returns
+    /// An error if the sketches use different units or are otherwise incompatible
+    pub fn merge<N: IndexMapping, T: Store>(&mut self, other: &DurationSketch<N, T>) -> Result<()> {
+        if self.unit != other.unit {
+            return Err(DDSketchError::different_parameter(
+                "units per second",
+                self.unit.per_second(),
+                other.unit.per_second(),
+            ));
+        }
+        
+        self.sketch.merge(&other.sketch)
+    }
+    
+    /// Clear all data from the sketch
+    pub fn clear(&mut self) {
+        self.sketch.clear();
+    }
+}
+
+/// The serialized form of a `DurationSketch`: its unit and a snapshot of its contents
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DurationSketchRepr {
+    unit: DurationUnit,
+    snapshot: crate::SketchSnapshot,
+}
+
+#[cfg(feature = "serde")]
+impl<M: IndexMapping, S: Store> serde::Serialize for DurationSketch<M, S> {
+    fn serialize<Ser: serde::Serializer>(&self, serializer: Ser) -> std::result::Result<Ser::Ok, Ser::Error> {
+        DurationSketchRepr {
+            unit: self.unit,
+            snapshot: self.sketch.snapshot(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DurationSketch {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let repr = DurationSketchRepr::deserialize(deserializer)?;
+        let sketch = DDSketch::from_snapshot(&repr.snapshot).map_err(serde::de::Error::custom)?;
+        Ok(DurationSketch::from_sketch(sketch, repr.unit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_duration_units() {
+        let duration = Duration::from_micros(1500);
+        
+        assert_eq!(DurationUnit::Seconds.to_value(duration), 0.0015);
+        assert_eq!(DurationUnit::Milliseconds.to_value(duration), 1.5);
+        assert_eq!(DurationUnit::Microseconds.to_value(duration), 1500.0);
+        assert_eq!(DurationUnit::Nanoseconds.to_value(duration), 1_500_000.0);
+        
+        assert_eq!(DurationUnit::Nanoseconds.to_duration(1_500_000.0).unwrap(), duration);
+        assert!(DurationUnit::Seconds.to_duration(-1.0).is_err());
+        assert!(DurationUnit::Seconds.to_duration(f64::NAN).is_err());
+    }
+    
+    #[test]
+    fn test_duration_sketch_quantiles() {
+        let mut sketch = DurationSketch::new(0.01, DurationUnit::Microseconds).unwrap();
+        assert!(sketch.quantile_duration(0.5).is_err());
+        
+        for ms in 1..=1000 {
+            sketch.add_duration(Duration::from_millis(ms));
+        }
+        
+        assert_eq!(sketch.count(), 1000);
+        assert_eq!(sketch.min_duration(), Some(Duration::from_millis(1)));
+        assert_eq!(sketch.max_duration(), Some(Duration::from_secs(1)));
+        
+        let median = sketch.quantile_duration(0.5).unwrap().as_secs_f64();
+        assert!((median - 0.5).abs() <= 0.5 * 0.01 + 1e-9);
+        
+        let mut other = DurationSketch::new(0.01, DurationUnit::Milliseconds).unwrap();
+        other.add_duration(Duration::from_millis(5));
+        assert!(matches!(sketch.merge(&other), Err(DDSketchError::IncompatibleSketches { .. })));
+    }
+    
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_duration_sketch_serde() {
+        let mut sketch = DurationSketch::new(0.02, DurationUnit::Milliseconds).unwrap();
+        sketch.add_duration_with_count(Duration::from_millis(40), 3);
+        sketch.add_duration(Duration::from_millis(250));
+        
+        let json = serde_json::to_string(&sketch).unwrap();
+        assert!(json.contains("\"Milliseconds\""));
+        
+        let restored: DurationSketch = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.unit(), DurationUnit::Milliseconds);
+        assert_eq!(restored.sketch().snapshot(), sketch.sketch().snapshot());
+        assert_eq!(restored.quantile_duration(0.9).unwrap(), sketch.quantile_duration(0.9).unwrap());
+    }
+}
@@ -0,0 +1,736 @@
+//! Binary encoding of sketches
+//!
+//! This module provides a compact, self-describing binary format for sketches, used
+//! to ship them between processes and languages. All multi-byte fixed-width fields
+//! are little-endian.
+//!
+//! ```text
+//! magic       4 bytes   "DDSK"
+//! version     u8        2
+//! accuracy    f64       relative accuracy
+//! flags       u8        bit 0: min present, bit 1: max present
+//! min, max    f64       each only when present
+//! sum         f64
+//! sum_squares f64
+//! zero_count  varint
+//! positive    varint bin count, then per bin: zigzag varint index delta, varint count
+//! negative    same layout as positive
+//! extensions  to the end: per field, varint tag, varint length, then that many bytes
+//! ```
+//!
+//! Bins are written in ascending index order and each index is stored as the
+//! difference from the previous one, so dense bins take two or three bytes each.
+//! The encoding is canonical: whatever order values were added or sketches merged
+//! in, the same bins, counts and summary fields always encode to the same bytes.
+//!
+//! Decoding dispatches on the version byte, and every version that has been written
+//! stays readable:
+//!
+//! - Version 1 ends after the negative bins.
+//! - Version 2 adds the extension fields. Readers skip fields whose tag they do not
+//!   know, so new fields can be added without a version bump and older releases
+//!   still read the result. The version is only bumped for changes older readers
+//!   cannot skip.
+//!
+//! The extension fields defined so far are:
+//!
+//! ```text
+//! tag 1       f64       minimum indexable value, only written when set
+//! tag 2       u8 + 3 f64  mapping kind (0 logarithmic, 1 tail-biased), gamma, tail
+//!                       gamma and offset, only written when the mapping is not the
+//!                       logarithmic mapping of the relative accuracy
+//! ```
+//!
+//! Decoding into a sketch checks the mapping, so a sketch encoded with another kind
+//! of mapping is refused rather than read with the wrong bins.
+
+use crate::{DDSketch, DDSketchError, Result, SketchSnapshot};
+use crate::checkpoint::{read_checkpoint, MAGIC as CHECKPOINT_MAGIC};
+use crate::mapping::{IndexMapping, MappingDescriptor, MappingKind};
+use crate::hash::StableHasher;
+use crate::store::Store;
+use std::hash::Hasher;
+
+/// The bytes every encoded sketch starts with
+pub(crate) const MAGIC: &[u8; 4] = b"DDSK";
+
+/// The current version of the format
+pub const ENCODING_VERSION: u8 = 2;
+
+pub(crate) const FLAG_MIN: u8 = 1;
+pub(crate) const FLAG_MAX: u8 = 1 << 1;
+
+/// The extension field holding the minimum indexable value
+const TAG_MIN_INDEXABLE_VALUE: u64 = 1;
+
+/// The extension field holding the descriptor of a mapping other than the default
+pub(crate) const TAG_MAPPING: u64 = 2;
+
+/// The length of the mapping field: the kind and three parameters
+pub(crate) const MAPPING_FIELD_LEN: usize = 1 + 3 * 8;
+
+impl SketchSnapshot {
+    /// Encode the snapshot in the binary format
+    pub fn encode(&self) -> Vec<u8> {
+        let num_bins = self.positive_bins.len() + self.negative_bins.len();
+        let mut bytes = Vec::with_capacity(48 + 3 * num_bins);
+        
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(ENCODING_VERSION);
+        bytes.extend_from_slice(&self.relative_accuracy.to_le_bytes());
+        
+        let flags = if self.min_value.is_some() { FLAG_MIN } else { 0 }
+            | if self.max_value.is_some() { FLAG_MAX } else { 0 };
+        bytes.push(flags);
+        for value in self.min_value.iter().chain(&self.max_value) {
+            // Adding zero writes a zero extreme as +0.0, however it was reached
+            bytes.extend_from_slice(&(value + 0.0).to_le_bytes());
+        }
+        
+        bytes.extend_from_slice(&self.sum.to_le_bytes());
+        bytes.extend_from_slice(&self.sum_squares.to_le_bytes());
+        write_varint(&mut bytes, self.zero_count);
+        write_bins(&mut bytes, &self.positive_bins);
+        write_bins(&mut bytes, &self.negative_bins);
+        
+        if self.min_indexable_value != 0.0 {
+            write_varint(&mut bytes, TAG_MIN_INDEXABLE_VALUE);
+            write_varint(&mut bytes, 8);
+            bytes.extend_from_slice(&self.min_indexable_value.to_le_bytes());
+        }
+        if let Some(descriptor) = &self.mapping {
+            write_varint(&mut bytes, TAG_MAPPING);
+            write_varint(&mut bytes, MAPPING_FIELD_LEN as u64);
+            write_mapping(&mut bytes, descriptor);
+        }
+        
+        bytes
+    }
+    
+    /// Decode a snapshot from the binary format
+    ///
+    /// # Returns
+    /// The snapshot, or `DDSketchError::InvalidEncoding` if the bytes are truncated,
+    /// have trailing data, or were not produced by `encode`
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        let (version, mut snapshot) = read_summary(&mut reader)?;
+        snapshot.positive_bins = reader.bins()?;
+        snapshot.negative_bins = reader.bins()?;
+        read_extensions(&mut reader, version, |tag, field| {
+            if tag == TAG_MIN_INDEXABLE_VALUE {
+                let field: [u8; 8] = field.try_into().map_err(|_| DDSketchError::InvalidEncoding)?;
+                snapshot.min_indexable_value = f64::from_le_bytes(field);
+            } else if tag == TAG_MAPPING {
+                snapshot.mapping = Some(read_mapping(field)?);
+            }
+            Ok(())
+        })?;
+        
+        Ok(snapshot)
+    }
+}
+
+/// Read the magic bytes and a version this release can decode
+pub(crate) fn read_version(reader: &mut Reader) -> Result<u8> {
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(DDSketchError::InvalidEncoding);
+    }
+    match reader.byte()? {
+        version @ 1..=ENCODING_VERSION => Ok(version),
+        _ => Err(DDSketchError::InvalidEncoding),
+    }
+}
+
+/// Read the fields after the bins, which must use up the rest of the bytes
+///
+/// Each field's tag and bytes are passed to `field`, which ignores the tags it does
+/// not know.
+pub(crate) fn read_extensions(
+    reader: &mut Reader,
+    version: u8,
+    mut field: impl FnMut(u64, &[u8]) -> Result<()>,
+) -> Result<()> {
+    if version >= 2 {
+        while !reader.bytes.is_empty() {
+            let tag = reader.varint()?;
+            let len = usize::try_from(reader.varint()?).map_err(|_| DDSketchError::InvalidEncoding)?;
+            field(tag, reader.take(len)?)?;
+        }
+    }
+    
+    if !reader.bytes.is_empty() {
+        return Err(DDSketchError::InvalidEncoding);
+    }
+    Ok(())
+}
+
+/// Write a mapping descriptor as a mapping field
+pub(crate) fn write_mapping(bytes: &mut Vec<u8>, descriptor: &MappingDescriptor) {
+    bytes.push(match descriptor.kind {
+        MappingKind::Logarithmic => 0,
+        MappingKind::TailBiased => 1,
+    });
+    for parameter in [descriptor.gamma, descriptor.tail_gamma, descriptor.offset] {
+        bytes.extend_from_slice(&parameter.to_le_bytes());
+    }
+}
+
+/// Read the descriptor in a mapping field
+pub(crate) fn read_mapping(field: &[u8]) -> Result<MappingDescriptor> {
+    if field.len() != MAPPING_FIELD_LEN {
+        return Err(DDSketchError::InvalidEncoding);
+    }
+    let kind = match field[0] {
+        0 => MappingKind::Logarithmic,
+        1 => MappingKind::TailBiased,
+        _ => return Err(DDSketchError::InvalidEncoding),
+    };
+    let parameter = |position: usize| {
+        let start = 1 + 8 * position;
+        f64::from_le_bytes(field[start..start + 8].try_into().expect("the field length was checked"))
+    };
+    
+    Ok(MappingDescriptor { kind, gamma: parameter(0), tail_gamma: parameter(1), offset: parameter(2) })
+}
+
+/// Read everything before the bins, returning the version and a snapshot without bins
+fn read_summary(reader: &mut Reader) -> Result<(u8, SketchSnapshot)> {
+    let version = read_version(reader)?;
+    
+    let relative_accuracy = reader.f64()?;
+    let flags = reader.byte()?;
+    let min_value = if flags & FLAG_MIN != 0 { Some(reader.f64()?) } else { None };
+    let max_value = if flags & FLAG_MAX != 0 { Some(reader.f64()?) } else { None };
+    let sum = reader.f64()?;
+    let sum_squares = reader.f64()?;
+    let zero_count = reader.varint()?;
+    
+    Ok((version, SketchSnapshot {
+        relative_accuracy,
+        positive_bins: Vec::new(),
+        negative_bins: Vec::new(),
+        zero_count,
+        min_value,
+        max_value,
+        sum,
+        sum_squares,
+        min_indexable_value: 0.0,
+        mapping: None,
+    }))
+}
+
+impl<M: IndexMapping, S: Store> DDSketch<M, S> {
+    /// Encode the sketch in the binary format
+    pub fn encode(&self) -> Vec<u8> {
+        self.snapshot().encode()
+    }
+    
+    /// Get an upper bound on the length of `encode()`, without encoding
+    ///
+    /// The bound is exact except for the bin index deltas, which are counted at their
+    /// widest, so it exceeds the encoded length by at most four bytes per bin. Useful
+    /// for sizing buffers before writing many sketches.
+    pub fn serialized_size_hint(&self) -> usize {
+        let extremes = 8 * (self.min_value.is_some() as usize + self.max_value.is_some() as usize);
+        let bins = |store: &S| {
+            varint_len(store.num_bins() as u64)
+                + store.iter().map(|(_, count)| MAX_INDEX_DELTA_LEN + varint_len(count)).sum::<usize>()
+        };
+        
+        MAGIC.len() + 1 + 8 + 1 + extremes + 8 + 8
+            + varint_len(self.zero_count)
+            + bins(&self.store)
+            + bins(&self.negative_store)
+            + if self.min_indexable_value != 0.0 { 10 } else { 0 }
+            + if self.custom_mapping().is_some() { 2 + MAPPING_FIELD_LEN } else { 0 }
+    }
+    
+    /// Hash the distribution the sketch holds
+    ///
+    /// Covers the normalized mapping descriptor, the zero count, the extremes and
+    /// every bin in index order, with a fixed hash function, so sketches holding the
+    /// same distribution hash equally regardless of insertion or merge order, process
+    /// or platform. Useful for deduplicating and caching sketches by content.
+    ///
+    /// The sum and sum of squares are left out: floating-point addition rounds
+    /// differently depending on the order values arrive in, so they would tell equal
+    /// distributions apart.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        let descriptor = self.mapping.descriptor().normalized();
+        hasher.write_u8(descriptor.kind as u8);
+        for parameter in [descriptor.gamma, descriptor.tail_gamma, descriptor.offset] {
+            hasher.write(&parameter.to_le_bytes());
+        }
+        
+        hasher.write(&self.zero_count.to_le_bytes());
+        for extreme in [self.min_value, self.max_value] {
+            // Adding zero hashes a zero extreme as +0.0, however it was reached
+            hasher.write(&extreme.map_or([0xff; 8], |value| (value + 0.0).to_le_bytes()));
+        }
+        for store in [&self.negative_store, &self.store] {
+            let bins = store.to_vec();
+            hasher.write(&(bins.len() as u64).to_le_bytes());
+            for (index, count) in bins {
+                hasher.write(&index.to_le_bytes());
+                hasher.write(&count.to_le_bytes());
+            }
+        }
+        hasher.finish()
+    }
+    
+    /// Merge an encoded sketch into this one without building a sketch from it first
+    ///
+    /// The bytes are decoded and validated as `decode` does, and every store is
+    /// checked to accept its bins before any is changed, so on a malformed or
+    /// incompatible encoding, or one that does not fit, the sketch is left unchanged.
+    ///
+    /// # Arguments
+    /// * `bytes` - A sketch encoded with `encode`
+    ///
+    /// # Returns
+    /// `DDSketchError::InvalidEncoding` if the bytes are not a valid encoding,
+    /// `DDSketchError::IncompatibleSketches` if the encoded sketch has a different
+    /// mapping, or an error if the merged counts cannot be held
+    pub fn merge_encoded(&mut self, bytes: &[u8]) -> Result<()> {
+        let snapshot = SketchSnapshot::decode(bytes)?;
+        let theirs = snapshot.mapping_descriptor().map_err(|_| DDSketchError::InvalidEncoding)?;
+        if !(snapshot.min_indexable_value.is_finite() && snapshot.min_indexable_value >= 0.0) {
+            return Err(DDSketchError::InvalidEncoding);
+        }
+        self.mapping.descriptor().check_mergeable(&theirs)?;
+        
+        let total = snapshot.positive_bins.iter()
+            .chain(&snapshot.negative_bins)
+            .try_fold(snapshot.zero_count, |total, &(_, count)| total.checked_add(count));
+        self.reserve_count(total.ok_or(DDSketchError::CountOverflow)?)?;
+        self.store.check_add(&mut snapshot.positive_bins.iter().copied())?;
+        self.negative_store.check_add(&mut snapshot.negative_bins.iter().copied())?;
+        
+        self.mark_modified();
+        for &(index, count) in &snapshot.positive_bins {
+            self.store.add(index, count);
+        }
+        for &(index, count) in &snapshot.negative_bins {
+            self.negative_store.add(index, count);
+        }
+        
+        self.zero_count += snapshot.zero_count;
+        self.sum += snapshot.sum;
+        self.sum_squares += snapshot.sum_squares;
+        if let Some(other_min) = snapshot.min_value {
+            self.min_value = Some(self.min_value.map_or(other_min, |min| min.min(other_min)));
+        }
+        if let Some(other_max) = snapshot.max_value {
+            self.max_value = Some(self.max_value.map_or(other_max, |max| max.max(other_max)));
+        }
+        self.fill_missing_min_max();
+        
+        Ok(())
+    }
+}
+
+impl<M: IndexMapping> DDSketch<M> {
+    /// Decode a sketch from the binary format, indexing its bins with a given mapping
+    ///
+    /// # Arguments
+    /// * `mapping` - The mapping the sketch was encoded with
+    /// * `bytes` - A sketch encoded with `encode`
+    ///
+    /// # Returns
+    /// The sketch, an error if the bytes are not a valid encoding, or
+    /// `DDSketchError::IncompatibleSketches` if they were encoded with a different mapping
+    pub fn decode_with_mapping(mapping: M, bytes: &[u8]) -> Result<Self> {
+        DDSketch::from_snapshot_with_mapping(mapping, &SketchSnapshot::decode(bytes)?)
+    }
+}
+
+impl DDSketch {
+    /// Decode a sketch from the binary format
+    ///
+    /// # Returns
+    /// The sketch, an error if the bytes are not a valid encoding, or
+    /// `DDSketchError::IncompatibleSketches` if the sketch was encoded with a mapping
+    /// other than the logarithmic one; use `decode_with_mapping` for those
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        DDSketch::from_snapshot(&SketchSnapshot::decode(bytes)?)
+    }
+    
+    /// Decode a sketch from any layout this crate has written
+    ///
+    /// Accepts every version of the binary format, as `decode` does, and checkpoint
+    /// files written by `save_to`, so stored blobs can be read without knowing how
+    /// they were written. Older layouts are upgraded to the current sketch.
+    ///
+    /// # Returns
+    /// The sketch, or `DDSketchError::InvalidEncoding` if the bytes are in no known
+    /// layout
+    pub fn decode_any(bytes: &[u8]) -> Result<Self> {
+        if bytes.starts_with(CHECKPOINT_MAGIC) {
+            return DDSketch::from_snapshot(&read_checkpoint(bytes)?);
+        }
+        DDSketch::decode(bytes)
+    }
+}
+
+/// The most bytes a zigzag-encoded difference of two `i32` indices takes as a varint
+const MAX_INDEX_DELTA_LEN: usize = 5;
+
+/// Get the number of bytes `write_varint` takes for a value
+pub(crate) fn varint_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+/// Write an unsigned LEB128 varint
+pub(crate) fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Write bins sorted by index as index deltas and counts
+pub(crate) fn write_bins(bytes: &mut Vec<u8>, bins: &[(i32, u64)]) {
+    write_varint(bytes, bins.len() as u64);
+    
+    let mut previous = 0i64;
+    for &(index, count) in bins {
+        let delta = index as i64 - previous;
+        write_varint(bytes, ((delta << 1) ^ (delta >> 63)) as u64);
+        write_varint(bytes, count);
+        previous = index as i64;
+    }
+}
+
+/// A cursor over the bytes left to decode
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(DDSketchError::InvalidEncoding);
+        }
+        
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+    
+    pub(crate) fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    
+    pub(crate) fn f64(&mut self) -> Result<f64> {
+        let bytes = self.take(8)?;
+        Ok(f64::from_le_bytes(bytes.try_into().expect("took eight bytes")))
+    }
+    
+    pub(crate) fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DDSketchError::InvalidEncoding)
+    }
+    
+    pub(crate) fn bins(&mut self) -> Result<Vec<(i32, u64)>> {
+        let mut bins = Vec::new();
+        self.for_each_bin(|index, count| {
+            bins.push((index, count));
+            Ok(())
+        })?;
+        Ok(bins)
+    }
+    
+    /// Read a bin list, passing each bin to `f` as it is decoded
+    pub(crate) fn for_each_bin(&mut self, mut f: impl FnMut(i32, u64) -> Result<()>) -> Result<()> {
+        let len = self.varint()?;
+        // Every bin takes at least two bytes, which bounds the work for hostile input
+        if len > self.bytes.len() as u64 / 2 {
+            return Err(DDSketchError::InvalidEncoding);
+        }
+        
+        let mut previous = 0i64;
+        for _ in 0..len {
+            let zigzag = self.varint()?;
+            let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            let index = previous.checked_add(delta)
+                .and_then(|index| i32::try_from(index).ok())
+                .ok_or(DDSketchError::InvalidEncoding)?;
+            f(index, self.varint()?)?;
+            previous = index as i64;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CollapsePolicy, TailBiasedMapping};
+    use crate::mapping::LogarithmicMapping;
+    
+    #[test]
+    fn test_encoding_round_trip() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        for i in -500..=2000 {
+            sketch.add(i as f64 * 0.37);
+        }
+        sketch.add_with_count(1e12, 1 << 40);
+        
+        let bytes = sketch.encode();
+        assert_eq!(&bytes[..4], b"DDSK");
+        
+        let decoded = DDSketch::decode(&bytes).unwrap();
+        assert_eq!(decoded.snapshot(), sketch.snapshot());
+        
+        let empty = DDSketch::new(0.05).unwrap();
+        assert_eq!(DDSketch::decode(&empty.encode()).unwrap().snapshot(), empty.snapshot());
+    }
+    
+    #[test]
+    fn test_encoding_rejects_malformed_input() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        sketch.add(3.0);
+        let bytes = sketch.encode();
+        
+        for len in 0..bytes.len() {
+            assert_eq!(SketchSnapshot::decode(&bytes[..len]), Err(DDSketchError::InvalidEncoding));
+        }
+        
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(SketchSnapshot::decode(&trailing), Err(DDSketchError::InvalidEncoding));
+        
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 99;
+        assert_eq!(SketchSnapshot::decode(&bad_version), Err(DDSketchError::InvalidEncoding));
+        
+        let mut truncated_extension = bytes.clone();
+        truncated_extension.extend_from_slice(&[1, 5, 0]);
+        assert_eq!(SketchSnapshot::decode(&truncated_extension), Err(DDSketchError::InvalidEncoding));
+    }
+    
+    /// A sketch of 0.0 and 1.0 at 1% relative accuracy, as version 1 wrote it
+    const VERSION_1: [u8; 51] = [
+        0x44, 0x44, 0x53, 0x4b, 0x01, 0x7b, 0x14, 0xae, 0x47, 0xe1, 0x7a, 0x84, 0x3f, 0x03, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, 0x01, 0x01,
+        0x00, 0x01, 0x00,
+    ];
+    
+    #[test]
+    fn test_decode_historical_versions() {
+        let mut expected = DDSketch::new(0.01).unwrap();
+        expected.add(0.0);
+        expected.add(1.0);
+        
+        // Version 1 has no extension fields and nothing may follow the bins
+        let version_1 = DDSketch::decode(&VERSION_1).unwrap();
+        assert_eq!(version_1.snapshot(), expected.snapshot());
+        let mut trailing = VERSION_1.to_vec();
+        trailing.push(0);
+        assert_eq!(DDSketch::decode(&trailing).unwrap_err(), DDSketchError::InvalidEncoding);
+        
+        // Version 2 is version 1 plus extension fields, which are skipped when unknown
+        let mut version_2 = VERSION_1.to_vec();
+        version_2[4] = 2;
+        assert_eq!(expected.encode(), version_2);
+        version_2.extend_from_slice(&[0x7f, 3, b'a', b'b', b'c', 0x80, 0x01, 0]);
+        assert_eq!(DDSketch::decode(&version_2).unwrap().snapshot(), expected.snapshot());
+        assert_eq!(crate::DDSketchView::new(&version_2).unwrap().count(), 2);
+        
+        let mut merged = DDSketch::new(0.01).unwrap();
+        merged.merge_encoded(&VERSION_1).unwrap();
+        merged.merge_encoded(&version_2).unwrap();
+        assert_eq!(merged.count(), 4);
+    }
+    
+    #[test]
+    fn test_decode_any() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        for i in 1..=100 {
+            sketch.add(i as f64);
+        }
+        
+        let path = std::env::temp_dir().join(format!("ddsketch-decode-any-{}", std::process::id()));
+        sketch.save_to(&path).unwrap();
+        let checkpoint = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        
+        assert_eq!(DDSketch::decode_any(&checkpoint).unwrap().snapshot(), sketch.snapshot());
+        assert_eq!(DDSketch::decode_any(&sketch.encode()).unwrap().snapshot(), sketch.snapshot());
+        assert_eq!(DDSketch::decode_any(&VERSION_1).unwrap().count(), 2);
+        assert_eq!(DDSketch::decode_any(b"DDXX").unwrap_err(), DDSketchError::InvalidEncoding);
+    }
+    
+    #[test]
+    fn test_merge_encoded() {
+        let mut left = DDSketch::new(0.01).unwrap();
+        let mut right = DDSketch::new(0.01).unwrap();
+        for i in -300..=1000 {
+            left.add(i as f64 * 0.5);
+            right.add(i as f64 * 3.0 + 7.0);
+        }
+        right.add(0.0);
+        
+        let mut expected = left.clone();
+        expected.merge(&right).unwrap();
+        left.merge_encoded(&right.encode()).unwrap();
+        assert_eq!(left.snapshot(), expected.snapshot());
+        
+        let mut empty = DDSketch::new(0.01).unwrap();
+        empty.merge_encoded(&right.encode()).unwrap();
+        assert_eq!(empty.snapshot(), right.snapshot());
+        
+        // Malformed or incompatible input leaves the sketch unchanged
+        let bytes = right.encode();
+        assert_eq!(left.merge_encoded(&bytes[..bytes.len() - 1]), Err(DDSketchError::InvalidEncoding));
+        let coarse = DDSketch::new(0.05).unwrap();
+        assert!(matches!(
+            left.merge_encoded(&coarse.encode()),
+            Err(DDSketchError::IncompatibleSketches { .. })
+        ));
+        assert_eq!(left.snapshot(), expected.snapshot());
+        
+        // A threshold that `decode` would refuse is refused here too
+        let mut thresholded = right.clone();
+        thresholded.set_min_indexable_value(0.5).unwrap();
+        let mut bytes = thresholded.encode();
+        let field = bytes.len() - 8;
+        bytes[field..].copy_from_slice(&f64::NAN.to_le_bytes());
+        assert!(DDSketch::decode(&bytes).is_err());
+        assert_eq!(left.merge_encoded(&bytes), Err(DDSketchError::InvalidEncoding));
+        assert_eq!(left.snapshot(), expected.snapshot());
+    }
+    
+    #[test]
+    fn test_merge_encoded_bin_limit() {
+        let mut strict = DDSketch::with_max_bins(0.01, 8, CollapsePolicy::Error).unwrap();
+        for value in [-3.0, -2.0, 1.0, 2.0] {
+            strict.add(value);
+        }
+        let before = strict.snapshot();
+        
+        // The negative bins fit but the positive ones do not, so neither is merged
+        let mut other = DDSketch::new(0.01).unwrap();
+        other.add(-4.0);
+        for i in 0..20 {
+            other.add(10.0 + i as f64);
+        }
+        assert_eq!(strict.merge_encoded(&other.encode()), Err(DDSketchError::BinLimitExceeded));
+        assert_eq!(strict.snapshot(), before);
+        
+        let mut collapsing = DDSketch::with_max_bins(0.01, 8, CollapsePolicy::Error).unwrap();
+        collapsing.merge(&strict).unwrap();
+        assert_eq!(collapsing.merge(&other), Err(DDSketchError::BinLimitExceeded));
+        assert_eq!(collapsing.snapshot(), before);
+    }
+    
+    #[test]
+    fn test_serialized_size_hint() {
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        assert_eq!(sketch.serialized_size_hint(), sketch.encode().len());
+        
+        for i in -500..=2000 {
+            sketch.add(i as f64 * 0.37);
+        }
+        sketch.add_with_count(1e12, 1 << 40);
+        let (hint, len) = (sketch.serialized_size_hint(), sketch.encode().len());
+        assert!(hint >= len && hint <= len + 4 * sketch.num_bins(), "hint={} len={}", hint, len);
+        
+        for value in [0, 1, 127, 128, 16383, 16384, u64::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            assert_eq!(varint_len(value), bytes.len());
+        }
+    }
+    
+    #[test]
+    fn test_content_hash() {
+        let values: Vec<f64> = (0..500).map(|i| ((i * 7919) % 1000) as f64 / 7.0 - 20.0).collect();
+        let mut inserted = DDSketch::new(0.01).unwrap();
+        for &value in &values {
+            inserted.add(value);
+        }
+        
+        // The same values, added in reverse into two sketches that are then merged
+        let (mut first, mut second) = (DDSketch::new(0.01).unwrap(), DDSketch::new(0.01).unwrap());
+        for (i, &value) in values.iter().rev().enumerate() {
+            if i % 3 == 0 { first.add(value) } else { second.add(value) }
+        }
+        second.merge(&first).unwrap();
+        
+        assert_eq!(second.content_hash(), inserted.content_hash());
+        assert_eq!(DDSketch::decode(&inserted.encode()).unwrap().content_hash(), inserted.content_hash());
+        assert_eq!(DDSketch::decode(&second.encode()).unwrap().encode(), second.encode());
+        
+        let mut negative_zero = DDSketch::new(0.01).unwrap();
+        negative_zero.add(-0.0);
+        let mut zero = DDSketch::new(0.01).unwrap();
+        zero.add(0.0);
+        assert_eq!(negative_zero.encode(), zero.encode());
+        assert_eq!(negative_zero.content_hash(), zero.content_hash());
+        
+        second.add(1.0);
+        assert_ne!(second.content_hash(), inserted.content_hash());
+        assert_ne!(DDSketch::new(0.02).unwrap().content_hash(), DDSketch::new(0.01).unwrap().content_hash());
+        
+        // A descriptor rebuilt from its gamma differs only in bits that normalizing rounds away
+        let descriptor = LogarithmicMapping::new(0.01).unwrap().descriptor();
+        let rebuilt = LogarithmicMapping::new(descriptor.gamma - 1.0 + 1e-15).unwrap().descriptor();
+        assert_eq!(rebuilt.normalized(), descriptor.normalized());
+    }
+    
+    #[test]
+    fn test_tail_biased_round_trip() {
+        let mut sketch = DDSketch::builder(0.05).tail_biased(0.001, 500.0).build().unwrap();
+        for value in 1..=1000 {
+            sketch.add(value as f64);
+        }
+        let bytes = sketch.encode();
+        assert!(bytes.len() <= sketch.serialized_size_hint());
+        
+        // The descriptor travels with the bins, and the default decoder refuses it
+        let snapshot = SketchSnapshot::decode(&bytes).unwrap();
+        let descriptor = snapshot.mapping.unwrap();
+        assert_eq!(descriptor.kind, MappingKind::TailBiased);
+        assert!(matches!(DDSketch::decode(&bytes), Err(DDSketchError::IncompatibleSketches { .. })));
+        assert!(matches!(DDSketch::from_snapshot(&sketch.snapshot()), Err(DDSketchError::IncompatibleSketches { .. })));
+        assert!(matches!(DDSketch::new(0.05).unwrap().merge_encoded(&bytes), Err(DDSketchError::IncompatibleSketches { .. })));
+        
+        let mapping = TailBiasedMapping::from_descriptor(&descriptor).unwrap();
+        let decoded = DDSketch::decode_with_mapping(mapping.clone(), &bytes).unwrap();
+        for quantile in [0.1, 0.5, 0.9, 0.99] {
+            let (expected, actual) = (sketch.get_quantile_value(quantile).unwrap(), decoded.get_quantile_value(quantile).unwrap());
+            assert!((actual - expected).abs() <= expected * 1e-12, "{} {}", actual, expected);
+        }
+        assert_eq!(decoded, sketch);
+        
+        let mut merged = DDSketch::decode_with_mapping(mapping, &bytes).unwrap();
+        merged.merge_encoded(&bytes).unwrap();
+        assert_eq!(merged.count(), 2000);
+        
+        let other_tail = DDSketch::decode_with_mapping(TailBiasedMapping::new(0.05, 0.002, 500.0).unwrap(), &bytes);
+        assert!(matches!(other_tail, Err(DDSketchError::IncompatibleSketches { .. })));
+        
+        // Sketches of the same values under different tails are not equal
+        let mut coarser_tail = DDSketch::builder(0.05).tail_biased(0.002, 500.0).build().unwrap();
+        let mut same_tail = DDSketch::builder(0.05).tail_biased(0.001, 500.0).build().unwrap();
+        for sketch in [&mut coarser_tail, &mut same_tail] {
+            sketch.add(1000.0);
+        }
+        let mut reference = DDSketch::builder(0.05).tail_biased(0.001, 500.0).build().unwrap();
+        reference.add(1000.0);
+        assert_eq!(same_tail, reference);
+        assert_ne!(coarser_tail, reference);
+    }
+}
@@ -0,0 +1,282 @@
+//! Exact answers for small sketches
+//!
+//! This module provides a sketch that keeps the raw values while there are few of
+//! them and answers quantiles exactly, switching to the binned representation once
+//! a threshold is crossed. Most per-key sketches in a large workload stay small, and
+//! for those the answers carry no approximation error at all.
+
+use crate::{DDSketch, DDSketchError, IntoSketchValue, QuantileInterpolation, Result};
+
+/// A sketch that is exact until it holds more than a threshold of values
+///
+/// While in exact mode, values and their counts are buffered, and quantiles are
+/// taken from the sorted values with the same rank convention as `DDSketch`, so
+/// answers only move by the sketch's accuracy when it switches. Once the total
+/// count exceeds the threshold, or on any merge, the buffer is flushed into the
+/// underlying `DDSketch` and the sketch stays binned until it is cleared.
+///
+/// Non-finite values are passed straight to the underlying sketch's policy.
+#[derive(Debug, Clone)]
+pub struct ExactModeDDSketch {
+    sketch: DDSketch,
+    /// The buffered values and counts, or `None` once binned
+    buffer: Option<Vec<(f64, u64)>>,
+    buffered_count: u64,
+    exact_threshold: u64,
+}
+
+impl ExactModeDDSketch {
+    /// Create a new sketch in exact mode
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy once binned (between 0 and 1)
+    /// * `exact_threshold` - The largest count that is answered exactly
+    ///
+    /// # Returns
+    /// A new ExactModeDDSketch, or an error if the relative accuracy is invalid
+    pub fn new(relative_accuracy: f64, exact_threshold: u64) -> Result<Self> {
+        Ok(ExactModeDDSketch {
+            sketch: DDSketch::new(relative_accuracy)?,
+            buffer: Some(Vec::new()),
+            buffered_count: 0,
+            exact_threshold,
+        })
+    }
+    
+    /// Check whether the sketch still answers exactly
+    pub fn is_exact(&self) -> bool {
+        self.buffer.is_some()
+    }
+    
+    /// Get the largest count that is answered exactly
+    pub fn exact_threshold(&self) -> u64 {
+        self.exact_threshold
+    }
+    
+    /// Get the relative accuracy once binned
+    pub fn relative_accuracy(&self) -> f64 {
+        self.sketch.relative_accuracy()
+    }
+    
+    /// Get the interpolation mode used for quantiles, in both modes
+    pub fn quantile_interpolation(&self) -> QuantileInterpolation {
+        self.sketch.quantile_interpolation()
+    }
+    
+    /// Set the interpolation mode used for quantiles, in both modes
+    ///
+    /// In exact mode, `Linear` interpolates between the two surrounding values and
+    /// every other mode returns the value at the rank.
+    pub fn set_quantile_interpolation(&mut self, interpolation: QuantileInterpolation) {
+        self.sketch.set_quantile_interpolation(interpolation);
+    }
+    
+    /// Add a value to the sketch
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        self.add_with_count(value, 1);
+    }
+    
+    /// Add a value with a specific count to the sketch
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&mut self, value: impl IntoSketchValue, count: u64) {
+        let value = value.into_sketch_value();
+        match &mut self.buffer {
+            Some(buffer) if value.is_finite() && count > 0 => {
+                buffer.push((value, count));
+                self.buffered_count += count;
+                if self.buffered_count > self.exact_threshold {
+                    self.flush();
+                }
+            }
+            _ => self.sketch.add_with_count(value, count),
+        }
+    }
+    
+    /// Get the total count of values in the sketch
+    pub fn count(&self) -> u64 {
+        self.buffered_count + self.sketch.count()
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+    
+    /// Get the minimum value in the sketch
+    pub fn min(&self) -> Option<f64> {
+        match &self.buffer {
+            Some(buffer) => buffer.iter().map(|&(value, _)| value).reduce(f64::min),
+            None => self.sketch.min(),
+        }
+    }
+    
+    /// Get the maximum value in the sketch
+    pub fn max(&self) -> Option<f64> {
+        match &self.buffer {
+            Some(buffer) => buffer.iter().map(|&(value, _)| value).reduce(f64::max),
+            None => self.sketch.max(),
+        }
+    }
+    
+    /// Get the value at a given quantile, exactly while in exact mode
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        let Some(buffer) = &self.buffer else {
+            return self.sketch.get_quantile_value(quantile);
+        };
+        
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        if self.buffered_count == 0 {
+            return Err(DDSketchError::EmptySketch);
+        }
+        
+        let mut sorted = buffer.clone();
+        sorted.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        let value_at_rank = |rank: u64| {
+            let mut cumulative = 0;
+            for &(value, count) in &sorted {
+                cumulative += count;
+                if rank < cumulative {
+                    return value;
+                }
+            }
+            sorted[sorted.len() - 1].0
+        };
+        
+        let total_count = self.buffered_count;
+        Ok(match self.sketch.quantile_interpolation() {
+            QuantileInterpolation::Linear => {
+                let rank = quantile * (total_count - 1) as f64;
+                let low = value_at_rank(rank.floor() as u64);
+                let high = value_at_rank(rank.ceil() as u64);
+                low + (rank - rank.floor()) * (high - low)
+            }
+            _ => value_at_rank((quantile * total_count as f64) as u64),
+        })
+    }
+    
+    /// Merge another sketch into this one, switching both to the binned representation
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to merge
+    ///
+    /// # Returns
+    /// An error if the sketches have different relative accuracies
+    pub fn merge(&mut self, other: &ExactModeDDSketch) -> Result<()> {
+        self.merge_sketch(&other.to_sketch())
+    }
+    
+    /// Merge a binned sketch into this one, switching to the binned representation
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to merge
+    ///
+    /// # Returns
+    /// An error if the sketches have different relative accuracies
+    pub fn merge_sketch(&mut self, other: &DDSketch) -> Result<()> {
+        if (self.relative_accuracy() - other.relative_accuracy()).abs() > 1e-10 {
+            return Err(DDSketchError::different_accuracy(self.relative_accuracy(), other.relative_accuracy()));
+        }
+        
+        self.flush();
+        self.sketch.merge(other)
+    }
+    
+    /// Switch to the binned representation
+    pub fn flush(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            for (value, count) in buffer {
+                self.sketch.add_with_count(value, count);
+            }
+            self.buffered_count = 0;
+        }
+    }
+    
+    /// Get a binned copy of the sketch
+    pub fn to_sketch(&self) -> DDSketch {
+        let mut sketch = self.clone();
+        sketch.flush();
+        sketch.sketch
+    }
+    
+    /// Convert into the binned sketch
+    pub fn into_sketch(mut self) -> DDSketch {
+        self.flush();
+        self.sketch
+    }
+    
+    /// Clear all data and return to exact mode
+    pub fn clear(&mut self) {
+        self.sketch.clear();
+        self.buffer = Some(Vec::new());
+        self.buffered_count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_exact_mode_answers_exactly() {
+        let mut sketch = ExactModeDDSketch::new(0.02, 1000).unwrap();
+        for value in [103.0, 101.0, 102.0, 100.0] {
+            sketch.add(value);
+        }
+        
+        assert!(sketch.is_exact());
+        assert_eq!(sketch.count(), 4);
+        assert_eq!((sketch.min(), sketch.max()), (Some(100.0), Some(103.0)));
+        assert_eq!(sketch.get_quantile_value(0.0).unwrap(), 100.0);
+        assert_eq!(sketch.get_quantile_value(0.5).unwrap(), 102.0);
+        assert_eq!(sketch.get_quantile_value(1.0).unwrap(), 103.0);
+        
+        sketch.set_quantile_interpolation(QuantileInterpolation::Linear);
+        assert_eq!(sketch.get_quantile_value(0.5).unwrap(), 101.5);
+        assert_eq!(sketch.get_quantile_value(1.5), Err(DDSketchError::InvalidQuantile { got: 1.5 }));
+        
+        // The binned answers agree within the accuracy
+        let binned = sketch.to_sketch();
+        assert!((binned.get_quantile_value(0.5).unwrap() - 101.5).abs() <= 101.5 * 0.02);
+        assert!(sketch.is_exact());
+    }
+    
+    #[test]
+    fn test_exact_mode_switches() {
+        let mut sketch = ExactModeDDSketch::new(0.02, 10).unwrap();
+        assert_eq!(sketch.get_quantile_value(0.5), Err(DDSketchError::EmptySketch));
+        
+        sketch.add_with_count(5.0, 10);
+        assert!(sketch.is_exact());
+        sketch.add(7.0);
+        assert!(!sketch.is_exact());
+        assert_eq!(sketch.count(), 11);
+        assert_eq!(sketch.max(), Some(7.0));
+        
+        let mut other = ExactModeDDSketch::new(0.02, 10).unwrap();
+        other.add(1.0);
+        let mut small = ExactModeDDSketch::new(0.02, 10).unwrap();
+        small.merge(&other).unwrap();
+        assert!(!small.is_exact());
+        assert_eq!(small.count(), 1);
+        
+        let incompatible = ExactModeDDSketch::new(0.05, 10).unwrap();
+        assert!(matches!(sketch.merge(&incompatible), Err(DDSketchError::IncompatibleSketches { .. })));
+        
+        sketch.clear();
+        assert!(sketch.is_exact() && sketch.is_empty());
+    }
+}
@@ -0,0 +1,314 @@
+//! C FFI bindings
+//!
+//! This module exposes the default sketch through `extern "C"` functions so that
+//! C, C++ and Go (through cgo) services can use it and exchange sketches in the
+//! format of the `encoding` module. The matching declarations are in
+//! `include/ddsketch.h`.
+//!
+//! `cargo build --release --features ffi` builds a shared library and a static one,
+//! `libddsketch_rs.so` (`.dylib`, or `ddsketch_rs.dll` on Windows) and
+//! `libddsketch_rs.a`, in `target/release`.
+//!
+//! Sketches are opaque pointers owned by the caller, who must release them with
+//! `ddsketch_free`. Functions that can fail return one of the `DDSKETCH_*` status codes.
+
+use crate::{DDSketch, DDSketchError};
+use std::os::raw::c_int;
+use std::ptr;
+use std::slice;
+
+/// The operation succeeded
+pub const DDSKETCH_OK: c_int = 0;
+/// A required pointer argument was null
+pub const DDSKETCH_ERR_NULL_POINTER: c_int = -1;
+/// The relative accuracy was invalid
+pub const DDSKETCH_ERR_INVALID_RELATIVE_ACCURACY: c_int = -2;
+/// The quantile was outside [0, 1]
+pub const DDSKETCH_ERR_INVALID_QUANTILE: c_int = -3;
+/// The sketch was empty
+pub const DDSKETCH_ERR_EMPTY_SKETCH: c_int = -4;
+/// The sketches could not be merged
+pub const DDSKETCH_ERR_INCOMPATIBLE_SKETCHES: c_int = -5;
+/// Any other error
+pub const DDSKETCH_ERR_OTHER: c_int = -6;
+
+/// Map an error to its status code
+fn status_code(error: &DDSketchError) -> c_int {
+    match error {
+        DDSketchError::InvalidRelativeAccuracy => DDSKETCH_ERR_INVALID_RELATIVE_ACCURACY,
+        DDSketchError::InvalidQuantile { .. } => DDSKETCH_ERR_INVALID_QUANTILE,
+        DDSketchError::EmptySketch => DDSKETCH_ERR_EMPTY_SKETCH,
+        DDSketchError::IncompatibleSketches { .. } => DDSKETCH_ERR_INCOMPATIBLE_SKETCHES,
+        _ => DDSKETCH_ERR_OTHER,
+    }
+}
+
+/// Create a new sketch
+///
+/// Returns null if the relative accuracy is invalid.
+#[no_mangle]
+pub extern "C" fn ddsketch_new(relative_accuracy: f64) -> *mut DDSketch {
+    match DDSketch::new(relative_accuracy) {
+        Ok(sketch) => Box::into_raw(Box::new(sketch)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Release a sketch
+///
+/// # Safety
+/// `sketch` must be null or a pointer returned by this library that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_free(sketch: *mut DDSketch) {
+    if !sketch.is_null() {
+        drop(Box::from_raw(sketch));
+    }
+}
+
+/// Add a value to a sketch
+///
+/// # Safety
+/// `sketch` must be null or a valid sketch pointer not used concurrently.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_add(sketch: *mut DDSketch, value: f64) -> c_int {
+    ddsketch_add_with_count(sketch, value, 1)
+}
+
+/// Add a value with a specific count to a sketch
+///
+/// # Safety
+/// `sketch` must be null or a valid sketch pointer not used concurrently.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_add_with_count(sketch: *mut DDSketch, value: f64, count: u64) -> c_int {
+    let Some(sketch) = sketch.as_mut() else {
+        return DDSKETCH_ERR_NULL_POINTER;
+    };
+    
+    match sketch.try_add_with_count(value, count) {
+        Ok(()) => DDSKETCH_OK,
+        Err(error) => status_code(&error),
+    }
+}
+
+/// Merge `other` into `sketch`
+///
+/// # Safety
+/// Both pointers must be null or valid sketch pointers, and must not be the same sketch.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_merge(sketch: *mut DDSketch, other: *const DDSketch) -> c_int {
+    let (Some(sketch), Some(other)) = (sketch.as_mut(), other.as_ref()) else {
+        return DDSKETCH_ERR_NULL_POINTER;
+    };
+    
+    match sketch.merge(other) {
+        Ok(()) => DDSKETCH_OK,
+        Err(error) => status_code(&error),
+    }
+}
+
+/// Write the value at a quantile to `out`
+///
+/// # Safety
+/// `sketch` must be null or a valid sketch pointer, and `out` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_quantile(sketch: *const DDSketch, quantile: f64, out: *mut f64) -> c_int {
+    let (Some(sketch), false) = (sketch.as_ref(), out.is_null()) else {
+        return DDSKETCH_ERR_NULL_POINTER;
+    };
+    
+    match sketch.get_quantile_value(quantile) {
+        Ok(value) => {
+            *out = value;
+            DDSKETCH_OK
+        }
+        Err(error) => status_code(&error),
+    }
+}
+
+/// Get the number of values in a sketch, or zero for a null pointer
+///
+/// # Safety
+/// `sketch` must be null or a valid sketch pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_count(sketch: *const DDSketch) -> u64 {
+    sketch.as_ref().map_or(0, |sketch| sketch.count())
+}
+
+/// Serialize a sketch, writing the length of the returned buffer to `len`
+///
+/// The buffer must be released with `ddsketch_bytes_free`. Returns null on a null argument.
+///
+/// # Safety
+/// `sketch` must be null or a valid sketch pointer, and `len` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_serialize(sketch: *const DDSketch, len: *mut usize) -> *mut u8 {
+    let (Some(sketch), false) = (sketch.as_ref(), len.is_null()) else {
+        return ptr::null_mut();
+    };
+    
+    let bytes = sketch.encode().into_boxed_slice();
+    *len = bytes.len();
+    Box::into_raw(bytes) as *mut u8
+}
+
+/// Release a buffer returned by `ddsketch_serialize`
+///
+/// # Safety
+/// `bytes` must be null or a buffer returned by `ddsketch_serialize` together with its length.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_bytes_free(bytes: *mut u8, len: usize) {
+    if !bytes.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(bytes, len)));
+    }
+}
+
+/// Deserialize a sketch
+///
+/// Returns null if the bytes are not a valid encoding.
+///
+/// # Safety
+/// `bytes` must be null or point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_deserialize(bytes: *const u8, len: usize) -> *mut DDSketch {
+    if bytes.is_null() {
+        return ptr::null_mut();
+    }
+    
+    match DDSketch::decode(slice::from_raw_parts(bytes, len)) {
+        Ok(sketch) => Box::into_raw(Box::new(sketch)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_ffi_lifecycle() {
+        unsafe {
+            assert!(ddsketch_new(0.0).is_null());
+            
+            let sketch = ddsketch_new(0.02);
+            let other = ddsketch_new(0.02);
+            for i in 1..=100 {
+                assert_eq!(ddsketch_add(sketch, i as f64), DDSKETCH_OK);
+            }
+            assert_eq!(ddsketch_add_with_count(other, 500.0, 10), DDSKETCH_OK);
+            assert_eq!(ddsketch_merge(sketch, other), DDSKETCH_OK);
+            assert_eq!(ddsketch_count(sketch), 110);
+            
+            let mut value = 0.0;
+            assert_eq!(ddsketch_quantile(sketch, 1.0, &mut value), DDSKETCH_OK);
+            assert_eq!(value, 500.0);
+            assert_eq!(ddsketch_quantile(sketch, 2.0, &mut value), DDSKETCH_ERR_INVALID_QUANTILE);
+            assert_eq!(ddsketch_quantile(ptr::null(), 0.5, &mut value), DDSKETCH_ERR_NULL_POINTER);
+            
+            ddsketch_free(sketch);
+            ddsketch_free(other);
+            ddsketch_free(ptr::null_mut());
+        }
+    }
+    
+    #[test]
+    fn test_ffi_serialization() {
+        unsafe {
+            let sketch = ddsketch_new(0.02);
+            ddsketch_add(sketch, 42.0);
+            
+            let mut len = 0;
+            let bytes = ddsketch_serialize(sketch, &mut len);
+            assert!(!bytes.is_null());
+            
+            let restored = ddsketch_deserialize(bytes, len);
+            assert_eq!(ddsketch_count(restored), 1);
+            assert_eq!((*restored).snapshot(), (*sketch).snapshot());
+            assert!(ddsketch_deserialize(bytes, len - 1).is_null());
+            
+            ddsketch_bytes_free(bytes, len);
+            ddsketch_free(restored);
+            ddsketch_free(sketch);
+        }
+    }
+    
+    /// Reduce a C type to a canonical spelling, such as `constDDSketch*`
+    fn c_type(declaration: &str) -> String {
+        declaration.split_whitespace().collect()
+    }
+    
+    /// Spell a Rust FFI type the way the header does
+    fn rust_type(declaration: &str) -> String {
+        let declaration = declaration.trim();
+        if let Some(pointee) = declaration.strip_prefix("*mut ") {
+            return format!("{}*", rust_type(pointee));
+        }
+        if let Some(pointee) = declaration.strip_prefix("*const ") {
+            return format!("const{}*", rust_type(pointee));
+        }
+        match declaration {
+            "" => "void",
+            "f64" => "double",
+            "u64" => "uint64_t",
+            "u8" => "uint8_t",
+            "usize" => "size_t",
+            "c_int" => "int",
+            other => other,
+        }
+        .to_string()
+    }
+    
+    #[test]
+    fn test_header_matches_bindings() {
+        let header = include_str!("../include/ddsketch.h");
+        let source = include_str!("ffi.rs");
+        
+        // `name(parameter types) -> return type` for every function on each side
+        let mut declared: Vec<String> = header.lines()
+            .map(str::trim)
+            .filter(|line| line.ends_with(");") && !line.starts_with("/*"))
+            .map(|line| {
+                let (head, parameters) = line.trim_end_matches(");").split_once('(').unwrap();
+                let name_at = head.rfind([' ', '*']).unwrap() + 1;
+                let parameters: Vec<String> = parameters.split(',')
+                    .map(|parameter| {
+                        let name_at = parameter.rfind([' ', '*']).unwrap() + 1;
+                        c_type(&parameter[..name_at])
+                    })
+                    .collect();
+                format!("{}({}) -> {}", &head[name_at..], parameters.join(","), c_type(&head[..name_at]))
+            })
+            .collect();
+        let marker = ["extern \"C\"", " fn "].concat();
+        let mut exported: Vec<String> = source.lines()
+            .filter_map(|line| line.split_once(marker.as_str()).map(|(_, signature)| signature))
+            .map(|signature| {
+                let (name, rest) = signature.split_once('(').unwrap();
+                let (parameters, output) = rest.split_once(')').unwrap();
+                let parameters: Vec<String> = parameters.split(", ")
+                    .map(|parameter| rust_type(parameter.split_once(':').unwrap().1))
+                    .collect();
+                let output = output.trim_end_matches('{').trim().trim_start_matches("->");
+                format!("{}({}) -> {}", name, parameters.join(","), rust_type(output))
+            })
+            .collect();
+        declared.sort();
+        exported.sort();
+        assert_eq!(declared.len(), 10);
+        assert_eq!(declared, exported);
+        
+        // The status codes agree too
+        for (name, value) in [
+            ("DDSKETCH_OK", DDSKETCH_OK),
+            ("DDSKETCH_ERR_NULL_POINTER", DDSKETCH_ERR_NULL_POINTER),
+            ("DDSKETCH_ERR_INVALID_RELATIVE_ACCURACY", DDSKETCH_ERR_INVALID_RELATIVE_ACCURACY),
+            ("DDSKETCH_ERR_INVALID_QUANTILE", DDSKETCH_ERR_INVALID_QUANTILE),
+            ("DDSKETCH_ERR_EMPTY_SKETCH", DDSKETCH_ERR_EMPTY_SKETCH),
+            ("DDSKETCH_ERR_INCOMPATIBLE_SKETCHES", DDSKETCH_ERR_INCOMPATIBLE_SKETCHES),
+            ("DDSKETCH_ERR_OTHER", DDSKETCH_ERR_OTHER),
+        ] {
+            assert!(header.contains(&format!("#define {} {}\n", name, value)), "{}", name);
+        }
+        let defined = header.lines().filter(|line| line.starts_with("#define DDSKETCH_") && line.split_whitespace().count() == 3);
+        assert_eq!(defined.count(), 7);
+    }
+}
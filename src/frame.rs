@@ -0,0 +1,194 @@
+//! Multi-sketch frames
+//!
+//! This module writes many encoded sketches to one buffer or file and reads them
+//! back. Each frame is the length of an encoded sketch followed by the sketch:
+//!
+//! ```text
+//! length      u32       little-endian length of the payload
+//! payload     a sketch in the binary format of the `encoding` module
+//! ```
+//!
+//! Frames carry no framing of their own beyond the length, so a stream of frames can
+//! be appended to and concatenated freely.
+
+use crate::{DDSketch, DDSketchError, Result};
+use crate::mapping::IndexMapping;
+use crate::store::Store;
+use std::io::{self, Read, Write};
+
+/// The length of the prefix before each frame's payload
+const LENGTH_PREFIX_LEN: usize = 4;
+
+/// Writes sketches as length-prefixed frames
+#[derive(Debug)]
+pub struct SketchFrameWriter<W: Write> {
+    writer: W,
+    frames_written: u64,
+}
+
+impl<W: Write> SketchFrameWriter<W> {
+    /// Create a writer that appends frames to `writer`
+    pub fn new(writer: W) -> Self {
+        SketchFrameWriter {
+            writer,
+            frames_written: 0,
+        }
+    }
+    
+    /// Write a sketch as one frame
+    ///
+    /// # Returns
+    /// `DDSketchError::Io` if writing failed
+    pub fn write<M: IndexMapping, S: Store>(&mut self, sketch: &DDSketch<M, S>) -> Result<()> {
+        self.write_encoded(&sketch.encode())
+    }
+    
+    /// Write an already encoded sketch as one frame
+    ///
+    /// # Returns
+    /// `DDSketchError::InvalidEncoding` if the payload is longer than a frame can
+    /// hold, or `DDSketchError::Io` if writing failed
+    pub fn write_encoded(&mut self, payload: &[u8]) -> Result<()> {
+        let len = u32::try_from(payload.len()).map_err(|_| DDSketchError::InvalidEncoding)?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(payload)?;
+        self.frames_written += 1;
+        Ok(())
+    }
+    
+    /// Get the number of frames written
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written
+    }
+    
+    /// Flush the underlying writer
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+    
+    /// Get the underlying writer back
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads sketches from length-prefixed frames
+///
+/// The reader is also an iterator over the decoded sketches, which stops at the end
+/// of the input.
+#[derive(Debug)]
+pub struct SketchFrameReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> SketchFrameReader<R> {
+    /// Create a reader over frames from `reader`
+    pub fn new(reader: R) -> Self {
+        SketchFrameReader { reader }
+    }
+    
+    /// Read the payload of the next frame without decoding it
+    ///
+    /// # Returns
+    /// The payload, `None` at the end of the input, `DDSketchError::InvalidEncoding`
+    /// if the input ends inside a frame, or `DDSketchError::Io` if reading failed
+    pub fn read_encoded(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut prefix = [0u8; LENGTH_PREFIX_LEN];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            match self.reader.read(&mut prefix[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(DDSketchError::InvalidEncoding),
+                Ok(read) => filled += read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+        
+        // Read through `take` rather than allocating the claimed length up front, so a
+        // corrupt prefix cannot trigger a huge allocation
+        let len = u32::from_le_bytes(prefix) as u64;
+        let mut payload = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut payload)?;
+        if payload.len() as u64 != len {
+            return Err(DDSketchError::InvalidEncoding);
+        }
+        Ok(Some(payload))
+    }
+    
+    /// Read and decode the next frame
+    ///
+    /// # Returns
+    /// The sketch, `None` at the end of the input, or an error if the frame could not
+    /// be read or decoded
+    pub fn read_sketch(&mut self) -> Result<Option<DDSketch>> {
+        self.read_encoded()?.map(|payload| DDSketch::decode(&payload)).transpose()
+    }
+    
+    /// Get the underlying reader back
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Iterator for SketchFrameReader<R> {
+    type Item = Result<DDSketch>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_sketch().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_frame_round_trip() {
+        let sketches: Vec<DDSketch> = (1..=3)
+            .map(|scale| {
+                let mut sketch = DDSketch::new(0.01).unwrap();
+                for i in 1..=100 * scale {
+                    sketch.add(i as f64 * scale as f64);
+                }
+                sketch
+            })
+            .collect();
+        
+        let mut writer = SketchFrameWriter::new(Vec::new());
+        for sketch in &sketches {
+            writer.write(sketch).unwrap();
+        }
+        writer.write(&DDSketch::new(0.05).unwrap()).unwrap();
+        assert_eq!(writer.frames_written(), 4);
+        let bytes = writer.into_inner();
+        
+        let decoded: Vec<DDSketch> = SketchFrameReader::new(&bytes[..]).collect::<Result<_>>().unwrap();
+        assert_eq!(decoded.len(), 4);
+        for (decoded, sketch) in decoded.iter().zip(&sketches) {
+            assert_eq!(decoded.snapshot(), sketch.snapshot());
+        }
+        assert!(decoded[3].is_empty());
+        assert!(SketchFrameReader::new(&[][..]).next().is_none());
+    }
+    
+    #[test]
+    fn test_frame_rejects_truncated_input() {
+        let mut writer = SketchFrameWriter::new(Vec::new());
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        sketch.add(42.0);
+        writer.write(&sketch).unwrap();
+        let bytes = writer.into_inner();
+        
+        for len in 1..bytes.len() {
+            let mut reader = SketchFrameReader::new(&bytes[..len]);
+            assert_eq!(reader.read_encoded(), Err(DDSketchError::InvalidEncoding));
+        }
+        
+        // A corrupt length claiming gigabytes fails without allocating them
+        let mut corrupt = u32::MAX.to_le_bytes().to_vec();
+        corrupt.extend_from_slice(&bytes[LENGTH_PREFIX_LEN..]);
+        assert_eq!(SketchFrameReader::new(&corrupt[..]).read_encoded(), Err(DDSketchError::InvalidEncoding));
+    }
+}
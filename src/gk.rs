@@ -0,0 +1,229 @@
+//! Greenwald-Khanna sketch
+//!
+//! This module provides the Greenwald-Khanna quantile summary, which answers any
+//! quantile within a rank error of `ε·n`. It cannot be merged without losing its
+//! guarantee, but for a single stream it often needs less memory than DDSketch.
+
+use crate::{DDSketchError, IntoSketchValue, Result};
+
+/// A summary tuple: a value, the rank gap to the previous tuple, and the rank uncertainty
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tuple {
+    value: f64,
+    /// The minimum rank of this tuple minus the minimum rank of the previous one
+    gap: u64,
+    /// The maximum rank minus the minimum rank of this tuple
+    delta: u64,
+}
+
+/// A Greenwald-Khanna sketch with an `ε·n` rank error bound
+///
+/// The summary keeps sorted tuples whose ranks are known to within `2ε·n`, and
+/// merges neighbouring tuples every `1 / 2ε` insertions while that bound holds.
+/// It holds `O(1/ε · log(ε·n))` tuples.
+#[derive(Debug, Clone)]
+pub struct GKSketch {
+    epsilon: f64,
+    tuples: Vec<Tuple>,
+    count: u64,
+    compress_interval: u64,
+}
+
+impl GKSketch {
+    /// Create a new, empty sketch
+    ///
+    /// # Arguments
+    /// * `epsilon` - The rank error bound as a fraction of the count (between 0 and 1)
+    ///
+    /// # Returns
+    /// A new GKSketch, or `DDSketchError::InvalidValue` if epsilon is out of range
+    pub fn new(epsilon: f64) -> Result<Self> {
+        if !(epsilon > 0.0 && epsilon < 1.0) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(GKSketch {
+            epsilon,
+            tuples: Vec::new(),
+            count: 0,
+            compress_interval: (1.0 / (2.0 * epsilon)).floor().max(1.0) as u64,
+        })
+    }
+    
+    /// Get the rank error bound as a fraction of the count
+    pub fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+    
+    /// Add a value to the sketch
+    ///
+    /// Non-finite values are ignored; use `try_add` to detect them.
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        let _ = self.try_add(value);
+    }
+    
+    /// Add a value to the sketch, reporting non-finite values
+    ///
+    /// # Returns
+    /// `DDSketchError::NonFiniteValue` if the value is NaN or infinite
+    pub fn try_add(&mut self, value: impl IntoSketchValue) -> Result<()> {
+        let value = value.into_sketch_value();
+        if !value.is_finite() {
+            return Err(DDSketchError::NonFiniteValue);
+        }
+        
+        let position = self.tuples.partition_point(|tuple| tuple.value <= value);
+        // The new extremes are known exactly; interior values inherit the current uncertainty
+        let delta = if position == 0 || position == self.tuples.len() {
+            0
+        } else {
+            self.band_limit().saturating_sub(1)
+        };
+        self.tuples.insert(position, Tuple { value, gap: 1, delta });
+        self.count += 1;
+        
+        if self.count.is_multiple_of(self.compress_interval) {
+            self.compress();
+        }
+        Ok(())
+    }
+    
+    /// Get the total count of values in the sketch
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    
+    /// Get the number of tuples in the summary
+    pub fn num_tuples(&self) -> usize {
+        self.tuples.len()
+    }
+    
+    /// Get the minimum value in the sketch
+    pub fn min(&self) -> Option<f64> {
+        self.tuples.first().map(|tuple| tuple.value)
+    }
+    
+    /// Get the maximum value in the sketch
+    pub fn max(&self) -> Option<f64> {
+        self.tuples.last().map(|tuple| tuple.value)
+    }
+    
+    /// Merge neighbouring tuples while the rank uncertainty stays within `2ε·n`
+    ///
+    /// This runs automatically every `1 / 2ε` insertions.
+    pub fn compress(&mut self) {
+        let limit = self.band_limit();
+        if self.tuples.len() < 3 {
+            return;
+        }
+        
+        // Walk from the end, folding each tuple into its successor when allowed, and
+        // never removing the first or last tuple so the extremes stay exact
+        let mut i = self.tuples.len() - 2;
+        while i >= 1 {
+            let next = self.tuples[i + 1];
+            let current = self.tuples[i];
+            if current.gap + next.gap + next.delta <= limit {
+                self.tuples[i + 1].gap += current.gap;
+                self.tuples.remove(i);
+            }
+            i -= 1;
+        }
+    }
+    
+    /// Get the value at a given quantile
+    ///
+    /// The returned value is one of the values added, whose rank is within `ε·n` of
+    /// `quantile · n`.
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        if self.tuples.is_empty() {
+            return Err(DDSketchError::EmptySketch);
+        }
+        
+        let rank = (quantile * self.count as f64).ceil().max(1.0);
+        let tolerance = self.epsilon * self.count as f64;
+        
+        let mut min_rank = 0;
+        for (i, tuple) in self.tuples.iter().enumerate() {
+            min_rank += tuple.gap;
+            if (min_rank + tuple.delta) as f64 > rank + tolerance {
+                return Ok(self.tuples[i.saturating_sub(1)].value);
+            }
+        }
+        Ok(self.tuples[self.tuples.len() - 1].value)
+    }
+    
+    /// Clear all data from the sketch
+    pub fn clear(&mut self) {
+        self.tuples.clear();
+        self.count = 0;
+    }
+    
+    /// Get the largest rank uncertainty a tuple may have, `⌊2ε·n⌋`
+    fn band_limit(&self) -> u64 {
+        (2.0 * self.epsilon * self.count as f64).floor() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_gk_rank_error() {
+        let epsilon = 0.01;
+        let mut sketch = GKSketch::new(epsilon).unwrap();
+        assert!(GKSketch::new(0.0).is_err());
+        assert_eq!(sketch.get_quantile_value(0.5), Err(DDSketchError::EmptySketch));
+        
+        let n = 50_000u64;
+        for i in 0..n {
+            // A fixed permutation of 1..=n
+            sketch.add(((i * 7919) % n + 1) as f64);
+        }
+        
+        assert_eq!(sketch.count(), n);
+        assert!(sketch.num_tuples() < 1000, "{} tuples", sketch.num_tuples());
+        assert_eq!((sketch.min(), sketch.max()), (Some(1.0), Some(n as f64)));
+        
+        for q in [0.0, 0.001, 0.1, 0.5, 0.9, 0.999, 1.0] {
+            let estimate = sketch.get_quantile_value(q).unwrap();
+            let rank_error = (estimate - q * n as f64).abs();
+            assert!(rank_error <= epsilon * n as f64 + 1.0, "q={} estimate={}", q, estimate);
+        }
+        assert_eq!(sketch.get_quantile_value(-0.1), Err(DDSketchError::InvalidQuantile { got: -0.1 }));
+    }
+    
+    #[test]
+    fn test_gk_small_inputs() {
+        let mut sketch = GKSketch::new(0.1).unwrap();
+        for value in [5.0, 1.0, 3.0] {
+            sketch.add(value);
+        }
+        assert_eq!(sketch.try_add(f64::INFINITY), Err(DDSketchError::NonFiniteValue));
+        
+        assert_eq!(sketch.get_quantile_value(0.0).unwrap(), 1.0);
+        assert_eq!(sketch.get_quantile_value(0.5).unwrap(), 3.0);
+        assert_eq!(sketch.get_quantile_value(1.0).unwrap(), 5.0);
+        
+        sketch.clear();
+        assert!(sketch.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+//! Global sketches
+//!
+//! This module provides a process-wide registry of named sketches, created on first
+//! use, so that code can record into a histogram without a registry being passed to
+//! every call site. The `sketch!` macro is the usual way in:
+//!
+//! ```
+//! ddsketch_rs::sketch!("http.request.latency_ms").record(12.5);
+//!
+//! let latency = ddsketch_rs::global::sketch("http.request.latency_ms").snapshot();
+//! assert_eq!(latency.count(), 1);
+//! ```
+
+use crate::{DDSketch, IntoSketchValue};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+/// The relative accuracy of every global sketch
+pub const GLOBAL_RELATIVE_ACCURACY: f64 = 0.01;
+
+/// A named sketch in the global registry
+///
+/// Global sketches live for the rest of the process, so handles are `&'static` and
+/// can be cached freely.
+#[derive(Debug)]
+pub struct GlobalSketch {
+    name: String,
+    sketch: Mutex<DDSketch>,
+}
+
+/// Lock a mutex, recovering the data if another thread panicked while holding it
+///
+/// Every critical section leaves its sketch valid, so a poisoned lock is safe to use.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn registry() -> &'static Mutex<HashMap<String, &'static GlobalSketch>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<String, &'static GlobalSketch>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Get the global sketch with a name, creating it if needed
+///
+/// This takes the registry lock; the `sketch!` macro looks a name up once per call
+/// site instead.
+pub fn sketch(name: &str) -> &'static GlobalSketch {
+    let mut registry = lock(registry());
+    if let Some(&sketch) = registry.get(name) {
+        return sketch;
+    }
+    
+    let sketch: &'static GlobalSketch = Box::leak(Box::new(GlobalSketch {
+        name: name.to_string(),
+        sketch: Mutex::new(
+            DDSketch::new(GLOBAL_RELATIVE_ACCURACY).expect("the global relative accuracy is valid"),
+        ),
+    }));
+    registry.insert(name.to_string(), sketch);
+    sketch
+}
+
+/// Get every global sketch, sorted by name
+pub fn sketches() -> Vec<&'static GlobalSketch> {
+    let mut sketches: Vec<&'static GlobalSketch> = lock(registry()).values().copied().collect();
+    sketches.sort_by(|a, b| a.name.cmp(&b.name));
+    sketches
+}
+
+impl GlobalSketch {
+    /// Get the name of the sketch
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    
+    /// Record a value
+    pub fn record(&self, value: impl IntoSketchValue) {
+        self.record_with_count(value, 1);
+    }
+    
+    /// Record a value with a specific count
+    pub fn record_with_count(&self, value: impl IntoSketchValue, count: u64) {
+        lock(&self.sketch).add_with_count(value, count);
+    }
+    
+    /// Copy the sketch's current contents
+    pub fn snapshot(&self) -> DDSketch {
+        lock(&self.sketch).clone()
+    }
+    
+    /// Take the sketch's contents, leaving it empty
+    ///
+    /// Each recorded value is returned by exactly one flush, which suits interval
+    /// reporting.
+    pub fn flush(&self) -> DDSketch {
+        let empty = DDSketch::new(GLOBAL_RELATIVE_ACCURACY).expect("the global relative accuracy is valid");
+        std::mem::replace(&mut *lock(&self.sketch), empty)
+    }
+}
+
+/// Get the global sketch with a literal name, creating it on first use
+///
+/// The lookup happens once per call site and is cached, so recording through the
+/// macro only locks the sketch itself. Use `global::sketch` for names built at run
+/// time.
+#[macro_export]
+macro_rules! sketch {
+    ($name:literal) => {{
+        static SKETCH: ::std::sync::OnceLock<&'static $crate::global::GlobalSketch> =
+            ::std::sync::OnceLock::new();
+        *SKETCH.get_or_init(|| $crate::global::sketch($name))
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_global_sketches() {
+        for i in 1..=100 {
+            crate::sketch!("global.test.latency").record(i as f64);
+        }
+        crate::sketch!("global.test.size").record_with_count(512u32, 3);
+        
+        let latency = sketch("global.test.latency");
+        assert!(std::ptr::eq(latency, crate::sketch!("global.test.latency")));
+        assert_eq!(latency.name(), "global.test.latency");
+        assert_eq!(latency.snapshot().count(), 100);
+        
+        let names: Vec<&str> = sketches().into_iter()
+            .map(GlobalSketch::name)
+            .filter(|name| name.starts_with("global.test."))
+            .collect();
+        assert_eq!(names, vec!["global.test.latency", "global.test.size"]);
+        
+        let size = sketch("global.test.size");
+        assert_eq!(size.flush().count(), 3);
+        assert!(size.snapshot().is_empty());
+    }
+}
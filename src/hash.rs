@@ -0,0 +1,83 @@
+//! Stable hashing
+//!
+//! Hashes written into encodings or compared across processes must not change with
+//! the Rust version or the process, as `std`'s default hasher may. This module holds
+//! the hasher the crate uses for them.
+
+use std::hash::Hasher;
+
+/// FNV-1a, a hasher whose output does not depend on the Rust version or the process
+pub(crate) struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    
+    // Integers are hashed as little-endian bytes, and `usize` as a `u64`, so the hash
+    // does not depend on the platform's byte order or pointer width
+    
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
+    }
+    
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+    
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+    
+    fn write_u128(&mut self, value: u128) {
+        self.write(&value.to_le_bytes());
+    }
+    
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_stable_hasher() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = StableHasher::default();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        // Published FNV-1a test vectors
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+    
+    #[test]
+    fn test_stable_hasher_integers() {
+        let hash = |write: &dyn Fn(&mut StableHasher)| {
+            let mut hasher = StableHasher::default();
+            write(&mut hasher);
+            hasher.finish()
+        };
+        let bytes = hash(&|hasher| hasher.write(&[5, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(hash(&|hasher| hasher.write_u64(5)), bytes);
+        assert_eq!(hash(&|hasher| hasher.write_usize(5)), bytes);
+        assert_eq!(hash(&|hasher| hasher.write_u32(0x0102_0304)), hash(&|hasher| hasher.write(&[4, 3, 2, 1])));
+        assert_eq!(hash(&|hasher| hasher.write_u16(0x0102)), hash(&|hasher| hasher.write(&[2, 1])));
+    }
+}
@@ -0,0 +1,355 @@
+//! Heavy hitters sketch
+//!
+//! This module provides the SpaceSaving algorithm, which tracks the most frequent items
+//! of a stream, such as endpoints or error codes, in a fixed number of counters.
+
+use crate::{DDSketchError, Result};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// An item reported by a `HeavyHitters` sketch
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeavyHitter<K> {
+    /// The item
+    pub item: K,
+    /// The estimated count, which never underestimates the true count
+    pub count: u64,
+    /// The largest amount by which `count` may overestimate the true count
+    pub error: u64,
+}
+
+impl<K> HeavyHitter<K> {
+    /// Get the smallest count the item can have
+    pub fn lower_bound(&self) -> u64 {
+        self.count - self.error
+    }
+}
+
+/// A mergeable top-k frequency sketch using the SpaceSaving algorithm
+///
+/// The sketch keeps `capacity` counters. An untracked item takes over the counter of
+/// the least frequent tracked item and inherits its count as error, so every count is
+/// an overestimate by at most `total / capacity`, and every item more frequent than
+/// that is tracked. Adding an untracked item to a full sketch scans the counters, so
+/// capacities of a few hundred or thousand suit it best.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(bound(serialize = "K: serde::Serialize + Eq + Hash")))]
+pub struct HeavyHitters<K> {
+    capacity: usize,
+    /// The estimated count and error of each tracked item
+    counters: HashMap<K, (u64, u64)>,
+    /// The total count of all items added
+    total: u64,
+}
+
+impl<K: Eq + Hash + Clone> HeavyHitters<K> {
+    /// Create a new, empty sketch
+    ///
+    /// # Arguments
+    /// * `capacity` - The number of items tracked at once
+    ///
+    /// # Returns
+    /// A new HeavyHitters, or `DDSketchError::InvalidValue` if the capacity is zero
+    pub fn new(capacity: usize) -> Result<Self> {
+        if capacity == 0 {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(HeavyHitters {
+            capacity,
+            counters: HashMap::with_capacity(capacity),
+            total: 0,
+        })
+    }
+    
+    /// Get the number of items tracked at once
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    
+    /// Get the number of items currently tracked
+    pub fn len(&self) -> usize {
+        self.counters.len()
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+    
+    /// Get the total count of all items added
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+    
+    /// Get the largest amount by which any count may overestimate the true count
+    pub fn max_error(&self) -> u64 {
+        self.untracked_count()
+    }
+    
+    /// Add one occurrence of an item
+    pub fn add(&mut self, item: K) {
+        self.add_with_count(item, 1);
+    }
+    
+    /// Add several occurrences of an item
+    ///
+    /// # Arguments
+    /// * `item` - The item to add
+    /// * `count` - The number of occurrences
+    pub fn add_with_count(&mut self, item: K, count: u64) {
+        if count == 0 {
+            return;
+        }
+        self.total = self.total.saturating_add(count);
+        
+        if let Some((tracked, _)) = self.counters.get_mut(&item) {
+            *tracked = tracked.saturating_add(count);
+        } else if self.counters.len() < self.capacity {
+            self.counters.insert(item, (count, 0));
+        } else {
+            let (evicted, &(min_count, _)) = self.counters.iter()
+                .min_by_key(|&(_, &(tracked, _))| tracked)
+                .expect("a full sketch tracks items");
+            let evicted = evicted.clone();
+            self.counters.remove(&evicted);
+            self.counters.insert(item, (min_count.saturating_add(count), min_count));
+        }
+    }
+    
+    /// Get the estimate for an item
+    ///
+    /// # Returns
+    /// The item's estimated count and error, or `None` if it is not tracked, in which
+    /// case its count is at most `max_error()`
+    pub fn get(&self, item: &K) -> Option<HeavyHitter<K>> {
+        self.counters.get(item).map(|&(count, error)| HeavyHitter { item: item.clone(), count, error })
+    }
+    
+    /// Get the most frequent items
+    ///
+    /// # Arguments
+    /// * `k` - The largest number of items to return
+    ///
+    /// # Returns
+    /// Up to `k` tracked items in descending order of estimated count
+    pub fn top(&self, k: usize) -> Vec<HeavyHitter<K>> {
+        let mut hitters: Vec<HeavyHitter<K>> = self.counters.iter()
+            .map(|(item, &(count, error))| HeavyHitter { item: item.clone(), count, error })
+            .collect();
+        hitters.sort_by(|a, b| b.count.cmp(&a.count).then(b.error.cmp(&a.error).reverse()));
+        hitters.truncate(k);
+        hitters
+    }
+    
+    /// Get the items that may make up more than a fraction of the total count
+    ///
+    /// Every item above the fraction is returned. Items whose lower bound is below it
+    /// may be false positives; check `HeavyHitter::lower_bound` to keep only certain ones.
+    ///
+    /// # Arguments
+    /// * `fraction` - The share of the total count (between 0 and 1)
+    ///
+    /// # Returns
+    /// The items in descending order of estimated count, or `DDSketchError::InvalidValue`
+    /// if the fraction is out of range
+    pub fn frequent(&self, fraction: f64) -> Result<Vec<HeavyHitter<K>>> {
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        let threshold = fraction * self.total as f64;
+        let mut hitters = self.top(self.capacity);
+        hitters.retain(|hitter| hitter.count as f64 > threshold);
+        Ok(hitters)
+    }
+    
+    /// Merge another sketch into this one
+    ///
+    /// Each item's counts from both sketches are added, with an item missing from a
+    /// full sketch counted at that sketch's smallest count and the same amount of
+    /// error. The `capacity` largest counts are then kept, so the merged sketch keeps
+    /// the guarantees of one built from both streams.
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to merge
+    ///
+    /// # Returns
+    /// `DDSketchError::IncompatibleSketches` if the capacities differ, in which case the
+    /// sketch is left unchanged
+    pub fn merge(&mut self, other: &HeavyHitters<K>) -> Result<()> {
+        if self.capacity != other.capacity {
+            return Err(DDSketchError::different_parameter("capacity", self.capacity as f64, other.capacity as f64));
+        }
+        
+        let (ours_missing, theirs_missing) = (self.untracked_count(), other.untracked_count());
+        let mut merged: HashMap<K, (u64, u64)> = HashMap::with_capacity(self.counters.len() + other.counters.len());
+        for (item, &(count, error)) in &self.counters {
+            let (other_count, other_error) = other.counters.get(item).copied()
+                .unwrap_or((theirs_missing, theirs_missing));
+            merged.insert(item.clone(), (count.saturating_add(other_count), error.saturating_add(other_error)));
+        }
+        for (item, &(count, error)) in &other.counters {
+            merged.entry(item.clone())
+                .or_insert((count.saturating_add(ours_missing), error.saturating_add(ours_missing)));
+        }
+        
+        if merged.len() > self.capacity {
+            let mut entries: Vec<(K, (u64, u64))> = merged.into_iter().collect();
+            entries.sort_unstable_by_key(|&(_, (count, _))| std::cmp::Reverse(count));
+            entries.truncate(self.capacity);
+            merged = entries.into_iter().collect();
+        }
+        
+        self.counters = merged;
+        self.total = self.total.saturating_add(other.total);
+        Ok(())
+    }
+    
+    /// Clear all data from the sketch
+    pub fn clear(&mut self) {
+        self.counters.clear();
+        self.total = 0;
+    }
+    
+    /// The largest count an untracked item can have
+    fn untracked_count(&self) -> u64 {
+        if self.counters.len() < self.capacity {
+            return 0;
+        }
+        self.counters.values().map(|&(count, _)| count).min().unwrap_or(0)
+    }
+}
+
+/// The serialized form of a `HeavyHitters`, checked before it becomes one
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(bound(deserialize = "K: serde::Deserialize<'de> + Eq + Hash"))]
+struct HeavyHittersRepr<K> {
+    capacity: usize,
+    counters: HashMap<K, (u64, u64)>,
+    total: u64,
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K: serde::Deserialize<'de> + Eq + Hash> serde::Deserialize<'de> for HeavyHitters<K> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        use serde::de::Error;
+        
+        let repr = HeavyHittersRepr::deserialize(deserializer)?;
+        if repr.capacity == 0 {
+            return Err(D::Error::custom("the capacity must be at least one"));
+        }
+        if repr.counters.len() > repr.capacity {
+            return Err(D::Error::custom("more counters than the capacity"));
+        }
+        if repr.counters.values().any(|&(count, error)| error > count) {
+            return Err(D::Error::custom("a counter's error exceeds its count"));
+        }
+        
+        Ok(HeavyHitters {
+            capacity: repr.capacity,
+            counters: repr.counters,
+            total: repr.total,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// A skewed stream where item `i` occurs about `1000 / (i + 1)` times
+    fn skewed_stream() -> Vec<u32> {
+        let mut items: Vec<u32> = (0..200u32).flat_map(|i| std::iter::repeat_n(i, 1000 / (i as usize + 1))).collect();
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for i in (1..items.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            items.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+        items
+    }
+    
+    #[test]
+    fn test_heavy_hitters_bounds() {
+        let stream = skewed_stream();
+        let mut exact: HashMap<u32, u64> = HashMap::new();
+        let mut sketch = HeavyHitters::new(20).unwrap();
+        for &item in &stream {
+            sketch.add(item);
+            *exact.entry(item).or_default() += 1;
+        }
+        
+        assert_eq!(sketch.count(), stream.len() as u64);
+        assert_eq!(sketch.len(), 20);
+        assert!(sketch.max_error() <= stream.len() as u64 / 20);
+        for hitter in sketch.top(20) {
+            let truth = exact[&hitter.item];
+            assert!(hitter.lower_bound() <= truth && truth <= hitter.count, "{:?} truth={}", hitter, truth);
+        }
+        
+        let top: Vec<u32> = sketch.top(3).into_iter().map(|hitter| hitter.item).collect();
+        assert_eq!(top, vec![0, 1, 2]);
+        let frequent = sketch.frequent(0.1).unwrap();
+        assert!(frequent.iter().any(|hitter| hitter.item == 0));
+        assert!(frequent.iter().all(|hitter| hitter.count as f64 > 0.1 * stream.len() as f64));
+        assert_eq!(sketch.get(&0).map(|hitter| hitter.item), Some(0));
+        assert!(sketch.frequent(1.5).is_err());
+        assert!(HeavyHitters::<u32>::new(0).is_err());
+    }
+    
+    #[test]
+    fn test_heavy_hitters_merge() {
+        let stream = skewed_stream();
+        let (left, right) = stream.split_at(stream.len() / 3);
+        let mut a = HeavyHitters::new(30).unwrap();
+        let mut b = HeavyHitters::new(30).unwrap();
+        left.iter().for_each(|&item| a.add(item));
+        right.iter().for_each(|&item| b.add(item));
+        a.merge(&b).unwrap();
+        
+        assert_eq!(a.count(), stream.len() as u64);
+        assert!(a.len() <= 30);
+        let top: Vec<u32> = a.top(3).into_iter().map(|hitter| hitter.item).collect();
+        assert_eq!(top, vec![0, 1, 2]);
+        for hitter in a.top(10) {
+            let truth = stream.iter().filter(|&&item| item == hitter.item).count() as u64;
+            assert!(hitter.lower_bound() <= truth && truth <= hitter.count, "{:?} truth={}", hitter, truth);
+        }
+        
+        let mut small = HeavyHitters::<u32>::new(5).unwrap();
+        assert!(matches!(small.merge(&b), Err(DDSketchError::IncompatibleSketches { .. })));
+        small.add_with_count(7, 0);
+        assert!(small.is_empty());
+    }
+    
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_heavy_hitters_serde() {
+        let mut sketch = HeavyHitters::new(4).unwrap();
+        for item in ["/a", "/b", "/a", "/c", "/a", "/d", "/e"] {
+            sketch.add(item.to_string());
+        }
+        let json = serde_json::to_string(&sketch).unwrap();
+        let decoded: HeavyHitters<String> = serde_json::from_str(&json).unwrap();
+        let sorted = |sketch: &HeavyHitters<String>| {
+            let mut hitters = sketch.top(4);
+            hitters.sort_by(|a, b| a.item.cmp(&b.item));
+            hitters
+        };
+        assert_eq!(sorted(&decoded), sorted(&sketch));
+        assert_eq!(decoded.count(), 7);
+        
+        for invalid in [
+            r#"{"capacity":0,"counters":{"/a":[1,0]},"total":1}"#,
+            r#"{"capacity":1,"counters":{"/a":[1,0],"/b":[1,0]},"total":2}"#,
+            r#"{"capacity":2,"counters":{"/a":[1,2]},"total":1}"#,
+        ] {
+            assert!(serde_json::from_str::<HeavyHitters<String>>(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
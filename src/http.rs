@@ -0,0 +1,195 @@
+//! Debug and scrape endpoint
+//!
+//! This module renders the state of a `SketchGroup` for an HTTP endpoint, as JSON for
+//! debugging and as Prometheus text for scraping. It depends on no HTTP library: the
+//! handler maps a request path to a `DebugResponse`, which any server turns into a
+//! response in a few lines. With axum, for example:
+//!
+//! ```text
+//! async fn sketches(State(group): State<Arc<Mutex<SketchGroup<String>>>>, uri: Uri) -> impl IntoResponse {
+//!     let response = group.lock().unwrap().debug_response("request_seconds", uri.path());
+//!     (StatusCode::from_u16(response.status).unwrap(), [(CONTENT_TYPE, response.content_type)], response.body)
+//! }
+//! ```
+
+use crate::{DDSketch, SketchGroup};
+use std::fmt::{Display, Write};
+use std::hash::Hash;
+
+/// The quantiles reported for every key
+pub const DEBUG_QUANTILES: [f64; 6] = [0.5, 0.75, 0.9, 0.95, 0.99, 0.999];
+
+/// The content type of the Prometheus text exposition format
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The content type of JSON responses
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// A rendered response to a debug request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugResponse {
+    /// The HTTP status code
+    pub status: u16,
+    /// The value of the `Content-Type` header
+    pub content_type: &'static str,
+    /// The response body
+    pub body: String,
+}
+
+impl<K: Eq + Hash + Display> SketchGroup<K> {
+    /// Answer a debug or scrape request
+    ///
+    /// Paths ending in `/json` get `to_json`, paths ending in `/metrics` get
+    /// `to_prometheus`, and any other path gets a 404.
+    ///
+    /// # Arguments
+    /// * `name` - The metric name used in the Prometheus text
+    /// * `path` - The path of the request, without its query string
+    pub fn debug_response(&self, name: &str, path: &str) -> DebugResponse {
+        let path = path.trim_end_matches('/');
+        if path.ends_with("/json") || path == "json" {
+            DebugResponse { status: 200, content_type: JSON_CONTENT_TYPE, body: self.to_json() }
+        } else if path.ends_with("/metrics") || path == "metrics" {
+            DebugResponse { status: 200, content_type: PROMETHEUS_CONTENT_TYPE, body: self.to_prometheus(name) }
+        } else {
+            DebugResponse { status: 404, content_type: "text/plain; charset=utf-8", body: "not found\n".to_string() }
+        }
+    }
+    
+    /// Render every sketch in the group as a JSON document
+    ///
+    /// The document holds the relative accuracy and, for each key in order of its
+    /// label, the count, sum, extremes, the `DEBUG_QUANTILES`, and every bin as
+    /// `[lower, upper, count]`. Values without a JSON representation are `null`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let _ = write!(json, "{{\"relative_accuracy\":{},\"sketches\":[", json_number(self.relative_accuracy()));
+        
+        for (position, (key, sketch)) in self.sorted_by_label().into_iter().enumerate() {
+            if position > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"key\":{},\"count\":{},\"sum\":{},\"min\":{},\"max\":{},\"quantiles\":{{",
+                json_string(&key),
+                sketch.count(),
+                json_number(sketch.sum()),
+                json_number(sketch.min().unwrap_or(f64::NAN)),
+                json_number(sketch.max().unwrap_or(f64::NAN)),
+            );
+            for (position, quantile) in DEBUG_QUANTILES.iter().enumerate() {
+                let value = sketch.get_quantile_value(*quantile).unwrap_or(f64::NAN);
+                let separator = if position > 0 { "," } else { "" };
+                let _ = write!(json, "{}\"{}\":{}", separator, quantile, json_number(value));
+            }
+            json.push_str("},\"bins\":[");
+            for (position, bin) in sketch.bins().enumerate() {
+                let separator = if position > 0 { "," } else { "" };
+                let _ = write!(json, "{}[{},{},{}]", separator, json_number(bin.lower), json_number(bin.upper), bin.count);
+            }
+            json.push_str("]}");
+        }
+        
+        json.push_str("]}");
+        json
+    }
+    
+    /// Render every sketch in the group as one Prometheus summary
+    ///
+    /// Each key becomes the value of a `key` label, in order of its label, and the
+    /// `DEBUG_QUANTILES` are reported.
+    ///
+    /// # Arguments
+    /// * `name` - The metric name
+    pub fn to_prometheus(&self, name: &str) -> String {
+        let mut text = format!("# TYPE {} summary\n", name);
+        for (key, sketch) in self.sorted_by_label() {
+            let summary = sketch.to_prometheus_summary(name, &[("key", &key)], &DEBUG_QUANTILES)
+                .expect("the debug quantiles are valid");
+            // Every key shares the one `# TYPE` line
+            for line in summary.lines().skip(1) {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        text
+    }
+    
+    /// Get the sketches with their keys' labels, sorted by label
+    fn sorted_by_label(&self) -> Vec<(String, &DDSketch)> {
+        let mut sketches: Vec<(String, &DDSketch)> = self.iter()
+            .map(|(key, sketch)| (key.to_string(), sketch))
+            .collect();
+        sketches.sort_by(|a, b| a.0.cmp(&b.0));
+        sketches
+    }
+}
+
+/// Format a number as JSON, which has no NaN or infinities
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// Format a string as a JSON string literal
+fn json_string(value: &str) -> String {
+    let mut literal = String::with_capacity(value.len() + 2);
+    literal.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(literal, "\\u{:04x}", c as u32);
+            }
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_debug_json() {
+        let mut group = SketchGroup::new(0.02).unwrap();
+        group.record("b\"", 2.0);
+        group.record("a", 1.0);
+        group.sketch_mut("empty");
+        
+        let json = group.to_json();
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let sketches = parsed["sketches"].as_array().unwrap();
+        assert_eq!(sketches.len(), 3);
+        assert_eq!(sketches[0]["key"], "a");
+        assert_eq!(sketches[0]["count"], 1);
+        assert_eq!(sketches[0]["quantiles"]["0.5"], 1.0);
+        assert_eq!(sketches[0]["bins"][0][2], 1);
+        assert_eq!(sketches[1]["key"], "b\"");
+        assert!(sketches[2]["min"].is_null());
+    }
+    
+    #[test]
+    fn test_debug_response_routing() {
+        let mut group = SketchGroup::new(0.02).unwrap();
+        group.record(200u16, 1.0);
+        group.record(500u16, 4.0);
+        
+        let response = group.debug_response("latency", "/debug/sketches/metrics");
+        assert_eq!((response.status, response.content_type), (200, PROMETHEUS_CONTENT_TYPE));
+        assert_eq!(response.body.matches("# TYPE").count(), 1);
+        assert!(response.body.contains("latency_count{key=\"500\"} 1\n"));
+        
+        let response = group.debug_response("latency", "/debug/sketches/json/");
+        assert_eq!((response.status, response.content_type), (200, JSON_CONTENT_TYPE));
+        assert_eq!(group.debug_response("latency", "/debug").status, 404);
+    }
+}
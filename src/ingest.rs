@@ -0,0 +1,155 @@
+//! Streaming ingestion from readers
+//!
+//! This module parses numbers out of text, one per line or from a column of
+//! comma-separated lines, and adds them to a sketch in chunks, so files of any size
+//! are ingested with bounded memory.
+
+use crate::{DDSketch, IndexMapping, Result, Store};
+use std::io::{BufRead, BufReader, Read};
+
+/// The number of values parsed before they are added to the sketch as a batch
+const INGEST_CHUNK_SIZE: usize = 4096;
+
+/// How numbers are laid out in the text read by `DDSketch::ingest_reader`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// One number per line
+    Lines,
+    /// Comma-separated lines with the number in a given zero-based column
+    Csv {
+        /// The column holding the number
+        column: usize,
+    },
+}
+
+impl InputFormat {
+    /// Extract the number from a line, if it holds one
+    fn parse(&self, line: &str) -> Option<f64> {
+        let field = match *self {
+            InputFormat::Lines => line,
+            InputFormat::Csv { column } => line.split(',').nth(column)?,
+        };
+        field.trim().parse().ok()
+    }
+}
+
+/// The outcome of ingesting a reader
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestStats {
+    /// The number of lines whose value was passed to the sketch
+    pub added: u64,
+    /// The number of non-empty lines without a number, such as CSV headers
+    pub skipped: u64,
+}
+
+impl<M: IndexMapping, S: Store> DDSketch<M, S> {
+    /// Add the numbers read from a text stream
+    ///
+    /// The reader is consumed line by line and parsed values are added in chunks with
+    /// `add_batch`, so memory use does not depend on the size of the input. Lines that
+    /// do not parse, including invalid UTF-8, are skipped and counted; blank lines are
+    /// ignored. Values the sketch refuses, such as NaN under a dropping policy, still
+    /// count as added.
+    ///
+    /// # Arguments
+    /// * `reader` - The source of the text
+    /// * `format` - Where the number is on each line
+    ///
+    /// # Returns
+    /// How many lines were added and skipped, or `DDSketchError::Io` if reading failed,
+    /// in which case the values read so far have been added
+    pub fn ingest_reader<R: Read>(&mut self, reader: R, format: InputFormat) -> Result<IngestStats> {
+        let mut reader = BufReader::new(reader);
+        let mut stats = IngestStats::default();
+        let mut line = Vec::new();
+        let mut chunk = Vec::with_capacity(INGEST_CHUNK_SIZE);
+        
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line);
+            if let Err(error) = read {
+                self.add_batch(&chunk);
+                return Err(error.into());
+            }
+            if line.is_empty() {
+                break;
+            }
+            
+            let text = std::str::from_utf8(&line).ok();
+            if text.is_some_and(|text| text.trim().is_empty()) {
+                continue;
+            }
+            match text.and_then(|text| format.parse(text)) {
+                Some(value) => {
+                    chunk.push(value);
+                    stats.added += 1;
+                }
+                None => stats.skipped += 1,
+            }
+            if chunk.len() == INGEST_CHUNK_SIZE {
+                self.add_batch(&chunk);
+                chunk.clear();
+            }
+        }
+        
+        self.add_batch(&chunk);
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DDSketchError;
+    
+    #[test]
+    fn test_ingest_lines() {
+        let text: String = (1..=10_000).map(|i| format!("{}\n", i)).collect::<String>() + "\nbogus\n7";
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        let stats = sketch.ingest_reader(text.as_bytes(), InputFormat::Lines).unwrap();
+        
+        assert_eq!(stats, IngestStats { added: 10_001, skipped: 1 });
+        assert_eq!(sketch.count(), 10_001);
+        assert_eq!(sketch.max(), Some(10_000.0));
+        let median = sketch.get_quantile_value(0.5).unwrap();
+        assert!((median - 5000.0).abs() / 5000.0 <= 0.01, "median={}", median);
+    }
+    
+    #[test]
+    fn test_ingest_csv() {
+        let text = "host,latency_ms\na, 12.5\nb,30\r\nc\nd,4\n";
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        let stats = sketch.ingest_reader(text.as_bytes(), InputFormat::Csv { column: 1 }).unwrap();
+        
+        assert_eq!(stats, IngestStats { added: 3, skipped: 2 });
+        assert_eq!((sketch.min(), sketch.max()), (Some(4.0), Some(30.0)));
+        
+        assert_eq!(InputFormat::Csv { column: 1 }.parse("a, 3 ,x"), Some(3.0));
+        assert_eq!(InputFormat::Csv { column: 5 }.parse("1,2"), None);
+        assert_eq!(InputFormat::Lines.parse("1,2"), None);
+        
+        let mut invalid = b"1\n2\n".to_vec();
+        invalid.extend_from_slice(&[0xff, b'\n']);
+        let stats = sketch.ingest_reader(invalid.as_slice(), InputFormat::Lines).unwrap();
+        assert_eq!(stats, IngestStats { added: 2, skipped: 1 });
+    }
+    
+    #[test]
+    fn test_ingest_read_error() {
+        struct Failing(bool);
+        impl Read for Failing {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if std::mem::replace(&mut self.0, true) {
+                    return Err(std::io::ErrorKind::ConnectionReset.into());
+                }
+                buf[..4].copy_from_slice(b"1\n2\n");
+                Ok(4)
+            }
+        }
+        
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        let error = sketch.ingest_reader(Failing(false), InputFormat::Lines).unwrap_err();
+        assert_eq!(error, DDSketchError::Io(std::io::ErrorKind::ConnectionReset));
+        assert_eq!(sketch.count(), 2);
+    }
+}
@@ -28,10 +28,92 @@
 pub mod ddsketch;
 pub mod store;
 pub mod mapping;
+pub mod snapshot;
+pub mod concurrent;
+pub mod sharded;
+pub mod published;
+pub mod cursor;
+pub mod weighted;
+pub mod duration;
+pub mod value;
+pub mod windowed;
+pub mod decay;
+pub mod registry;
+pub mod prometheus;
+pub mod encoding;
+pub mod checkpoint;
+pub mod delta;
+pub mod uniform;
+pub mod tdigest;
+pub mod moments;
+pub mod gk;
+pub mod req;
+pub mod exact;
+pub mod compare;
+pub mod reservoir;
+pub mod view;
+pub mod merged;
+pub mod watch;
+pub mod multires;
+pub mod builder;
+pub mod plot;
+pub mod ingest;
+pub mod pool;
+pub mod heavyhitters;
+pub mod minhash;
+pub mod frame;
+pub mod conditioned;
+pub mod recency;
+pub mod rate;
+mod hash;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "dogstatsd")]
+pub mod dogstatsd;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+#[cfg(feature = "global")]
+pub mod global;
 
-pub use ddsketch::DDSketch;
-pub use store::Store;
-pub use mapping::IndexMapping;
+pub use ddsketch::{DDSketch, DynDDSketch, QuantileEstimate, QuantileBin, QuantileInterpolation, SketchSummary, SketchReport, ReportedQuantile, NonFinitePolicy, Bin};
+pub use store::{Store, CollapsePolicy, FixedStore, HybridStore};
+pub use mapping::{IndexMapping, IncompatibilityReason, IntegerKeyTable, KeyCache, MappingDescriptor, MappingKind, TailBiasedMapping};
+pub use snapshot::{SketchSnapshot, ColumnarSnapshot};
+pub use concurrent::ConcurrentDDSketch;
+pub use sharded::{ShardedDDSketch, ShardHandle};
+pub use published::SnapshotDDSketch;
+pub use cursor::QueryCursor;
+pub use weighted::WeightedDDSketch;
+pub use duration::{DurationSketch, DurationUnit};
+pub use value::IntoSketchValue;
+pub use windowed::RollingDDSketch;
+pub use decay::ExpDecayDDSketch;
+pub use registry::{SketchGroup, Degradation};
+pub use uniform::UniformDDSketch;
+pub use tdigest::TDigest;
+pub use moments::MomentsSketch;
+pub use gk::GKSketch;
+pub use req::ReqSketch;
+pub use exact::ExactModeDDSketch;
+pub use delta::DeltaBytes;
+pub use compare::{DistributionDiff, QuantileDelta};
+pub use reservoir::SampledDDSketch;
+pub use view::DDSketchView;
+pub use merged::MergedView;
+pub use multires::MultiResolutionDDSketch;
+pub use builder::DDSketchBuilder;
+pub use plot::PlotPoints;
+pub use ingest::{InputFormat, IngestStats};
+pub use pool::{SketchPool, PooledSketch};
+pub use heavyhitters::{HeavyHitters, HeavyHitter};
+pub use minhash::MinHash;
+pub use frame::{SketchFrameWriter, SketchFrameReader};
+pub use conditioned::ConditionedSketch;
+pub use recency::RecencyDDSketch;
+pub use rate::SketchDelta;
+pub use watch::{QuantileWatch, QuantileSource, WatchRule, WatchEvent, WatchEventKind, Comparison, EmptyWindow};
 
 /// Errors that can occur in DDSketch operations
 #[derive(Debug, Clone, PartialEq)]
@@ -39,11 +121,35 @@ pub enum DDSketchError {
     /// Invalid relative accuracy (must be between 0 and 1)
     InvalidRelativeAccuracy,
     /// Invalid quantile value (must be between 0 and 1)
-    InvalidQuantile,
+    InvalidQuantile {
+        /// The quantile that was passed
+        got: f64,
+    },
     /// Empty sketch (no values added)
     EmptySketch,
     /// Incompatible sketches for merging
-    IncompatibleSketches,
+    IncompatibleSketches {
+        /// The gamma of this sketch's mapping, for sketches binned by one
+        self_gamma: Option<f64>,
+        /// The gamma of the other sketch's mapping, for sketches binned by one
+        other_gamma: Option<f64>,
+        /// The first difference found between the sketches
+        reason: IncompatibilityReason,
+    },
+    /// Invalid value for an index mapping (must be positive and finite)
+    InvalidValue,
+    /// A store refused counts that would exceed its maximum number of bins
+    BinLimitExceeded,
+    /// A NaN or infinite value was refused under `NonFinitePolicy::Error`
+    NonFiniteValue,
+    /// A value too small or too large in magnitude for the mapping was refused in strict mode
+    ValueOutOfRange,
+    /// Bytes could not be decoded as a sketch
+    InvalidEncoding,
+    /// Counts were refused because the total count would exceed `u64::MAX`
+    CountOverflow,
+    /// Reading or writing a sketch file failed
+    Io(std::io::ErrorKind),
 }
 
 impl std::fmt::Display for DDSketchError {
@@ -52,14 +158,35 @@ impl std::fmt::Display for DDSketchError {
             DDSketchError::InvalidRelativeAccuracy => {
                 write!(f, "Relative accuracy must be between 0 and 1")
             }
-            DDSketchError::InvalidQuantile => {
-                write!(f, "Quantile must be between 0 and 1")
+            DDSketchError::InvalidQuantile { got } => {
+                write!(f, "Quantile must be between 0 and 1, got {}", got)
             }
             DDSketchError::EmptySketch => {
                 write!(f, "Cannot compute quantile from empty sketch")
             }
-            DDSketchError::IncompatibleSketches => {
-                write!(f, "Sketches are incompatible for merging")
+            DDSketchError::IncompatibleSketches { reason, .. } => {
+                write!(f, "Sketches are incompatible for merging: {}", reason)
+            }
+            DDSketchError::InvalidValue => {
+                write!(f, "Value must be positive and finite")
+            }
+            DDSketchError::BinLimitExceeded => {
+                write!(f, "Maximum number of bins exceeded")
+            }
+            DDSketchError::NonFiniteValue => {
+                write!(f, "Value must be finite")
+            }
+            DDSketchError::ValueOutOfRange => {
+                write!(f, "Value is outside the range the mapping can index")
+            }
+            DDSketchError::InvalidEncoding => {
+                write!(f, "Invalid sketch encoding")
+            }
+            DDSketchError::CountOverflow => {
+                write!(f, "Total count would exceed the largest representable count")
+            }
+            DDSketchError::Io(kind) => {
+                write!(f, "I/O error: {}", kind)
             }
         }
     }
@@ -67,5 +194,32 @@ impl std::fmt::Display for DDSketchError {
 
 impl std::error::Error for DDSketchError {}
 
+impl DDSketchError {
+    /// Report sketches whose logarithmic mappings have different relative accuracies
+    pub(crate) fn different_accuracy(ours: f64, theirs: f64) -> Self {
+        let (ours, theirs) = (1.0 + ours, 1.0 + theirs);
+        DDSketchError::IncompatibleSketches {
+            self_gamma: Some(ours),
+            other_gamma: Some(theirs),
+            reason: IncompatibilityReason::DifferentGamma { ours, theirs },
+        }
+    }
+    
+    /// Report sketches that differ in a parameter other than their mapping
+    pub(crate) fn different_parameter(name: &'static str, ours: f64, theirs: f64) -> Self {
+        DDSketchError::IncompatibleSketches {
+            self_gamma: None,
+            other_gamma: None,
+            reason: IncompatibilityReason::DifferentParameter { name, ours, theirs },
+        }
+    }
+}
+
+impl From<std::io::Error> for DDSketchError {
+    fn from(error: std::io::Error) -> Self {
+        DDSketchError::Io(error.kind())
+    }
+}
+
 /// Result type for DDSketch operations
 pub type Result<T> = std::result::Result<T, DDSketchError>;
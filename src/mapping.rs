@@ -1,14 +1,363 @@
 //! Index mapping for DDSketch
 //!
 //! This module provides the logarithmic index mapping that is core to DDSketch's
-//! relative error guarantees.
+//! relative error guarantees, and a tail-biased variant that spends more bins on
+//! large values.
 
 use crate::{DDSketchError, Result};
+use std::fmt;
+
+/// The relative difference below which two gammas or offsets are considered equal
+///
+/// Bins only drift apart by `index × difference`, so this keeps every index up to
+/// about a billion aligned while tolerating rounding in how gamma was computed.
+const MAPPING_TOLERANCE: f64 = 1e-12;
+
+/// The family of function a mapping uses to turn values into indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MappingKind {
+    /// `index = ⌊log_γ(value) + offset⌋`
+    Logarithmic,
+    /// Logarithmic with a coarse `γ` below a threshold and a finer `tail_gamma` above it
+    TailBiased,
+}
+
+/// The parameters that determine which bin every value falls into
+///
+/// Two sketches can be merged bin by bin exactly when their descriptors agree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MappingDescriptor {
+    /// The family of the mapping
+    pub kind: MappingKind,
+    /// The ratio between the bounds of consecutive bins
+    pub gamma: f64,
+    /// The ratio between the bounds of consecutive bins above the tail threshold,
+    /// equal to `gamma` for mappings without a separate tail
+    pub tail_gamma: f64,
+    /// The shift applied to indices
+    pub offset: f64,
+}
+
+/// Why two sketches cannot be merged bin by bin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IncompatibilityReason {
+    /// The mappings belong to different families
+    DifferentKind {
+        /// The kind of this sketch's mapping
+        ours: MappingKind,
+        /// The kind of the other sketch's mapping
+        theirs: MappingKind,
+    },
+    /// The bins grow at different rates
+    DifferentGamma {
+        /// The gamma of this sketch's mapping
+        ours: f64,
+        /// The gamma of the other sketch's mapping
+        theirs: f64,
+    },
+    /// The bins have the same width but are shifted
+    DifferentOffset {
+        /// The offset of this sketch's mapping
+        ours: f64,
+        /// The offset of the other sketch's mapping
+        theirs: f64,
+    },
+    /// A parameter outside the mapping differs, such as a unit or a compression
+    DifferentParameter {
+        /// The name of the parameter
+        name: &'static str,
+        /// The value of the parameter in this sketch
+        ours: f64,
+        /// The value of the parameter in the other sketch
+        theirs: f64,
+    },
+    /// The sketch no longer holds the state a delta was computed from
+    DifferentBase,
+}
+
+impl fmt::Display for IncompatibilityReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncompatibilityReason::DifferentKind { ours, theirs } => {
+                write!(f, "mapping kinds differ: {:?} and {:?}", ours, theirs)
+            }
+            IncompatibilityReason::DifferentGamma { ours, theirs } => {
+                write!(f, "gammas differ: {} and {}", ours, theirs)
+            }
+            IncompatibilityReason::DifferentOffset { ours, theirs } => {
+                write!(f, "offsets differ: {} and {}", ours, theirs)
+            }
+            IncompatibilityReason::DifferentParameter { name, ours, theirs } => {
+                write!(f, "{} differs: {} and {}", name, ours, theirs)
+            }
+            IncompatibilityReason::DifferentBase => {
+                write!(f, "the sketch does not hold the base the delta was computed from")
+            }
+        }
+    }
+}
+
+impl MappingDescriptor {
+    /// Check whether values fall into the same bins under both mappings
+    ///
+    /// # Arguments
+    /// * `other` - The descriptor to compare with
+    ///
+    /// # Returns
+    /// The first difference found, in the order kind, gamma, offset
+    pub fn check_compatible(&self, other: &MappingDescriptor) -> std::result::Result<(), IncompatibilityReason> {
+        if self.kind != other.kind {
+            return Err(IncompatibilityReason::DifferentKind { ours: self.kind, theirs: other.kind });
+        }
+        if (self.gamma - other.gamma).abs() > MAPPING_TOLERANCE * self.gamma.max(other.gamma) {
+            return Err(IncompatibilityReason::DifferentGamma { ours: self.gamma, theirs: other.gamma });
+        }
+        if (self.tail_gamma - other.tail_gamma).abs() > MAPPING_TOLERANCE * self.tail_gamma.max(other.tail_gamma) {
+            return Err(IncompatibilityReason::DifferentGamma { ours: self.tail_gamma, theirs: other.tail_gamma });
+        }
+        if (self.offset - other.offset).abs() > MAPPING_TOLERANCE * self.offset.abs().max(other.offset.abs()).max(1.0) {
+            return Err(IncompatibilityReason::DifferentOffset { ours: self.offset, theirs: other.offset });
+        }
+        Ok(())
+    }
+    
+    /// Check that values fall into the same bins under both mappings, reporting a
+    /// difference as a sketch incompatibility
+    pub(crate) fn check_mergeable(&self, other: &MappingDescriptor) -> Result<()> {
+        self.check_compatible(other).map_err(|reason| DDSketchError::IncompatibleSketches {
+            self_gamma: Some(self.gamma),
+            other_gamma: Some(other.gamma),
+            reason,
+        })
+    }
+    
+    /// Round the parameters to ten significant digits
+    ///
+    /// The same mapping built along different paths, say from a relative accuracy or
+    /// from a decoded gamma, can differ in the last bits of its parameters. Rounding
+    /// well above those bits gives such descriptors identical values, so they can be
+    /// hashed or compared exactly.
+    pub fn normalized(&self) -> MappingDescriptor {
+        MappingDescriptor {
+            kind: self.kind,
+            gamma: round_significant(self.gamma),
+            tail_gamma: round_significant(self.tail_gamma),
+            offset: round_significant(self.offset),
+        }
+    }
+}
+
+/// Round to ten significant digits, counting digits from the units for values below one
+fn round_significant(value: f64) -> f64 {
+    let scale = 10f64.powi(9 - value.abs().max(1.0).log10().floor() as i32);
+    // Adding zero turns a negative zero positive
+    (value * scale).round() / scale + 0.0
+}
+
+/// The number of bins remembered by a `KeyCache`
+const KEY_CACHE_SIZE: usize = 4;
+
+/// The relative margin kept inside cached bin bounds
+///
+/// Values this close to a bound could round into the neighbouring bin when the key is
+/// computed, so they are not answered from the cache.
+const KEY_CACHE_MARGIN: f64 = 1e-9;
+
+/// A tiny cache of the bins recent values fell into
+///
+/// Quantized inputs such as timer readings repeat the same few values, and every value
+/// inside a cached bin gets its key without computing a logarithm. The cache keeps the
+/// most recently used bins first and evicts the least recently used.
+#[derive(Debug, Clone, Default)]
+pub struct KeyCache {
+    /// `(lowest, highest, key)` of the cached bins, most recently used first
+    entries: [(f64, f64, i32); KEY_CACHE_SIZE],
+    len: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl KeyCache {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Get the number of lookups answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+    
+    /// Get the number of lookups that had to compute the key
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+    
+    /// Find the key of a value in the cached bins, moving the bin to the front
+    fn lookup(&mut self, value: f64) -> Option<i32> {
+        let position = self.entries[..self.len].iter()
+            .position(|&(lowest, highest, _)| lowest <= value && value < highest);
+        match position {
+            Some(position) => {
+                self.hits += 1;
+                self.entries[..=position].rotate_right(1);
+                Some(self.entries[0].2)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+    
+    /// Remember a bin, evicting the least recently used one if the cache is full
+    fn insert(&mut self, lower: f64, upper: f64, key: i32) {
+        self.len = (self.len + 1).min(KEY_CACHE_SIZE);
+        self.entries[..self.len].rotate_right(1);
+        self.entries[0] = (lower * (1.0 + KEY_CACHE_MARGIN), upper * (1.0 - KEY_CACHE_MARGIN), key);
+    }
+}
+
+/// The integers below which `IntegerKeyTable` stores every key directly
+const SMALL_INTEGER_LIMIT: u64 = 1024;
+
+/// The most bins above `SMALL_INTEGER_LIMIT` an `IntegerKeyTable` holds, 512 KiB of bounds
+const MAX_INTEGER_KEY_BINS: i32 = 1 << 16;
+
+/// Precomputed keys of a mapping for integer values
+///
+/// Integer inputs such as nanosecond durations or byte counts can be keyed without a
+/// conversion or a logarithm: keys below 1024 are read from a table, and larger values
+/// are located with a binary search among the integer bounds of the bins within the
+/// value's power of two. Keys are exactly those `key` gives for `value as f64`.
+///
+/// Covering every integer takes about `19 / relative_accuracy` bins, so for accuracies
+/// finer than about 0.03% the table stops at the last power of two within 65,536 bins,
+/// and larger values must be keyed with the mapping.
+#[derive(Debug, Clone)]
+pub struct IntegerKeyTable {
+    /// The keys of the integers below `SMALL_INTEGER_LIMIT`
+    small: Vec<i32>,
+    /// The key of the bin holding `SMALL_INTEGER_LIMIT`
+    first_key: i32,
+    /// The largest integer the table has a key for
+    max_value: u64,
+    /// The lowest integer in each bin from `first_key` on
+    bounds: Vec<u64>,
+    /// The position in `bounds` of the bin holding each power of two, and the last bin
+    octave_starts: [usize; 65],
+}
+
+impl IntegerKeyTable {
+    /// Build the table for a mapping
+    ///
+    /// This computes a key for every bin from 1024 up, at most 65,536 of them and
+    /// about `19 / relative_accuracy` when they reach `u64::MAX`, so the table is meant
+    /// to be built once and reused.
+    pub fn new<M: IndexMapping + ?Sized>(mapping: &M) -> Self {
+        let key = |value: u64| mapping.key(value as f64);
+        let small = (0..SMALL_INTEGER_LIMIT).map(key).collect();
+        let first_key = key(SMALL_INTEGER_LIMIT);
+        
+        // Cover whole powers of two, as many as fit in the bin budget
+        let (mut max_value, mut last_key) = (SMALL_INTEGER_LIMIT - 1, first_key);
+        for octave in SMALL_INTEGER_LIMIT.trailing_zeros() + 1..=64 {
+            let (end, end_key) = match octave {
+                64 => (u64::MAX, key(u64::MAX)),
+                _ => ((1 << octave) - 1, key(1 << octave)),
+            };
+            if end_key.saturating_sub(first_key) > MAX_INTEGER_KEY_BINS {
+                break;
+            }
+            (max_value, last_key) = (end, end_key);
+        }
+        
+        let mut bounds = vec![SMALL_INTEGER_LIMIT];
+        for index in first_key.saturating_add(1)..=last_key {
+            // The lowest integer with a key of at least `index`, near the bin's lower bound
+            let previous = *bounds.last().expect("bounds start with the limit");
+            let estimate = mapping.lower_bound(index);
+            let (mut low, mut high) = (
+                ((estimate * (1.0 - 1e-6)) as u64).max(previous),
+                ((estimate * (1.0 + 1e-6)) as u64).saturating_add(2),
+            );
+            if key(low) >= index || key(high) < index {
+                (low, high) = (previous, u64::MAX);
+            }
+            while low < high {
+                let middle = low + (high - low) / 2;
+                if key(middle) >= index {
+                    high = middle;
+                } else {
+                    low = middle + 1;
+                }
+            }
+            bounds.push(low);
+        }
+        
+        let mut octave_starts = [bounds.len() - 1; 65];
+        for (octave, start) in octave_starts.iter_mut().enumerate().take(64) {
+            *start = bounds.partition_point(|&bound| bound <= 1u64 << octave).saturating_sub(1);
+        }
+        
+        IntegerKeyTable { small, first_key, max_value, bounds, octave_starts }
+    }
+    
+    /// Get the key of a positive integer
+    ///
+    /// The result is unspecified for zero, as for `IndexMapping::key`.
+    ///
+    /// # Returns
+    /// The key, or `None` if the value is beyond the table, in which case it should be
+    /// keyed with the mapping
+    pub fn key(&self, value: u64) -> Option<i32> {
+        if value < SMALL_INTEGER_LIMIT {
+            return Some(self.small[value as usize]);
+        }
+        if value > self.max_value {
+            return None;
+        }
+        
+        let octave = 63 - value.leading_zeros() as usize;
+        let (start, end) = (self.octave_starts[octave], self.octave_starts[octave + 1]);
+        let offset = self.bounds[start + 1..=end].partition_point(|&bound| bound <= value);
+        Some(self.first_key + (start + offset) as i32)
+    }
+}
 
 /// Trait for mapping values to indices
 pub trait IndexMapping {
-    /// Map a value to its corresponding index
-    fn key(&self, value: f64) -> Result<i32>;
+    /// Map a positive, finite value to its corresponding index
+    ///
+    /// The result is unspecified for zero, negative, or non-finite values; use
+    /// `checked_key` when the input has not been validated.
+    fn key(&self, value: f64) -> i32;
+    
+    /// Map a value to its corresponding index, rejecting values that are not positive and finite
+    fn checked_key(&self, value: f64) -> Result<i32> {
+        if value > 0.0 && value.is_finite() {
+            Ok(self.key(value))
+        } else {
+            Err(DDSketchError::InvalidValue)
+        }
+    }
+    
+    /// Map a positive, finite value to its index, reusing the bins of recent values
+    ///
+    /// The result is the same as `key`. On a miss the bounds of the value's bin are
+    /// computed and cached, which relies on `lower_bound` and `upper_bound` describing
+    /// exactly the values that `key` maps to each index.
+    fn key_cached(&self, value: f64, cache: &mut KeyCache) -> i32 {
+        if let Some(key) = cache.lookup(value) {
+            return key;
+        }
+        
+        let key = self.key(value);
+        cache.insert(self.lower_bound(key), self.upper_bound(key), key);
+        key
+    }
     
     /// Get the value corresponding to an index
     fn value(&self, index: i32) -> f64;
@@ -21,6 +370,123 @@ pub trait IndexMapping {
     
     /// Get the maximum possible index
     fn max_possible_index(&self) -> i32;
+    
+    /// Get the parameters that determine which bin every value falls into
+    ///
+    /// The default implementation describes a logarithmic mapping with
+    /// `γ = 1 + relative_accuracy` and no offset, as `LogarithmicMapping` uses;
+    /// mappings with another layout must override it.
+    fn descriptor(&self) -> MappingDescriptor {
+        MappingDescriptor {
+            kind: MappingKind::Logarithmic,
+            gamma: 1.0 + self.relative_accuracy(),
+            tail_gamma: 1.0 + self.relative_accuracy(),
+            offset: 0.0,
+        }
+    }
+    
+    /// Get the lowest value that maps to an index
+    ///
+    /// The default implementation assumes `value(index)` returns the lower bound of
+    /// the bucket, as `LogarithmicMapping` does.
+    fn lower_bound(&self, index: i32) -> f64 {
+        self.value(index)
+    }
+    
+    /// Get the value just above the highest value that maps to an index
+    ///
+    /// Buckets are half-open, so this is the lower bound of the next bucket.
+    fn upper_bound(&self, index: i32) -> f64 {
+        self.lower_bound(index.saturating_add(1))
+    }
+    
+    /// Get the smallest positive value that maps to an index of its own
+    ///
+    /// Smaller values would share the saturated `min_possible_index`, or lose precision
+    /// as subnormal floats.
+    fn min_indexable_value(&self) -> f64 {
+        self.lower_bound(self.min_possible_index().saturating_add(1)).max(f64::MIN_POSITIVE)
+    }
+    
+    /// Get the largest value that maps to an index of its own
+    ///
+    /// Larger values would share the saturated `max_possible_index`.
+    fn max_indexable_value(&self) -> f64 {
+        self.lower_bound(self.max_possible_index()).min(f64::MAX)
+    }
+    
+    /// Get the range of values `[lower, upper)` that share a bucket with a positive, finite value
+    fn bucket_bounds(&self, value: f64) -> (f64, f64) {
+        let index = self.key(value);
+        (self.lower_bound(index), self.upper_bound(index))
+    }
+    
+    /// Get the width of the bucket containing a positive, finite value, relative to that value
+    ///
+    /// This is the resolution available at `value`: a width of 0.04 at 100 ms means
+    /// values within roughly ±2 ms of it may be reported identically.
+    fn bucket_relative_width(&self, value: f64) -> f64 {
+        let (lower, upper) = self.bucket_bounds(value);
+        (upper - lower) / value
+    }
+}
+
+impl<T: IndexMapping + ?Sized> IndexMapping for Box<T> {
+    fn key(&self, value: f64) -> i32 {
+        (**self).key(value)
+    }
+    
+    fn checked_key(&self, value: f64) -> Result<i32> {
+        (**self).checked_key(value)
+    }
+    
+    fn key_cached(&self, value: f64, cache: &mut KeyCache) -> i32 {
+        (**self).key_cached(value, cache)
+    }
+    
+    fn value(&self, index: i32) -> f64 {
+        (**self).value(index)
+    }
+    
+    fn relative_accuracy(&self) -> f64 {
+        (**self).relative_accuracy()
+    }
+    
+    fn min_possible_index(&self) -> i32 {
+        (**self).min_possible_index()
+    }
+    
+    fn max_possible_index(&self) -> i32 {
+        (**self).max_possible_index()
+    }
+    
+    fn descriptor(&self) -> MappingDescriptor {
+        (**self).descriptor()
+    }
+    
+    fn lower_bound(&self, index: i32) -> f64 {
+        (**self).lower_bound(index)
+    }
+    
+    fn upper_bound(&self, index: i32) -> f64 {
+        (**self).upper_bound(index)
+    }
+    
+    fn min_indexable_value(&self) -> f64 {
+        (**self).min_indexable_value()
+    }
+    
+    fn max_indexable_value(&self) -> f64 {
+        (**self).max_indexable_value()
+    }
+    
+    fn bucket_bounds(&self, value: f64) -> (f64, f64) {
+        (**self).bucket_bounds(value)
+    }
+    
+    fn bucket_relative_width(&self, value: f64) -> f64 {
+        (**self).bucket_relative_width(value)
+    }
 }
 
 /// Logarithmic index mapping
@@ -56,13 +522,8 @@ impl LogarithmicMapping {
 }
 
 impl IndexMapping for LogarithmicMapping {
-    fn key(&self, value: f64) -> Result<i32> {
-        if value <= 0.0 {
-            return Ok(i32::MIN);
-        }
-        
-        let index = (value.ln() * self.multiplier + self.offset).floor() as i32;
-        Ok(index)
+    fn key(&self, value: f64) -> i32 {
+        (value.ln() * self.multiplier + self.offset).floor() as i32
     }
     
     fn value(&self, index: i32) -> f64 {
@@ -77,6 +538,146 @@ impl IndexMapping for LogarithmicMapping {
         self.relative_accuracy
     }
     
+    fn descriptor(&self) -> MappingDescriptor {
+        let gamma = (1.0 / self.multiplier).exp();
+        MappingDescriptor {
+            kind: MappingKind::Logarithmic,
+            gamma,
+            tail_gamma: gamma,
+            offset: self.offset,
+        }
+    }
+    
+    fn min_possible_index(&self) -> i32 {
+        i32::MIN
+    }
+    
+    fn max_possible_index(&self) -> i32 {
+        i32::MAX
+    }
+}
+
+/// Tail-biased index mapping
+///
+/// Values below `threshold` are binned logarithmically with the body accuracy and
+/// values at or above it with the finer tail accuracy, so the upper tail, where
+/// latency outliers live, is resolved more tightly than the bulk of the data at a
+/// modest cost in bins. Index 0 starts exactly at the threshold, so the two pieces
+/// meet without a gap or overlap.
+#[derive(Debug, Clone)]
+pub struct TailBiasedMapping {
+    body_accuracy: f64,
+    tail_accuracy: f64,
+    threshold: f64,
+    /// The multiplier for values below the threshold
+    body_multiplier: f64,
+    /// The multiplier for values at or above the threshold
+    tail_multiplier: f64,
+}
+
+impl TailBiasedMapping {
+    /// Create a new tail-biased mapping
+    ///
+    /// # Arguments
+    /// * `body_accuracy` - The relative accuracy below the threshold
+    /// * `tail_accuracy` - The relative accuracy at and above the threshold, no larger
+    ///   than `body_accuracy`
+    /// * `threshold` - The positive value where the tail starts
+    ///
+    /// # Returns
+    /// The mapping, or `DDSketchError::InvalidRelativeAccuracy` if an accuracy is not
+    /// in (0, 1) or the tail is coarser than the body, or `DDSketchError::InvalidValue`
+    /// if the threshold is not positive and finite
+    pub fn new(body_accuracy: f64, tail_accuracy: f64, threshold: f64) -> Result<Self> {
+        let valid = |accuracy: f64| accuracy > 0.0 && accuracy < 1.0;
+        if !valid(body_accuracy) || !valid(tail_accuracy) || tail_accuracy > body_accuracy {
+            return Err(DDSketchError::InvalidRelativeAccuracy);
+        }
+        if !(threshold > 0.0 && threshold.is_finite()) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(TailBiasedMapping {
+            body_accuracy,
+            tail_accuracy,
+            threshold,
+            body_multiplier: 1.0 / (1.0 + body_accuracy).ln(),
+            tail_multiplier: 1.0 / (1.0 + tail_accuracy).ln(),
+        })
+    }
+    
+    /// Get the relative accuracy below the threshold
+    pub fn body_accuracy(&self) -> f64 {
+        self.body_accuracy
+    }
+    
+    /// Get the relative accuracy at and above the threshold
+    pub fn tail_accuracy(&self) -> f64 {
+        self.tail_accuracy
+    }
+    
+    /// Get the value where the tail starts
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+    
+    /// Rebuild a tail-biased mapping from its descriptor, such as one read from an
+    /// encoded sketch
+    ///
+    /// # Arguments
+    /// * `descriptor` - The descriptor of a tail-biased mapping
+    ///
+    /// # Returns
+    /// The mapping, or `DDSketchError::InvalidValue` if the descriptor is not of a
+    /// valid tail-biased mapping
+    pub fn from_descriptor(descriptor: &MappingDescriptor) -> Result<Self> {
+        if descriptor.kind != MappingKind::TailBiased {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        // The offset is `-ln(threshold) / ln(gamma)`
+        let threshold = (-descriptor.offset * descriptor.gamma.ln()).exp();
+        let mapping = TailBiasedMapping::new(descriptor.gamma - 1.0, descriptor.tail_gamma - 1.0, threshold)
+            .map_err(|_| DDSketchError::InvalidValue)?;
+        descriptor.check_compatible(&mapping.descriptor()).map_err(|_| DDSketchError::InvalidValue)?;
+        Ok(mapping)
+    }
+}
+
+impl IndexMapping for TailBiasedMapping {
+    fn key(&self, value: f64) -> i32 {
+        let log_ratio = value.ln() - self.threshold.ln();
+        if value >= self.threshold {
+            (log_ratio * self.tail_multiplier).floor() as i32
+        } else {
+            // Strictly negative, so the body never reaches index 0
+            ((log_ratio * self.body_multiplier).floor() as i32).min(-1)
+        }
+    }
+    
+    fn value(&self, index: i32) -> f64 {
+        if index == i32::MIN {
+            return 0.0;
+        }
+        
+        let multiplier = if index >= 0 { self.tail_multiplier } else { self.body_multiplier };
+        self.threshold * (index as f64 / multiplier).exp()
+    }
+    
+    /// The body accuracy, the weakest guarantee the mapping gives
+    fn relative_accuracy(&self) -> f64 {
+        self.body_accuracy
+    }
+    
+    fn descriptor(&self) -> MappingDescriptor {
+        MappingDescriptor {
+            kind: MappingKind::TailBiased,
+            gamma: (1.0 / self.body_multiplier).exp(),
+            tail_gamma: (1.0 / self.tail_multiplier).exp(),
+            offset: -self.threshold.ln() * self.body_multiplier,
+        }
+    }
+    
     fn min_possible_index(&self) -> i32 {
         i32::MIN
     }
@@ -107,19 +708,93 @@ mod tests {
         
         // Test that key and value are inverses for positive values
         let value = 100.0;
-        let index = mapping.key(value).unwrap();
+        let index = mapping.key(value);
         let recovered = mapping.value(index);
         
         // The relative error should be within the specified accuracy
         let relative_error = (recovered - value).abs() / value;
         assert!(relative_error <= mapping.relative_accuracy());
         
-        // Test zero and negative values
-        assert_eq!(mapping.key(0.0).unwrap(), i32::MIN);
-        assert_eq!(mapping.key(-1.0).unwrap(), i32::MIN);
+        // Test zero, negative, and non-finite values
+        assert_eq!(mapping.checked_key(value), Ok(index));
+        assert_eq!(mapping.checked_key(0.0), Err(DDSketchError::InvalidValue));
+        assert_eq!(mapping.checked_key(-1.0), Err(DDSketchError::InvalidValue));
+        assert_eq!(mapping.checked_key(f64::INFINITY), Err(DDSketchError::InvalidValue));
+        assert_eq!(mapping.checked_key(f64::NAN), Err(DDSketchError::InvalidValue));
         assert_eq!(mapping.value(i32::MIN), 0.0);
     }
     
+    #[test]
+    fn test_bucket_bounds() {
+        let mapping = LogarithmicMapping::new(0.02).unwrap();
+        
+        for &value in &[0.001, 1.0, 100.0, 12345.6] {
+            let (lower, upper) = mapping.bucket_bounds(value);
+            assert!(lower <= value && value < upper);
+            assert_eq!(mapping.key(lower), mapping.key(value));
+            assert!((upper / lower - 1.02).abs() < 1e-9);
+            
+            let width = mapping.bucket_relative_width(value);
+            assert!(width > 0.0 && width <= 0.02 + 1e-12);
+        }
+    }
+    
+    #[test]
+    fn test_index_bounds() {
+        let mapping = LogarithmicMapping::new(0.01).unwrap();
+        
+        for index in [-500, -1, 0, 1, 700] {
+            let (lower, upper) = (mapping.lower_bound(index), mapping.upper_bound(index));
+            assert_eq!(mapping.key(lower * (1.0 + 1e-12)), index);
+            assert_eq!(mapping.key(upper * (1.0 - 1e-12)), index);
+            assert_eq!(mapping.key(upper * (1.0 + 1e-12)), index + 1);
+            assert_eq!(upper, mapping.lower_bound(index + 1));
+        }
+        assert_eq!(mapping.lower_bound(i32::MIN), 0.0);
+    }
+    
+    #[test]
+    fn test_descriptor_compatibility() {
+        let mapping = LogarithmicMapping::new(0.01).unwrap();
+        let descriptor = mapping.descriptor();
+        assert_eq!(descriptor.kind, MappingKind::Logarithmic);
+        assert!((descriptor.gamma - 1.01).abs() < 1e-12);
+        assert_eq!(descriptor.offset, 0.0);
+        
+        let boxed: Box<dyn IndexMapping> = Box::new(mapping.clone());
+        assert_eq!(descriptor.check_compatible(&boxed.descriptor()), Ok(()));
+        
+        let coarser = LogarithmicMapping::new(0.02).unwrap().descriptor();
+        assert!(matches!(
+            descriptor.check_compatible(&coarser),
+            Err(IncompatibilityReason::DifferentGamma { .. })
+        ));
+        let shifted = MappingDescriptor { offset: 0.5, ..descriptor };
+        assert_eq!(
+            descriptor.check_compatible(&shifted),
+            Err(IncompatibilityReason::DifferentOffset { ours: 0.0, theirs: 0.5 })
+        );
+        assert_eq!(
+            IncompatibilityReason::DifferentOffset { ours: 0.0, theirs: 0.5 }.to_string(),
+            "offsets differ: 0 and 0.5"
+        );
+    }
+    
+    #[test]
+    fn test_indexable_range() {
+        let mapping = LogarithmicMapping::new(0.01).unwrap();
+        assert_eq!(mapping.min_indexable_value(), f64::MIN_POSITIVE);
+        assert_eq!(mapping.max_indexable_value(), f64::MAX);
+        
+        // A fine mapping runs out of indices well within the range of floats
+        let fine = LogarithmicMapping::new(1e-9).unwrap();
+        let max = fine.max_indexable_value();
+        assert!(max > 1.0 && max < 1e10);
+        assert!(fine.key(max * 0.999) < i32::MAX);
+        assert_eq!(fine.key(max * 10.0), i32::MAX);
+        assert!((fine.min_indexable_value() * max - 1.0).abs() < 1e-3);
+    }
+    
     #[test]
     fn test_monotonicity() {
         let mapping = LogarithmicMapping::new(0.02).unwrap();
@@ -128,11 +803,119 @@ mod tests {
         let mut indices = Vec::new();
         
         for &value in &values {
-            indices.push(mapping.key(value).unwrap());
+            indices.push(mapping.key(value));
         }
         
         for i in 1..indices.len() {
             assert!(indices[i] >= indices[i-1]);
         }
     }
+    
+    #[test]
+    fn test_tail_biased_mapping() {
+        let mapping = TailBiasedMapping::new(0.05, 0.005, 100.0).unwrap();
+        assert_eq!(mapping.relative_accuracy(), 0.05);
+        assert_eq!(mapping.key(100.0), 0);
+        assert_eq!(mapping.key(99.9), -1);
+        assert!((mapping.upper_bound(-1) - 100.0).abs() < 1e-9);
+        
+        for value in [0.01, 1.0, 37.0, 99.0, 100.0, 150.0, 1e4, 1e9] {
+            let (lower, upper) = mapping.bucket_bounds(value);
+            assert!(lower <= value && value < upper * (1.0 + 1e-12), "value={}", value);
+            let accuracy = if value >= 100.0 { 0.005 } else { 0.05 };
+            assert!(mapping.bucket_relative_width(value) <= accuracy * (1.0 + 1e-9), "value={}", value);
+        }
+        
+        assert!(TailBiasedMapping::new(0.01, 0.02, 100.0).is_err());
+        assert!(TailBiasedMapping::new(0.05, 0.0, 100.0).is_err());
+        assert_eq!(TailBiasedMapping::new(0.05, 0.01, 0.0).unwrap_err(), DDSketchError::InvalidValue);
+        
+        let descriptor = mapping.descriptor();
+        assert_eq!(descriptor.kind, MappingKind::TailBiased);
+        assert_eq!(descriptor.check_compatible(&mapping.clone().descriptor()), Ok(()));
+        assert!(matches!(
+            descriptor.check_compatible(&LogarithmicMapping::new(0.05).unwrap().descriptor()),
+            Err(IncompatibilityReason::DifferentKind { .. })
+        ));
+        let finer_tail = TailBiasedMapping::new(0.05, 0.001, 100.0).unwrap().descriptor();
+        assert!(matches!(
+            descriptor.check_compatible(&finer_tail),
+            Err(IncompatibilityReason::DifferentGamma { .. })
+        ));
+        let moved = TailBiasedMapping::new(0.05, 0.005, 200.0).unwrap().descriptor();
+        assert!(matches!(
+            descriptor.check_compatible(&moved),
+            Err(IncompatibilityReason::DifferentOffset { .. })
+        ));
+        
+        let rebuilt = TailBiasedMapping::from_descriptor(&descriptor).unwrap();
+        assert!((rebuilt.threshold() - 100.0).abs() < 1e-9);
+        assert_eq!(rebuilt.descriptor().check_compatible(&descriptor), Ok(()));
+        let logarithmic = LogarithmicMapping::new(0.05).unwrap().descriptor();
+        assert_eq!(TailBiasedMapping::from_descriptor(&logarithmic).unwrap_err(), DDSketchError::InvalidValue);
+    }
+    
+    #[test]
+    fn test_key_cache() {
+        let mapping = LogarithmicMapping::new(0.01).unwrap();
+        let mut cache = KeyCache::new();
+        
+        let values = [100.0, 100.05, 100.0, 5.0, 250.0, 100.02, 5.01, 250.1];
+        for &value in values.iter().cycle().take(100) {
+            assert_eq!(mapping.key_cached(value, &mut cache), mapping.key(value), "value={}", value);
+        }
+        assert!(cache.hits() > 50, "hits={}", cache.hits());
+        assert_eq!(cache.hits() + cache.misses(), 100);
+        
+        // Values at bin bounds are never answered from a neighbouring bin
+        for index in -50..50 {
+            for value in [mapping.lower_bound(index), mapping.upper_bound(index)] {
+                assert_eq!(mapping.key_cached(value, &mut cache), mapping.key(value));
+            }
+        }
+        
+        let tail_biased = TailBiasedMapping::new(0.05, 0.001, 10.0).unwrap();
+        let mut cache = KeyCache::new();
+        for value in [9.99, 10.0, 10.001, 9.5, 10.0, 9.99] {
+            assert_eq!(tail_biased.key_cached(value, &mut cache), tail_biased.key(value));
+        }
+    }
+    
+    #[test]
+    fn test_integer_key_table() {
+        let mapping = LogarithmicMapping::new(0.02).unwrap();
+        let table = IntegerKeyTable::new(&mapping);
+        
+        let mut values: Vec<u64> = (1..5000).collect();
+        values.extend(table.bounds.iter().flat_map(|&bound| [bound - 1, bound, bound + 1]));
+        values.extend((0..64).flat_map(|shift| [(1u64 << shift) - 1, 1 << shift, (1 << shift) + 1]));
+        values.extend([u64::MAX, u64::MAX - 1, 1 << 53, (1 << 53) + 1, 123_456_789_012_345]);
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..10_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            values.push(state >> (state % 60));
+        }
+        for value in values.into_iter().filter(|&value| value > 0) {
+            assert_eq!(table.key(value), Some(mapping.key(value as f64)), "value={}", value);
+        }
+        
+        let tail_biased = TailBiasedMapping::new(0.05, 0.001, 1e6).unwrap();
+        let table = IntegerKeyTable::new(&tail_biased);
+        for value in (1..100).map(|i| i * 9_999 + 999_000).chain([1_000_000, 999_999, 1 << 40]) {
+            assert_eq!(table.key(value), Some(tail_biased.key(value as f64)), "value={}", value);
+        }
+        
+        // Fine accuracies cover whole powers of two within the bin budget
+        let fine = LogarithmicMapping::new(0.0001).unwrap();
+        let table = IntegerKeyTable::new(&fine);
+        assert!(table.bounds.len() <= MAX_INTEGER_KEY_BINS as usize + 1);
+        assert!(table.max_value < u64::MAX && (table.max_value + 1).is_power_of_two());
+        for value in [1023, 1024, 5000, table.max_value / 3, table.max_value - 1, table.max_value] {
+            assert_eq!(table.key(value), Some(fine.key(value as f64)), "value={}", value);
+        }
+        assert_eq!(table.key(table.max_value + 1), None);
+        assert_eq!(table.key(u64::MAX), None);
+    }
 }
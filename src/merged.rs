@@ -0,0 +1,252 @@
+//! Merged views
+//!
+//! This module answers queries over the union of several compatible sketches
+//! without building a merged sketch, for one-off queries across many shards.
+
+use crate::{DDSketch, DDSketchError, Result};
+use crate::ddsketch::RankedBin;
+use crate::mapping::{IndexMapping, LogarithmicMapping};
+use crate::store::{DenseStore, Store};
+
+/// A read-only view of the union of several sketches
+///
+/// The view borrows the sketches and queries each of them, so no bins are copied.
+/// A quantile is found by a binary search over bins that counts the values at or
+/// below a bin in every sketch, taking O(k log n) per step for k sketches of n bins,
+/// on top of each sketch's cached rank index. Answers are the same as those of the
+/// merged sketch with `QuantileInterpolation::LowerBound`.
+#[derive(Debug, Clone)]
+pub struct MergedView<'a, M: IndexMapping = LogarithmicMapping, S: Store = DenseStore> {
+    sketches: Vec<&'a DDSketch<M, S>>,
+    count: u64,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+}
+
+/// The distance of the positive and negative bins from zero in `bin_order`, which
+/// keeps the bins of each sign apart whatever their indices
+const SIGN_OFFSET: i64 = 1 << 33;
+
+/// The position of a bin in value order, comparable across compatible sketches
+fn bin_order(bin: RankedBin) -> i64 {
+    match bin {
+        RankedBin::Negative(index) => -SIGN_OFFSET - index as i64,
+        RankedBin::Zero => 0,
+        RankedBin::Positive(index) => SIGN_OFFSET + index as i64,
+        RankedBin::Beyond => 2 * SIGN_OFFSET,
+    }
+}
+
+impl<'a, M: IndexMapping, S: Store> MergedView<'a, M, S> {
+    /// Create a view of the union of some sketches
+    ///
+    /// # Arguments
+    /// * `sketches` - The sketches to view together
+    ///
+    /// # Returns
+    /// The view, or `DDSketchError::IncompatibleSketches` if the sketches could not be
+    /// merged with one another
+    pub fn new(sketches: impl IntoIterator<Item = &'a DDSketch<M, S>>) -> Result<Self> {
+        let sketches: Vec<&'a DDSketch<M, S>> = sketches.into_iter().collect();
+        if let Some(first) = sketches.first() {
+            for sketch in &sketches {
+                first.check_mergeable(*sketch)?;
+            }
+        }
+        
+        let non_empty = || sketches.iter().filter(|sketch| !sketch.is_empty());
+        Ok(MergedView {
+            count: sketches.iter().map(|sketch| sketch.count()).sum(),
+            min_value: non_empty().filter_map(|sketch| sketch.min()).reduce(f64::min),
+            max_value: non_empty().filter_map(|sketch| sketch.max()).reduce(f64::max),
+            sketches,
+        })
+    }
+    
+    /// Get the sketches in the view
+    pub fn sketches(&self) -> &[&'a DDSketch<M, S>] {
+        &self.sketches
+    }
+    
+    /// Get the total count of values in the union
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    
+    /// Check if the union is empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    
+    /// Get the minimum value in the union
+    pub fn min(&self) -> Option<f64> {
+        self.min_value
+    }
+    
+    /// Get the maximum value in the union
+    pub fn max(&self) -> Option<f64> {
+        self.max_value
+    }
+    
+    /// Get the sum of all values in the union
+    pub fn sum(&self) -> f64 {
+        self.sketches.iter().map(|sketch| sketch.sum()).sum()
+    }
+    
+    /// Get the mean of the values in the union, or `None` if it is empty
+    pub fn mean(&self) -> Option<f64> {
+        (!self.is_empty()).then(|| self.sum() / self.count as f64)
+    }
+    
+    /// Get the value at a given quantile of the union
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        let (min, max) = match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) if !self.is_empty() => (min, max),
+            _ => return Err(DDSketchError::EmptySketch),
+        };
+        
+        let rank = (quantile * self.count as f64) as u64;
+        if rank >= self.count {
+            return Ok(max);
+        }
+        
+        // Find the first bin in value order at or below which more than `rank` values lie
+        let (mut low, mut high) = (-2 * SIGN_OFFSET, 2 * SIGN_OFFSET);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.count_at_or_below(middle) > rank {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        
+        let mapping = &self.sketches[0].mapping;
+        let value = match self.bin_with_order(low) {
+            RankedBin::Negative(index) => -mapping.upper_bound(index),
+            RankedBin::Positive(index) => mapping.lower_bound(index),
+            RankedBin::Zero => 0.0,
+            RankedBin::Beyond => max,
+        };
+        Ok(value.clamp(min, max))
+    }
+    
+    /// Get the values at several quantiles of the union
+    ///
+    /// # Arguments
+    /// * `quantiles` - The quantiles to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated values, in the order of `quantiles`
+    pub fn get_quantile_values(&self, quantiles: &[f64]) -> Result<Vec<f64>> {
+        quantiles.iter().map(|&quantile| self.get_quantile_value(quantile)).collect()
+    }
+    
+    /// Get the estimated number of values less than or equal to a given value
+    ///
+    /// This is the sum of `DDSketch::rank` over the sketches, which answers exactly
+    /// beyond each sketch's own extremes.
+    ///
+    /// # Arguments
+    /// * `value` - The value to compare against
+    pub fn rank(&self, value: f64) -> u64 {
+        self.sketches.iter().map(|sketch| sketch.rank(value)).sum()
+    }
+    
+    /// Get the estimated fraction of values less than or equal to a given value
+    ///
+    /// # Arguments
+    /// * `value` - The value to compare against
+    ///
+    /// # Returns
+    /// The estimated fraction (between 0 and 1) of values at or below `value`
+    pub fn cdf(&self, value: f64) -> Result<f64> {
+        if self.is_empty() {
+            return Err(DDSketchError::EmptySketch);
+        }
+        
+        Ok(self.rank(value) as f64 / self.count as f64)
+    }
+    
+    /// Count the values in bins at or before a position in value order
+    fn count_at_or_below(&self, order: i64) -> u64 {
+        self.sketches.iter()
+            .map(|sketch| {
+                let rank_index = sketch.rank_index();
+                let position = rank_index.partition_point(|&(bin, _)| bin_order(bin) <= order);
+                position.checked_sub(1).map_or(0, |previous| rank_index[previous].1)
+            })
+            .sum()
+    }
+    
+    /// Find the bin at a position in value order, which some sketch holds
+    fn bin_with_order(&self, order: i64) -> RankedBin {
+        self.sketches.iter()
+            .find_map(|sketch| {
+                let rank_index = sketch.rank_index();
+                let position = rank_index.partition_point(|&(bin, _)| bin_order(bin) < order);
+                rank_index.get(position).map(|&(bin, _)| bin).filter(|&bin| bin_order(bin) == order)
+            })
+            .unwrap_or(RankedBin::Beyond)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_merged_view_matches_merge() {
+        let mut shards = Vec::new();
+        for shard in 0..5 {
+            let mut sketch = DDSketch::new(0.02).unwrap();
+            for i in 0..200 {
+                sketch.add((i * 5 + shard) as f64 - 300.0);
+            }
+            shards.push(sketch);
+        }
+        let mut merged = DDSketch::new(0.02).unwrap();
+        merged.merge_all(&shards).unwrap();
+        
+        let view = MergedView::new(&shards).unwrap();
+        assert_eq!(view.count(), merged.count());
+        assert_eq!((view.min(), view.max()), (merged.min(), merged.max()));
+        assert!((view.sum() - merged.sum()).abs() < 1e-9);
+        for i in 0..=100 {
+            let q = i as f64 / 100.0;
+            assert_eq!(view.get_quantile_value(q).unwrap(), merged.get_quantile_value(q).unwrap(), "q={}", q);
+        }
+        for value in [-400.0, -150.0, 0.0, 17.0, 650.0, 1000.0] {
+            assert_eq!(view.rank(value), merged.rank(value), "value={}", value);
+        }
+        assert_eq!(view.cdf(200.0).unwrap(), merged.cdf(200.0).unwrap());
+    }
+    
+    #[test]
+    fn test_merged_view_edge_cases() {
+        let empty = DDSketch::new(0.02).unwrap();
+        let mut zeros = DDSketch::new(0.02).unwrap();
+        zeros.add_with_count(0.0, 3);
+        
+        let view = MergedView::new([&empty, &zeros]).unwrap();
+        assert_eq!(view.get_quantile_value(0.5).unwrap(), 0.0);
+        assert_eq!(view.get_quantile_values(&[0.0, 1.0]).unwrap(), vec![0.0, 0.0]);
+        
+        let view = MergedView::new([&empty]).unwrap();
+        assert_eq!(view.get_quantile_value(0.5), Err(DDSketchError::EmptySketch));
+        assert_eq!(view.cdf(1.0), Err(DDSketchError::EmptySketch));
+        assert!(MergedView::<LogarithmicMapping, DenseStore>::new([]).unwrap().is_empty());
+        
+        let coarse = DDSketch::new(0.05).unwrap();
+        assert!(matches!(MergedView::new([&zeros, &coarse]).unwrap_err(), DDSketchError::IncompatibleSketches { .. }));
+    }
+}
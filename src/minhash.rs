@@ -0,0 +1,210 @@
+//! MinHash signatures
+//!
+//! This module provides fixed-size MinHash signatures of sets, which estimate the
+//! Jaccard similarity of two sets and merge into the signature of their union.
+
+use crate::{DDSketchError, Result};
+use crate::hash::StableHasher;
+use std::hash::{Hash, Hasher};
+
+/// Mix a 64-bit value into an unrelated one (the splitmix64 finalizer)
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+/// A MinHash signature of a set
+///
+/// The signature keeps, for each of `num_hashes` hash functions, the smallest hash of
+/// any item added. The share of positions where two signatures agree estimates the
+/// Jaccard similarity of the sets with a standard error of about `1 / √num_hashes`.
+/// Items are hashed with a fixed algorithm, so signatures built in different processes
+/// can be compared and merged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MinHash {
+    mins: Vec<u64>,
+}
+
+impl MinHash {
+    /// Create the signature of an empty set
+    ///
+    /// # Arguments
+    /// * `num_hashes` - The number of hash functions, which sets the accuracy
+    ///
+    /// # Returns
+    /// A new MinHash, or `DDSketchError::InvalidValue` if `num_hashes` is zero
+    pub fn new(num_hashes: usize) -> Result<Self> {
+        if num_hashes == 0 {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(MinHash { mins: vec![u64::MAX; num_hashes] })
+    }
+    
+    /// Get the number of hash functions
+    pub fn num_hashes(&self) -> usize {
+        self.mins.len()
+    }
+    
+    /// Check whether no item has been added
+    pub fn is_empty(&self) -> bool {
+        self.mins.iter().all(|&min| min == u64::MAX)
+    }
+    
+    /// Get the smallest hash seen for each hash function
+    pub fn signature(&self) -> &[u64] {
+        &self.mins
+    }
+    
+    /// Add an item to the set
+    ///
+    /// Adding an item more than once has no further effect.
+    pub fn add<T: Hash + ?Sized>(&mut self, item: &T) {
+        let mut hasher = StableHasher::default();
+        item.hash(&mut hasher);
+        let base = hasher.finish();
+        
+        for (seed, min) in self.mins.iter_mut().enumerate() {
+            let hash = mix(base ^ mix(seed as u64 + 1));
+            *min = (*min).min(hash);
+        }
+    }
+    
+    /// Estimate the Jaccard similarity with another set
+    ///
+    /// # Arguments
+    /// * `other` - The signature of the other set
+    ///
+    /// # Returns
+    /// The estimated size of the intersection over the size of the union (between 0
+    /// and 1), `DDSketchError::IncompatibleSketches` if the signatures have different
+    /// numbers of hashes, or `DDSketchError::EmptySketch` if both sets are empty
+    pub fn jaccard(&self, other: &MinHash) -> Result<f64> {
+        self.check_compatible(other)?;
+        if self.is_empty() && other.is_empty() {
+            return Err(DDSketchError::EmptySketch);
+        }
+        
+        let matches = self.mins.iter().zip(&other.mins).filter(|(a, b)| a == b).count();
+        Ok(matches as f64 / self.mins.len() as f64)
+    }
+    
+    /// Merge another signature into this one, giving the signature of the union
+    ///
+    /// # Arguments
+    /// * `other` - The signature to merge
+    ///
+    /// # Returns
+    /// `DDSketchError::IncompatibleSketches` if the signatures have different numbers
+    /// of hashes, in which case the signature is left unchanged
+    pub fn merge(&mut self, other: &MinHash) -> Result<()> {
+        self.check_compatible(other)?;
+        for (min, &other_min) in self.mins.iter_mut().zip(&other.mins) {
+            *min = (*min).min(other_min);
+        }
+        Ok(())
+    }
+    
+    /// Clear all data from the signature
+    pub fn clear(&mut self) {
+        self.mins.fill(u64::MAX);
+    }
+    
+    fn check_compatible(&self, other: &MinHash) -> Result<()> {
+        if self.mins.len() != other.mins.len() {
+            return Err(DDSketchError::different_parameter(
+                "number of hashes",
+                self.mins.len() as f64,
+                other.mins.len() as f64,
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct MinHashRepr {
+    mins: Vec<u64>,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MinHash {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        use serde::de::Error;
+        
+        let repr = MinHashRepr::deserialize(deserializer)?;
+        if repr.mins.is_empty() {
+            return Err(D::Error::custom("the number of hashes must be at least one"));
+        }
+        
+        Ok(MinHash { mins: repr.mins })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_minhash_jaccard() {
+        let mut a = MinHash::new(512).unwrap();
+        let mut b = MinHash::new(512).unwrap();
+        assert_eq!(a.jaccard(&b), Err(DDSketchError::EmptySketch));
+        
+        // 600 shared items out of 1400 in the union
+        for i in 0..1000 {
+            a.add(&i);
+        }
+        for i in 400..1400 {
+            b.add(&i);
+        }
+        let estimate = a.jaccard(&b).unwrap();
+        let exact = 600.0 / 1400.0;
+        assert!((estimate - exact).abs() < 3.0 / (512f64).sqrt(), "estimate={}", estimate);
+        assert_eq!(a.jaccard(&a).unwrap(), 1.0);
+        
+        let mut words = MinHash::new(64).unwrap();
+        words.add("checkout");
+        words.add("checkout");
+        let mut same = MinHash::new(64).unwrap();
+        same.add(&"checkout".to_string());
+        assert_eq!(words, same);
+        assert!(MinHash::new(0).is_err());
+    }
+    
+    #[test]
+    fn test_minhash_merge() {
+        let mut left = MinHash::new(128).unwrap();
+        let mut right = MinHash::new(128).unwrap();
+        let mut whole = MinHash::new(128).unwrap();
+        for i in 0..500u64 {
+            if i % 3 == 0 { left.add(&i) } else { right.add(&i) }
+            whole.add(&i);
+        }
+        left.merge(&right).unwrap();
+        assert_eq!(left, whole);
+        
+        let mut other = MinHash::new(64).unwrap();
+        assert!(matches!(other.merge(&whole), Err(DDSketchError::IncompatibleSketches { .. })));
+        assert!(other.jaccard(&whole).is_err());
+        whole.clear();
+        assert!(whole.is_empty());
+    }
+    
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_minhash_serde() {
+        let mut signature = MinHash::new(16).unwrap();
+        for i in 0..100u64 {
+            signature.add(&i);
+        }
+        let json = serde_json::to_string(&signature).unwrap();
+        let decoded: MinHash = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, signature);
+        
+        assert!(serde_json::from_str::<MinHash>(r#"{"mins":[]}"#).is_err());
+    }
+}
@@ -0,0 +1,561 @@
+//! Moments sketch
+//!
+//! This module provides the moments sketch: the count, min, max and power sums of
+//! the values up to a fixed order. It takes a dozen `f64`s regardless of the data,
+//! merges by adding the sums, and recovers approximate quantiles by solving for the
+//! maximum-entropy distribution that has the recorded moments.
+
+use crate::{DDSketchError, IntoSketchValue, Result};
+
+/// The highest supported order; beyond it power sums lose too much precision
+pub const MAX_MOMENTS_ORDER: usize = 20;
+
+/// The number of grid points used to integrate the maximum-entropy density
+const GRID_SIZE: usize = 1024;
+
+/// The largest gradient at which the maximum-entropy solver has converged
+const SOLVER_TOLERANCE: f64 = 1e-9;
+
+/// The maximum number of Newton steps per solve
+const SOLVER_MAX_ITERATIONS: usize = 200;
+
+/// A sketch of the power sums of a set of values
+///
+/// Quantiles are estimated by fitting a density `exp(Σ λⱼ Tⱼ(u))` over `[min, max]`,
+/// where `Tⱼ` are Chebyshev polynomials, whose moments match the recorded ones. The
+/// estimate is smooth and has no guaranteed error bound; it is best for smooth,
+/// unimodal data, and degrades for data with sharp spikes or far from zero relative
+/// to its spread, where the raw power sums lose precision. If the solver does not
+/// converge at the full order, lower orders are tried down to a uniform fit.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MomentsSketch {
+    /// Power sums `Σ xⁱ` for `i` from 1 to the order
+    power_sums: Vec<f64>,
+    count: u64,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+}
+
+impl MomentsSketch {
+    /// Create a new, empty moments sketch
+    ///
+    /// # Arguments
+    /// * `order` - The highest power to track (between 1 and `MAX_MOMENTS_ORDER`);
+    ///   around 10 is a good balance of accuracy and numerical stability
+    ///
+    /// # Returns
+    /// A new MomentsSketch, or `DDSketchError::InvalidValue` if the order is out of range
+    pub fn new(order: usize) -> Result<Self> {
+        if !(1..=MAX_MOMENTS_ORDER).contains(&order) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(MomentsSketch {
+            power_sums: vec![0.0; order],
+            count: 0,
+            min_value: None,
+            max_value: None,
+        })
+    }
+    
+    /// Get the highest power tracked by the sketch
+    pub fn order(&self) -> usize {
+        self.power_sums.len()
+    }
+    
+    /// Add a value to the sketch
+    ///
+    /// Non-finite values, and values that would overflow the count, are ignored; use
+    /// `try_add` to detect them.
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        let _ = self.try_add_with_count(value, 1);
+    }
+    
+    /// Add a value with a specific count to the sketch
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&mut self, value: impl IntoSketchValue, count: u64) {
+        let _ = self.try_add_with_count(value, count);
+    }
+    
+    /// Add a value to the sketch, reporting non-finite values
+    ///
+    /// # Returns
+    /// `DDSketchError::NonFiniteValue` if the value is NaN or infinite, or
+    /// `DDSketchError::CountOverflow` if the count would exceed `u64::MAX`
+    pub fn try_add(&mut self, value: impl IntoSketchValue) -> Result<()> {
+        self.try_add_with_count(value, 1)
+    }
+    
+    /// Add a value with a specific count to the sketch, reporting non-finite values
+    ///
+    /// # Returns
+    /// `DDSketchError::NonFiniteValue` if the value is NaN or infinite, or
+    /// `DDSketchError::CountOverflow` if the count would exceed `u64::MAX`
+    pub fn try_add_with_count(&mut self, value: impl IntoSketchValue, count: u64) -> Result<()> {
+        let value = value.into_sketch_value();
+        if !value.is_finite() {
+            return Err(DDSketchError::NonFiniteValue);
+        }
+        if count == 0 {
+            return Ok(());
+        }
+        
+        self.count = self.count.checked_add(count).ok_or(DDSketchError::CountOverflow)?;
+        let mut power = count as f64;
+        for sum in &mut self.power_sums {
+            power *= value;
+            *sum += power;
+        }
+        self.min_value = Some(self.min_value.map_or(value, |min| min.min(value)));
+        self.max_value = Some(self.max_value.map_or(value, |max| max.max(value)));
+        
+        Ok(())
+    }
+    
+    /// Get the total count of values in the sketch
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    
+    /// Get the minimum value in the sketch
+    pub fn min(&self) -> Option<f64> {
+        self.min_value
+    }
+    
+    /// Get the maximum value in the sketch
+    pub fn max(&self) -> Option<f64> {
+        self.max_value
+    }
+    
+    /// Get the sum of the values raised to a power
+    ///
+    /// # Arguments
+    /// * `power` - The power (between 1 and the order)
+    pub fn power_sum(&self, power: usize) -> Option<f64> {
+        power.checked_sub(1).and_then(|i| self.power_sums.get(i)).copied()
+    }
+    
+    /// Get the mean of the values, or `None` if the sketch is empty
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.power_sums[0] / self.count as f64)
+    }
+    
+    /// Get the population variance of the values
+    ///
+    /// Returns `None` if the sketch is empty or tracks only the first power.
+    pub fn variance(&self) -> Option<f64> {
+        let mean = self.mean()?;
+        let mean_square = self.power_sum(2)? / self.count as f64;
+        Some((mean_square - mean * mean).max(0.0))
+    }
+    
+    /// Merge another moments sketch into this one
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to merge
+    ///
+    /// # Returns
+    /// `DDSketchError::IncompatibleSketches` if the sketches have different orders, or
+    /// `DDSketchError::CountOverflow` if the merged count would exceed `u64::MAX`, in
+    /// which case the sketch is left unchanged
+    pub fn merge(&mut self, other: &MomentsSketch) -> Result<()> {
+        if self.order() != other.order() {
+            return Err(DDSketchError::different_parameter("order", self.order() as f64, other.order() as f64));
+        }
+        
+        self.count = self.count.checked_add(other.count).ok_or(DDSketchError::CountOverflow)?;
+        for (sum, other_sum) in self.power_sums.iter_mut().zip(&other.power_sums) {
+            *sum += other_sum;
+        }
+        
+        if let Some(other_min) = other.min_value {
+            self.min_value = Some(self.min_value.map_or(other_min, |min| min.min(other_min)));
+        }
+        if let Some(other_max) = other.max_value {
+            self.max_value = Some(self.max_value.map_or(other_max, |max| max.max(other_max)));
+        }
+        
+        Ok(())
+    }
+    
+    /// Get the estimated value at a given quantile
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        Ok(self.get_quantile_values(&[quantile])?[0])
+    }
+    
+    /// Get the estimated values at several quantiles, solving for the density once
+    ///
+    /// # Arguments
+    /// * `quantiles` - The quantiles to query (each between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated values, in the order of `quantiles`
+    pub fn get_quantile_values(&self, quantiles: &[f64]) -> Result<Vec<f64>> {
+        if let Some(&got) = quantiles.iter().find(|q| !(0.0..=1.0).contains(*q)) {
+            return Err(DDSketchError::InvalidQuantile { got });
+        }
+        
+        let (Some(min), Some(max)) = (self.min_value, self.max_value) else {
+            return Err(DDSketchError::EmptySketch);
+        };
+        if min == max {
+            return Ok(vec![min; quantiles.len()]);
+        }
+        
+        let cdf = self.solve_cdf(min, max);
+        let to_value = |u: f64| (min + (u + 1.0) * (max - min) / 2.0).clamp(min, max);
+        
+        Ok(quantiles.iter()
+            .map(|&q| {
+                if q == 0.0 {
+                    return min;
+                }
+                if q == 1.0 {
+                    return max;
+                }
+                
+                let cell = cdf.partition_point(|&c| c < q).min(GRID_SIZE - 1);
+                let below = if cell == 0 { 0.0 } else { cdf[cell - 1] };
+                let fraction = if cdf[cell] > below { (q - below) / (cdf[cell] - below) } else { 0.5 };
+                to_value(-1.0 + (cell as f64 + fraction) * grid_step())
+            })
+            .collect())
+    }
+    
+    /// Clear all data from the sketch
+    pub fn clear(&mut self) {
+        self.power_sums.iter_mut().for_each(|sum| *sum = 0.0);
+        self.count = 0;
+        self.min_value = None;
+        self.max_value = None;
+    }
+    
+    /// Get the Chebyshev moments `E[Tⱼ(u)]` of the values rescaled to `u` in `[-1, 1]`
+    fn chebyshev_moments(&self, min: f64, max: f64) -> Vec<f64> {
+        let order = self.order();
+        let n = self.count as f64;
+        
+        // u = a·x + b, so E[uʲ] = Σᵢ C(j, i) aⁱ b^(j-i) E[xⁱ]
+        let a = 2.0 / (max - min);
+        let b = -(max + min) / (max - min);
+        let raw: Vec<f64> = std::iter::once(1.0)
+            .chain(self.power_sums.iter().map(|sum| sum / n))
+            .collect();
+        
+        let mut scaled = vec![0.0; order + 1];
+        for (j, moment) in scaled.iter_mut().enumerate() {
+            let mut binomial = 1.0;
+            for (i, raw_moment) in raw.iter().enumerate().take(j + 1) {
+                *moment += binomial * a.powi(i as i32) * b.powi((j - i) as i32) * raw_moment;
+                binomial *= (j - i) as f64 / (i + 1) as f64;
+            }
+        }
+        
+        chebyshev_coefficients(order)
+            .iter()
+            .map(|coefficients| {
+                let moment: f64 = coefficients.iter().zip(&scaled).map(|(c, m)| c * m).sum();
+                moment.clamp(-1.0, 1.0)
+            })
+            .collect()
+    }
+    
+    /// Fit the maximum-entropy density and return its CDF at the end of each grid cell
+    fn solve_cdf(&self, min: f64, max: f64) -> Vec<f64> {
+        let moments = self.chebyshev_moments(min, max);
+        let grid: Vec<Vec<f64>> = (0..GRID_SIZE)
+            .map(|p| chebyshev_values(-1.0 + (p as f64 + 0.5) * grid_step(), self.order()))
+            .collect();
+        
+        let density = (0..=self.order()).rev()
+            .find_map(|order| solve_max_entropy(&moments[..=order], &grid))
+            .unwrap_or_else(|| vec![1.0; GRID_SIZE]);
+        
+        let total: f64 = density.iter().sum();
+        let mut cumulative = 0.0;
+        density.iter()
+            .map(|d| {
+                cumulative += d / total;
+                cumulative
+            })
+            .collect()
+    }
+}
+
+/// The serialized form of a `MomentsSketch`, checked before it becomes one
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct MomentsSketchRepr {
+    power_sums: Vec<f64>,
+    count: u64,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MomentsSketch {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        use serde::de::Error;
+        
+        let repr = MomentsSketchRepr::deserialize(deserializer)?;
+        let mut sketch = MomentsSketch::new(repr.power_sums.len()).map_err(D::Error::custom)?;
+        if repr.power_sums.iter().any(|sum| sum.is_nan()) {
+            return Err(D::Error::custom("power sums must not be NaN"));
+        }
+        
+        // Values and extremes come and go together
+        match (repr.min_value, repr.max_value) {
+            (None, None) if repr.count == 0 => {}
+            (Some(min), Some(max)) if repr.count > 0 && min.is_finite() && max.is_finite() && min <= max => {}
+            _ => return Err(D::Error::custom("count and extremes disagree")),
+        }
+        
+        sketch.power_sums = repr.power_sums;
+        sketch.count = repr.count;
+        sketch.min_value = repr.min_value;
+        sketch.max_value = repr.max_value;
+        Ok(sketch)
+    }
+}
+
+/// The width of a grid cell over `[-1, 1]`
+fn grid_step() -> f64 {
+    2.0 / GRID_SIZE as f64
+}
+
+/// Get the power-basis coefficients of the Chebyshev polynomials up to an order
+fn chebyshev_coefficients(order: usize) -> Vec<Vec<f64>> {
+    let mut polynomials = vec![vec![1.0], vec![0.0, 1.0]];
+    for j in 2..=order {
+        // T(j) = 2u·T(j-1) - T(j-2)
+        let mut next = vec![0.0; j + 1];
+        for (i, c) in polynomials[j - 1].iter().enumerate() {
+            next[i + 1] += 2.0 * c;
+        }
+        for (i, c) in polynomials[j - 2].iter().enumerate() {
+            next[i] -= c;
+        }
+        polynomials.push(next);
+    }
+    polynomials.truncate(order + 1);
+    polynomials
+}
+
+/// Evaluate the Chebyshev polynomials up to an order at a point
+fn chebyshev_values(u: f64, order: usize) -> Vec<f64> {
+    let mut values = vec![1.0, u];
+    for j in 2..=order {
+        values.push(2.0 * u * values[j - 1] - values[j - 2]);
+    }
+    values.truncate(order + 1);
+    values
+}
+
+/// Find the density `exp(Σ λⱼ Tⱼ)` on the grid whose Chebyshev moments match
+///
+/// Minimizes the convex dual `∫ exp(Σ λⱼ Tⱼ) - Σ λⱼ μⱼ` with damped Newton steps.
+/// Returns the unnormalized density at each grid point, or `None` if the solver
+/// does not converge.
+fn solve_max_entropy(moments: &[f64], grid: &[Vec<f64>]) -> Option<Vec<f64>> {
+    let size = moments.len();
+    let step = grid_step();
+    let density = |lambda: &[f64]| -> Vec<f64> {
+        grid.iter()
+            .map(|t| lambda.iter().zip(t).map(|(l, t)| l * t).sum::<f64>().exp())
+            .collect()
+    };
+    let dual = |lambda: &[f64], density: &[f64]| {
+        density.iter().sum::<f64>() * step - lambda.iter().zip(moments).map(|(l, m)| l * m).sum::<f64>()
+    };
+    
+    // Start from the uniform density on [-1, 1]
+    let mut lambda = vec![0.0; size];
+    lambda[0] = 0.5f64.ln();
+    let mut current = density(&lambda);
+    
+    for _ in 0..SOLVER_MAX_ITERATIONS {
+        let mut gradient = vec![0.0; size];
+        let mut hessian = vec![vec![0.0; size]; size];
+        for (t, f) in grid.iter().zip(&current) {
+            for ((g, row), t_j) in gradient.iter_mut().zip(&mut hessian).zip(t) {
+                *g += t_j * f * step;
+                for (h, t_l) in row.iter_mut().zip(t) {
+                    *h += t_j * t_l * f * step;
+                }
+            }
+        }
+        for (g, moment) in gradient.iter_mut().zip(moments) {
+            *g -= moment;
+        }
+        
+        if gradient.iter().all(|g| g.abs() < SOLVER_TOLERANCE) {
+            return Some(current);
+        }
+        
+        let direction = solve_linear(hessian, gradient)?;
+        let value = dual(&lambda, &current);
+        let mut scale = 1.0;
+        loop {
+            let candidate: Vec<f64> = lambda.iter().zip(&direction).map(|(l, d)| l - scale * d).collect();
+            let candidate_density = density(&candidate);
+            let candidate_value = dual(&candidate, &candidate_density);
+            if candidate_value.is_finite() && candidate_value <= value {
+                lambda = candidate;
+                current = candidate_density;
+                break;
+            }
+            
+            scale /= 2.0;
+            if scale < 1e-10 {
+                return None;
+            }
+        }
+    }
+    
+    None
+}
+
+/// Solve a dense linear system by Gaussian elimination with partial pivoting
+fn solve_linear(mut matrix: Vec<Vec<f64>>, mut rhs: Vec<f64>) -> Option<Vec<f64>> {
+    let size = rhs.len();
+    for column in 0..size {
+        let pivot = (column..size).max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))?;
+        let pivot_value = matrix[pivot][column];
+        if pivot_value.is_nan() || pivot_value.abs() <= 1e-300 {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        rhs.swap(column, pivot);
+        
+        let (upper, lower) = matrix.split_at_mut(column + 1);
+        let pivot_row = &upper[column];
+        for (offset, row) in lower.iter_mut().enumerate() {
+            let factor = row[column] / pivot_row[column];
+            for (x, p) in row[column..].iter_mut().zip(&pivot_row[column..]) {
+                *x -= factor * p;
+            }
+            rhs[column + 1 + offset] -= factor * rhs[column];
+        }
+    }
+    
+    let mut solution = vec![0.0; size];
+    for row in (0..size).rev() {
+        let tail: f64 = (row + 1..size).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (rhs[row] - tail) / matrix[row][row];
+    }
+    
+    solution.iter().all(|x| x.is_finite()).then_some(solution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_moments_sketch_statistics() {
+        let mut sketch = MomentsSketch::new(4).unwrap();
+        assert!(MomentsSketch::new(0).is_err());
+        assert!(MomentsSketch::new(MAX_MOMENTS_ORDER + 1).is_err());
+        
+        for value in [1.0, 2.0, 3.0, 4.0] {
+            sketch.add(value);
+        }
+        assert_eq!(sketch.count(), 4);
+        assert_eq!(sketch.mean(), Some(2.5));
+        assert_eq!(sketch.variance(), Some(1.25));
+        assert_eq!(sketch.power_sum(3), Some(100.0));
+        assert_eq!(sketch.power_sum(5), None);
+        assert_eq!(sketch.try_add(f64::NAN), Err(DDSketchError::NonFiniteValue));
+        
+        let mut other = MomentsSketch::new(4).unwrap();
+        other.add_with_count(-1.0, 2);
+        sketch.merge(&other).unwrap();
+        assert_eq!(sketch.count(), 6);
+        assert_eq!(sketch.min(), Some(-1.0));
+        let error = sketch.merge(&MomentsSketch::new(3).unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "Sketches are incompatible for merging: order differs: 4 and 3");
+    }
+    
+    #[test]
+    fn test_moments_sketch_quantiles() {
+        let mut uniform = MomentsSketch::new(10).unwrap();
+        let mut skewed = MomentsSketch::new(10).unwrap();
+        let n = 10_000;
+        for i in 0..n {
+            let u = (i as f64 + 0.5) / n as f64;
+            uniform.add(100.0 + 50.0 * u);
+            // Exponential with mean 1, through the inverse CDF
+            skewed.add(-(1.0 - u).ln());
+        }
+        
+        for q in [0.1, 0.5, 0.9] {
+            let estimate = uniform.get_quantile_value(q).unwrap();
+            assert!((estimate - (100.0 + 50.0 * q)).abs() < 1.0, "q={} estimate={}", q, estimate);
+            
+            let expected = -(1.0 - q).ln();
+            let estimate = skewed.get_quantile_value(q).unwrap();
+            assert!((estimate - expected).abs() < 0.05 * expected.max(0.5), "q={} estimate={}", q, estimate);
+        }
+        
+        assert_eq!(uniform.get_quantile_value(0.0).unwrap(), uniform.min().unwrap());
+        assert_eq!(uniform.get_quantile_value(1.0).unwrap(), uniform.max().unwrap());
+        assert_eq!(uniform.get_quantile_value(2.0), Err(DDSketchError::InvalidQuantile { got: 2.0 }));
+        
+        let mut constant = MomentsSketch::new(6).unwrap();
+        assert_eq!(constant.get_quantile_value(0.5), Err(DDSketchError::EmptySketch));
+        constant.add_with_count(7.0, 10);
+        assert_eq!(constant.get_quantile_value(0.5).unwrap(), 7.0);
+    }
+    
+    #[test]
+    fn test_moments_count_overflow() {
+        let mut sketch = MomentsSketch::new(4).unwrap();
+        sketch.add_with_count(1.0, u64::MAX - 1);
+        assert_eq!(sketch.try_add_with_count(2.0, 2), Err(DDSketchError::CountOverflow));
+        assert_eq!((sketch.count(), sketch.max(), sketch.power_sum(1)), (u64::MAX - 1, Some(1.0), Some((u64::MAX - 1) as f64)));
+        
+        let copy = sketch.clone();
+        assert_eq!(sketch.merge(&copy), Err(DDSketchError::CountOverflow));
+        assert_eq!(sketch, copy);
+    }
+    
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_moments_serde() {
+        let mut sketch = MomentsSketch::new(6).unwrap();
+        for i in 1..=100 {
+            sketch.add(i as f64);
+        }
+        let json = serde_json::to_string(&sketch).unwrap();
+        assert_eq!(serde_json::from_str::<MomentsSketch>(&json).unwrap(), sketch);
+        
+        let empty = r#"{"power_sums":[0.0],"count":0,"min_value":null,"max_value":null}"#;
+        assert!(serde_json::from_str::<MomentsSketch>(empty).unwrap().is_empty());
+        for invalid in [
+            r#"{"power_sums":[],"count":3,"min_value":1.0,"max_value":2.0}"#,
+            r#"{"power_sums":[6.0],"count":3,"min_value":3.0,"max_value":1.0}"#,
+            r#"{"power_sums":[6.0],"count":3,"min_value":null,"max_value":null}"#,
+            r#"{"power_sums":[0.0],"count":0,"min_value":1.0,"max_value":1.0}"#,
+        ] {
+            assert!(serde_json::from_str::<MomentsSketch>(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Multi-resolution sketches
+//!
+//! This module provides a sketch that records every value at a coarse accuracy and
+//! the upper tail again at a fine accuracy, so that high quantiles are precise
+//! without paying for fine bins across the whole range.
+
+use crate::{DDSketch, DDSketchError, IntoSketchValue, Result};
+
+/// A coarse sketch of all values paired with a fine sketch of the values above a threshold
+///
+/// Every value goes into the coarse sketch, and values at or above `tail_threshold`
+/// also go into the fine one. A quantile whose rank falls among the tail values is
+/// answered by the fine sketch, to within its relative accuracy; any other quantile
+/// is answered by the coarse sketch. For latencies, a threshold near the expected
+/// p99 with a fine accuracy of 0.5% keeps p99.9 precise while most values only cost
+/// coarse bins.
+#[derive(Debug, Clone)]
+pub struct MultiResolutionDDSketch {
+    coarse: DDSketch,
+    fine: DDSketch,
+    tail_threshold: f64,
+}
+
+impl MultiResolutionDDSketch {
+    /// Create a new, empty sketch
+    ///
+    /// # Arguments
+    /// * `coarse_accuracy` - The relative accuracy for all values (between 0 and 1)
+    /// * `fine_accuracy` - The relative accuracy for values in the tail (between 0 and 1)
+    /// * `tail_threshold` - The smallest value recorded at the fine accuracy
+    ///
+    /// # Returns
+    /// A new MultiResolutionDDSketch, an error if either relative accuracy is invalid,
+    /// or `DDSketchError::InvalidValue` if the threshold is not finite
+    pub fn new(coarse_accuracy: f64, fine_accuracy: f64, tail_threshold: f64) -> Result<Self> {
+        if !tail_threshold.is_finite() {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(MultiResolutionDDSketch {
+            coarse: DDSketch::new(coarse_accuracy)?,
+            fine: DDSketch::new(fine_accuracy)?,
+            tail_threshold,
+        })
+    }
+    
+    /// Get the sketch of all values
+    pub fn coarse(&self) -> &DDSketch {
+        &self.coarse
+    }
+    
+    /// Get the sketch of the values at or above the tail threshold
+    pub fn fine(&self) -> &DDSketch {
+        &self.fine
+    }
+    
+    /// Get the smallest value recorded at the fine accuracy
+    pub fn tail_threshold(&self) -> f64 {
+        self.tail_threshold
+    }
+    
+    /// Add a value to the sketch
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        self.add_with_count(value, 1);
+    }
+    
+    /// Add a value with a specific count to the sketch
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&mut self, value: impl IntoSketchValue, count: u64) {
+        let value = value.into_sketch_value();
+        self.coarse.add_with_count(value, count);
+        if value.is_finite() && value >= self.tail_threshold {
+            self.fine.add_with_count(value, count);
+        }
+    }
+    
+    /// Get the total count of values in the sketch
+    pub fn count(&self) -> u64 {
+        self.coarse.count()
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.coarse.is_empty()
+    }
+    
+    /// Get the value at a given quantile
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile, from the fine sketch if it holds the
+    /// quantile's rank and from the coarse sketch otherwise
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        
+        let (count, tail_count) = (self.coarse.count(), self.fine.count());
+        let rank = (quantile * count as f64) as u64;
+        let body_count = count - tail_count;
+        if tail_count == 0 || rank < body_count || rank >= count {
+            return self.coarse.get_quantile_value(quantile);
+        }
+        
+        // Ask for the middle of the rank within the tail, so that rounding cannot move it
+        let tail_rank = rank - body_count;
+        self.fine.get_quantile_value((tail_rank as f64 + 0.5) / tail_count as f64)
+    }
+    
+    /// Merge another sketch into this one
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to merge
+    ///
+    /// # Returns
+    /// `DDSketchError::IncompatibleSketches` if the sketches have different accuracies
+    /// or tail thresholds, in which case the sketch is left unchanged
+    pub fn merge(&mut self, other: &MultiResolutionDDSketch) -> Result<()> {
+        if self.tail_threshold != other.tail_threshold {
+            return Err(DDSketchError::different_parameter("tail threshold", self.tail_threshold, other.tail_threshold));
+        }
+        self.coarse.check_mergeable(&other.coarse)?;
+        self.fine.check_mergeable(&other.fine)?;
+        
+        self.coarse.merge(&other.coarse)?;
+        self.fine.merge(&other.fine)
+    }
+    
+    /// Clear all data from the sketch
+    pub fn clear(&mut self) {
+        self.coarse.clear();
+        self.fine.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_multi_resolution_tail_accuracy() {
+        let mut sketch = MultiResolutionDDSketch::new(0.05, 0.005, 900.0).unwrap();
+        let mut coarse_only = DDSketch::new(0.05).unwrap();
+        for i in 1..=10_000 {
+            let value = i as f64 / 10.0;
+            sketch.add(value);
+            coarse_only.add(value);
+        }
+        assert_eq!(sketch.count(), 10_000);
+        assert_eq!(sketch.fine().count(), 1001);
+        
+        let p999 = sketch.get_quantile_value(0.999).unwrap();
+        assert!((p999 - 999.0).abs() <= 999.0 * 0.005, "{}", p999);
+        
+        // Quantiles below the tail come from the coarse sketch
+        assert_eq!(sketch.get_quantile_value(0.5).unwrap(), coarse_only.get_quantile_value(0.5).unwrap());
+        assert_eq!(sketch.get_quantile_value(1.0).unwrap(), 1000.0);
+        assert!(sketch.fine().num_bins() < coarse_only.num_bins() * 2);
+    }
+    
+    #[test]
+    fn test_multi_resolution_merge() {
+        let mut a = MultiResolutionDDSketch::new(0.05, 0.005, 10.0).unwrap();
+        let mut b = MultiResolutionDDSketch::new(0.05, 0.005, 10.0).unwrap();
+        a.add(1.0);
+        b.add_with_count(20.0, 3);
+        
+        a.merge(&b).unwrap();
+        assert_eq!((a.count(), a.fine().count()), (4, 3));
+        assert!((a.get_quantile_value(0.9).unwrap() - 20.0).abs() <= 20.0 * 0.005);
+        
+        let other_threshold = MultiResolutionDDSketch::new(0.05, 0.005, 50.0).unwrap();
+        assert!(matches!(a.merge(&other_threshold), Err(DDSketchError::IncompatibleSketches { .. })));
+        assert!(MultiResolutionDDSketch::new(0.05, 0.005, f64::NAN).is_err());
+        
+        a.clear();
+        assert!(a.is_empty());
+        assert_eq!(a.get_quantile_value(0.5), Err(DDSketchError::EmptySketch));
+    }
+}
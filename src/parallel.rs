@@ -0,0 +1,113 @@
+//! Parallel ingestion and merging
+//!
+//! This module builds and combines sketches on the rayon thread pool by sharding
+//! the input, sketching each shard independently and reducing the results.
+
+use crate::{DDSketch, DDSketchError, Result};
+use rayon::prelude::*;
+
+/// The number of values sketched by one task in `par_from_slice`
+const PARALLEL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The number of sketches merged by one task in `par_merge_all`
+const PARALLEL_MERGE_CHUNK_SIZE: usize = 64;
+
+impl DDSketch {
+    /// Build a sketch of a slice of values in parallel
+    ///
+    /// # Arguments
+    /// * `values` - The values to add
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    ///
+    /// # Returns
+    /// A sketch holding every value of the slice
+    pub fn par_from_slice(values: &[f64], relative_accuracy: f64) -> Result<Self> {
+        let empty = DDSketch::new(relative_accuracy)?;
+        
+        Ok(values
+            .par_chunks(PARALLEL_CHUNK_SIZE)
+            .map(|chunk| {
+                let mut sketch = empty.clone();
+                sketch.add_batch(chunk);
+                sketch
+            })
+            .reduce(
+                || empty.clone(),
+                |mut sketch, other| {
+                    sketch.merge(&other).expect("shards share the same relative accuracy");
+                    sketch
+                },
+            ))
+    }
+    
+    /// Merge many sketches into this one with a parallel tree reduction
+    ///
+    /// # Arguments
+    /// * `sketches` - The sketches to merge
+    ///
+    /// # Returns
+    /// An error if any sketch is incompatible, in which case the sketch is left unchanged
+    pub fn par_merge_all(&mut self, sketches: &[DDSketch]) -> Result<()> {
+        let relative_accuracy = self.relative_accuracy();
+        
+        let merged = sketches
+            .par_chunks(PARALLEL_MERGE_CHUNK_SIZE)
+            .map(|chunk| {
+                let mut sketch = DDSketch::new(relative_accuracy)?;
+                sketch.merge_all(chunk)?;
+                Ok::<_, DDSketchError>(sketch)
+            })
+            .try_reduce_with(|mut sketch, other| {
+                sketch.merge(&other)?;
+                Ok(sketch)
+            });
+        
+        match merged {
+            Some(merged) => self.merge(&merged?),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DDSketchError;
+    
+    #[test]
+    fn test_par_from_slice() {
+        let values: Vec<f64> = (0..200_000).map(|i| (i % 5000) as f64 - 1000.0).collect();
+        
+        let parallel = DDSketch::par_from_slice(&values, 0.02).unwrap();
+        let mut sequential = DDSketch::new(0.02).unwrap();
+        sequential.add_batch(&values);
+        
+        assert_eq!(parallel.snapshot(), sequential.snapshot());
+        assert!(DDSketch::par_from_slice(&values, 0.0).is_err());
+        assert!(DDSketch::par_from_slice(&[], 0.02).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_par_merge_all() {
+        let sketches: Vec<DDSketch> = (0..300)
+            .map(|shard| {
+                let mut sketch = DDSketch::new(0.02).unwrap();
+                for i in 0..50 {
+                    sketch.add((shard * 50 + i) as f64);
+                }
+                sketch
+            })
+            .collect();
+        
+        let mut parallel = DDSketch::new(0.02).unwrap();
+        parallel.par_merge_all(&sketches).unwrap();
+        
+        let mut sequential = DDSketch::new(0.02).unwrap();
+        sequential.merge_all(&sketches).unwrap();
+        assert_eq!(parallel.snapshot(), sequential.snapshot());
+        
+        let mut incompatible = DDSketch::new(0.05).unwrap();
+        assert!(matches!(incompatible.par_merge_all(&sketches), Err(DDSketchError::IncompatibleSketches { .. })));
+        assert!(incompatible.is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+//! Plotting support for DDSketch
+//!
+//! This module turns the bins of a sketch into density and CDF point series for any
+//! charting tool and, with the `plotters` feature, renders a histogram as SVG.
+
+use crate::{DDSketch, IndexMapping, Store};
+#[cfg(feature = "plotters")]
+use crate::{DDSketchError, Result};
+
+/// Density and CDF series derived from the bins of a sketch
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlotPoints {
+    /// `(value, density)` at the relative midpoint of each bin, where the density is
+    /// the fraction of all values per unit of value in that bin
+    pub density: Vec<(f64, f64)>,
+    /// `(value, fraction)` at the upper end of each bin, where the fraction is the
+    /// share of values at or below that bin
+    pub cdf: Vec<(f64, f64)>,
+}
+
+impl<M: IndexMapping, S: Store> DDSketch<M, S> {
+    /// Get density and CDF point series for plotting the sketch
+    ///
+    /// Points are in ascending value order, with the outermost bins narrowed to the
+    /// recorded min/max. Bins without width, such as the zero bucket or a sketch of a
+    /// single value, have no density and only appear in the CDF.
+    ///
+    /// # Returns
+    /// The point series, both empty for an empty sketch
+    pub fn to_plot_points(&self) -> PlotPoints {
+        let (min, max) = match (self.min(), self.max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return PlotPoints::default(),
+        };
+        
+        let total = self.count() as f64;
+        let mut points = PlotPoints::default();
+        let mut cumulative_count = 0;
+        for bin in self.bins() {
+            let (lower, upper) = (bin.lower.clamp(min, max), bin.upper.clamp(min, max));
+            if upper > lower {
+                let midpoint = if lower > 0.0 {
+                    crate::ddsketch::relative_midpoint(lower, upper)
+                } else if upper < 0.0 {
+                    -crate::ddsketch::relative_midpoint(-upper, -lower)
+                } else {
+                    (lower + upper) / 2.0
+                };
+                points.density.push((midpoint, bin.count as f64 / total / (upper - lower)));
+            }
+            cumulative_count += bin.count;
+            points.cdf.push((upper, cumulative_count as f64 / total));
+        }
+        points
+    }
+    
+    /// Render the bins of the sketch as an SVG histogram
+    ///
+    /// Each bin is drawn as a bar spanning its value range, narrowed to the recorded
+    /// min/max, with a height equal to its density.
+    ///
+    /// # Arguments
+    /// * `path` - The file to write the SVG to
+    /// * `size` - The width and height of the image in pixels
+    ///
+    /// # Returns
+    /// `DDSketchError::EmptySketch` if the sketch is empty, or `DDSketchError::Io` if
+    /// the image could not be drawn or written
+    #[cfg(feature = "plotters")]
+    pub fn render_histogram_svg(&self, path: impl AsRef<std::path::Path>, size: (u32, u32)) -> Result<()> {
+        use plotters::prelude::*;
+        
+        let (min, max) = match (self.min(), self.max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Err(DDSketchError::EmptySketch),
+        };
+        let total = self.count() as f64;
+        let bars: Vec<(f64, f64, f64)> = self.bins()
+            .map(|bin| (bin.lower.clamp(min, max), bin.upper.clamp(min, max), bin.count))
+            .filter(|&(lower, upper, _)| upper > lower)
+            .map(|(lower, upper, count)| (lower, upper, count as f64 / total / (upper - lower)))
+            .collect();
+        let peak = bars.iter().map(|&(_, _, density)| density).fold(0.0, f64::max);
+        // Keep the axes valid when every value is the same
+        let (x_start, x_end) = if max > min { (min, max) } else { (min - 0.5, max + 0.5) };
+        let y_end = if peak > 0.0 { peak * 1.1 } else { 1.0 };
+        
+        let root = SVGBackend::new(path.as_ref(), size).into_drawing_area();
+        root.fill(&WHITE).map_err(drawing_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(60)
+            .build_cartesian_2d(x_start..x_end, 0.0..y_end)
+            .map_err(drawing_error)?;
+        chart.configure_mesh()
+            .disable_mesh()
+            .x_desc("Value")
+            .y_desc("Density")
+            .draw()
+            .map_err(drawing_error)?;
+        chart.draw_series(bars.iter().map(|&(lower, upper, density)| {
+            Rectangle::new([(lower, 0.0), (upper, density)], BLUE.mix(0.6).filled())
+        }))
+        .map_err(drawing_error)?;
+        root.present().map_err(drawing_error)
+    }
+}
+
+/// Convert a failure to draw or write an SVG into an I/O error
+#[cfg(feature = "plotters")]
+fn drawing_error(error: plotters::drawing::DrawingAreaErrorKind<std::io::Error>) -> DDSketchError {
+    use plotters::drawing::DrawingAreaErrorKind;
+    use plotters_backend::DrawingErrorKind;
+    
+    match error {
+        DrawingAreaErrorKind::BackendError(DrawingErrorKind::DrawingError(error)) => DDSketchError::Io(error.kind()),
+        _ => DDSketchError::Io(std::io::ErrorKind::Other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_plot_points() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        assert_eq!(sketch.to_plot_points(), PlotPoints::default());
+        
+        for i in 1..=1000 {
+            sketch.add(i as f64);
+        }
+        sketch.add_with_count(0.0, 10);
+        sketch.add(-5.0);
+        let points = sketch.to_plot_points();
+        
+        assert_eq!(points.cdf.len(), sketch.num_bins() + 1);
+        assert!(points.cdf.windows(2).all(|pair| pair[0].0 <= pair[1].0 && pair[0].1 <= pair[1].1));
+        assert_eq!(points.cdf.last().unwrap(), &(1000.0, 1.0));
+        
+        // The density integrates to the share of values outside the zero bucket
+        let bins: Vec<_> = sketch.bins().filter(|bin| bin.upper > bin.lower).collect();
+        assert_eq!(points.density.len(), bins.len());
+        let mass: f64 = bins.iter().zip(&points.density)
+            .map(|(bin, &(_, density))| {
+                density * (bin.upper.min(1000.0) - bin.lower.max(-5.0))
+            })
+            .sum();
+        assert!((mass - 1001.0 / 1011.0).abs() < 1e-9, "mass={}", mass);
+        let &(value, density) = points.density.iter().find(|&&(value, _)| value > 500.0).unwrap();
+        assert!((density - 1.0 / 1011.0).abs() / (1.0 / 1011.0) < 0.1, "density at {}={}", value, density);
+    }
+    
+    #[cfg(feature = "plotters")]
+    #[test]
+    fn test_render_histogram_svg() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        let path = std::env::temp_dir().join(format!("ddsketch-histogram-{}.svg", std::process::id()));
+        assert_eq!(sketch.render_histogram_svg(&path, (640, 480)), Err(DDSketchError::EmptySketch));
+        
+        for i in 1..=500 {
+            sketch.add(i as f64 * 0.1);
+        }
+        sketch.render_histogram_svg(&path, (640, 480)).unwrap();
+        let svg = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.matches("<rect").count() > sketch.num_bins());
+    }
+}
@@ -0,0 +1,175 @@
+//! Pooled allocation for short-lived sketches
+//!
+//! Creating and dropping a sketch per request allocates and frees the hash maps behind
+//! its stores every time. A `SketchPool` keeps dropped sketches, cleared but with their
+//! allocations intact, and hands them out again.
+
+use crate::{DDSketch, NonFinitePolicy, QuantileInterpolation, Result};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// The default number of idle sketches a pool keeps
+pub const DEFAULT_MAX_IDLE: usize = 1024;
+
+/// A pool of sketches with the same relative accuracy
+///
+/// Sketches handed out by `get` return to the pool when dropped. They are cleared and
+/// their settings reset, so a recycled sketch behaves exactly like a new one, but the
+/// capacity of its stores is kept. The pool can be shared between threads.
+#[derive(Debug)]
+pub struct SketchPool {
+    relative_accuracy: f64,
+    idle: Mutex<Vec<DDSketch>>,
+    max_idle: usize,
+}
+
+/// Lock a mutex, recovering the data if a panicking thread poisoned it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl SketchPool {
+    /// Create an empty pool
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy of the sketches (between 0 and 1)
+    ///
+    /// # Returns
+    /// The pool, or `DDSketchError::InvalidRelativeAccuracy` if the accuracy is invalid
+    pub fn new(relative_accuracy: f64) -> Result<Self> {
+        DDSketch::new(relative_accuracy)?;
+        Ok(SketchPool {
+            relative_accuracy,
+            idle: Mutex::new(Vec::new()),
+            max_idle: DEFAULT_MAX_IDLE,
+        })
+    }
+    
+    /// Set how many idle sketches the pool keeps; sketches returned beyond that are freed
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+    
+    /// Get the relative accuracy of the sketches in the pool
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+    
+    /// Get the number of idle sketches waiting to be reused
+    pub fn idle(&self) -> usize {
+        lock(&self.idle).len()
+    }
+    
+    /// Get an empty sketch, reusing an idle one if there is one
+    pub fn get(&self) -> PooledSketch<'_> {
+        let sketch = lock(&self.idle).pop().unwrap_or_else(|| {
+            DDSketch::new(self.relative_accuracy).expect("the accuracy was validated by new")
+        });
+        PooledSketch { sketch: Some(sketch), pool: self }
+    }
+    
+    /// Take back a sketch, keeping its allocations if the pool has room
+    fn recycle(&self, mut sketch: DDSketch) {
+        let mut idle = lock(&self.idle);
+        if idle.len() >= self.max_idle {
+            return;
+        }
+        
+        sketch.clear();
+        sketch.set_quantile_interpolation(QuantileInterpolation::default());
+        sketch.set_non_finite_policy(NonFinitePolicy::default());
+        sketch.set_strict(false);
+        sketch.set_key_cache(false);
+        idle.push(sketch);
+    }
+}
+
+/// A sketch borrowed from a `SketchPool`, returned to it when dropped
+#[derive(Debug)]
+pub struct PooledSketch<'a> {
+    /// Always present until the sketch is dropped or detached
+    sketch: Option<DDSketch>,
+    pool: &'a SketchPool,
+}
+
+impl PooledSketch<'_> {
+    /// Detach the sketch from the pool, so it is not recycled
+    pub fn into_inner(mut self) -> DDSketch {
+        self.sketch.take().expect("the sketch is present until dropped")
+    }
+}
+
+impl Deref for PooledSketch<'_> {
+    type Target = DDSketch;
+    
+    fn deref(&self) -> &DDSketch {
+        self.sketch.as_ref().expect("the sketch is present until dropped")
+    }
+}
+
+impl DerefMut for PooledSketch<'_> {
+    fn deref_mut(&mut self) -> &mut DDSketch {
+        self.sketch.as_mut().expect("the sketch is present until dropped")
+    }
+}
+
+impl Drop for PooledSketch<'_> {
+    fn drop(&mut self) {
+        if let Some(sketch) = self.sketch.take() {
+            self.pool.recycle(sketch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DDSketchError;
+    
+    #[test]
+    fn test_pool_recycles_sketches() {
+        let pool = SketchPool::new(0.01).unwrap();
+        assert_eq!(SketchPool::new(0.0).unwrap_err(), DDSketchError::InvalidRelativeAccuracy);
+        
+        {
+            let mut sketch = pool.get();
+            for i in 1..=1000 {
+                sketch.add(i as f64);
+            }
+            sketch.set_strict(true);
+            assert!((sketch.get_quantile_value(0.5).unwrap() - 500.0).abs() <= 5.0);
+        }
+        assert_eq!(pool.idle(), 1);
+        
+        let reused = pool.get();
+        assert_eq!(pool.idle(), 0);
+        assert!(reused.is_empty());
+        assert!(!reused.is_strict());
+        assert_eq!(*reused, DDSketch::new(0.01).unwrap());
+        
+        let detached = pool.get().into_inner();
+        assert!(detached.is_empty());
+        drop(reused);
+        assert_eq!(pool.idle(), 1);
+    }
+    
+    #[test]
+    fn test_pool_limits_idle_sketches() {
+        let pool = SketchPool::new(0.02).unwrap().with_max_idle(2);
+        let sketches: Vec<_> = (0..5).map(|_| pool.get()).collect();
+        drop(sketches);
+        assert_eq!(pool.idle(), 2);
+        
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..100 {
+                        pool.get().add(i as f64);
+                    }
+                });
+            }
+        });
+        assert!(pool.idle() <= 2);
+    }
+}
@@ -0,0 +1,266 @@
+//! Prometheus exposition
+//!
+//! This module renders sketches in the Prometheus text exposition format, so that
+//! they can be served from a scrape endpoint directly, and imports classic
+//! Prometheus histograms into sketches.
+
+use crate::{DDSketch, DDSketchError, Result};
+use crate::mapping::IndexMapping;
+use crate::store::Store;
+use std::fmt::Write;
+
+impl<M: IndexMapping, S: Store> DDSketch<M, S> {
+    /// Render the sketch as a Prometheus summary
+    ///
+    /// Produces a `# TYPE` line, one line per quantile and the `_sum` and `_count`
+    /// lines. An empty sketch reports `NaN` for every quantile, as Prometheus clients do.
+    ///
+    /// # Arguments
+    /// * `name` - The metric name
+    /// * `labels` - The labels attached to every line
+    /// * `quantiles` - The quantiles to report (between 0 and 1)
+    ///
+    /// # Returns
+    /// The exposition text, or an error if a quantile is out of range
+    pub fn to_prometheus_summary(&self, name: &str, labels: &[(&str, &str)], quantiles: &[f64]) -> Result<String> {
+        let values = match self.get_quantile_values(quantiles) {
+            Ok(values) => values,
+            Err(DDSketchError::EmptySketch) => vec![f64::NAN; quantiles.len()],
+            Err(error) => return Err(error),
+        };
+        
+        let mut text = String::new();
+        let _ = writeln!(text, "# TYPE {} summary", name);
+        for (quantile, value) in quantiles.iter().zip(values) {
+            let _ = writeln!(
+                text,
+                "{}{} {}",
+                name,
+                format_labels(labels, Some(("quantile", &quantile.to_string()))),
+                value,
+            );
+        }
+        let _ = writeln!(text, "{}_sum{} {}", name, format_labels(labels, None), self.sum());
+        let _ = writeln!(text, "{}_count{} {}", name, format_labels(labels, None), self.count());
+        
+        Ok(text)
+    }
+}
+
+impl DDSketch {
+    /// Build a sketch from the buckets of a Prometheus classic histogram
+    ///
+    /// Each bucket's count is spread uniformly over the values it covers, from the
+    /// previous bound to its `le` bound, and divided among the sketch bins in that range.
+    /// As in Prometheus' `histogram_quantile`, the first bucket starts at zero when its
+    /// bound is positive, and the `+Inf` bucket is placed at the highest finite bound;
+    /// a first bucket with a bound at or below zero is placed at its bound.
+    ///
+    /// The uniform spread is an assumption, so the imported sketch is only as accurate
+    /// as the histogram: a quantile can be off by up to the width of the bucket holding
+    /// it, on top of the sketch's relative accuracy. The sum and sum of squares are
+    /// estimated from the same assumption, and `min()`/`max()` are narrowed to the
+    /// outermost occupied buckets.
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    /// * `buckets` - Pairs of an `le` bound and the cumulative count at that bound, in
+    ///   ascending order of bound
+    ///
+    /// # Returns
+    /// A new DDSketch holding the histogram, `DDSketchError::InvalidValue` if the bounds
+    /// are not ascending, a count decreases, or a bucket holding values has no finite
+    /// bound to place them at, or `DDSketchError::ValueOutOfRange` if a bound cannot be
+    /// indexed by the mapping
+    pub fn from_prometheus_histogram(relative_accuracy: f64, buckets: &[(f64, u64)]) -> Result<Self> {
+        let mut sketch = DDSketch::new(relative_accuracy)?;
+        let (mut lowest, mut highest) = (f64::INFINITY, f64::NEG_INFINITY);
+        let mut previous: Option<(f64, u64)> = None;
+        
+        for &(bound, cumulative) in buckets {
+            let (range, count) = match previous {
+                None if bound.is_nan() || bound == f64::NEG_INFINITY => return Err(DDSketchError::InvalidValue),
+                None if bound > 0.0 && bound.is_finite() => ((0.0, bound), cumulative),
+                None => ((bound, bound), cumulative),
+                Some((previous_bound, previous_count)) => {
+                    if bound.is_nan() || bound <= previous_bound || !previous_bound.is_finite() || cumulative < previous_count {
+                        return Err(DDSketchError::InvalidValue);
+                    }
+                    let range = if bound.is_finite() { (previous_bound, bound) } else { (previous_bound, previous_bound) };
+                    (range, cumulative - previous_count)
+                }
+            };
+            previous = Some((bound, cumulative));
+            if count == 0 {
+                continue;
+            }
+            
+            let (low, high) = range;
+            if !high.is_finite() {
+                return Err(DDSketchError::InvalidValue);
+            }
+            if !sketch.is_indexable(low.abs().max(high.abs())) {
+                return Err(DDSketchError::ValueOutOfRange);
+            }
+            sketch.add_uniform(low, high, count);
+            lowest = lowest.min(low);
+            highest = highest.max(high);
+        }
+        
+        sketch.fill_missing_min_max();
+        if let (Some(min), Some(max)) = (sketch.min_value, sketch.max_value) {
+            sketch.min_value = Some(min.max(lowest));
+            sketch.max_value = Some(max.min(highest));
+        }
+        
+        Ok(sketch)
+    }
+    
+    /// Add `count` values spread uniformly over `[low, high]`, or placed at `low` if the
+    /// range is a single point
+    fn add_uniform(&mut self, low: f64, high: f64, count: u64) {
+        let width = high - low;
+        if width == 0.0 {
+            if low == 0.0 {
+                self.zero_count += count;
+            } else if low > 0.0 {
+                self.store.add(self.mapping.key(low), count);
+            } else {
+                self.negative_store.add(self.mapping.key(-low), count);
+            }
+            self.sum += low * count as f64;
+            self.sum_squares += low * low * count as f64;
+            self.mark_modified();
+            return;
+        }
+        
+        // Split the range at zero; negative values are spread over their magnitudes
+        let negative_width = (high.min(0.0) - low).max(0.0);
+        let negative_count = ((count as f64 * negative_width / width).round() as u64).min(count);
+        spread_uniform(&self.mapping, &mut self.negative_store, (-high).max(0.0), -low, negative_count);
+        spread_uniform(&self.mapping, &mut self.store, low.max(0.0), high, count - negative_count);
+        
+        self.sum += (low + high) / 2.0 * count as f64;
+        self.sum_squares += (low * low + low * high + high * high) / 3.0 * count as f64;
+        self.mark_modified();
+    }
+}
+
+/// Spread `count` values uniformly over the magnitudes in `[low, high]`
+///
+/// Bins are filled from the top down, each receiving the rounded increase in the
+/// cumulative count, until the whole count has been placed. Magnitudes below the
+/// smallest indexable value end up in the lowest bin.
+fn spread_uniform(mapping: &impl IndexMapping, store: &mut impl Store, low: f64, high: f64, count: u64) {
+    let mut index = mapping.key(high).max(mapping.min_possible_index());
+    let mut assigned = 0;
+    
+    while assigned < count {
+        let lower = mapping.lower_bound(index);
+        let cumulative = if lower <= low || index <= mapping.min_possible_index() {
+            count
+        } else {
+            ((count as f64 * (high - lower) / (high - low)).round() as u64).clamp(assigned, count)
+        };
+        if cumulative > assigned {
+            store.add(index, cumulative - assigned);
+            assigned = cumulative;
+        }
+        index -= 1;
+    }
+}
+
+/// Format a label set, with an optional extra label at the end
+fn format_labels(labels: &[(&str, &str)], extra: Option<(&str, &str)>) -> String {
+    let pairs: Vec<String> = labels.iter().copied()
+        .chain(extra)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect();
+    
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// Escape a label value as required by the exposition format
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_prometheus_summary() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        for value in [1.0, 2.0, 3.0, 4.0] {
+            sketch.add(value);
+        }
+        
+        let text = sketch
+            .to_prometheus_summary("request_seconds", &[("path", "/a\"b")], &[0.0, 1.0])
+            .unwrap();
+        assert_eq!(
+            text,
+            "# TYPE request_seconds summary\n\
+             request_seconds{path=\"/a\\\"b\",quantile=\"0\"} 1\n\
+             request_seconds{path=\"/a\\\"b\",quantile=\"1\"} 4\n\
+             request_seconds_sum{path=\"/a\\\"b\"} 10\n\
+             request_seconds_count{path=\"/a\\\"b\"} 4\n"
+        );
+        
+        assert!(sketch.to_prometheus_summary("x", &[], &[1.5]).is_err());
+    }
+    
+    #[test]
+    fn test_prometheus_summary_empty() {
+        let sketch = DDSketch::new(0.02).unwrap();
+        let text = sketch.to_prometheus_summary("x", &[], &[0.5]).unwrap();
+        
+        assert_eq!(text, "# TYPE x summary\nx{quantile=\"0.5\"} NaN\nx_sum 0\nx_count 0\n");
+    }
+    
+    #[test]
+    fn test_prometheus_histogram_import() {
+        let buckets = [(0.1, 10), (0.5, 30), (1.0, 60), (5.0, 90), (f64::INFINITY, 100)];
+        let sketch = DDSketch::from_prometheus_histogram(0.01, &buckets).unwrap();
+        assert_eq!(sketch.count(), 100);
+        assert_eq!(sketch.max(), Some(5.0));
+        assert!(sketch.min().unwrap() >= 0.0);
+        assert!(!sketch.has_exact_extremes());
+        
+        // Counts stay within the buckets they were recorded in
+        for &(bound, cumulative) in &buckets[..4] {
+            let rank = sketch.rank(bound * 0.99) as f64;
+            assert!((rank - cumulative as f64).abs() <= 2.0, "{} at {}", rank, bound);
+        }
+        let median = sketch.get_quantile_value(0.5).unwrap();
+        assert!((0.5..=1.0).contains(&median), "{}", median);
+        assert!((sketch.get_quantile_value(0.95).unwrap() - 5.0).abs() <= 5.0 * 0.01);
+        
+        // The imported sketch merges with sketches recorded directly
+        let mut recorded = DDSketch::new(0.01).unwrap();
+        recorded.add(0.75);
+        recorded.merge(&sketch).unwrap();
+        assert_eq!(recorded.count(), 101);
+    }
+    
+    #[test]
+    fn test_prometheus_histogram_import_edge_cases() {
+        let sketch = DDSketch::from_prometheus_histogram(0.01, &[(-1.0, 2), (1.0, 6), (f64::INFINITY, 6)]).unwrap();
+        assert_eq!(sketch.count(), 6);
+        assert_eq!(sketch.min(), Some(-1.0));
+        assert_eq!(sketch.count_below(0.0).round(), 4.0);
+        
+        assert!(DDSketch::from_prometheus_histogram(0.01, &[]).unwrap().is_empty());
+        assert!(DDSketch::from_prometheus_histogram(0.01, &[(f64::INFINITY, 0)]).unwrap().is_empty());
+        assert_eq!(DDSketch::from_prometheus_histogram(0.01, &[(f64::INFINITY, 3)]), Err(DDSketchError::InvalidValue));
+        assert_eq!(DDSketch::from_prometheus_histogram(0.01, &[(1.0, 5), (2.0, 4)]), Err(DDSketchError::InvalidValue));
+        assert_eq!(DDSketch::from_prometheus_histogram(0.01, &[(2.0, 1), (1.0, 4)]), Err(DDSketchError::InvalidValue));
+        assert_eq!(DDSketch::from_prometheus_histogram(0.01, &[(f64::NAN, 1)]), Err(DDSketchError::InvalidValue));
+        assert_eq!(DDSketch::from_prometheus_histogram(1e-9, &[(100.0, 1)]), Err(DDSketchError::ValueOutOfRange));
+    }
+}
@@ -0,0 +1,147 @@
+//! Published snapshots for concurrent readers
+//!
+//! This module provides a sketch whose writers apply updates to a private copy and
+//! publish immutable snapshots of it, so that readers such as a metrics scrape see a
+//! consistent sketch without ever waiting for ingestion.
+
+use crate::{DDSketch, Result};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+
+/// A DDSketch that is written in batches and read through published snapshots
+///
+/// Writers serialize on the private sketch, and `publish` swaps in a copy of it. A
+/// reader only holds a lock for as long as it takes to clone the `Arc` of the
+/// current snapshot, never while a writer updates or copies the sketch, and the
+/// snapshot it gets never changes: its count, bins and extremes always agree.
+#[derive(Debug)]
+pub struct SnapshotDDSketch {
+    writer: Mutex<DDSketch>,
+    published: RwLock<Arc<DDSketch>>,
+}
+
+impl SnapshotDDSketch {
+    /// Create a new sketch with an empty published snapshot
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    ///
+    /// # Returns
+    /// A new SnapshotDDSketch, or an error if the relative accuracy is invalid
+    pub fn new(relative_accuracy: f64) -> Result<Self> {
+        let sketch = DDSketch::new(relative_accuracy)?;
+        
+        Ok(SnapshotDDSketch {
+            published: RwLock::new(Arc::new(sketch.clone())),
+            writer: Mutex::new(sketch),
+        })
+    }
+    
+    /// Apply a batch of updates to the writers' sketch
+    ///
+    /// Updates are not visible to readers until the next `publish`.
+    ///
+    /// # Arguments
+    /// * `update` - The updates to apply
+    pub fn update<T>(&self, update: impl FnOnce(&mut DDSketch) -> T) -> T {
+        update(&mut self.writer())
+    }
+    
+    /// Add a batch of values to the writers' sketch
+    ///
+    /// # Arguments
+    /// * `values` - The values to add
+    pub fn add_batch(&self, values: &[f64]) {
+        self.update(|sketch| sketch.add_batch(values));
+    }
+    
+    /// Make every update applied so far visible to readers
+    pub fn publish(&self) {
+        self.publish_from(&self.writer());
+    }
+    
+    /// Apply a batch of updates and publish the result
+    ///
+    /// # Arguments
+    /// * `update` - The updates to apply
+    pub fn update_and_publish<T>(&self, update: impl FnOnce(&mut DDSketch) -> T) -> T {
+        let mut writer = self.writer();
+        let result = update(&mut writer);
+        self.publish_from(&writer);
+        result
+    }
+    
+    /// Get the latest published snapshot
+    ///
+    /// The snapshot stays valid, and unchanged, for as long as it is held.
+    pub fn load(&self) -> Arc<DDSketch> {
+        Arc::clone(&self.published.read().unwrap_or_else(PoisonError::into_inner))
+    }
+    
+    /// Publish a copy of the writers' sketch
+    ///
+    /// The caller holds the writers' lock, so snapshots are published in the order
+    /// their updates were applied. Readers wait only for the pointer swap, not the copy.
+    fn publish_from(&self, writer: &DDSketch) {
+        let snapshot = Arc::new(writer.clone());
+        *self.published.write().unwrap_or_else(PoisonError::into_inner) = snapshot;
+    }
+    
+    /// Lock the writers' sketch, recovering it if a writer panicked mid-batch
+    fn writer(&self) -> MutexGuard<'_, DDSketch> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_snapshot_sketch_publishing() {
+        let sketch = SnapshotDDSketch::new(0.01).unwrap();
+        sketch.add_batch(&[1.0, 2.0, 3.0]);
+        assert!(sketch.load().is_empty());
+        
+        sketch.publish();
+        let first = sketch.load();
+        assert_eq!(first.count(), 3);
+        
+        let count = sketch.update_and_publish(|writer| {
+            writer.add(4.0);
+            writer.count()
+        });
+        assert_eq!(count, 4);
+        assert_eq!(sketch.load().max(), Some(4.0));
+        
+        // Snapshots held by readers never change
+        assert_eq!(first.count(), 3);
+        assert!(SnapshotDDSketch::new(0.0).is_err());
+    }
+    
+    #[test]
+    fn test_snapshot_sketch_concurrent_readers() {
+        let sketch = SnapshotDDSketch::new(0.01).unwrap();
+        
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for batch in 0..100 {
+                    let values: Vec<f64> = (0..100).map(|i| (batch * 100 + i + 1) as f64).collect();
+                    sketch.update_and_publish(|writer| writer.add_batch(&values));
+                }
+            });
+            
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        let snapshot = sketch.load();
+                        let bins: u64 = snapshot.bins().map(|bin| bin.count).sum();
+                        assert_eq!(bins, snapshot.count());
+                        assert_eq!(snapshot.count() % 100, 0);
+                    }
+                });
+            }
+        });
+        
+        assert_eq!(sketch.load().count(), 10_000);
+    }
+}
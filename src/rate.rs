@@ -0,0 +1,170 @@
+//! Rates of change between snapshots of cumulative sketches
+//!
+//! Monitoring agents often scrape a sketch that only ever grows, the way counters
+//! are scraped. This module turns two such snapshots into the distribution of the
+//! values recorded in between, along with per-second rates.
+
+use crate::{DDSketch, DDSketchError, Result, SketchSnapshot};
+use crate::store::Store;
+use std::time::Duration;
+
+/// The values recorded by a cumulative sketch between two snapshots of it
+///
+/// The interval's bins are the later snapshot's minus the earlier one's. If any
+/// count went down, the source was restarted in between, and, as with counter
+/// resets, the later snapshot is taken to hold exactly the values recorded since.
+///
+/// The interval's sum is exact, but its extremes are only known to within the
+/// relative accuracy, since a cumulative sketch does not record when its minimum
+/// and maximum were seen.
+#[derive(Debug, Clone)]
+pub struct SketchDelta {
+    interval: DDSketch,
+    elapsed: Duration,
+    reset: bool,
+}
+
+impl SketchDelta {
+    /// Compute the values recorded between two snapshots of a cumulative sketch
+    ///
+    /// # Arguments
+    /// * `earlier` - The snapshot taken first
+    /// * `later` - The snapshot taken `elapsed` after `earlier`
+    /// * `elapsed` - The time between the two snapshots
+    ///
+    /// # Returns
+    /// The delta, `DDSketchError::InvalidValue` if `elapsed` is zero, an error if
+    /// either snapshot is invalid, or `DDSketchError::IncompatibleSketches` if the
+    /// snapshots have different relative accuracies
+    pub fn between(earlier: &SketchSnapshot, later: &SketchSnapshot, elapsed: Duration) -> Result<Self> {
+        if elapsed.is_zero() {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        let earlier = DDSketch::from_snapshot(earlier)?;
+        let mut interval = DDSketch::from_snapshot(later)?;
+        earlier.check_mergeable(&interval)?;
+        
+        let reset = counts_decreased(&earlier, &interval);
+        if !reset {
+            interval.subtract(&earlier)?;
+        }
+        Ok(SketchDelta { interval, elapsed, reset })
+    }
+    
+    /// Get the sketch of the values recorded in the interval
+    pub fn interval(&self) -> &DDSketch {
+        &self.interval
+    }
+    
+    /// Convert into the sketch of the values recorded in the interval
+    pub fn into_interval(self) -> DDSketch {
+        self.interval
+    }
+    
+    /// Get the time between the two snapshots
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+    
+    /// Check whether the source was restarted between the snapshots
+    ///
+    /// After a restart the interval holds only the later snapshot, so the values
+    /// recorded between the earlier snapshot and the restart are missing from it.
+    pub fn is_reset(&self) -> bool {
+        self.reset
+    }
+    
+    /// Get the count of values recorded in the interval
+    pub fn count(&self) -> u64 {
+        self.interval.count()
+    }
+    
+    /// Get the average number of values recorded per second
+    pub fn count_rate(&self) -> f64 {
+        self.interval.count() as f64 / self.elapsed.as_secs_f64()
+    }
+    
+    /// Get the average sum of the values recorded per second
+    pub fn sum_rate(&self) -> f64 {
+        self.interval.sum() / self.elapsed.as_secs_f64()
+    }
+    
+    /// Get the value at a given quantile of the interval
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile of the values recorded in the
+    /// interval, or `DDSketchError::EmptySketch` if none were
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.interval.get_quantile_value(quantile)
+    }
+}
+
+/// Check whether any bin of `later` holds less than the same bin of `earlier`
+fn counts_decreased(earlier: &DDSketch, later: &DDSketch) -> bool {
+    later.zero_count < earlier.zero_count
+        || earlier.store.iter().any(|(index, count)| later.store.get(index) < count)
+        || earlier.negative_store.iter().any(|(index, count)| later.negative_store.get(index) < count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_sketch_delta_between_snapshots() {
+        let mut cumulative = DDSketch::new(0.01).unwrap();
+        for value in 1..=100 {
+            cumulative.add(value as f64);
+        }
+        let earlier = cumulative.snapshot();
+        for value in 1..=1000 {
+            cumulative.add(value as f64 / 10.0);
+        }
+        cumulative.add(-5.0);
+        let later = cumulative.snapshot();
+        
+        let delta = SketchDelta::between(&earlier, &later, Duration::from_secs(10)).unwrap();
+        assert!(!delta.is_reset());
+        assert_eq!(delta.count(), 1001);
+        assert_eq!(delta.count_rate(), 100.1);
+        assert!((delta.sum_rate() - (50050.0 - 5.0) / 10.0).abs() < 1e-6);
+        
+        let p99 = delta.get_quantile_value(0.99).unwrap();
+        assert!((p99 - 99.0).abs() <= 99.0 * 0.02, "{}", p99);
+        assert_eq!(delta.interval().min(), Some(-5.0));
+        
+        // An unchanged sketch yields an empty interval
+        let idle = SketchDelta::between(&later, &later, Duration::from_secs(1)).unwrap();
+        assert_eq!((idle.count(), idle.count_rate()), (0, 0.0));
+        assert_eq!(idle.get_quantile_value(0.5), Err(DDSketchError::EmptySketch));
+        
+        assert_eq!(SketchDelta::between(&earlier, &later, Duration::ZERO).unwrap_err(), DDSketchError::InvalidValue);
+        let other_accuracy = DDSketch::new(0.02).unwrap().snapshot();
+        assert!(matches!(
+            SketchDelta::between(&earlier, &other_accuracy, Duration::from_secs(1)),
+            Err(DDSketchError::IncompatibleSketches { .. })
+        ));
+    }
+    
+    #[test]
+    fn test_sketch_delta_reset() {
+        let mut cumulative = DDSketch::new(0.01).unwrap();
+        cumulative.add_with_count(50.0, 10);
+        let earlier = cumulative.snapshot();
+        
+        // The source restarts, and records fewer values in the same bin and a new one
+        let mut restarted = DDSketch::new(0.01).unwrap();
+        restarted.add_with_count(50.0, 4);
+        restarted.add_with_count(500.0, 20);
+        
+        let delta = SketchDelta::between(&earlier, &restarted.snapshot(), Duration::from_secs(2)).unwrap();
+        assert!(delta.is_reset());
+        assert_eq!(delta.count(), 24);
+        assert_eq!(delta.count_rate(), 12.0);
+        assert_eq!(delta.into_interval().max(), Some(500.0));
+    }
+}
@@ -0,0 +1,242 @@
+//! Sketches that remember when each bin was last updated
+//!
+//! This module provides a sketch that records a coarse last-update time per bin, so
+//! that bins no recent value has landed in can be expired without full windowing.
+
+use crate::ddsketch::{relative_midpoint, RankedBin};
+use crate::{DDSketch, DDSketchError, IntoSketchValue, Result};
+use crate::mapping::IndexMapping;
+use crate::store::Store;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// A sketch with a last-update time per bin
+///
+/// Times are kept in whole multiples of the resolution since the sketch was created,
+/// so a minute resolution costs one integer per bin. `evict_older_than` removes the
+/// bins no value has landed in for a while, which lets a long-lived sketch shed
+/// outliers that no longer reflect current behavior. Unlike `RollingDDSketch`, a bin
+/// that keeps receiving values keeps all of its count, however old some of it is.
+///
+/// Non-finite values are passed to the sketch but have no bin, so they never expire.
+#[derive(Debug, Clone)]
+pub struct RecencyDDSketch {
+    sketch: DDSketch,
+    /// The tick each bin was last updated at
+    last_update: HashMap<RankedBin, u64>,
+    resolution: Duration,
+    /// The time tick 0 starts at
+    start: Instant,
+}
+
+impl RecencyDDSketch {
+    /// Create a new, empty sketch
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    /// * `resolution` - The granularity of the last-update times, such as a minute
+    ///
+    /// # Returns
+    /// A new RecencyDDSketch, an error if the relative accuracy is invalid, or
+    /// `DDSketchError::InvalidValue` if the resolution is zero
+    pub fn new(relative_accuracy: f64, resolution: Duration) -> Result<Self> {
+        if resolution.is_zero() {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(RecencyDDSketch {
+            sketch: DDSketch::new(relative_accuracy)?,
+            last_update: HashMap::new(),
+            resolution,
+            start: Instant::now(),
+        })
+    }
+    
+    /// Get the sketch of every value not yet evicted
+    pub fn sketch(&self) -> &DDSketch {
+        &self.sketch
+    }
+    
+    /// Get the granularity of the last-update times
+    pub fn resolution(&self) -> Duration {
+        self.resolution
+    }
+    
+    /// Add a value to the sketch now
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        self.add_with_count(value, 1);
+    }
+    
+    /// Add a value with a specific count to the sketch now
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&mut self, value: impl IntoSketchValue, count: u64) {
+        self.add_at(value, count, Instant::now());
+    }
+    
+    /// Add a value with a specific count as of a given time
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    /// * `now` - The time to record as the bin's last update
+    pub fn add_at(&mut self, value: impl IntoSketchValue, count: u64, now: Instant) {
+        let value = value.into_sketch_value();
+        if count == 0 || self.sketch.try_add_with_count(value, count).is_err() {
+            return;
+        }
+        
+        let bin = if !value.is_finite() {
+            return;
+        } else if value == 0.0 {
+            RankedBin::Zero
+        } else if value > 0.0 {
+            RankedBin::Positive(self.sketch.mapping.key(value))
+        } else {
+            RankedBin::Negative(self.sketch.mapping.key(-value))
+        };
+        let tick = self.tick(now);
+        let last_update = self.last_update.entry(bin).or_insert(tick);
+        *last_update = (*last_update).max(tick);
+    }
+    
+    /// Get the total count of values not yet evicted
+    pub fn count(&self) -> u64 {
+        self.sketch.count()
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.sketch.is_empty()
+    }
+    
+    /// Get the value at a given quantile
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile of the values not yet evicted
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.sketch.get_quantile_value(quantile)
+    }
+    
+    /// Remove the bins that have not been updated for at least `max_age`
+    ///
+    /// # Arguments
+    /// * `max_age` - How long a bin may go without an update before it is evicted
+    ///
+    /// # Returns
+    /// The count of values evicted
+    pub fn evict_older_than(&mut self, max_age: Duration) -> u64 {
+        self.evict_older_than_at(max_age, Instant::now())
+    }
+    
+    /// Remove the bins that have not been updated for at least `max_age` as of a given time
+    ///
+    /// Last-update times are only known to within the resolution, so a bin is evicted
+    /// once every instant of the resolution step it was last updated in is at least
+    /// `max_age` before `now`. The sum and sum of squares are reduced by estimates
+    /// from the evicted bins' midpoints, and the extremes are narrowed to the bins
+    /// that remain.
+    ///
+    /// # Arguments
+    /// * `max_age` - How long a bin may go without an update before it is evicted
+    /// * `now` - The time to measure ages from
+    ///
+    /// # Returns
+    /// The count of values evicted
+    pub fn evict_older_than_at(&mut self, max_age: Duration, now: Instant) -> u64 {
+        let Some(cutoff) = now.checked_sub(max_age) else {
+            return 0;
+        };
+        // Bins last updated in a tick that ends by the cutoff are evicted
+        let cutoff_tick = cutoff.checked_duration_since(self.start)
+            .map_or(0, |elapsed| (elapsed.as_nanos() / self.resolution.as_nanos()) as u64);
+        
+        let mut expired = DDSketch::new(self.sketch.relative_accuracy())
+            .expect("relative accuracy was validated on construction");
+        self.last_update.retain(|&bin, &mut last_update| {
+            if last_update >= cutoff_tick {
+                return true;
+            }
+            
+            let count = match bin {
+                RankedBin::Negative(index) => self.sketch.negative_store.get(index),
+                RankedBin::Positive(index) => self.sketch.store.get(index),
+                RankedBin::Zero | RankedBin::Beyond => self.sketch.zero_count,
+            };
+            let (lower, upper) = self.sketch.bin_bounds(bin);
+            let midpoint = relative_midpoint(lower, upper);
+            match bin {
+                RankedBin::Negative(index) => expired.negative_store.add(index, count),
+                RankedBin::Positive(index) => expired.store.add(index, count),
+                RankedBin::Zero | RankedBin::Beyond => expired.zero_count += count,
+            }
+            expired.sum += midpoint * count as f64;
+            expired.sum_squares += midpoint * midpoint * count as f64;
+            false
+        });
+        
+        let evicted = expired.count();
+        if evicted > 0 {
+            self.sketch.subtract(&expired).expect("the sketches share a relative accuracy");
+        }
+        evicted
+    }
+    
+    /// Clear all data from the sketch
+    pub fn clear(&mut self) {
+        self.sketch.clear();
+        self.last_update.clear();
+    }
+    
+    /// Count the whole resolution steps between the creation of the sketch and `now`
+    fn tick(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.start).as_nanos() / self.resolution.as_nanos()) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_recency_evicts_stale_bins() {
+        let minute = Duration::from_secs(60);
+        let mut sketch = RecencyDDSketch::new(0.01, minute).unwrap();
+        assert!(RecencyDDSketch::new(0.01, Duration::ZERO).is_err());
+        let start = sketch.start;
+        
+        // An old outlier, and recent values that keep one old bin alive
+        sketch.add_at(5000.0, 1, start);
+        sketch.add_at(0.0, 2, start);
+        sketch.add_at(10.0, 3, start);
+        sketch.add_at(10.0, 1, start + minute * 20);
+        sketch.add_at(-4.0, 2, start + minute * 25);
+        sketch.add_at(f64::NAN, 1, start);
+        assert_eq!(sketch.count(), 9);
+        
+        // Nothing is old enough yet
+        assert_eq!(sketch.evict_older_than_at(minute * 30, start + minute * 20), 0);
+        
+        assert_eq!(sketch.evict_older_than_at(minute * 10, start + minute * 30), 3);
+        assert_eq!(sketch.count(), 6);
+        assert_eq!(sketch.sketch().count_at(10.0), 4);
+        assert_eq!(sketch.sketch().zero_count(), 0);
+        assert!(sketch.sketch().max().unwrap() <= 10.0 * 1.01);
+        assert_eq!(sketch.sketch().min(), Some(-4.0));
+        
+        // Values updated within the last step are kept
+        assert_eq!(sketch.evict_older_than_at(minute * 5, start + minute * 30), 4);
+        assert_eq!(sketch.evict_older_than_at(minute, start + minute * 26), 0);
+        assert_eq!(sketch.evict_older_than_at(minute, start + minute * 27), 2);
+        assert!(sketch.sketch().is_empty());
+        assert_eq!(sketch.sketch().sum(), 0.0);
+    }
+}
@@ -0,0 +1,399 @@
+//! Labeled sketch registries
+//!
+//! This module provides a map from label keys, such as an endpoint name or a status
+//! code, to sketches that share a common configuration, with an optional bin budget
+//! shared by all of them.
+
+use crate::{DDSketch, DDSketchError, IntoSketchValue, Result, SketchSnapshot};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// What a group has discarded to stay within its bin budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Degradation {
+    /// The number of sketches evicted
+    pub evicted_sketches: u64,
+    /// The number of bins held by the evicted sketches
+    pub evicted_bins: u64,
+    /// The number of values held by the evicted sketches
+    pub evicted_values: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    sketch: DDSketch,
+    last_update: u64,
+}
+
+/// A group of sketches keyed by label
+///
+/// Sketches are created on first use with the group's relative accuracy, so every
+/// sketch in a group, and in groups with the same configuration, can be merged.
+///
+/// A group created with `with_bin_budget` caps the bins held across all of its
+/// sketches. When recording pushes the total over the budget, the least recently
+/// updated sketches are evicted until it fits again, so a burst of new labels costs
+/// stale series rather than unbounded memory. The sketch being recorded into is
+/// never evicted.
+#[derive(Debug, Clone)]
+pub struct SketchGroup<K> {
+    sketches: HashMap<K, Entry>,
+    relative_accuracy: f64,
+    bin_budget: Option<usize>,
+    total_bins: usize,
+    /// Set when a sketch was handed out mutably and `total_bins` may be out of date
+    stale_bins: bool,
+    clock: u64,
+    degradation: Degradation,
+}
+
+impl<K: Eq + Hash> SketchGroup<K> {
+    /// Create a new, empty group
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy of every sketch in the group
+    ///
+    /// # Returns
+    /// A new SketchGroup, or an error if the relative accuracy is invalid
+    pub fn new(relative_accuracy: f64) -> Result<Self> {
+        DDSketch::new(relative_accuracy)?;
+        
+        Ok(SketchGroup {
+            sketches: HashMap::new(),
+            relative_accuracy,
+            bin_budget: None,
+            total_bins: 0,
+            stale_bins: false,
+            clock: 0,
+            degradation: Degradation::default(),
+        })
+    }
+    
+    /// Create a new, empty group holding at most `max_bins` bins across its sketches
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy of every sketch in the group
+    /// * `max_bins` - The total number of bins the group's sketches may hold
+    ///
+    /// # Returns
+    /// A new SketchGroup, or an error if the relative accuracy is invalid or
+    /// `max_bins` is zero (`DDSketchError::InvalidValue`)
+    pub fn with_bin_budget(relative_accuracy: f64, max_bins: usize) -> Result<Self> {
+        if max_bins == 0 {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        let mut group = SketchGroup::new(relative_accuracy)?;
+        group.bin_budget = Some(max_bins);
+        Ok(group)
+    }
+    
+    /// Get the total number of bins the group may hold, if it has a budget
+    pub fn bin_budget(&self) -> Option<usize> {
+        self.bin_budget
+    }
+    
+    /// Get the number of bins held across all sketches
+    pub fn num_bins(&self) -> usize {
+        if self.stale_bins {
+            self.sketches.values().map(|entry| entry.sketch.num_bins()).sum()
+        } else {
+            self.total_bins
+        }
+    }
+    
+    /// Get what has been evicted to stay within the bin budget
+    pub fn degradation(&self) -> Degradation {
+        self.degradation
+    }
+    
+    /// Get the relative accuracy shared by the group's sketches
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+    
+    /// Record a value under a key
+    ///
+    /// # Arguments
+    /// * `key` - The label of the sketch to record into
+    /// * `value` - The value to add
+    pub fn record(&mut self, key: K, value: impl IntoSketchValue) {
+        self.record_with_count(key, value, 1);
+    }
+    
+    /// Record a value with a specific count under a key
+    ///
+    /// # Arguments
+    /// * `key` - The label of the sketch to record into
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn record_with_count(&mut self, key: K, value: impl IntoSketchValue, count: u64) {
+        let entry = self.entry(key);
+        let before = entry.sketch.num_bins();
+        entry.sketch.add_with_count(value, count);
+        let grown = entry.sketch.num_bins() - before;
+        
+        self.total_bins += grown;
+        if grown > 0 {
+            self.enforce_budget_sparing(Some(self.clock));
+        }
+    }
+    
+    /// Get the sketch for a key, creating it if needed
+    ///
+    /// Bins added through the returned sketch count against the budget from the
+    /// next call to `record` or `enforce_budget`.
+    pub fn sketch_mut(&mut self, key: K) -> &mut DDSketch {
+        self.stale_bins = true;
+        &mut self.entry(key).sketch
+    }
+    
+    /// Get the sketch for a key
+    pub fn get(&self, key: &K) -> Option<&DDSketch> {
+        self.sketches.get(key).map(|entry| &entry.sketch)
+    }
+    
+    /// Remove the sketch for a key
+    pub fn remove(&mut self, key: &K) -> Option<DDSketch> {
+        let entry = self.sketches.remove(key)?;
+        self.total_bins = self.total_bins.saturating_sub(entry.sketch.num_bins());
+        Some(entry.sketch)
+    }
+    
+    /// Evict the least recently updated sketches until the group fits its bin budget
+    ///
+    /// Recording enforces the budget automatically; call this after changing sketches
+    /// through `sketch_mut`.
+    pub fn enforce_budget(&mut self) {
+        self.enforce_budget_sparing(None);
+    }
+    
+    /// Get the number of keys in the group
+    pub fn len(&self) -> usize {
+        self.sketches.len()
+    }
+    
+    /// Check if the group has no keys
+    pub fn is_empty(&self) -> bool {
+        self.sketches.is_empty()
+    }
+    
+    /// Iterate over the keys and their sketches, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &DDSketch)> + '_ {
+        self.sketches.iter().map(|(key, entry)| (key, &entry.sketch))
+    }
+    
+    /// Take a snapshot of every sketch in the group
+    pub fn snapshot(&self) -> HashMap<K, SketchSnapshot>
+    where
+        K: Clone,
+    {
+        self.sketches.iter()
+            .map(|(key, entry)| (key.clone(), entry.sketch.snapshot()))
+            .collect()
+    }
+    
+    /// Remove and return every sketch, leaving the group empty
+    ///
+    /// Useful for reporting deltas: drain on every flush and export the results.
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, DDSketch)> + '_ {
+        self.total_bins = 0;
+        self.stale_bins = false;
+        self.sketches.drain().map(|(key, entry)| (key, entry.sketch))
+    }
+    
+    /// Take every sketch, leaving the group empty
+    ///
+    /// Unlike `drain`, the result does not borrow the group, so values can be
+    /// recorded again while the taken sketches are exported. Each recorded value is
+    /// returned by exactly one `take`.
+    pub fn take(&mut self) -> HashMap<K, DDSketch> {
+        self.total_bins = 0;
+        self.stale_bins = false;
+        std::mem::take(&mut self.sketches).into_iter()
+            .map(|(key, entry)| (key, entry.sketch))
+            .collect()
+    }
+    
+    /// Merge another group into this one, key by key
+    ///
+    /// # Arguments
+    /// * `other` - The group to merge
+    ///
+    /// # Returns
+    /// An error if the groups have different relative accuracies, in which case the
+    /// group is left unchanged
+    pub fn merge(&mut self, other: &SketchGroup<K>) -> Result<()>
+    where
+        K: Clone,
+    {
+        if (self.relative_accuracy - other.relative_accuracy).abs() > 1e-10 {
+            return Err(DDSketchError::different_accuracy(self.relative_accuracy, other.relative_accuracy));
+        }
+        
+        for (key, other) in &other.sketches {
+            let entry = self.entry(key.clone());
+            let before = entry.sketch.num_bins();
+            entry.sketch.merge(&other.sketch)?;
+            self.total_bins += entry.sketch.num_bins() - before;
+        }
+        self.enforce_budget();
+        
+        Ok(())
+    }
+    
+    /// Remove every sketch from the group
+    pub fn clear(&mut self) {
+        self.sketches.clear();
+        self.total_bins = 0;
+        self.stale_bins = false;
+    }
+    
+    /// Get the entry for a key, creating it if needed, and mark it as just updated
+    fn entry(&mut self, key: K) -> &mut Entry {
+        let relative_accuracy = self.relative_accuracy;
+        self.clock += 1;
+        let entry = self.sketches.entry(key).or_insert_with(|| Entry {
+            sketch: DDSketch::new(relative_accuracy).expect("relative accuracy was validated on construction"),
+            last_update: 0,
+        });
+        entry.last_update = self.clock;
+        entry
+    }
+    
+    /// Evict sketches until the budget holds, except the one updated at tick `spared`
+    fn enforce_budget_sparing(&mut self, spared: Option<u64>) {
+        let Some(budget) = self.bin_budget else {
+            return;
+        };
+        if self.stale_bins {
+            self.total_bins = self.sketches.values().map(|entry| entry.sketch.num_bins()).sum();
+            self.stale_bins = false;
+        }
+        
+        while self.total_bins > budget {
+            let oldest = self.sketches.values()
+                .map(|entry| entry.last_update)
+                .filter(|&last_update| Some(last_update) != spared)
+                .min();
+            // Only the spared sketch is left, and evicting it would lose the value just recorded
+            let Some(oldest) = oldest else {
+                break;
+            };
+            
+            // Ticks are unique, so this removes exactly the oldest sketch
+            let degradation = &mut self.degradation;
+            let total_bins = &mut self.total_bins;
+            self.sketches.retain(|_, entry| {
+                if entry.last_update != oldest {
+                    return true;
+                }
+                let bins = entry.sketch.num_bins();
+                *total_bins -= bins;
+                degradation.evicted_sketches += 1;
+                degradation.evicted_bins += bins as u64;
+                degradation.evicted_values += entry.sketch.count();
+                false
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_sketch_group_record() {
+        let mut group = SketchGroup::new(0.02).unwrap();
+        assert!(SketchGroup::<&str>::new(0.0).is_err());
+        
+        for i in 1..=100 {
+            group.record("/users", i as f64);
+            group.record_with_count("/orders", i as f64 * 10.0, 2);
+        }
+        
+        assert_eq!(group.len(), 2);
+        assert_eq!(group.get(&"/users").unwrap().count(), 100);
+        assert_eq!(group.get(&"/orders").unwrap().count(), 200);
+        assert!(group.get(&"/missing").is_none());
+        
+        let snapshot = group.snapshot();
+        assert_eq!(snapshot[&"/orders"].count(), 200);
+        
+        let mut drained: Vec<_> = group.drain().map(|(key, sketch)| (key, sketch.count())).collect();
+        drained.sort();
+        assert_eq!(drained, vec![("/orders", 200), ("/users", 100)]);
+        assert!(group.is_empty());
+        
+        group.record("/users", 1.0);
+        let taken = group.take();
+        group.record("/users", 2.0);
+        assert_eq!(taken[&"/users"].max(), Some(1.0));
+        assert_eq!(group.get(&"/users").unwrap().count(), 1);
+        assert_eq!(group.num_bins(), 1);
+    }
+    
+    #[test]
+    fn test_sketch_group_merge() {
+        let mut a = SketchGroup::new(0.02).unwrap();
+        let mut b = SketchGroup::new(0.02).unwrap();
+        
+        a.record(200u16, 1.0);
+        b.record(200u16, 3.0);
+        b.record(500u16, 9.0);
+        
+        a.merge(&b).unwrap();
+        assert_eq!(a.get(&200).unwrap().count(), 2);
+        assert_eq!(a.get(&500).unwrap().max(), Some(9.0));
+        
+        let c = SketchGroup::new(0.05).unwrap();
+        assert!(matches!(a.merge(&c), Err(DDSketchError::IncompatibleSketches { .. })));
+    }
+    
+    #[test]
+    fn test_sketch_group_bin_budget() {
+        assert!(SketchGroup::<u32>::with_bin_budget(0.02, 0).is_err());
+        let mut group = SketchGroup::with_bin_budget(0.02, 100).unwrap();
+        assert_eq!(group.bin_budget(), Some(100));
+        
+        // Each label's values fall in 30 distinct bins
+        for label in 0..10u32 {
+            for i in 0..30 {
+                group.record(label, 1.05f64.powi(i));
+            }
+            assert!(group.num_bins() <= 100);
+        }
+        
+        // The three most recently updated labels survive
+        let mut labels: Vec<_> = group.iter().map(|(&label, _)| label).collect();
+        labels.sort();
+        assert_eq!(labels, vec![7, 8, 9]);
+        assert_eq!(group.degradation(), Degradation {
+            evicted_sketches: 7,
+            evicted_bins: 210,
+            evicted_values: 210,
+        });
+        
+        // Recording into an old label makes it the most recent
+        group.record(7, 1.0);
+        for i in 0..30 {
+            group.record(10, 1.05f64.powi(i));
+        }
+        assert!(group.get(&7).is_some());
+        assert!(group.get(&8).is_none());
+        
+        // A single sketch over budget is kept rather than losing the value just recorded
+        let mut tiny = SketchGroup::with_bin_budget(0.02, 2).unwrap();
+        for i in 0..5 {
+            tiny.record("only", 10f64.powi(i));
+        }
+        assert_eq!(tiny.get(&"only").unwrap().count(), 5);
+        
+        tiny.sketch_mut("other").add(3.0);
+        assert_eq!(tiny.num_bins(), 6);
+        tiny.enforce_budget();
+        assert!(tiny.get(&"only").is_none());
+        assert_eq!(tiny.num_bins(), 1);
+    }
+}
@@ -0,0 +1,389 @@
+//! Relative-error quantiles sketch
+//!
+//! This module provides the REQ sketch of Cormode, Karnin, Liberty, Thaler and
+//! Veselý. Its rank error is relative to the distance from one end of the
+//! distribution, so extreme tail quantiles such as p99.99 are answered with a rank
+//! error that shrinks as the tail gets thinner.
+
+use crate::{DDSketchError, IntoSketchValue, Result};
+
+/// The number of sections each compactor starts with
+const INITIAL_NUM_SECTIONS: usize = 3;
+
+/// The smallest section size that sections are halved down to
+const MIN_SECTION_SIZE: usize = 4;
+
+/// The seed of the coin flips that choose which items survive a compaction
+const COIN_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// One level of the sketch, whose items each stand for `2^level` values
+#[derive(Debug, Clone)]
+struct Compactor {
+    items: Vec<f64>,
+    section_size: f64,
+    num_sections: usize,
+    /// A counter whose trailing ones choose how many sections the next compaction takes
+    state: u64,
+}
+
+impl Compactor {
+    fn new(section_size: usize) -> Self {
+        Compactor {
+            items: Vec::new(),
+            section_size: section_size as f64,
+            num_sections: INITIAL_NUM_SECTIONS,
+            state: 0,
+        }
+    }
+    
+    fn section_size(&self) -> usize {
+        nearest_even(self.section_size)
+    }
+    
+    fn nominal_capacity(&self) -> usize {
+        2 * self.num_sections * self.section_size()
+    }
+    
+    /// Remove a range of the lowest (or highest) items and return every other one of them
+    fn compact(&mut self, high_rank_accuracy: bool, coin: bool) -> Vec<f64> {
+        self.items.sort_unstable_by(f64::total_cmp);
+        
+        let sections = (self.state.trailing_ones() as usize + 1).min(self.num_sections);
+        let mut retained = self.nominal_capacity() / 2 + (self.num_sections - sections) * self.section_size();
+        if (self.items.len() - retained) % 2 == 1 {
+            retained += 1;
+        }
+        
+        // Compacting the low end keeps the high ranks exact, and vice versa
+        let range = if high_rank_accuracy {
+            0..self.items.len() - retained
+        } else {
+            retained..self.items.len()
+        };
+        let promoted = self.items.drain(range).skip(coin as usize).step_by(2).collect();
+        
+        self.state += 1;
+        self.ensure_enough_sections();
+        promoted
+    }
+    
+    /// Double the sections and shrink them by `√2` as compactions accumulate
+    fn ensure_enough_sections(&mut self) {
+        let shrunk = self.section_size / std::f64::consts::SQRT_2;
+        if self.state >= 1 << (self.num_sections - 1) && nearest_even(shrunk) >= MIN_SECTION_SIZE {
+            self.section_size = shrunk;
+            self.num_sections *= 2;
+        }
+    }
+}
+
+/// A REQ sketch with relative rank error at one end of the distribution
+///
+/// In high-rank-accuracy mode, the mode for latency tails, the error in the rank of
+/// the value returned for quantile `q` is proportional to `1 - q`; in low-rank mode
+/// it is proportional to `q`. Larger `k` means more retained items and a smaller
+/// error.
+///
+/// Compactions keep every other item, starting with the first or the second as
+/// decided by a pseudo-random coin. The coin is seeded with a constant, so the sketch
+/// is deterministic for a given input order.
+#[derive(Debug, Clone)]
+pub struct ReqSketch {
+    k: usize,
+    high_rank_accuracy: bool,
+    compactors: Vec<Compactor>,
+    count: u64,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+    coin_state: u64,
+}
+
+impl ReqSketch {
+    /// Create a new, empty REQ sketch
+    ///
+    /// # Arguments
+    /// * `k` - The initial section size, an even number between 4 and 1024; 12 is a
+    ///   common choice
+    /// * `high_rank_accuracy` - Whether the high ranks (true) or low ranks (false)
+    ///   are the accurate end
+    ///
+    /// # Returns
+    /// A new ReqSketch, or `DDSketchError::InvalidValue` if `k` is out of range
+    pub fn new(k: usize, high_rank_accuracy: bool) -> Result<Self> {
+        if !(MIN_SECTION_SIZE..=1024).contains(&k) || k % 2 == 1 {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(ReqSketch {
+            k,
+            high_rank_accuracy,
+            compactors: vec![Compactor::new(k)],
+            count: 0,
+            min_value: None,
+            max_value: None,
+            coin_state: COIN_SEED,
+        })
+    }
+    
+    /// Get the initial section size
+    pub fn k(&self) -> usize {
+        self.k
+    }
+    
+    /// Check whether the high ranks are the accurate end
+    pub fn is_high_rank_accuracy(&self) -> bool {
+        self.high_rank_accuracy
+    }
+    
+    /// Add a value to the sketch
+    ///
+    /// Non-finite values are ignored; use `try_add` to detect them.
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        let _ = self.try_add(value);
+    }
+    
+    /// Add a value to the sketch, reporting non-finite values
+    ///
+    /// # Returns
+    /// `DDSketchError::NonFiniteValue` if the value is NaN or infinite
+    pub fn try_add(&mut self, value: impl IntoSketchValue) -> Result<()> {
+        let value = value.into_sketch_value();
+        if !value.is_finite() {
+            return Err(DDSketchError::NonFiniteValue);
+        }
+        
+        self.min_value = Some(self.min_value.map_or(value, |min| min.min(value)));
+        self.max_value = Some(self.max_value.map_or(value, |max| max.max(value)));
+        self.count += 1;
+        self.compactors[0].items.push(value);
+        self.compress();
+        
+        Ok(())
+    }
+    
+    /// Get the total count of values in the sketch
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    
+    /// Get the minimum value in the sketch
+    pub fn min(&self) -> Option<f64> {
+        self.min_value
+    }
+    
+    /// Get the maximum value in the sketch
+    pub fn max(&self) -> Option<f64> {
+        self.max_value
+    }
+    
+    /// Get the number of items retained across all levels
+    pub fn num_retained(&self) -> usize {
+        self.compactors.iter().map(|compactor| compactor.items.len()).sum()
+    }
+    
+    /// Get the estimated fraction of values less than or equal to a value
+    pub fn get_rank(&self, value: f64) -> Result<f64> {
+        if self.is_empty() {
+            return Err(DDSketchError::EmptySketch);
+        }
+        
+        let weight: u64 = self.weighted_items()
+            .filter(|&(item, _)| item <= value)
+            .map(|(_, weight)| weight)
+            .sum();
+        Ok(weight as f64 / self.count as f64)
+    }
+    
+    /// Get the value at a given quantile
+    ///
+    /// Returns the smallest retained value whose estimated rank is at least
+    /// `quantile · n`, with the exact min and max at the ends.
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        let (Some(min), Some(max)) = (self.min_value, self.max_value) else {
+            return Err(DDSketchError::EmptySketch);
+        };
+        if quantile == 0.0 {
+            return Ok(min);
+        }
+        if quantile == 1.0 {
+            return Ok(max);
+        }
+        
+        let mut items: Vec<(f64, u64)> = self.weighted_items().collect();
+        items.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        
+        let rank = quantile * self.count as f64;
+        let mut cumulative = 0;
+        for (item, weight) in items {
+            cumulative += weight;
+            if cumulative as f64 >= rank {
+                return Ok(item);
+            }
+        }
+        Ok(max)
+    }
+    
+    /// Merge another REQ sketch into this one
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to merge
+    ///
+    /// # Returns
+    /// `DDSketchError::IncompatibleSketches` if the sketches differ in `k` or in
+    /// which end is accurate
+    pub fn merge(&mut self, other: &ReqSketch) -> Result<()> {
+        if self.k != other.k {
+            return Err(DDSketchError::different_parameter("k", self.k as f64, other.k as f64));
+        }
+        if self.high_rank_accuracy != other.high_rank_accuracy {
+            return Err(DDSketchError::different_parameter(
+                "high rank accuracy",
+                self.high_rank_accuracy as u8 as f64,
+                other.high_rank_accuracy as u8 as f64,
+            ));
+        }
+        if other.is_empty() {
+            return Ok(());
+        }
+        
+        while self.compactors.len() < other.compactors.len() {
+            self.compactors.push(Compactor::new(self.k));
+        }
+        for (compactor, other_compactor) in self.compactors.iter_mut().zip(&other.compactors) {
+            compactor.items.extend_from_slice(&other_compactor.items);
+            compactor.state |= other_compactor.state;
+            if other_compactor.num_sections > compactor.num_sections {
+                compactor.num_sections = other_compactor.num_sections;
+                compactor.section_size = other_compactor.section_size;
+            }
+        }
+        
+        self.count += other.count;
+        if let Some(other_min) = other.min_value {
+            self.min_value = Some(self.min_value.map_or(other_min, |min| min.min(other_min)));
+        }
+        if let Some(other_max) = other.max_value {
+            self.max_value = Some(self.max_value.map_or(other_max, |max| max.max(other_max)));
+        }
+        
+        self.compress();
+        Ok(())
+    }
+    
+    /// Clear all data from the sketch
+    pub fn clear(&mut self) {
+        *self = ReqSketch::new(self.k, self.high_rank_accuracy).expect("k was validated on construction");
+    }
+    
+    /// Iterate over the retained items with the number of values each stands for
+    fn weighted_items(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        self.compactors.iter().enumerate().flat_map(|(level, compactor)| {
+            compactor.items.iter().map(move |&item| (item, 1u64 << level))
+        })
+    }
+    
+    /// Compact every level that is over capacity, from the bottom up
+    fn compress(&mut self) {
+        let mut level = 0;
+        while level < self.compactors.len() {
+            if self.compactors[level].items.len() >= self.compactors[level].nominal_capacity() {
+                if level + 1 == self.compactors.len() {
+                    self.compactors.push(Compactor::new(self.k));
+                }
+                
+                let coin = self.flip_coin();
+                let promoted = self.compactors[level].compact(self.high_rank_accuracy, coin);
+                self.compactors[level + 1].items.extend(promoted);
+            }
+            level += 1;
+        }
+    }
+    
+    /// Flip a coin with an xorshift generator
+    fn flip_coin(&mut self) -> bool {
+        self.coin_state ^= self.coin_state << 13;
+        self.coin_state ^= self.coin_state >> 7;
+        self.coin_state ^= self.coin_state << 17;
+        self.coin_state & 1 == 1
+    }
+}
+
+/// Round a section size to the nearest even number
+fn nearest_even(value: f64) -> usize {
+    2 * (value / 2.0).round() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_req_tail_accuracy() {
+        let mut sketch = ReqSketch::new(12, true).unwrap();
+        assert!(ReqSketch::new(7, true).is_err());
+        assert_eq!(sketch.get_quantile_value(0.5), Err(DDSketchError::EmptySketch));
+        
+        let n = 200_000u64;
+        for i in 0..n {
+            sketch.add(((i * 7919) % n + 1) as f64);
+        }
+        
+        assert_eq!(sketch.count(), n);
+        assert!(sketch.num_retained() < 5000, "{} retained", sketch.num_retained());
+        assert_eq!(sketch.get_quantile_value(1.0).unwrap(), n as f64);
+        assert_eq!(sketch.get_quantile_value(0.0).unwrap(), 1.0);
+        
+        // The rank error shrinks with the distance from the top
+        for (q, tolerance) in [(0.5, 0.02), (0.99, 0.002), (0.9999, 0.0001)] {
+            let estimate = sketch.get_quantile_value(q).unwrap();
+            let rank = estimate / n as f64;
+            assert!((rank - q).abs() <= tolerance, "q={} estimate={}", q, estimate);
+        }
+        
+        let rank = sketch.get_rank(n as f64 * 0.999).unwrap();
+        assert!((rank - 0.999).abs() <= 0.0005);
+    }
+    
+    #[test]
+    fn test_req_merge() {
+        let mut a = ReqSketch::new(12, false).unwrap();
+        let mut b = ReqSketch::new(12, false).unwrap();
+        for i in 0..20_000 {
+            a.add(i as f64);
+            b.add(20_000.0 + i as f64);
+        }
+        assert_eq!(b.try_add(f64::NAN), Err(DDSketchError::NonFiniteValue));
+        
+        a.merge(&b).unwrap();
+        assert_eq!(a.count(), 40_000);
+        assert_eq!((a.min(), a.max()), (Some(0.0), Some(39_999.0)));
+        
+        // Low-rank mode is accurate near the bottom
+        let p001 = a.get_quantile_value(0.001).unwrap();
+        assert!((p001 - 40.0).abs() <= 10.0, "p001={}", p001);
+        
+        let other = ReqSketch::new(12, true).unwrap();
+        assert!(matches!(a.merge(&other), Err(DDSketchError::IncompatibleSketches { .. })));
+        
+        a.clear();
+        assert!(a.is_empty());
+        assert_eq!(a.num_retained(), 0);
+    }
+}
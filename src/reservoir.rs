@@ -0,0 +1,255 @@
+//! Raw sample reservoirs
+//!
+//! This module provides a sketch that keeps a small uniform sample of the raw values
+//! it was given, so that a suspicious percentile can be checked against real values
+//! instead of bin boundaries.
+
+use crate::{DDSketch, IntoSketchValue, Result};
+
+/// The seed of the generator that chooses which values are kept
+const SAMPLE_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// A DDSketch with a uniform reservoir of the raw values added to it
+///
+/// The reservoir holds up to `capacity` values, each added value being equally
+/// likely to be among them (Vitter's algorithm R). Merging combines the two
+/// reservoirs into a uniform sample of the union, weighting each side by the number
+/// of values it has seen. Non-finite values are passed to the sketch's policy and
+/// never sampled.
+#[derive(Debug, Clone)]
+pub struct SampledDDSketch {
+    sketch: DDSketch,
+    samples: Vec<f64>,
+    capacity: usize,
+    /// The number of values offered to the reservoir
+    seen: u64,
+    rng_state: u64,
+}
+
+impl SampledDDSketch {
+    /// Create a new, empty sketch
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy of the sketch (between 0 and 1)
+    /// * `capacity` - The largest number of raw values kept
+    ///
+    /// # Returns
+    /// A new SampledDDSketch, or an error if the relative accuracy is invalid
+    pub fn new(relative_accuracy: f64, capacity: usize) -> Result<Self> {
+        Ok(SampledDDSketch {
+            sketch: DDSketch::new(relative_accuracy)?,
+            samples: Vec::with_capacity(capacity),
+            capacity,
+            seen: 0,
+            rng_state: SAMPLE_SEED,
+        })
+    }
+    
+    /// Get the underlying sketch
+    pub fn sketch(&self) -> &DDSketch {
+        &self.sketch
+    }
+    
+    /// Get the sampled raw values, in no particular order
+    pub fn samples(&self) -> &[f64] {
+        &self.samples
+    }
+    
+    /// Get the largest number of raw values kept
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    
+    /// Add a value to the sketch
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        self.add_with_count(value, 1);
+    }
+    
+    /// Add a value with a specific count to the sketch
+    ///
+    /// Each of the `count` copies is offered to the reservoir separately, so this
+    /// takes time proportional to the count.
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&mut self, value: impl IntoSketchValue, count: u64) {
+        let value = value.into_sketch_value();
+        self.sketch.add_with_count(value, count);
+        if !value.is_finite() {
+            return;
+        }
+        
+        for _ in 0..count {
+            self.seen += 1;
+            if self.samples.len() < self.capacity {
+                self.samples.push(value);
+            } else {
+                let slot = self.next_random() % self.seen;
+                if slot < self.capacity as u64 {
+                    self.samples[slot as usize] = value;
+                }
+            }
+        }
+    }
+    
+    /// Merge another sketch and its samples into this one
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to merge
+    ///
+    /// # Returns
+    /// An error if the sketches have different relative accuracies, in which case the
+    /// sketch is left unchanged
+    pub fn merge(&mut self, other: &SampledDDSketch) -> Result<()> {
+        self.sketch.merge(&other.sketch)?;
+        
+        // Each sample stands for `seen / len` values of its side; draw without
+        // replacement with probability proportional to that weight
+        let weight = |seen: u64, len: usize| if len == 0 { 0.0 } else { seen as f64 / len as f64 };
+        let (ours_weight, theirs_weight) = (weight(self.seen, self.samples.len()), weight(other.seen, other.samples.len()));
+        let mut ours = std::mem::take(&mut self.samples);
+        let mut theirs = other.samples.clone();
+        
+        while self.samples.len() < self.capacity && !(ours.is_empty() && theirs.is_empty()) {
+            let ours_mass = ours_weight * ours.len() as f64;
+            let theirs_mass = theirs_weight * theirs.len() as f64;
+            let draw = (self.next_random() >> 11) as f64 / (1u64 << 53) as f64;
+            let pool = if draw * (ours_mass + theirs_mass) < ours_mass { &mut ours } else { &mut theirs };
+            let position = (self.next_random() % pool.len() as u64) as usize;
+            self.samples.push(pool.swap_remove(position));
+        }
+        self.seen += other.seen;
+        
+        Ok(())
+    }
+    
+    /// Convert into the underlying sketch, dropping the samples
+    pub fn into_sketch(self) -> DDSketch {
+        self.sketch
+    }
+    
+    /// Clear all data and samples
+    pub fn clear(&mut self) {
+        self.sketch.clear();
+        self.samples.clear();
+        self.seen = 0;
+    }
+    
+    /// Draw from an xorshift generator
+    fn next_random(&mut self) -> u64 {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        self.rng_state
+    }
+}
+
+/// The serialized form of a `SampledDDSketch`: a snapshot of its contents and its reservoir
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SampledDDSketchRepr {
+    snapshot: crate::SketchSnapshot,
+    capacity: usize,
+    seen: u64,
+    samples: Vec<f64>,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SampledDDSketch {
+    fn serialize<Ser: serde::Serializer>(&self, serializer: Ser) -> std::result::Result<Ser::Ok, Ser::Error> {
+        SampledDDSketchRepr {
+            snapshot: self.sketch.snapshot(),
+            capacity: self.capacity,
+            seen: self.seen,
+            samples: self.samples.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SampledDDSketch {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let repr = SampledDDSketchRepr::deserialize(deserializer)?;
+        if repr.samples.len() > repr.capacity || repr.samples.len() as u64 > repr.seen {
+            return Err(serde::de::Error::custom("more samples than the reservoir can hold"));
+        }
+        
+        Ok(SampledDDSketch {
+            sketch: DDSketch::from_snapshot(&repr.snapshot).map_err(serde::de::Error::custom)?,
+            samples: repr.samples,
+            capacity: repr.capacity,
+            seen: repr.seen,
+            rng_state: SAMPLE_SEED,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DDSketchError;
+    
+    #[test]
+    fn test_reservoir_sampling() {
+        let mut sketch = SampledDDSketch::new(0.01, 100).unwrap();
+        for i in 0..50 {
+            sketch.add(i as f64);
+        }
+        assert_eq!(sketch.samples().len(), 50);
+        
+        for i in 50..100_000 {
+            sketch.add(i as f64);
+        }
+        sketch.add(f64::NAN);
+        assert_eq!(sketch.samples().len(), 100);
+        assert_eq!(sketch.sketch().count(), 100_000);
+        
+        // A uniform sample has about half its values below the median
+        let below = sketch.samples().iter().filter(|&&value| value < 50_000.0).count();
+        assert!((30..=70).contains(&below), "{} samples below the median", below);
+        
+        sketch.clear();
+        assert!(sketch.samples().is_empty());
+    }
+    
+    #[test]
+    fn test_reservoir_merge() {
+        let mut small = SampledDDSketch::new(0.01, 100).unwrap();
+        let mut large = SampledDDSketch::new(0.01, 100).unwrap();
+        for _ in 0..1000 {
+            small.add(1.0);
+        }
+        for _ in 0..9000 {
+            large.add(2.0);
+        }
+        
+        small.merge(&large).unwrap();
+        assert_eq!(small.sketch().count(), 10_000);
+        assert_eq!(small.samples().len(), 100);
+        let from_small = small.samples().iter().filter(|&&value| value == 1.0).count();
+        assert!((3..=20).contains(&from_small), "{} samples from the smaller side", from_small);
+        
+        let incompatible = SampledDDSketch::new(0.05, 100).unwrap();
+        assert!(matches!(small.merge(&incompatible), Err(DDSketchError::IncompatibleSketches { .. })));
+    }
+    
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_reservoir_serde() {
+        let mut sketch = SampledDDSketch::new(0.02, 10).unwrap();
+        for i in 1..=25 {
+            sketch.add(i as f64);
+        }
+        
+        let json = serde_json::to_string(&sketch).unwrap();
+        let restored: SampledDDSketch = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.samples(), sketch.samples());
+        assert_eq!(restored.capacity(), 10);
+        assert_eq!(restored.sketch().snapshot(), sketch.sketch().snapshot());
+    }
+}
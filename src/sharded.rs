@@ -0,0 +1,228 @@
+//! Sharded DDSketch
+//!
+//! This module provides a sketch that each thread records into through its own
+//! handle, without any synchronization on the add path, and that is merged into a
+//! single sketch only when read.
+
+use crate::{DDSketch, IntoSketchValue, Result};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// The number of values a handle records before publishing them to its shard
+pub const HANDLE_PUBLISH_INTERVAL: u64 = 1024;
+
+/// The shards of a `ShardedDDSketch`
+#[derive(Debug)]
+struct Shards {
+    /// The shards of handles that may still publish
+    live: Vec<Arc<Mutex<DDSketch>>>,
+    /// The merged contents of shards whose handles were dropped
+    retired: DDSketch,
+}
+
+/// A DDSketch split into per-thread shards that are merged on demand
+///
+/// Each thread records through a `ShardHandle` into a sketch it owns outright, so
+/// adding a value takes no lock and touches no shared cache line. Every
+/// `HANDLE_PUBLISH_INTERVAL` values, on `ShardHandle::publish`, and when dropped, a
+/// handle merges what it recorded into its shard, which `snapshot` and `flush` read.
+/// Values a handle has not yet published are not visible to readers.
+#[derive(Debug)]
+pub struct ShardedDDSketch {
+    relative_accuracy: f64,
+    shards: Mutex<Shards>,
+}
+
+/// A handle through which one thread records into a `ShardedDDSketch`
+///
+/// The handle does not borrow the sketch, so it can be moved into a spawned thread.
+#[derive(Debug)]
+pub struct ShardHandle {
+    local: DDSketch,
+    shard: Arc<Mutex<DDSketch>>,
+}
+
+/// Lock a mutex, recovering the data if another thread panicked while holding it
+///
+/// Every critical section leaves its sketch valid, so a poisoned lock is safe to use.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl ShardedDDSketch {
+    /// Create a new sharded sketch with no shards
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    ///
+    /// # Returns
+    /// A new ShardedDDSketch, or an error if the relative accuracy is invalid
+    pub fn new(relative_accuracy: f64) -> Result<Self> {
+        Ok(ShardedDDSketch {
+            relative_accuracy,
+            shards: Mutex::new(Shards {
+                live: Vec::new(),
+                retired: DDSketch::new(relative_accuracy)?,
+            }),
+        })
+    }
+    
+    /// Get the relative accuracy of the sketch
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+    
+    /// Create a handle with a new shard for the calling thread to record into
+    pub fn handle(&self) -> ShardHandle {
+        let shard = Arc::new(Mutex::new(self.empty_sketch()));
+        lock(&self.shards).live.push(Arc::clone(&shard));
+        
+        ShardHandle {
+            local: self.empty_sketch(),
+            shard,
+        }
+    }
+    
+    /// Get the number of shards whose handles are still alive
+    pub fn num_shards(&self) -> usize {
+        let mut shards = lock(&self.shards);
+        shards.retire_dropped();
+        shards.live.len()
+    }
+    
+    /// Merge every shard into a new sketch, leaving the shards unchanged
+    pub fn snapshot(&self) -> DDSketch {
+        let mut shards = lock(&self.shards);
+        shards.retire_dropped();
+        
+        let mut merged = shards.retired.clone();
+        for shard in &shards.live {
+            merged.merge(&*lock(shard)).expect("shards share the relative accuracy");
+        }
+        merged
+    }
+    
+    /// Merge every shard into a new sketch and clear the shards
+    ///
+    /// Each value published to a shard is returned by exactly one flush, which makes
+    /// this suited to interval reporting.
+    pub fn flush(&self) -> DDSketch {
+        let mut shards = lock(&self.shards);
+        shards.retire_dropped();
+        
+        let mut merged = std::mem::replace(&mut shards.retired, self.empty_sketch());
+        for shard in &shards.live {
+            let mut shard = lock(shard);
+            merged.merge(&*shard).expect("shards share the relative accuracy");
+            shard.clear();
+        }
+        merged
+    }
+    
+    fn empty_sketch(&self) -> DDSketch {
+        DDSketch::new(self.relative_accuracy).expect("the relative accuracy was validated")
+    }
+}
+
+impl Shards {
+    /// Fold the shards of dropped handles into the retired sketch
+    fn retire_dropped(&mut self) {
+        let retired = &mut self.retired;
+        self.live.retain(|shard| {
+            if Arc::strong_count(shard) > 1 {
+                return true;
+            }
+            retired.merge(&*lock(shard)).expect("shards share the relative accuracy");
+            false
+        });
+    }
+}
+
+impl ShardHandle {
+    /// Add a value to the thread's shard
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        self.add_with_count(value, 1);
+    }
+    
+    /// Add a value with a specific count to the thread's shard
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&mut self, value: impl IntoSketchValue, count: u64) {
+        self.local.add_with_count(value, count);
+        if self.local.count() >= HANDLE_PUBLISH_INTERVAL {
+            self.publish();
+        }
+    }
+    
+    /// Make the values recorded through this handle visible to readers
+    pub fn publish(&mut self) {
+        if self.local.is_empty() && self.local.non_finite_count() == 0 {
+            return;
+        }
+        
+        lock(&self.shard).merge(&self.local).expect("shards share the relative accuracy");
+        self.local.clear();
+    }
+}
+
+impl Drop for ShardHandle {
+    fn drop(&mut self) {
+        self.publish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_sharded_sketch_threads() {
+        let sketch = ShardedDDSketch::new(0.01).unwrap();
+        
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let mut handle = sketch.handle();
+                scope.spawn(move || {
+                    for i in 1..=2500 {
+                        handle.add((thread * 2500 + i) as f64);
+                    }
+                });
+            }
+        });
+        
+        // Every handle was dropped, publishing its remaining values
+        assert_eq!(sketch.num_shards(), 0);
+        let snapshot = sketch.snapshot();
+        assert_eq!(snapshot.count(), 10_000);
+        let median = snapshot.get_quantile_value(0.5).unwrap();
+        assert!((median - 5000.0).abs() <= 5000.0 * 0.01 + 1.0);
+    }
+    
+    #[test]
+    fn test_sharded_sketch_publish_and_flush() {
+        let sketch = ShardedDDSketch::new(0.02).unwrap();
+        let mut handle = sketch.handle();
+        handle.add(1.0);
+        assert_eq!(sketch.snapshot().count(), 0);
+        
+        handle.publish();
+        assert_eq!(sketch.snapshot().count(), 1);
+        for _ in 0..HANDLE_PUBLISH_INTERVAL {
+            handle.add(2.0);
+        }
+        assert_eq!(sketch.num_shards(), 1);
+        assert_eq!(sketch.snapshot().count(), HANDLE_PUBLISH_INTERVAL + 1);
+        
+        assert_eq!(sketch.flush().count(), HANDLE_PUBLISH_INTERVAL + 1);
+        assert!(sketch.snapshot().is_empty());
+        
+        handle.add(3.0);
+        drop(handle);
+        assert_eq!(sketch.flush().max(), Some(3.0));
+        assert!(ShardedDDSketch::new(1.5).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+//! Snapshots of DDSketch contents
+//!
+//! This module provides plain-data copies of a sketch's state. `SketchSnapshot`
+//! stores bins as `(index, count)` pairs, while `ColumnarSnapshot` keeps indices
+//! and counts in separate arrays for cache-friendly bulk processing.
+
+use crate::Result;
+use crate::mapping::{IndexMapping, LogarithmicMapping, MappingDescriptor};
+
+/// A point-in-time copy of a sketch's contents
+///
+/// Bins are sorted by ascending index. Negative values are stored by the index
+/// of their absolute value, mirroring the sketch's negative store.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SketchSnapshot {
+    /// The relative accuracy of the sketch the snapshot was taken from
+    pub relative_accuracy: f64,
+    /// The (index, count) pairs of the positive store
+    pub positive_bins: Vec<(i32, u64)>,
+    /// The (index, count) pairs of the negative store
+    pub negative_bins: Vec<(i32, u64)>,
+    /// Count of zero values
+    pub zero_count: u64,
+    /// The minimum value seen
+    pub min_value: Option<f64>,
+    /// The maximum value seen
+    pub max_value: Option<f64>,
+    /// The sum of all values
+    pub sum: f64,
+    /// The sum of the squares of all values
+    pub sum_squares: f64,
+    /// The magnitude below which nonzero values are counted as zero, or 0 if unset
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub min_indexable_value: f64,
+    /// The mapping the bins were indexed with, or `None` for the logarithmic mapping
+    /// of `relative_accuracy`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mapping: Option<MappingDescriptor>,
+}
+
+impl SketchSnapshot {
+    /// Get the descriptor of the mapping the bins were indexed with
+    ///
+    /// # Returns
+    /// The descriptor, or an error if there is none and the relative accuracy is invalid
+    pub fn mapping_descriptor(&self) -> Result<MappingDescriptor> {
+        match self.mapping {
+            Some(descriptor) => Ok(descriptor),
+            None => Ok(LogarithmicMapping::new(self.relative_accuracy)?.descriptor()),
+        }
+    }
+    
+    /// Get the total count of values in the snapshot
+    pub fn count(&self) -> u64 {
+        let positive: u64 = self.positive_bins.iter().map(|&(_, count)| count).sum();
+        let negative: u64 = self.negative_bins.iter().map(|&(_, count)| count).sum();
+        positive + negative + self.zero_count
+    }
+}
+
+/// A struct-of-arrays copy of a sketch's contents
+///
+/// Indices and counts are kept in parallel arrays so that bulk analytics can
+/// scan them with vectorized loops or expose them as zero-copy column views.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnarSnapshot {
+    relative_accuracy: f64,
+    positive_indices: Vec<i32>,
+    positive_counts: Vec<u64>,
+    negative_indices: Vec<i32>,
+    negative_counts: Vec<u64>,
+    zero_count: u64,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+    sum: f64,
+    sum_squares: f64,
+    min_indexable_value: f64,
+    mapping: Option<MappingDescriptor>,
+}
+
+impl ColumnarSnapshot {
+    /// Get the relative accuracy of the sketch the snapshot was taken from
+    pub fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+    
+    /// Get the indices of the positive store, in ascending order
+    pub fn positive_indices(&self) -> &[i32] {
+        &self.positive_indices
+    }
+    
+    /// Get the counts of the positive store, parallel to `positive_indices`
+    pub fn positive_counts(&self) -> &[u64] {
+        &self.positive_counts
+    }
+    
+    /// Get the indices of the negative store, in ascending order
+    pub fn negative_indices(&self) -> &[i32] {
+        &self.negative_indices
+    }
+    
+    /// Get the counts of the negative store, parallel to `negative_indices`
+    pub fn negative_counts(&self) -> &[u64] {
+        &self.negative_counts
+    }
+    
+    /// Get the count of zero values
+    pub fn zero_count(&self) -> u64 {
+        self.zero_count
+    }
+    
+    /// Get the minimum value seen
+    pub fn min_value(&self) -> Option<f64> {
+        self.min_value
+    }
+    
+    /// Get the maximum value seen
+    pub fn max_value(&self) -> Option<f64> {
+        self.max_value
+    }
+    
+    /// Get the sum of all values
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+    
+    /// Get the sum of the squares of all values
+    pub fn sum_squares(&self) -> f64 {
+        self.sum_squares
+    }
+    
+    /// Get the magnitude below which nonzero values are counted as zero, or 0 if unset
+    pub fn min_indexable_value(&self) -> f64 {
+        self.min_indexable_value
+    }
+    
+    /// Get the mapping the bins were indexed with, or `None` for the logarithmic
+    /// mapping of the relative accuracy
+    pub fn mapping(&self) -> Option<MappingDescriptor> {
+        self.mapping
+    }
+    
+    /// Get the total count of values in the snapshot
+    pub fn count(&self) -> u64 {
+        self.positive_counts.iter().sum::<u64>()
+            + self.negative_counts.iter().sum::<u64>()
+            + self.zero_count
+    }
+}
+
+impl From<&SketchSnapshot> for ColumnarSnapshot {
+    fn from(snapshot: &SketchSnapshot) -> Self {
+        let (positive_indices, positive_counts) = snapshot.positive_bins.iter().copied().unzip();
+        let (negative_indices, negative_counts) = snapshot.negative_bins.iter().copied().unzip();
+        
+        ColumnarSnapshot {
+            relative_accuracy: snapshot.relative_accuracy,
+            positive_indices,
+            positive_counts,
+            negative_indices,
+            negative_counts,
+            zero_count: snapshot.zero_count,
+            min_value: snapshot.min_value,
+            max_value: snapshot.max_value,
+            sum: snapshot.sum,
+            sum_squares: snapshot.sum_squares,
+            min_indexable_value: snapshot.min_indexable_value,
+            mapping: snapshot.mapping,
+        }
+    }
+}
+
+impl From<SketchSnapshot> for ColumnarSnapshot {
+    fn from(snapshot: SketchSnapshot) -> Self {
+        ColumnarSnapshot::from(&snapshot)
+    }
+}
+
+impl From<&ColumnarSnapshot> for SketchSnapshot {
+    fn from(columnar: &ColumnarSnapshot) -> Self {
+        SketchSnapshot {
+            relative_accuracy: columnar.relative_accuracy,
+            positive_bins: columnar.positive_indices.iter().copied()
+                .zip(columnar.positive_counts.iter().copied())
+                .collect(),
+            negative_bins: columnar.negative_indices.iter().copied()
+                .zip(columnar.negative_counts.iter().copied())
+                .collect(),
+            zero_count: columnar.zero_count,
+            min_value: columnar.min_value,
+            max_value: columnar.max_value,
+            sum: columnar.sum,
+            sum_squares: columnar.sum_squares,
+            min_indexable_value: columnar.min_indexable_value,
+            mapping: columnar.mapping,
+        }
+    }
+}
+
+impl From<ColumnarSnapshot> for SketchSnapshot {
+    fn from(columnar: ColumnarSnapshot) -> Self {
+        SketchSnapshot::from(&columnar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn sample_snapshot() -> SketchSnapshot {
+        SketchSnapshot {
+            relative_accuracy: 0.02,
+            positive_bins: vec![(1, 4), (5, 2), (9, 1)],
+            negative_bins: vec![(3, 3)],
+            zero_count: 2,
+            min_value: Some(-1.5),
+            max_value: Some(12.0),
+            sum: 25.5,
+            sum_squares: 180.25,
+            min_indexable_value: 1e-9,
+            mapping: None,
+        }
+    }
+    
+    #[test]
+    fn test_columnar_layout() {
+        let columnar = ColumnarSnapshot::from(&sample_snapshot());
+        
+        assert_eq!(columnar.positive_indices(), &[1, 5, 9]);
+        assert_eq!(columnar.positive_counts(), &[4, 2, 1]);
+        assert_eq!(columnar.negative_indices(), &[3]);
+        assert_eq!(columnar.negative_counts(), &[3]);
+        assert_eq!(columnar.zero_count(), 2);
+        assert_eq!(columnar.count(), 12);
+    }
+    
+    #[test]
+    fn test_columnar_round_trip() {
+        let snapshot = sample_snapshot();
+        let columnar = ColumnarSnapshot::from(snapshot.clone());
+        
+        assert_eq!(SketchSnapshot::from(columnar), snapshot);
+        assert_eq!(snapshot.count(), 12);
+    }
+}
@@ -4,81 +4,1067 @@
 //! mapping from indices to counts.
 
 use std::collections::HashMap;
-use crate::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::{DDSketchError, Result};
 
 /// Trait for storing index-count pairs
 pub trait Store {
     /// Add a count to the given index
     fn add(&mut self, index: i32, count: u64);
     
+    /// Add a count to the given index, reporting counts the store refuses to hold
+    fn try_add(&mut self, index: i32, count: u64) -> Result<()> {
+        self.add(index, count);
+        Ok(())
+    }
+    
+    /// Remove up to `count` from the given index
+    ///
+    /// # Returns
+    /// The count actually removed, which is less than `count` if the bin held less
+    fn remove(&mut self, index: i32, count: u64) -> u64;
+    
     /// Get the count for a given index
     fn get(&self, index: i32) -> u64;
     
-    /// Get the total count across all indices
-    fn total_count(&self) -> u64;
+    /// Get the total count across all indices
+    fn total_count(&self) -> u64;
+    
+    /// Check if the store is empty
+    fn is_empty(&self) -> bool;
+    
+    /// Get the minimum index with a non-zero count
+    fn min_index(&self) -> Option<i32>;
+    
+    /// Get the maximum index with a non-zero count
+    fn max_index(&self) -> Option<i32>;
+    
+    /// Iterate over all (index, count) pairs
+    fn iter(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_>;
+    
+    /// Get the number of non-empty bins
+    fn num_bins(&self) -> usize {
+        self.iter().count()
+    }
+    
+    /// Iterate over all (index, count) pairs in ascending index order
+    ///
+    /// Stores whose `iter` is already ordered, as reported by `has_ordered_ranks`,
+    /// stream their bins; the others collect and sort them first.
+    fn iter_ascending(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        if self.has_ordered_ranks() {
+            return self.iter();
+        }
+        let mut bins: Vec<(i32, u64)> = self.iter().collect();
+        bins.sort_unstable_by_key(|&(index, _)| index);
+        Box::new(bins.into_iter())
+    }
+    
+    /// Iterate over all (index, count) pairs in descending index order
+    ///
+    /// The default collects the ascending bins and reverses them; stores that keep
+    /// their bins in order walk them backwards instead.
+    fn iter_descending(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        let bins: Vec<(i32, u64)> = self.iter_ascending().collect();
+        Box::new(bins.into_iter().rev())
+    }
+    
+    /// Copy the (index, count) pairs of all bins out in ascending index order
+    ///
+    /// The result is plain owned data, so it can outlive the store or be sent to
+    /// another thread.
+    fn to_vec(&self) -> Vec<(i32, u64)> {
+        self.iter_ascending().collect()
+    }
+    
+    /// Get the bin holding a rank, counting values in ascending index order from 0
+    ///
+    /// # Returns
+    /// The index and count of the bin, or `None` if `rank` is not below the total count
+    fn key_at_rank(&self, rank: u64) -> Option<(i32, u64)> {
+        self.key_span_at_rank(rank).map(|(index, count, _)| (index, count))
+    }
+    
+    /// Get the bin holding a rank together with the ranks before it, counting values
+    /// in ascending index order from 0
+    ///
+    /// # Returns
+    /// The index and count of the bin and the total count of the bins below it, or
+    /// `None` if `rank` is not below the total count
+    fn key_span_at_rank(&self, rank: u64) -> Option<(i32, u64, u64)> {
+        bin_holding_rank(self.iter_ascending(), rank)
+    }
+    
+    /// Check whether `iter` yields the bins in ascending index order, so ordered
+    /// traversals and `key_at_rank` need not collect and sort them
+    fn has_ordered_ranks(&self) -> bool {
+        false
+    }
+    
+    /// Estimate the memory used by the store, including the store itself, in bytes
+    fn memory_size_bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+    
+    /// Merge another store into this one
+    fn merge(&mut self, other: &dyn Store);
+    
+    /// Merge another store into this one, reporting counts the store refuses to hold
+    fn try_merge(&mut self, other: &dyn Store) -> Result<()> {
+        self.merge(other);
+        Ok(())
+    }
+    
+    /// Check that the store would hold the given bins, without adding them
+    ///
+    /// Lets a caller adding to several stores validate them all first, so a refusal
+    /// leaves every store unchanged.
+    fn check_add(&self, _bins: &mut dyn Iterator<Item = (i32, u64)>) -> Result<()> {
+        Ok(())
+    }
+    
+    /// Reserve room for at least `additional` more bins
+    fn reserve(&mut self, _additional: usize) {}
+    
+    /// Release allocated room beyond what the current bins need
+    fn shrink_to_fit(&mut self) {}
+    
+    /// Get the inclusive range of indices whose counts may sit in a different bin
+    ///
+    /// Only stores that collapse bins to stay within a budget report a range.
+    fn collapsed_index_range(&self) -> Option<(i32, i32)> {
+        None
+    }
+    
+    /// Remove the counts of another store from this one, saturating at zero per bin
+    fn subtract(&mut self, other: &dyn Store) {
+        for (index, count) in other.iter() {
+            self.remove(index, count);
+        }
+    }
+    
+    /// Clear all data
+    fn clear(&mut self);
+}
+
+impl<T: Store + ?Sized> Store for Box<T> {
+    fn add(&mut self, index: i32, count: u64) {
+        (**self).add(index, count)
+    }
+    
+    fn try_add(&mut self, index: i32, count: u64) -> Result<()> {
+        (**self).try_add(index, count)
+    }
+    
+    fn remove(&mut self, index: i32, count: u64) -> u64 {
+        (**self).remove(index, count)
+    }
+    
+    fn get(&self, index: i32) -> u64 {
+        (**self).get(index)
+    }
+    
+    fn total_count(&self) -> u64 {
+        (**self).total_count()
+    }
+    
+    fn is_empty(&self) -> bool {
+        (**self).is_empty()
+    }
+    
+    fn min_index(&self) -> Option<i32> {
+        (**self).min_index()
+    }
+    
+    fn max_index(&self) -> Option<i32> {
+        (**self).max_index()
+    }
+    
+    fn iter(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        (**self).iter()
+    }
+    
+    fn num_bins(&self) -> usize {
+        (**self).num_bins()
+    }
+    
+    fn iter_ascending(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        (**self).iter_ascending()
+    }
+    
+    fn iter_descending(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        (**self).iter_descending()
+    }
+    
+    fn to_vec(&self) -> Vec<(i32, u64)> {
+        (**self).to_vec()
+    }
+    
+    fn key_at_rank(&self, rank: u64) -> Option<(i32, u64)> {
+        (**self).key_at_rank(rank)
+    }
+    
+    fn key_span_at_rank(&self, rank: u64) -> Option<(i32, u64, u64)> {
+        (**self).key_span_at_rank(rank)
+    }
+    
+    fn has_ordered_ranks(&self) -> bool {
+        (**self).has_ordered_ranks()
+    }
+    
+    fn memory_size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + (**self).memory_size_bytes()
+    }
+    
+    fn merge(&mut self, other: &dyn Store) {
+        (**self).merge(other)
+    }
+    
+    fn try_merge(&mut self, other: &dyn Store) -> Result<()> {
+        (**self).try_merge(other)
+    }
+    
+    fn check_add(&self, bins: &mut dyn Iterator<Item = (i32, u64)>) -> Result<()> {
+        (**self).check_add(bins)
+    }
+    
+    fn reserve(&mut self, additional: usize) {
+        (**self).reserve(additional)
+    }
+    
+    fn shrink_to_fit(&mut self) {
+        (**self).shrink_to_fit()
+    }
+    
+    fn collapsed_index_range(&self) -> Option<(i32, i32)> {
+        (**self).collapsed_index_range()
+    }
+    
+    fn subtract(&mut self, other: &dyn Store) {
+        (**self).subtract(other)
+    }
+    
+    fn clear(&mut self) {
+        (**self).clear()
+    }
+}
+
+/// Find the bin holding a rank among bins in ascending index order, with the count
+/// of the bins before it
+fn bin_holding_rank(bins: impl Iterator<Item = (i32, u64)>, rank: u64) -> Option<(i32, u64, u64)> {
+    let mut rank_before = 0u64;
+    for (index, count) in bins {
+        if rank_before + count > rank {
+            return Some((index, count, rank_before));
+        }
+        rank_before += count;
+    }
+    None
+}
+
+/// A simple HashMap-based store
+#[derive(Debug, Clone)]
+pub struct DenseStore {
+    bins: HashMap<i32, u64>,
+    total_count: u64,
+}
+
+impl DenseStore {
+    /// Create a new empty store
+    pub fn new() -> Self {
+        DenseStore {
+            bins: HashMap::new(),
+            total_count: 0,
+        }
+    }
+    
+    /// Create a store with initial capacity
+    pub fn with_capacity(capacity: usize) -> Self {
+        DenseStore {
+            bins: HashMap::with_capacity(capacity),
+            total_count: 0,
+        }
+    }
+}
+
+impl Default for DenseStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Store for DenseStore {
+    fn add(&mut self, index: i32, count: u64) {
+        if count == 0 {
+            return;
+        }
+        
+        *self.bins.entry(index).or_insert(0) += count;
+        self.total_count += count;
+    }
+    
+    fn remove(&mut self, index: i32, count: u64) -> u64 {
+        let Some(bin) = self.bins.get_mut(&index) else {
+            return 0;
+        };
+        
+        let removed = count.min(*bin);
+        *bin -= removed;
+        if *bin == 0 {
+            self.bins.remove(&index);
+        }
+        self.total_count -= removed;
+        removed
+    }
+    
+    fn get(&self, index: i32) -> u64 {
+        self.bins.get(&index).copied().unwrap_or(0)
+    }
+    
+    fn total_count(&self) -> u64 {
+        self.total_count
+    }
+    
+    fn is_empty(&self) -> bool {
+        self.total_count == 0
+    }
+    
+    fn min_index(&self) -> Option<i32> {
+        self.bins.keys().min().copied()
+    }
+    
+    fn max_index(&self) -> Option<i32> {
+        self.bins.keys().max().copied()
+    }
+    
+    fn iter(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        Box::new(self.bins.iter().map(|(&index, &count)| (index, count)))
+    }
+    
+    fn num_bins(&self) -> usize {
+        self.bins.len()
+    }
+    
+    /// Estimate the memory used by the store
+    ///
+    /// Counts the allocated hash table slots, each holding an entry and a control byte.
+    fn memory_size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.bins.capacity() * (std::mem::size_of::<(i32, u64)>() + 1)
+    }
+    
+    fn merge(&mut self, other: &dyn Store) {
+        for (index, count) in other.iter() {
+            self.add(index, count);
+        }
+    }
+    
+    fn reserve(&mut self, additional: usize) {
+        self.bins.reserve(additional);
+    }
+    
+    fn shrink_to_fit(&mut self) {
+        self.bins.shrink_to_fit();
+    }
+    
+    fn clear(&mut self) {
+        self.bins.clear();
+        self.total_count = 0;
+    }
+}
+
+/// The number of bins a `HybridStore` holds sparsely before considering a dense array
+pub const DEFAULT_SPARSE_MAX_BINS: usize = 32;
+
+/// The lowest fraction of occupied slots at which a `HybridStore` switches to a dense array
+const DENSE_MIN_OCCUPANCY: f64 = 0.25;
+
+/// The representation of a `HybridStore`
+#[derive(Debug, Clone)]
+enum HybridBins {
+    /// Sorted indices and their counts, kept apart to avoid padding
+    Sparse { indices: Vec<i32>, counts: Vec<u64> },
+    /// Counts for the contiguous indices starting at `offset`
+    Dense { offset: i32, counts: Vec<u64> },
+}
+
+/// A store that starts as a small sorted vector and becomes a contiguous array
+///
+/// Small stores take twelve bytes per bin and no hash table, which matters when a
+/// process holds millions of mostly tiny sketches. Once the store holds more than
+/// `sparse_max_bins` bins that occupy at least a quarter of the index range they
+/// span, it switches to a dense array with constant-time updates. A dense store
+/// stays dense until it is cleared.
+#[derive(Debug, Clone)]
+pub struct HybridStore {
+    bins: HybridBins,
+    total_count: u64,
+    sparse_max_bins: usize,
+}
+
+impl HybridStore {
+    /// Create a new empty store that switches at the default number of bins
+    pub fn new() -> Self {
+        Self::with_sparse_max_bins(DEFAULT_SPARSE_MAX_BINS)
+    }
+    
+    /// Create a new empty store that holds up to `sparse_max_bins` bins sparsely
+    pub fn with_sparse_max_bins(sparse_max_bins: usize) -> Self {
+        HybridStore {
+            bins: HybridBins::Sparse { indices: Vec::new(), counts: Vec::new() },
+            total_count: 0,
+            sparse_max_bins,
+        }
+    }
+    
+    /// Check whether the store has switched to a dense array
+    pub fn is_dense(&self) -> bool {
+        matches!(self.bins, HybridBins::Dense { .. })
+    }
+    
+    /// Switch to a dense array if the sparse bins are numerous and close enough together
+    fn densify_if_needed(&mut self) {
+        let HybridBins::Sparse { indices, counts } = &self.bins else {
+            return;
+        };
+        let (Some(&first), Some(&last)) = (indices.first(), indices.last()) else {
+            return;
+        };
+        
+        let span = (last as i64 - first as i64 + 1) as f64;
+        if indices.len() <= self.sparse_max_bins || (indices.len() as f64) < span * DENSE_MIN_OCCUPANCY {
+            return;
+        }
+        
+        let mut dense = vec![0; span as usize];
+        for (&index, &count) in indices.iter().zip(counts) {
+            dense[(index - first) as usize] = count;
+        }
+        self.bins = HybridBins::Dense { offset: first, counts: dense };
+    }
+}
+
+impl Default for HybridStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Store for HybridStore {
+    fn add(&mut self, index: i32, count: u64) {
+        if count == 0 {
+            return;
+        }
+        self.total_count += count;
+        
+        match &mut self.bins {
+            HybridBins::Sparse { indices, counts } => {
+                match indices.binary_search(&index) {
+                    Ok(position) => counts[position] += count,
+                    Err(position) => {
+                        indices.insert(position, index);
+                        counts.insert(position, count);
+                        self.densify_if_needed();
+                    }
+                }
+            }
+            HybridBins::Dense { offset, counts } => {
+                // Grow the array to cover the index, on whichever side it falls
+                if index < *offset {
+                    let extra = (*offset as i64 - index as i64) as usize;
+                    counts.splice(0..0, std::iter::repeat_n(0, extra));
+                    *offset = index;
+                } else if (index as i64 - *offset as i64) as usize >= counts.len() {
+                    counts.resize((index as i64 - *offset as i64) as usize + 1, 0);
+                }
+                counts[(index as i64 - *offset as i64) as usize] += count;
+            }
+        }
+    }
+    
+    fn remove(&mut self, index: i32, count: u64) -> u64 {
+        let removed = match &mut self.bins {
+            HybridBins::Sparse { indices, counts } => {
+                let Ok(position) = indices.binary_search(&index) else {
+                    return 0;
+                };
+                let removed = count.min(counts[position]);
+                counts[position] -= removed;
+                if counts[position] == 0 {
+                    indices.remove(position);
+                    counts.remove(position);
+                }
+                removed
+            }
+            HybridBins::Dense { offset, counts } => {
+                let Some(bin) = usize::try_from(index as i64 - *offset as i64).ok()
+                    .and_then(|position| counts.get_mut(position))
+                else {
+                    return 0;
+                };
+                let removed = count.min(*bin);
+                *bin -= removed;
+                removed
+            }
+        };
+        
+        self.total_count -= removed;
+        removed
+    }
+    
+    fn get(&self, index: i32) -> u64 {
+        match &self.bins {
+            HybridBins::Sparse { indices, counts } => indices.binary_search(&index)
+                .map_or(0, |position| counts[position]),
+            HybridBins::Dense { offset, counts } => usize::try_from(index as i64 - *offset as i64).ok()
+                .and_then(|position| counts.get(position))
+                .copied()
+                .unwrap_or(0),
+        }
+    }
+    
+    fn total_count(&self) -> u64 {
+        self.total_count
+    }
+    
+    fn is_empty(&self) -> bool {
+        self.total_count == 0
+    }
+    
+    fn min_index(&self) -> Option<i32> {
+        self.iter().next().map(|(index, _)| index)
+    }
+    
+    fn max_index(&self) -> Option<i32> {
+        match &self.bins {
+            HybridBins::Sparse { indices, .. } => indices.last().copied(),
+            HybridBins::Dense { offset, counts } => counts.iter().rposition(|&count| count > 0)
+                .map(|position| offset + position as i32),
+        }
+    }
+    
+    /// Iterate over the non-empty bins in ascending index order
+    fn iter(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        match &self.bins {
+            HybridBins::Sparse { indices, counts } => Box::new(indices.iter().copied().zip(counts.iter().copied())),
+            HybridBins::Dense { offset, counts } => Box::new(
+                counts.iter()
+                    .enumerate()
+                    .filter(|&(_, &count)| count > 0)
+                    .map(move |(position, &count)| (offset + position as i32, count)),
+            ),
+        }
+    }
+    
+    fn iter_descending(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        match &self.bins {
+            HybridBins::Sparse { indices, counts } => {
+                Box::new(indices.iter().copied().zip(counts.iter().copied()).rev())
+            }
+            HybridBins::Dense { offset, counts } => Box::new(
+                counts.iter()
+                    .enumerate()
+                    .rev()
+                    .filter(|&(_, &count)| count > 0)
+                    .map(move |(position, &count)| (offset + position as i32, count)),
+            ),
+        }
+    }
+    
+    fn has_ordered_ranks(&self) -> bool {
+        true
+    }
+    
+    fn memory_size_bytes(&self) -> usize {
+        let heap = match &self.bins {
+            HybridBins::Sparse { indices, counts } => {
+                indices.capacity() * std::mem::size_of::<i32>() + counts.capacity() * std::mem::size_of::<u64>()
+            }
+            HybridBins::Dense { counts, .. } => counts.capacity() * std::mem::size_of::<u64>(),
+        };
+        std::mem::size_of::<Self>() + heap
+    }
+    
+    fn merge(&mut self, other: &dyn Store) {
+        for (index, count) in other.iter() {
+            self.add(index, count);
+        }
+    }
+    
+    fn shrink_to_fit(&mut self) {
+        match &mut self.bins {
+            HybridBins::Sparse { indices, counts } => {
+                indices.shrink_to_fit();
+                counts.shrink_to_fit();
+            }
+            HybridBins::Dense { counts, .. } => counts.shrink_to_fit(),
+        }
+    }
+    
+    fn clear(&mut self) {
+        self.bins = HybridBins::Sparse { indices: Vec::new(), counts: Vec::new() };
+        self.total_count = 0;
+    }
+}
+
+/// A HashMap-based store with fractional counts
+///
+/// Used by `WeightedDDSketch`, where each value carries an arbitrary positive weight
+/// instead of an integer count.
+#[derive(Debug, Clone, Default)]
+pub struct WeightedStore {
+    bins: HashMap<i32, f64>,
+    total_weight: f64,
+}
+
+impl WeightedStore {
+    /// Create a new empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Add a weight to the given index
+    pub fn add(&mut self, index: i32, weight: f64) {
+        if weight <= 0.0 {
+            return;
+        }
+        
+        *self.bins.entry(index).or_insert(0.0) += weight;
+        self.total_weight += weight;
+    }
+    
+    /// Get the weight for a given index
+    pub fn get(&self, index: i32) -> f64 {
+        self.bins.get(&index).copied().unwrap_or(0.0)
+    }
+    
+    /// Get the total weight across all indices
+    pub fn total_weight(&self) -> f64 {
+        self.total_weight
+    }
+    
+    /// Check if the store is empty
+    pub fn is_empty(&self) -> bool {
+        self.bins.is_empty()
+    }
+    
+    /// Iterate over all (index, weight) pairs
+    pub fn iter(&self) -> impl Iterator<Item = (i32, f64)> + '_ {
+        self.bins.iter().map(|(&index, &weight)| (index, weight))
+    }
+    
+    /// Merge another store into this one
+    pub fn merge(&mut self, other: &WeightedStore) {
+        for (index, weight) in other.iter() {
+            self.add(index, weight);
+        }
+    }
+    
+    /// Multiply every weight by a factor
+    ///
+    /// Bins whose weight falls below `min_weight` are dropped.
+    pub fn scale(&mut self, factor: f64, min_weight: f64) {
+        self.bins.retain(|_, weight| {
+            *weight *= factor;
+            *weight >= min_weight && *weight > 0.0
+        });
+        self.total_weight = self.bins.values().sum();
+    }
+    
+    /// Clear all weights
+    pub fn clear(&mut self) {
+        self.bins.clear();
+        self.total_weight = 0.0;
+    }
+}
+
+/// Policy applied by a `CollapsingStore` when its bin budget is exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollapsePolicy {
+    /// Fold the lowest bins into the lowest remaining bin, losing precision at the low end
+    #[default]
+    CollapseLowest,
+    /// Fold the highest bins into the highest remaining bin, losing precision at the high end
+    CollapseHighest,
+    /// Refuse counts that would require a new bin once the budget is reached
+    Error,
+}
+
+/// Estimate the capacity of a hash table created to hold `requested` entries
+///
+/// Follows the standard library's current growth policy, which holds 3 or 7 entries
+/// when small and otherwise keeps a power of two slots at most 7/8 full. That policy
+/// is not part of the standard library's API and may change between releases.
+fn table_capacity(requested: usize) -> usize {
+    match requested {
+        0 => 0,
+        1..=3 => 3,
+        4..=7 => 7,
+        _ => (requested * 8 / 7).next_power_of_two() / 8 * 7,
+    }
+}
+
+/// A collapsing store that maintains a maximum number of bins
+#[derive(Debug, Clone)]
+pub struct CollapsingStore {
+    store: DenseStore,
+    max_num_bins: usize,
+    policy: CollapsePolicy,
+    /// The number of times bins were folded to stay within the budget
+    collapse_count: u64,
+    /// The range of indices whose counts may have been folded into another bin
+    collapsed_range: Option<(i32, i32)>,
+    /// The capacity of the bin table when the store was created
+    bin_capacity: usize,
+}
+
+impl CollapsingStore {
+    /// Create a new collapsing store with the given maximum number of bins
+    pub fn new(max_num_bins: usize) -> Self {
+        Self::with_policy(max_num_bins, CollapsePolicy::default())
+    }
+    
+    /// Create a new collapsing store with the given maximum number of bins and collapse policy
+    pub fn with_policy(max_num_bins: usize, policy: CollapsePolicy) -> Self {
+        // A new bin is inserted before the excess is collapsed, so leave room for one more
+        let store = DenseStore::with_capacity(max_num_bins + 1);
+        CollapsingStore {
+            bin_capacity: store.bins.capacity(),
+            store,
+            max_num_bins,
+            policy,
+            collapse_count: 0,
+            collapsed_range: None,
+        }
+    }
+    
+    /// Get the collapse policy of this store
+    pub fn policy(&self) -> CollapsePolicy {
+        self.policy
+    }
+    
+    /// Get the maximum number of bins of this store
+    pub fn max_num_bins(&self) -> usize {
+        self.max_num_bins
+    }
+    
+    /// Check whether any bins have been collapsed since the store was created or cleared
+    pub fn is_collapsed(&self) -> bool {
+        self.collapse_count > 0
+    }
+    
+    /// Get the number of times bins were collapsed to stay within the budget
+    pub fn collapse_count(&self) -> u64 {
+        self.collapse_count
+    }
+    
+    /// Estimate `memory_size_bytes()` of a new store with a given maximum number of
+    /// bins, without creating one
+    ///
+    /// The estimate relies on the standard library's hash table growth policy, see
+    /// `table_capacity`. With the current policy the store never reports more as it
+    /// fills, since the bin table is sized for the budget up front and shrunk back
+    /// after collapsing.
+    pub(crate) fn memory_size_for(max_num_bins: usize) -> usize {
+        std::mem::size_of::<Self>() + table_capacity(max_num_bins + 1) * (std::mem::size_of::<(i32, u64)>() + 1)
+    }
+    
+    /// Collapse bins if necessary to maintain the maximum number of bins
+    ///
+    /// The bin table can grow while bins are added before collapsing, or to clear out
+    /// the slots of collapsed bins; it is shrunk back so the store's footprint stays
+    /// that of `max_num_bins` bins.
+    fn collapse_if_needed(&mut self) {
+        self.collapse_excess_bins();
+        if self.store.bins.capacity() > self.bin_capacity {
+            self.store.bins.shrink_to(self.max_num_bins + 1);
+        }
+    }
+    
+    fn collapse_excess_bins(&mut self) {
+        // Always keep at least one bin so that counts are never lost
+        let max_num_bins = self.max_num_bins.max(1);
+        if self.store.bins.len() <= max_num_bins {
+            return;
+        }
+        
+        let mut sorted_indices: Vec<i32> = self.store.bins.keys().copied().collect();
+        sorted_indices.sort_unstable();
+        let excess = sorted_indices.len() - max_num_bins;
+        
+        // Fold the excess bins into the nearest bin that is kept
+        let (collapsed, target) = match self.policy {
+            CollapsePolicy::CollapseLowest | CollapsePolicy::Error => {
+                (&sorted_indices[..excess], sorted_indices[excess])
+            }
+            CollapsePolicy::CollapseHighest => {
+                (&sorted_indices[max_num_bins..], sorted_indices[max_num_bins - 1])
+            }
+        };
+        
+        let mut collapsed_count = 0;
+        for index in collapsed {
+            collapsed_count += self.store.bins.remove(index).unwrap_or(0);
+        }
+        
+        *self.store.bins.entry(target).or_insert(0) += collapsed_count;
+        
+        let low = collapsed[0].min(target);
+        let high = collapsed[collapsed.len() - 1].max(target);
+        self.collapsed_range = Some(self.collapsed_range
+            .map_or((low, high), |(previous_low, previous_high)| (previous_low.min(low), previous_high.max(high))));
+        self.collapse_count += 1;
+    }
+    
+    /// Check whether adding the given indices would exceed the bin budget
+    fn exceeds_budget(&self, new_indices: impl Iterator<Item = (i32, u64)>) -> bool {
+        let new_bins = new_indices
+            .filter(|&(index, count)| count > 0 && !self.store.bins.contains_key(&index))
+            .count();
+        self.store.bins.len() + new_bins > self.max_num_bins
+    }
+}
+
+impl Store for CollapsingStore {
+    /// Add a count to the given index
+    ///
+    /// With `CollapsePolicy::Error`, counts that would require a new bin beyond the
+    /// budget are dropped; use `try_add` to observe the rejection.
+    fn add(&mut self, index: i32, count: u64) {
+        let _ = self.try_add(index, count);
+    }
     
-    /// Check if the store is empty
-    fn is_empty(&self) -> bool;
+    fn try_add(&mut self, index: i32, count: u64) -> Result<()> {
+        if self.policy == CollapsePolicy::Error
+            && self.exceeds_budget(std::iter::once((index, count)))
+        {
+            return Err(DDSketchError::BinLimitExceeded);
+        }
+        
+        self.store.add(index, count);
+        self.collapse_if_needed();
+        Ok(())
+    }
     
-    /// Get the minimum index with a non-zero count
-    fn min_index(&self) -> Option<i32>;
+    /// Remove up to `count` from the given index
+    ///
+    /// Counts that were folded into a collapsed bin are no longer found under their
+    /// original index, so they are not removed.
+    fn remove(&mut self, index: i32, count: u64) -> u64 {
+        self.store.remove(index, count)
+    }
     
-    /// Get the maximum index with a non-zero count
-    fn max_index(&self) -> Option<i32>;
+    fn get(&self, index: i32) -> u64 {
+        self.store.get(index)
+    }
     
-    /// Iterate over all (index, count) pairs
-    fn iter(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_>;
+    fn total_count(&self) -> u64 {
+        self.store.total_count()
+    }
+    
+    fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+    
+    fn min_index(&self) -> Option<i32> {
+        self.store.min_index()
+    }
+    
+    fn max_index(&self) -> Option<i32> {
+        self.store.max_index()
+    }
+    
+    fn iter(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        self.store.iter()
+    }
+    
+    fn num_bins(&self) -> usize {
+        self.store.num_bins()
+    }
+    
+    fn memory_size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() - std::mem::size_of::<DenseStore>() + self.store.memory_size_bytes()
+    }
     
     /// Merge another store into this one
-    fn merge(&mut self, other: &dyn Store);
+    ///
+    /// With `CollapsePolicy::Error`, bins that do not fit in the budget are dropped;
+    /// use `try_merge` to reject the whole merge instead.
+    fn merge(&mut self, other: &dyn Store) {
+        if self.policy == CollapsePolicy::Error {
+            for (index, count) in other.iter() {
+                let _ = self.try_add(index, count);
+            }
+            return;
+        }
+        
+        self.store.merge(other);
+        self.collapse_if_needed();
+    }
     
-    /// Clear all data
-    fn clear(&mut self);
+    fn try_merge(&mut self, other: &dyn Store) -> Result<()> {
+        self.check_add(&mut other.iter())?;
+        self.merge(other);
+        Ok(())
+    }
+    
+    fn check_add(&self, bins: &mut dyn Iterator<Item = (i32, u64)>) -> Result<()> {
+        if self.policy == CollapsePolicy::Error && self.exceeds_budget(bins) {
+            return Err(DDSketchError::BinLimitExceeded);
+        }
+        Ok(())
+    }
+    
+    /// Values with indices in this range have lost their accuracy guarantee. Collapses
+    /// caused by bins merged in from other stores are included; collapses that happened
+    /// in the other stores before the merge are not.
+    fn collapsed_index_range(&self) -> Option<(i32, i32)> {
+        self.collapsed_range
+    }
+    
+    fn shrink_to_fit(&mut self) {
+        self.store.shrink_to_fit();
+    }
+    
+    fn clear(&mut self) {
+        self.store.clear();
+        self.collapse_count = 0;
+        self.collapsed_range = None;
+    }
 }
 
-/// A simple HashMap-based store
+/// A store of `N` contiguous bins held inline, with no heap allocation
+///
+/// The bins cover a window of `N` consecutive indices that slides to follow the
+/// highest index added. Counts below the window are folded into its lowest bin, as
+/// `CollapsePolicy::CollapseLowest` does, so the high quantiles keep their accuracy
+/// guarantee. Adding, removing and merging never allocate; iterating boxes the
+/// iterator, as every store does.
 #[derive(Debug, Clone)]
-pub struct DenseStore {
-    bins: HashMap<i32, u64>,
+pub struct FixedStore<const N: usize> {
+    counts: [u64; N],
+    /// The index of `counts[0]`
+    offset: i32,
     total_count: u64,
+    /// The number of times bins were folded to stay within the window
+    collapse_count: u64,
+    /// The range of indices whose counts may have been folded into another bin
+    collapsed_range: Option<(i32, i32)>,
 }
 
-impl DenseStore {
-    /// Create a new empty store
+impl<const N: usize> FixedStore<N> {
+    /// Create a new, empty store
+    ///
+    /// # Panics
+    /// At compile time if `N` is zero
     pub fn new() -> Self {
-        DenseStore {
-            bins: HashMap::new(),
+        const { assert!(N > 0, "a FixedStore needs at least one bin") };
+        
+        FixedStore {
+            counts: [0; N],
+            offset: 0,
             total_count: 0,
+            collapse_count: 0,
+            collapsed_range: None,
         }
     }
     
-    /// Create a store with initial capacity
-    pub fn with_capacity(capacity: usize) -> Self {
-        DenseStore {
-            bins: HashMap::with_capacity(capacity),
-            total_count: 0,
+    /// Get the number of times bins were folded to stay within the window
+    pub fn collapse_count(&self) -> u64 {
+        self.collapse_count
+    }
+    
+    /// Get the position of an index in the window, sliding or folding as needed
+    fn slot(&mut self, index: i32) -> usize {
+        let (index, offset, width) = (index as i64, self.offset as i64, N as i64);
+        if self.total_count == 0 {
+            self.counts = [0; N];
+            self.offset = index as i32;
+            return 0;
         }
+        
+        if index >= offset + width {
+            // Slide up so the index is the last bin, folding what falls off the bottom
+            let new_offset = index - width + 1;
+            let shift = (new_offset - offset).min(width) as usize;
+            let folded: u64 = self.counts[..shift].iter().sum();
+            if folded > 0 {
+                let lowest = self.counts[..shift].iter().position(|&count| count > 0).unwrap_or(0);
+                self.record_collapse(self.offset + lowest as i32, new_offset as i32);
+            }
+            self.counts.copy_within(shift.., 0);
+            self.counts[N - shift..].fill(0);
+            self.offset = new_offset as i32;
+            self.counts[0] += folded;
+            return N - 1;
+        }
+        
+        if index < offset {
+            // Slide down as far as the highest bin allows, then fold into the lowest bin
+            let highest = self.counts.iter().rposition(|&count| count > 0).unwrap_or(0) as i64 + offset;
+            let new_offset = index.max(highest - width + 1);
+            if new_offset < offset {
+                let shift = (offset - new_offset) as usize;
+                self.counts.copy_within(..N - shift, shift);
+                self.counts[..shift].fill(0);
+                self.offset = new_offset as i32;
+            }
+            if index < new_offset {
+                self.record_collapse(index as i32, new_offset as i32);
+                return 0;
+            }
+        }
+        
+        (index - self.offset as i64) as usize
+    }
+    
+    fn record_collapse(&mut self, low: i32, high: i32) {
+        self.collapsed_range = Some(self.collapsed_range
+            .map_or((low, high), |(previous_low, previous_high)| (previous_low.min(low), previous_high.max(high))));
+        self.collapse_count += 1;
     }
 }
 
-impl Default for DenseStore {
+impl<const N: usize> Default for FixedStore<N> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl Store for DenseStore {
+impl<const N: usize> Store for FixedStore<N> {
     fn add(&mut self, index: i32, count: u64) {
         if count == 0 {
             return;
         }
         
-        *self.bins.entry(index).or_insert(0) += count;
+        let slot = self.slot(index);
+        self.counts[slot] += count;
         self.total_count += count;
     }
     
+    /// Remove up to `count` from the given index
+    ///
+    /// Counts that were folded into the lowest bin are no longer found under their
+    /// original index, so they are not removed.
+    fn remove(&mut self, index: i32, count: u64) -> u64 {
+        let position = index as i64 - self.offset as i64;
+        if !(0..N as i64).contains(&position) {
+            return 0;
+        }
+        
+        let bin = &mut self.counts[position as usize];
+        let removed = count.min(*bin);
+        *bin -= removed;
+        self.total_count -= removed;
+        removed
+    }
+    
     fn get(&self, index: i32) -> u64 {
-        self.bins.get(&index).copied().unwrap_or(0)
+        let position = index as i64 - self.offset as i64;
+        if (0..N as i64).contains(&position) {
+            self.counts[position as usize]
+        } else {
+            0
+        }
     }
     
     fn total_count(&self) -> u64 {
@@ -90,15 +1076,27 @@ impl Store for DenseStore {
     }
     
     fn min_index(&self) -> Option<i32> {
-        self.bins.keys().min().copied()
+        self.counts.iter().position(|&count| count > 0).map(|position| self.offset + position as i32)
     }
     
     fn max_index(&self) -> Option<i32> {
-        self.bins.keys().max().copied()
+        self.counts.iter().rposition(|&count| count > 0).map(|position| self.offset + position as i32)
     }
     
     fn iter(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
-        Box::new(self.bins.iter().map(|(&index, &count)| (index, count)))
+        Box::new(self.counts.iter().enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(position, &count)| (self.offset + position as i32, count)))
+    }
+    
+    fn iter_descending(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        Box::new(self.counts.iter().enumerate().rev()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(position, &count)| (self.offset + position as i32, count)))
+    }
+    
+    fn has_ordered_ranks(&self) -> bool {
+        true
     }
     
     fn merge(&mut self, other: &dyn Store) {
@@ -107,110 +1105,158 @@ impl Store for DenseStore {
         }
     }
     
+    fn collapsed_index_range(&self) -> Option<(i32, i32)> {
+        self.collapsed_range
+    }
+    
     fn clear(&mut self) {
-        self.bins.clear();
+        self.counts = [0; N];
         self.total_count = 0;
+        self.collapse_count = 0;
+        self.collapsed_range = None;
     }
 }
 
-/// A collapsing store that maintains a maximum number of bins
-#[derive(Debug, Clone)]
-pub struct CollapsingStore {
-    store: DenseStore,
-    max_num_bins: usize,
+/// A fixed-range store of atomic counters
+///
+/// Counts can be added through a shared reference, which lets many threads record
+/// into the same store without locking. Indices outside the configured range are
+/// clamped to the first or last bin.
+#[derive(Debug)]
+pub struct AtomicDenseStore {
+    counts: Box<[AtomicU64]>,
+    min_index: i32,
+    total_count: AtomicU64,
 }
 
-impl CollapsingStore {
-    /// Create a new collapsing store with the given maximum number of bins
-    pub fn new(max_num_bins: usize) -> Self {
-        CollapsingStore {
-            store: DenseStore::with_capacity(max_num_bins),
-            max_num_bins,
+impl AtomicDenseStore {
+    /// Create a new store covering the indices from `min_index` to `max_index`, inclusive
+    pub fn new(min_index: i32, max_index: i32) -> Self {
+        let (min_index, max_index) = (min_index.min(max_index), min_index.max(max_index));
+        let num_bins = (max_index as i64 - min_index as i64 + 1) as usize;
+        
+        AtomicDenseStore {
+            counts: (0..num_bins).map(|_| AtomicU64::new(0)).collect(),
+            min_index,
+            total_count: AtomicU64::new(0),
         }
     }
     
-    /// Collapse bins if necessary to maintain the maximum number of bins
-    fn collapse_if_needed(&mut self) {
-        if self.store.bins.len() <= self.max_num_bins {
+    /// Add a count to the given index through a shared reference
+    pub fn add_atomic(&self, index: i32, count: u64) {
+        if count == 0 {
             return;
         }
         
-        // Simple collapsing strategy: merge adjacent bins
-        let mut sorted_indices: Vec<i32> = self.store.bins.keys().copied().collect();
-        sorted_indices.sort_unstable();
-        
-        while sorted_indices.len() > self.max_num_bins {
-            // Find the pair of adjacent bins with the smallest combined count
-            let mut min_combined_count = u64::MAX;
-            let mut merge_index = 0;
-            
-            for i in 0..sorted_indices.len() - 1 {
-                let count1 = self.store.get(sorted_indices[i]);
-                let count2 = self.store.get(sorted_indices[i + 1]);
-                let combined = count1 + count2;
-                
-                if combined < min_combined_count {
-                    min_combined_count = combined;
-                    merge_index = i;
-                }
-            }
-            
-            // Merge the bins
-            let index1 = sorted_indices[merge_index];
-            let index2 = sorted_indices[merge_index + 1];
-            let count1 = self.store.get(index1);
-            let count2 = self.store.get(index2);
-            
-            // Remove both bins
-            self.store.bins.remove(&index1);
-            self.store.bins.remove(&index2);
-            
-            // Add combined count to the lower index
-            self.store.bins.insert(index1, count1 + count2);
-            
-            // Update sorted indices
-            sorted_indices.remove(merge_index + 1);
-        }
+        // The total is raised before the bin, so it never falls below the sum of the
+        // bins and `take_atomic` cannot subtract more than it holds
+        self.total_count.fetch_add(count, Ordering::Relaxed);
+        self.counts[self.position(index)].fetch_add(count, Ordering::Relaxed);
+    }
+    
+    /// Reset every bin through a shared reference, returning the counts it held
+    ///
+    /// Each bin is swapped with zero on its own, so a count added concurrently is
+    /// either returned here or left for the next call, never both or neither.
+    ///
+    /// # Returns
+    /// The (index, count) pairs of the non-empty bins, in ascending index order
+    pub fn take_atomic(&self) -> Vec<(i32, u64)> {
+        let bins: Vec<(i32, u64)> = self.counts.iter().enumerate()
+            .filter_map(|(position, count)| {
+                let count = count.swap(0, Ordering::Relaxed);
+                (count > 0).then_some((self.min_index + position as i32, count))
+            })
+            .collect();
+        let taken: u64 = bins.iter().map(|&(_, count)| count).sum();
+        self.total_count.fetch_sub(taken, Ordering::Relaxed);
+        bins
+    }
+    
+    /// Get the range of indices covered without clamping
+    pub fn index_range(&self) -> (i32, i32) {
+        (self.min_index, self.min_index + (self.counts.len() - 1) as i32)
+    }
+    
+    fn position(&self, index: i32) -> usize {
+        let offset = index as i64 - self.min_index as i64;
+        offset.clamp(0, self.counts.len() as i64 - 1) as usize
     }
 }
 
-impl Store for CollapsingStore {
+impl Store for AtomicDenseStore {
     fn add(&mut self, index: i32, count: u64) {
-        self.store.add(index, count);
-        self.collapse_if_needed();
+        self.add_atomic(index, count);
+    }
+    
+    fn remove(&mut self, index: i32, count: u64) -> u64 {
+        let bin = &self.counts[self.position(index)];
+        let previous = bin.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+            Some(current - count.min(current))
+        }).unwrap_or_else(|current| current);
+        
+        let removed = count.min(previous);
+        self.total_count.fetch_sub(removed, Ordering::Relaxed);
+        removed
     }
     
     fn get(&self, index: i32) -> u64 {
-        self.store.get(index)
+        let offset = index as i64 - self.min_index as i64;
+        if offset < 0 || offset >= self.counts.len() as i64 {
+            return 0;
+        }
+        self.counts[offset as usize].load(Ordering::Relaxed)
     }
     
     fn total_count(&self) -> u64 {
-        self.store.total_count()
+        self.total_count.load(Ordering::Relaxed)
     }
     
     fn is_empty(&self) -> bool {
-        self.store.is_empty()
+        self.total_count() == 0
     }
     
     fn min_index(&self) -> Option<i32> {
-        self.store.min_index()
+        self.iter().next().map(|(index, _)| index)
     }
     
     fn max_index(&self) -> Option<i32> {
-        self.store.max_index()
+        self.iter().last().map(|(index, _)| index)
     }
     
     fn iter(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
-        self.store.iter()
+        Box::new(self.counts.iter().enumerate().filter_map(move |(position, count)| {
+            let count = count.load(Ordering::Relaxed);
+            (count > 0).then_some((self.min_index + position as i32, count))
+        }))
+    }
+    
+    fn iter_descending(&self) -> Box<dyn Iterator<Item = (i32, u64)> + '_> {
+        Box::new(self.counts.iter().enumerate().rev().filter_map(move |(position, count)| {
+            let count = count.load(Ordering::Relaxed);
+            (count > 0).then_some((self.min_index + position as i32, count))
+        }))
+    }
+    
+    fn has_ordered_ranks(&self) -> bool {
+        true
+    }
+    
+    fn memory_size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.counts.len() * std::mem::size_of::<AtomicU64>()
     }
     
     fn merge(&mut self, other: &dyn Store) {
-        self.store.merge(other);
-        self.collapse_if_needed();
+        for (index, count) in other.iter() {
+            self.add_atomic(index, count);
+        }
     }
     
     fn clear(&mut self) {
-        self.store.clear();
+        for count in self.counts.iter() {
+            count.store(0, Ordering::Relaxed);
+        }
+        self.total_count.store(0, Ordering::Relaxed);
     }
 }
 
@@ -259,6 +1305,198 @@ mod tests {
         assert_eq!(store1.get(30), 4);
     }
     
+    #[test]
+    fn test_hybrid_store() {
+        let mut store = HybridStore::with_sparse_max_bins(4);
+        for index in [10, -3, 7, 10] {
+            store.add(index, 2);
+        }
+        assert!(!store.is_dense());
+        assert_eq!(store.iter().collect::<Vec<_>>(), vec![(-3, 2), (7, 2), (10, 4)]);
+        
+        // Far-apart bins stay sparse even beyond the bin count
+        for index in 1..=30 {
+            store.add(index * 100, 1);
+        }
+        assert!(!store.is_dense());
+        
+        let mut store = HybridStore::with_sparse_max_bins(4);
+        for index in 0..8 {
+            store.add(index, 1);
+        }
+        assert!(store.is_dense());
+        store.add(-5, 3);
+        store.add(20, 1);
+        assert_eq!(store.get(-5), 3);
+        assert_eq!(store.get(100), 0);
+        assert_eq!((store.min_index(), store.max_index()), (Some(-5), Some(20)));
+        assert_eq!(store.total_count(), 12);
+        
+        assert_eq!(store.remove(20, 5), 1);
+        assert_eq!(store.max_index(), Some(7));
+        assert_eq!(store.num_bins(), 9);
+        
+        store.clear();
+        assert!(store.is_empty() && !store.is_dense());
+        
+        // Contiguous bins take less memory as an array than as a hash table
+        let mut hashed = DenseStore::new();
+        for index in 0..1000 {
+            store.add(index, 1);
+            hashed.add(index, 1);
+        }
+        assert!(store.memory_size_bytes() < hashed.memory_size_bytes());
+    }
+    
+    #[test]
+    fn test_fixed_store() {
+        let mut store = FixedStore::<4>::new();
+        assert!(store.is_empty() && store.min_index().is_none());
+        
+        store.add(10, 2);
+        store.add(8, 1);
+        store.add(11, 1);
+        assert_eq!((store.min_index(), store.max_index()), (Some(8), Some(11)));
+        assert_eq!(store.collapsed_index_range(), None);
+        
+        // Sliding up folds index 8 into the new lowest bin
+        store.add(12, 1);
+        assert_eq!(store.iter().collect::<Vec<_>>(), vec![(9, 1), (10, 2), (11, 1), (12, 1)]);
+        assert_eq!(store.collapsed_index_range(), Some((8, 9)));
+        
+        // Indices below the window land in its lowest bin
+        store.add(-100, 3);
+        assert_eq!(store.get(9), 4);
+        assert_eq!(store.total_count(), 8);
+        assert_eq!(store.collapsed_index_range(), Some((-100, 9)));
+        assert_eq!(store.collapse_count(), 2);
+        
+        // The window slides down when the high bins allow it
+        let mut store = FixedStore::<4>::new();
+        store.add(5, 1);
+        store.add(3, 1);
+        assert_eq!(store.iter().collect::<Vec<_>>(), vec![(3, 1), (5, 1)]);
+        assert_eq!(store.remove(5, 2), 1);
+        
+        let mut other = DenseStore::new();
+        other.add(100, 1);
+        other.add(1, 1);
+        store.merge(&other);
+        assert_eq!(store.iter().collect::<Vec<_>>(), vec![(97, 2), (100, 1)]);
+        assert_eq!(std::mem::size_of::<FixedStore<64>>(), store.memory_size_bytes() + 60 * 8);
+        
+        store.clear();
+        assert!(store.is_empty() && store.collapsed_index_range().is_none());
+    }
+    
+    #[test]
+    fn test_collapse_telemetry() {
+        let mut store = CollapsingStore::new(3);
+        for index in 10..13 {
+            store.add(index, 1);
+        }
+        assert!(!store.is_collapsed());
+        assert_eq!(store.collapsed_index_range(), None);
+        
+        store.add(13, 1);
+        store.add(5, 1);
+        assert!(store.is_collapsed());
+        assert_eq!(store.collapse_count(), 2);
+        assert_eq!(store.collapsed_index_range(), Some((5, 11)));
+        
+        let mut highest = CollapsingStore::with_policy(2, CollapsePolicy::CollapseHighest);
+        for index in [1, 2, 7] {
+            highest.add(index, 1);
+        }
+        assert_eq!(highest.collapsed_index_range(), Some((2, 7)));
+        
+        highest.clear();
+        assert!(!highest.is_collapsed());
+        assert_eq!(highest.collapsed_index_range(), None);
+    }
+    
+    #[test]
+    fn test_store_memory_size() {
+        let mut store = DenseStore::new();
+        let empty_size = store.memory_size_bytes();
+        assert_eq!(store.num_bins(), 0);
+        
+        for index in 0..100 {
+            store.add(index, 1);
+        }
+        assert_eq!(store.num_bins(), 100);
+        assert!(store.memory_size_bytes() >= empty_size + 100 * 12);
+        
+        let mut collapsing = CollapsingStore::new(10);
+        for index in 0..100 {
+            collapsing.add(index, 1);
+        }
+        assert_eq!(collapsing.num_bins(), 10);
+        
+        let atomic = AtomicDenseStore::new(0, 99);
+        assert_eq!(atomic.num_bins(), 0);
+        assert!(atomic.memory_size_bytes() >= 800);
+        
+        let boxed: Box<dyn Store> = Box::new(store);
+        assert_eq!(boxed.num_bins(), 100);
+    }
+    
+    #[test]
+    fn test_store_subtract() {
+        let mut store = DenseStore::new();
+        store.add(1, 5);
+        store.add(2, 3);
+        
+        assert_eq!(store.remove(1, 2), 2);
+        assert_eq!(store.remove(2, 10), 3);
+        assert_eq!(store.remove(7, 1), 0);
+        assert_eq!(store.get(1), 3);
+        assert_eq!(store.max_index(), Some(1));
+        assert_eq!(store.total_count(), 3);
+        
+        let mut other = DenseStore::new();
+        other.add(1, 1);
+        other.add(4, 2);
+        store.subtract(&other);
+        assert_eq!(store.get(1), 2);
+        assert_eq!(store.total_count(), 2);
+        
+        let mut atomic = AtomicDenseStore::new(0, 3);
+        atomic.add(2, 4);
+        assert_eq!(atomic.remove(2, 6), 4);
+        assert!(atomic.is_empty());
+    }
+    
+    #[test]
+    fn test_weighted_store() {
+        let mut store = WeightedStore::new();
+        assert!(store.is_empty());
+        
+        store.add(1, 0.25);
+        store.add(1, 0.5);
+        store.add(3, 2.0);
+        store.add(4, 0.0);
+        store.add(4, -1.0);
+        
+        assert_eq!(store.get(1), 0.75);
+        assert_eq!(store.get(4), 0.0);
+        assert_eq!(store.total_weight(), 2.75);
+        
+        let mut other = WeightedStore::new();
+        other.add(3, 1.5);
+        store.merge(&other);
+        assert_eq!(store.get(3), 3.5);
+        
+        store.scale(0.5, 0.5);
+        assert_eq!(store.get(1), 0.0);
+        assert_eq!(store.get(3), 1.75);
+        assert_eq!(store.total_weight(), 1.75);
+        
+        store.clear();
+        assert!(store.is_empty());
+        assert_eq!(store.total_weight(), 0.0);
+    }
+    
     #[test]
     fn test_collapsing_store() {
         let mut store = CollapsingStore::new(2);
@@ -272,4 +1510,103 @@ mod tests {
         assert!(store.store.bins.len() <= 2);
         assert_eq!(store.total_count(), 10);
     }
+    
+    #[test]
+    fn test_collapsing_store_memory_size_for() {
+        for max_num_bins in [0, 1, 2, 3, 6, 7, 12, 13, 100, 1000, 4095] {
+            let mut store = CollapsingStore::new(max_num_bins);
+            assert_eq!(store.memory_size_bytes(), CollapsingStore::memory_size_for(max_num_bins), "{}", max_num_bins);
+            for index in 0..3 * max_num_bins as i32 + 2 {
+                store.add(index, 1);
+            }
+            assert!(store.memory_size_bytes() <= CollapsingStore::memory_size_for(max_num_bins), "{}", max_num_bins);
+        }
+    }
+    
+    #[test]
+    fn test_collapsing_store_policies() {
+        let mut lowest = CollapsingStore::with_policy(2, CollapsePolicy::CollapseLowest);
+        let mut highest = CollapsingStore::with_policy(2, CollapsePolicy::CollapseHighest);
+        
+        for store in [&mut lowest, &mut highest] {
+            store.add(10, 5);
+            store.add(20, 3);
+            store.add(30, 2);
+        }
+        
+        assert_eq!(lowest.get(20), 8);
+        assert_eq!(lowest.get(30), 2);
+        assert_eq!(lowest.min_index(), Some(20));
+        
+        assert_eq!(highest.get(10), 5);
+        assert_eq!(highest.get(20), 5);
+        assert_eq!(highest.max_index(), Some(20));
+    }
+    
+    #[test]
+    fn test_collapsing_store_error_policy() {
+        let mut store = CollapsingStore::with_policy(2, CollapsePolicy::Error);
+        
+        store.add(10, 5);
+        store.add(20, 3);
+        assert_eq!(store.try_add(30, 2), Err(DDSketchError::BinLimitExceeded));
+        assert_eq!(store.try_add(10, 1), Ok(()));
+        assert_eq!(store.get(30), 0);
+        assert_eq!(store.total_count(), 9);
+        
+        let mut other = DenseStore::new();
+        other.add(40, 1);
+        assert_eq!(store.try_merge(&other), Err(DDSketchError::BinLimitExceeded));
+        assert_eq!(store.total_count(), 9);
+    }
+    
+    #[test]
+    fn test_atomic_dense_store() {
+        let store = AtomicDenseStore::new(-5, 5);
+        
+        store.add_atomic(0, 3);
+        store.add_atomic(4, 1);
+        store.add_atomic(100, 2); // Clamped to the last bin
+        store.add_atomic(-100, 1); // Clamped to the first bin
+        
+        assert_eq!(store.index_range(), (-5, 5));
+        assert_eq!(store.total_count(), 7);
+        assert_eq!(store.get(5), 2);
+        assert_eq!(store.get(-5), 1);
+        assert_eq!(store.min_index(), Some(-5));
+        assert_eq!(store.max_index(), Some(5));
+        assert_eq!(store.iter().collect::<Vec<_>>(), vec![(-5, 1), (0, 3), (4, 1), (5, 2)]);
+    }
+    
+    #[test]
+    fn test_key_at_rank() {
+        let bins = [(-3, 2), (0, 1), (4, 5), (9, 1)];
+        let mut dense = DenseStore::new();
+        let mut hybrid = HybridStore::new();
+        let mut fixed = FixedStore::<16>::new();
+        let atomic = AtomicDenseStore::new(-8, 12);
+        for &(index, count) in &bins {
+            dense.add(index, count);
+            hybrid.add(index, count);
+            fixed.add(index, count);
+            atomic.add_atomic(index, count);
+        }
+        
+        let expected = [(-3, 2), (-3, 2), (0, 1), (4, 5), (4, 5), (4, 5), (4, 5), (4, 5), (9, 1)];
+        let stores: [&dyn Store; 4] = [&dense, &hybrid, &fixed, &atomic];
+        for store in stores {
+            for (rank, &bin) in expected.iter().enumerate() {
+                assert_eq!(store.key_at_rank(rank as u64), Some(bin));
+            }
+            assert_eq!(store.key_at_rank(9), None);
+            assert_eq!(store.key_span_at_rank(4), Some((4, 5, 3)));
+            assert_eq!(store.key_span_at_rank(8), Some((9, 1, 8)));
+            assert_eq!(store.to_vec(), bins);
+            assert!(store.iter_ascending().eq(bins.iter().copied()));
+            assert!(store.iter_descending().eq(bins.iter().rev().copied()));
+        }
+        assert!(!dense.has_ordered_ranks());
+        assert!(hybrid.has_ordered_ranks() && fixed.has_ordered_ranks());
+        assert_eq!(DenseStore::new().key_at_rank(0), None);
+    }
 }
@@ -0,0 +1,427 @@
+//! t-digest
+//!
+//! This module provides a mergeable t-digest in the merging-digest variant. Where
+//! DDSketch bounds the relative error of the returned value, the t-digest bounds the
+//! error in rank, and is most precise near the extreme quantiles. Having both in one
+//! crate makes it easy to compare them on the same data.
+
+use crate::{DDSketchError, IntoSketchValue, Result};
+use std::borrow::Cow;
+use std::f64::consts::PI;
+
+/// How many values are buffered, per unit of compression, before they are merged
+const BUFFER_FACTOR: f64 = 5.0;
+
+/// A cluster of nearby values, summarized by their mean and total weight
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Centroid {
+    /// The mean of the values in the centroid
+    pub mean: f64,
+    /// The number of values in the centroid
+    pub weight: f64,
+}
+
+/// A merging t-digest
+///
+/// Values are buffered and periodically merged into a sorted list of centroids whose
+/// sizes are bounded by the `k1` scale function, `k(q) = δ / 2π · asin(2q - 1)`, where
+/// `δ` is the compression. The digest holds at most about `δ` centroids, and centroids
+/// near the tails are small, so extreme quantiles are the most precise.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TDigest {
+    compression: f64,
+    centroids: Vec<Centroid>,
+    buffer: Vec<Centroid>,
+    count: u64,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+}
+
+impl TDigest {
+    /// Create a new, empty t-digest
+    ///
+    /// # Arguments
+    /// * `compression` - The compression parameter `δ`; 100 is a common choice, and
+    ///   higher values trade memory for accuracy
+    ///
+    /// # Returns
+    /// A new TDigest, or `DDSketchError::InvalidValue` if the compression is not a
+    /// finite number of at least 1
+    pub fn new(compression: f64) -> Result<Self> {
+        if !(compression >= 1.0 && compression.is_finite()) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(TDigest {
+            compression,
+            centroids: Vec::new(),
+            buffer: Vec::new(),
+            count: 0,
+            min_value: None,
+            max_value: None,
+        })
+    }
+    
+    /// Get the compression parameter
+    pub fn compression(&self) -> f64 {
+        self.compression
+    }
+    
+    /// Add a value to the digest
+    ///
+    /// Non-finite values, and values that would overflow the count, are ignored; use
+    /// `try_add` to detect them.
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        let _ = self.try_add_with_count(value, 1);
+    }
+    
+    /// Add a value with a specific count to the digest
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&mut self, value: impl IntoSketchValue, count: u64) {
+        let _ = self.try_add_with_count(value, count);
+    }
+    
+    /// Add a value to the digest, reporting non-finite values
+    ///
+    /// # Returns
+    /// `DDSketchError::NonFiniteValue` if the value is NaN or infinite, or
+    /// `DDSketchError::CountOverflow` if the count would exceed `u64::MAX`
+    pub fn try_add(&mut self, value: impl IntoSketchValue) -> Result<()> {
+        self.try_add_with_count(value, 1)
+    }
+    
+    /// Add a value with a specific count to the digest, reporting non-finite values
+    ///
+    /// # Returns
+    /// `DDSketchError::NonFiniteValue` if the value is NaN or infinite, or
+    /// `DDSketchError::CountOverflow` if the count would exceed `u64::MAX`
+    pub fn try_add_with_count(&mut self, value: impl IntoSketchValue, count: u64) -> Result<()> {
+        let value = value.into_sketch_value();
+        if !value.is_finite() {
+            return Err(DDSketchError::NonFiniteValue);
+        }
+        if count == 0 {
+            return Ok(());
+        }
+        
+        self.count = self.count.checked_add(count).ok_or(DDSketchError::CountOverflow)?;
+        self.min_value = Some(self.min_value.map_or(value, |min| min.min(value)));
+        self.max_value = Some(self.max_value.map_or(value, |max| max.max(value)));
+        self.buffer.push(Centroid { mean: value, weight: count as f64 });
+        
+        if self.buffer.len() as f64 >= BUFFER_FACTOR * self.compression {
+            self.compress();
+        }
+        Ok(())
+    }
+    
+    /// Get the total count of values in the digest
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+    
+    /// Check if the digest is empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    
+    /// Get the minimum value in the digest
+    pub fn min(&self) -> Option<f64> {
+        self.min_value
+    }
+    
+    /// Get the maximum value in the digest
+    pub fn max(&self) -> Option<f64> {
+        self.max_value
+    }
+    
+    /// Get the merged centroids, in ascending order of their means
+    ///
+    /// Values still in the buffer are not included; call `compress` first to see them.
+    pub fn centroids(&self) -> &[Centroid] {
+        &self.centroids
+    }
+    
+    /// Merge the buffered values into the centroids
+    pub fn compress(&mut self) {
+        if !self.buffer.is_empty() {
+            self.centroids = self.merged_centroids().into_owned();
+            self.buffer.clear();
+        }
+    }
+    
+    /// Merge another digest into this one
+    ///
+    /// Digests with different compressions can be merged; the result keeps this
+    /// digest's compression.
+    ///
+    /// # Arguments
+    /// * `other` - The digest to merge
+    ///
+    /// # Returns
+    /// `DDSketchError::CountOverflow` if the merged count would exceed `u64::MAX`, in
+    /// which case the digest is left unchanged
+    pub fn merge(&mut self, other: &TDigest) -> Result<()> {
+        if other.is_empty() {
+            return Ok(());
+        }
+        
+        self.count = self.count.checked_add(other.count).ok_or(DDSketchError::CountOverflow)?;
+        self.buffer.extend_from_slice(&other.centroids);
+        self.buffer.extend_from_slice(&other.buffer);
+        
+        if let Some(other_min) = other.min_value {
+            self.min_value = Some(self.min_value.map_or(other_min, |min| min.min(other_min)));
+        }
+        if let Some(other_max) = other.max_value {
+            self.max_value = Some(self.max_value.map_or(other_max, |max| max.max(other_max)));
+        }
+        
+        self.compress();
+        Ok(())
+    }
+    
+    /// Get the value at a given quantile
+    ///
+    /// The estimate interpolates linearly between the means of neighboring centroids,
+    /// and between the extreme centroids and the exact min and max.
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        
+        let (Some(min), Some(max)) = (self.min_value, self.max_value) else {
+            return Err(DDSketchError::EmptySketch);
+        };
+        
+        let centroids = self.merged_centroids();
+        let total = self.count as f64;
+        let target = quantile * total;
+        
+        let first = centroids[0];
+        if target < first.weight / 2.0 {
+            return Ok(min + (first.mean - min) * target / (first.weight / 2.0));
+        }
+        
+        let mut cumulative = 0.0;
+        for pair in centroids.windows(2) {
+            let (left, right) = (pair[0], pair[1]);
+            let left_mid = cumulative + left.weight / 2.0;
+            let right_mid = cumulative + left.weight + right.weight / 2.0;
+            if target < right_mid {
+                let fraction = (target - left_mid) / (right_mid - left_mid);
+                return Ok(left.mean + (right.mean - left.mean) * fraction);
+            }
+            cumulative += left.weight;
+        }
+        
+        let last = centroids[centroids.len() - 1];
+        let last_mid = total - last.weight / 2.0;
+        let fraction = ((target - last_mid) / (last.weight / 2.0)).min(1.0);
+        Ok(last.mean + (max - last.mean) * fraction)
+    }
+    
+    /// Clear all data from the digest
+    pub fn clear(&mut self) {
+        self.centroids.clear();
+        self.buffer.clear();
+        self.count = 0;
+        self.min_value = None;
+        self.max_value = None;
+    }
+    
+    /// Get the centroids with the buffer merged in, without modifying the digest
+    fn merged_centroids(&self) -> Cow<'_, [Centroid]> {
+        if self.buffer.is_empty() {
+            return Cow::Borrowed(&self.centroids);
+        }
+        
+        let mut sorted: Vec<Centroid> = self.centroids.iter().chain(&self.buffer).copied().collect();
+        sorted.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+        
+        let total: f64 = sorted.iter().map(|centroid| centroid.weight).sum();
+        let mut merged = Vec::with_capacity(self.compression.ceil() as usize);
+        let mut current = sorted[0];
+        let mut so_far = 0.0;
+        let mut limit = total * self.next_quantile_limit(0.0);
+        
+        for &centroid in &sorted[1..] {
+            if so_far + current.weight + centroid.weight <= limit {
+                let weight = current.weight + centroid.weight;
+                current.mean += (centroid.mean - current.mean) * centroid.weight / weight;
+                current.weight = weight;
+            } else {
+                so_far += current.weight;
+                limit = total * self.next_quantile_limit(so_far / total);
+                merged.push(current);
+                current = centroid;
+            }
+        }
+        merged.push(current);
+        
+        Cow::Owned(merged)
+    }
+    
+    /// Get the highest quantile a centroid starting at quantile `q` may reach
+    fn next_quantile_limit(&self, q: f64) -> f64 {
+        let k = self.compression / (2.0 * PI) * (2.0 * q - 1.0).clamp(-1.0, 1.0).asin() + 1.0;
+        if k >= self.compression / 4.0 {
+            return 1.0;
+        }
+        ((2.0 * PI * k / self.compression).sin() + 1.0) / 2.0
+    }
+}
+
+/// The serialized form of a `TDigest`, checked before it becomes one
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct TDigestRepr {
+    compression: f64,
+    centroids: Vec<Centroid>,
+    buffer: Vec<Centroid>,
+    count: u64,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for TDigest {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        use serde::de::Error;
+        
+        let repr = TDigestRepr::deserialize(deserializer)?;
+        let mut digest = TDigest::new(repr.compression).map_err(D::Error::custom)?;
+        
+        let valid = |centroid: &Centroid| centroid.mean.is_finite() && centroid.weight.is_finite() && centroid.weight > 0.0;
+        if !repr.centroids.iter().chain(&repr.buffer).all(valid) {
+            return Err(D::Error::custom("centroids must have finite means and positive weights"));
+        }
+        if !repr.centroids.windows(2).all(|pair| pair[0].mean <= pair[1].mean) {
+            return Err(D::Error::custom("centroids must be in ascending order of their means"));
+        }
+        
+        // Values and extremes come and go together, and the extremes bound the means
+        let has_values = !(repr.centroids.is_empty() && repr.buffer.is_empty());
+        match (repr.min_value, repr.max_value) {
+            (None, None) if repr.count == 0 && !has_values => {}
+            (Some(min), Some(max)) if repr.count > 0 && has_values && min <= max => {
+                if !repr.centroids.iter().chain(&repr.buffer).all(|centroid| (min..=max).contains(&centroid.mean)) {
+                    return Err(D::Error::custom("centroid means must lie between the min and max"));
+                }
+            }
+            _ => return Err(D::Error::custom("count, centroids and extremes disagree")),
+        }
+        
+        digest.centroids = repr.centroids;
+        digest.buffer = repr.buffer;
+        digest.count = repr.count;
+        digest.min_value = repr.min_value;
+        digest.max_value = repr.max_value;
+        Ok(digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_tdigest_rank_error() {
+        let mut digest = TDigest::new(100.0).unwrap();
+        assert!(TDigest::new(0.0).is_err());
+        assert_eq!(digest.get_quantile_value(0.5), Err(DDSketchError::EmptySketch));
+        
+        let n = 100_000;
+        for i in 0..n {
+            // A fixed permutation so the digest does not see sorted input
+            digest.add(((i * 7919) % n) as f64);
+        }
+        digest.compress();
+        
+        assert_eq!(digest.count(), n as u64);
+        assert!(digest.centroids().len() <= 100);
+        assert_eq!(digest.get_quantile_value(0.0).unwrap(), 0.0);
+        assert_eq!(digest.get_quantile_value(1.0).unwrap(), (n - 1) as f64);
+        
+        for q in [0.001, 0.01, 0.25, 0.5, 0.75, 0.99, 0.999] {
+            let estimate = digest.get_quantile_value(q).unwrap();
+            let rank_error = (estimate / n as f64 - q).abs();
+            assert!(rank_error < 0.01, "q={} estimate={}", q, estimate);
+        }
+        assert_eq!(digest.get_quantile_value(1.5), Err(DDSketchError::InvalidQuantile { got: 1.5 }));
+    }
+    
+    #[test]
+    fn test_tdigest_merge() {
+        let mut a = TDigest::new(50.0).unwrap();
+        let mut b = TDigest::new(50.0).unwrap();
+        for i in 0..5000 {
+            a.add(i as f64);
+            b.add_with_count(5000.0 + i as f64, 1);
+        }
+        assert_eq!(b.try_add(f64::NAN), Err(DDSketchError::NonFiniteValue));
+        
+        a.merge(&b).unwrap();
+        assert_eq!(a.count(), 10000);
+        assert_eq!((a.min(), a.max()), (Some(0.0), Some(9999.0)));
+        
+        let median = a.get_quantile_value(0.5).unwrap();
+        assert!((median - 5000.0).abs() < 200.0);
+        
+        a.clear();
+        assert!(a.is_empty());
+    }
+    
+    #[test]
+    fn test_tdigest_count_overflow() {
+        let mut digest = TDigest::new(100.0).unwrap();
+        digest.add_with_count(1.0, u64::MAX - 1);
+        assert_eq!(digest.try_add_with_count(2.0, 2), Err(DDSketchError::CountOverflow));
+        assert_eq!((digest.count(), digest.max()), (u64::MAX - 1, Some(1.0)));
+        
+        let copy = digest.clone();
+        assert_eq!(digest.merge(&copy), Err(DDSketchError::CountOverflow));
+        assert_eq!(digest.count(), u64::MAX - 1);
+        digest.compress();
+        assert_eq!(digest.centroids().len(), 1);
+    }
+    
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_tdigest_serde() {
+        let mut digest = TDigest::new(100.0).unwrap();
+        for i in 0..1000 {
+            digest.add(i as f64);
+        }
+        let json = serde_json::to_string(&digest).unwrap();
+        let restored: TDigest = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.count(), 1000);
+        assert_eq!(restored.get_quantile_value(0.5).unwrap(), digest.get_quantile_value(0.5).unwrap());
+        
+        let empty = r#"{"compression":100.0,"centroids":[],"buffer":[],"count":0,"min_value":null,"max_value":null}"#;
+        assert!(serde_json::from_str::<TDigest>(empty).unwrap().is_empty());
+        for invalid in [
+            r#"{"compression":100.0,"centroids":[],"buffer":[],"count":5,"min_value":1.0,"max_value":2.0}"#,
+            r#"{"compression":0.0,"centroids":[{"mean":1.0,"weight":1.0}],"buffer":[],"count":1,"min_value":1.0,"max_value":1.0}"#,
+            r#"{"compression":100.0,"centroids":[{"mean":1.0,"weight":0.0}],"buffer":[],"count":1,"min_value":1.0,"max_value":1.0}"#,
+            r#"{"compression":100.0,"centroids":[{"mean":2.0,"weight":1.0},{"mean":1.0,"weight":1.0}],"buffer":[],"count":2,"min_value":1.0,"max_value":2.0}"#,
+            r#"{"compression":100.0,"centroids":[{"mean":1.0,"weight":1.0}],"buffer":[],"count":1,"min_value":null,"max_value":null}"#,
+        ] {
+            assert!(serde_json::from_str::<TDigest>(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
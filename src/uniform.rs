@@ -0,0 +1,241 @@
+//! Uniformly collapsing sketches
+//!
+//! This module implements the collapsing strategy of UDDSketch. Instead of folding
+//! the lowest or highest bins, which leaves the accuracy of part of the range
+//! unbounded, every pair of adjacent bins is merged when the bin budget is
+//! exceeded. Merging bins `2j` and `2j + 1` into bin `j` squares gamma, so memory
+//! stays bounded while a single, known relative accuracy holds across the whole range.
+
+use crate::{DDSketch, DDSketchError, IntoSketchValue, Result};
+use crate::mapping::LogarithmicMapping;
+use crate::store::{DenseStore, Store};
+
+/// A sketch that coarsens its accuracy uniformly to stay within a bin budget
+///
+/// With this crate's mapping, gamma is `1 + α`, so each collapse turns a relative
+/// accuracy `α` into `2α + α²`. The current bound is reported by `relative_accuracy`.
+/// Once another collapse would take the accuracy to 1 or beyond, collapsing stops and
+/// the budget may be exceeded; this takes a value range far wider than `f64` allows
+/// unless the budget is a handful of bins.
+#[derive(Debug, Clone)]
+pub struct UniformDDSketch {
+    sketch: DDSketch,
+    initial_relative_accuracy: f64,
+    max_num_bins: usize,
+    collapse_count: u32,
+}
+
+impl UniformDDSketch {
+    /// Create a new uniformly collapsing sketch
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy before any collapse (between 0 and 1)
+    /// * `max_num_bins` - The maximum number of bins across both signs
+    ///
+    /// # Returns
+    /// A new UniformDDSketch, or an error if the relative accuracy is invalid
+    pub fn new(relative_accuracy: f64, max_num_bins: usize) -> Result<Self> {
+        Ok(UniformDDSketch {
+            sketch: DDSketch::new(relative_accuracy)?,
+            initial_relative_accuracy: relative_accuracy,
+            max_num_bins,
+            collapse_count: 0,
+        })
+    }
+    
+    /// Get the relative accuracy currently guaranteed across the whole range
+    pub fn relative_accuracy(&self) -> f64 {
+        self.sketch.relative_accuracy()
+    }
+    
+    /// Get the relative accuracy the sketch was created with
+    pub fn initial_relative_accuracy(&self) -> f64 {
+        self.initial_relative_accuracy
+    }
+    
+    /// Get the maximum number of bins
+    pub fn max_num_bins(&self) -> usize {
+        self.max_num_bins
+    }
+    
+    /// Get the number of uniform collapses so far
+    pub fn collapse_count(&self) -> u32 {
+        self.collapse_count
+    }
+    
+    /// Get the underlying sketch at its current accuracy
+    pub fn sketch(&self) -> &DDSketch {
+        &self.sketch
+    }
+    
+    /// Convert into the underlying sketch at its current accuracy
+    pub fn into_sketch(self) -> DDSketch {
+        self.sketch
+    }
+    
+    /// Add a value to the sketch
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: impl IntoSketchValue) {
+        self.add_with_count(value, 1);
+    }
+    
+    /// Add a value with a specific count to the sketch
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&mut self, value: impl IntoSketchValue, count: u64) {
+        self.sketch.add_with_count(value, count);
+        self.collapse_to_budget();
+    }
+    
+    /// Get the total count of values in the sketch
+    pub fn count(&self) -> u64 {
+        self.sketch.count()
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.sketch.is_empty()
+    }
+    
+    /// Get the value at a given quantile
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value, within `relative_accuracy()` of the true quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.sketch.get_quantile_value(quantile)
+    }
+    
+    /// Merge another uniformly collapsing sketch into this one
+    ///
+    /// The sketch at the finer accuracy is collapsed to the coarser one first, so
+    /// the result holds the weaker of the two guarantees.
+    ///
+    /// # Arguments
+    /// * `other` - The sketch to merge
+    ///
+    /// # Returns
+    /// An error if the sketches started from different relative accuracies, in which
+    /// case the sketch is left unchanged
+    pub fn merge(&mut self, other: &UniformDDSketch) -> Result<()> {
+        if (self.initial_relative_accuracy - other.initial_relative_accuracy).abs() > 1e-10 {
+            return Err(DDSketchError::different_accuracy(
+                self.initial_relative_accuracy,
+                other.initial_relative_accuracy,
+            ));
+        }
+        
+        while self.collapse_count < other.collapse_count {
+            self.collapse()?;
+        }
+        
+        let mut aligned = None;
+        if other.collapse_count < self.collapse_count {
+            let mut other = other.clone();
+            while other.collapse_count < self.collapse_count {
+                other.collapse()?;
+            }
+            aligned = Some(other);
+        }
+        self.sketch.merge(&aligned.as_ref().unwrap_or(other).sketch)?;
+        
+        self.collapse_to_budget();
+        Ok(())
+    }
+    
+    /// Clear all data and restore the initial relative accuracy
+    pub fn clear(&mut self) {
+        self.sketch = DDSketch::new(self.initial_relative_accuracy)
+            .expect("relative accuracy was validated on construction");
+        self.collapse_count = 0;
+    }
+    
+    /// Collapse until the sketch fits its budget or cannot be coarsened further
+    fn collapse_to_budget(&mut self) {
+        while self.sketch.num_bins() > self.max_num_bins {
+            if self.collapse().is_err() {
+                break;
+            }
+        }
+    }
+    
+    /// Merge every pair of adjacent bins, squaring gamma
+    fn collapse(&mut self) -> Result<()> {
+        let gamma = 1.0 + self.sketch.relative_accuracy();
+        let mapping = LogarithmicMapping::new(gamma * gamma - 1.0)?;
+        
+        // Bin `j` of the new mapping covers `[γ^2j, γ^(2j+2))`, i.e. old bins 2j and 2j + 1
+        let halve = |store: &DenseStore| {
+            let mut halved = DenseStore::new();
+            for (index, count) in store.iter() {
+                halved.add(index.div_euclid(2), count);
+            }
+            halved
+        };
+        
+        self.sketch.store = halve(&self.sketch.store);
+        self.sketch.negative_store = halve(&self.sketch.negative_store);
+        self.sketch.mapping = mapping;
+        self.sketch.mark_modified();
+        self.collapse_count += 1;
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_uniform_collapse_keeps_uniform_accuracy() {
+        let mut sketch = UniformDDSketch::new(0.01, 64).unwrap();
+        let mut values = Vec::new();
+        for i in 0..2000 {
+            let value = 1.013f64.powi(i) * 1e-3;
+            sketch.add(value);
+            values.push(value);
+        }
+        
+        assert!(sketch.sketch().num_bins() <= 64);
+        assert!(sketch.collapse_count() > 0);
+        let alpha = sketch.relative_accuracy();
+        assert!(alpha > 0.01 && alpha < 1.0);
+        
+        // Unlike bin dropping, both ends of the range keep the same guarantee
+        for q in [0.0, 0.01, 0.5, 0.99, 1.0] {
+            let expected = values[((q * 1999.0) as usize).min(1999)];
+            let estimate = sketch.get_quantile_value(q).unwrap();
+            assert!((estimate - expected).abs() <= expected * alpha * 1.01, "q={}", q);
+        }
+    }
+    
+    #[test]
+    fn test_uniform_merge_aligns_accuracy() {
+        let mut coarse = UniformDDSketch::new(0.01, 16).unwrap();
+        let mut fine = UniformDDSketch::new(0.01, 1000).unwrap();
+        for i in 1..=1000 {
+            coarse.add(i as f64);
+            fine.add(-(i as f64));
+        }
+        assert!(coarse.collapse_count() > fine.collapse_count());
+        
+        fine.merge(&coarse).unwrap();
+        assert_eq!(fine.count(), 2000);
+        assert_eq!(fine.relative_accuracy(), coarse.relative_accuracy());
+        assert_eq!(fine.sketch().min(), Some(-1000.0));
+        
+        let mut other = UniformDDSketch::new(0.02, 16).unwrap();
+        assert!(matches!(other.merge(&coarse), Err(DDSketchError::IncompatibleSketches { .. })));
+        
+        fine.clear();
+        assert_eq!(fine.relative_accuracy(), 0.01);
+        assert_eq!(fine.collapse_count(), 0);
+    }
+}
@@ -0,0 +1,41 @@
+//! Numeric input conversion
+//!
+//! This module provides the trait that lets sketches accept integer and
+//! single-precision inputs directly, converting them to `f64` internally.
+
+/// A numeric type that can be recorded in a sketch
+///
+/// Integers wider than 53 bits are rounded to the nearest representable `f64`, which
+/// is far below the relative accuracy of any sketch.
+pub trait IntoSketchValue {
+    /// Convert the value to the `f64` recorded by the sketch
+    fn into_sketch_value(self) -> f64;
+}
+
+macro_rules! impl_into_sketch_value {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoSketchValue for $ty {
+                fn into_sketch_value(self) -> f64 {
+                    self as f64
+                }
+            }
+        )*
+    };
+}
+
+impl_into_sketch_value!(f32, f64, u8, u16, u32, u64, i8, i16, i32, i64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_into_sketch_value() {
+        assert_eq!(1.5f32.into_sketch_value(), 1.5);
+        assert_eq!(2.25f64.into_sketch_value(), 2.25);
+        assert_eq!(7u32.into_sketch_value(), 7.0);
+        assert_eq!(1_000_000_007u64.into_sketch_value(), 1_000_000_007.0);
+        assert_eq!((-42i64).into_sketch_value(), -42.0);
+    }
+}
@@ -0,0 +1,305 @@
+//! Zero-copy views over encoded sketches
+//!
+//! This module answers queries directly over bytes in the format of the encoding
+//! module, without allocating or building a sketch, for services that hold many
+//! encoded sketches and read a handful of numbers from each.
+
+use crate::{DDSketch, DDSketchError, Result};
+use crate::encoding::{read_extensions, read_mapping, read_version, Reader, FLAG_MAX, FLAG_MIN, TAG_MAPPING};
+use crate::mapping::{IndexMapping, LogarithmicMapping};
+
+/// The bins of one sign in an encoded sketch
+#[derive(Debug, Clone, Copy)]
+struct EncodedBins<'a> {
+    /// The encoded bins, after their length
+    bytes: &'a [u8],
+    len: u64,
+    total_count: u64,
+}
+
+impl<'a> EncodedBins<'a> {
+    /// Read and validate a bin section, leaving the reader after it
+    fn read(reader: &mut Reader<'a>) -> Result<Self> {
+        let len = reader.varint()?;
+        let start = reader.bytes;
+        let mut bins = EncodedBins { bytes: start, len, total_count: 0 };
+        
+        let mut previous = 0i64;
+        for _ in 0..len {
+            let zigzag = reader.varint()?;
+            let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            previous = previous.checked_add(delta)
+                .filter(|&index| i32::try_from(index).is_ok())
+                .ok_or(DDSketchError::InvalidEncoding)?;
+            bins.total_count = bins.total_count.checked_add(reader.varint()?)
+                .ok_or(DDSketchError::InvalidEncoding)?;
+        }
+        
+        bins.bytes = &start[..start.len() - reader.bytes.len()];
+        Ok(bins)
+    }
+    
+    /// Iterate over the bins in ascending index order
+    fn iter(&self) -> impl Iterator<Item = (i32, u64)> + 'a {
+        let mut reader = Reader { bytes: self.bytes };
+        let mut previous = 0i64;
+        // The section was validated when the view was created
+        (0..self.len).map_while(move |_| {
+            let zigzag = reader.varint().ok()?;
+            previous += (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            Some((previous as i32, reader.varint().ok()?))
+        })
+    }
+}
+
+/// A read-only sketch backed by its encoded bytes
+///
+/// Creating a view validates the bytes in one pass without allocating, after which
+/// every query walks the encoded bins directly. Answers are the same as those of
+/// the decoded `DDSketch` with the default `QuantileInterpolation::LowerBound`.
+#[derive(Debug, Clone)]
+pub struct DDSketchView<'a> {
+    bytes: &'a [u8],
+    mapping: LogarithmicMapping,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+    sum: f64,
+    zero_count: u64,
+    positive: EncodedBins<'a>,
+    negative: EncodedBins<'a>,
+}
+
+impl<'a> DDSketchView<'a> {
+    /// Create a view over an encoded sketch
+    ///
+    /// # Arguments
+    /// * `bytes` - A sketch encoded by `DDSketch::encode`
+    ///
+    /// # Returns
+    /// The view, or `DDSketchError::InvalidEncoding` if the bytes are not a valid
+    /// encoding, or `DDSketchError::InvalidRelativeAccuracy` if they hold an invalid
+    /// relative accuracy
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        let version = read_version(&mut reader)?;
+        
+        let mapping = LogarithmicMapping::new(reader.f64()?)?;
+        let flags = reader.byte()?;
+        let min_value = if flags & FLAG_MIN != 0 { Some(reader.f64()?) } else { None };
+        let max_value = if flags & FLAG_MAX != 0 { Some(reader.f64()?) } else { None };
+        let sum = reader.f64()?;
+        let _sum_squares = reader.f64()?;
+        let zero_count = reader.varint()?;
+        let positive = EncodedBins::read(&mut reader)?;
+        let negative = EncodedBins::read(&mut reader)?;
+        read_extensions(&mut reader, version, |tag, field| {
+            // Views index bins with the logarithmic mapping of the relative accuracy
+            if tag == TAG_MAPPING {
+                mapping.descriptor().check_mergeable(&read_mapping(field)?)?;
+            }
+            Ok(())
+        })?;
+        
+        Ok(DDSketchView {
+            bytes,
+            mapping,
+            min_value,
+            max_value,
+            sum,
+            zero_count,
+            positive,
+            negative,
+        })
+    }
+    
+    /// Get the bytes the view reads from
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+    
+    /// Get the relative accuracy of the sketch
+    pub fn relative_accuracy(&self) -> f64 {
+        self.mapping.relative_accuracy()
+    }
+    
+    /// Get the total count of values in the sketch
+    pub fn count(&self) -> u64 {
+        self.negative.total_count + self.zero_count + self.positive.total_count
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+    
+    /// Get the minimum value in the sketch
+    pub fn min(&self) -> Option<f64> {
+        self.min_value
+    }
+    
+    /// Get the maximum value in the sketch
+    pub fn max(&self) -> Option<f64> {
+        self.max_value
+    }
+    
+    /// Get the sum of all values in the sketch
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+    
+    /// Get the mean of the values in the sketch, or `None` if it is empty
+    pub fn mean(&self) -> Option<f64> {
+        (!self.is_empty()).then(|| self.sum / self.count() as f64)
+    }
+    
+    /// Get the value at a given quantile
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        if self.is_empty() {
+            return Err(DDSketchError::EmptySketch);
+        }
+        
+        let min = self.min_value.unwrap_or(f64::NEG_INFINITY);
+        let max = self.max_value.unwrap_or(f64::INFINITY);
+        let mut rank = (quantile * self.count() as f64) as u64;
+        
+        if rank < self.negative.total_count {
+            // Negative bins hold magnitudes, so the lowest values are at the highest indices
+            let reverse_rank = self.negative.total_count - 1 - rank;
+            let mut cumulative_count = 0;
+            for (index, count) in self.negative.iter() {
+                cumulative_count += count;
+                if cumulative_count > reverse_rank {
+                    return Ok((-self.mapping.upper_bound(index)).clamp(min, max));
+                }
+            }
+        }
+        rank -= self.negative.total_count;
+        
+        if rank < self.zero_count {
+            return Ok(0.0f64.clamp(min, max));
+        }
+        rank -= self.zero_count;
+        
+        let mut cumulative_count = 0;
+        for (index, count) in self.positive.iter() {
+            cumulative_count += count;
+            if cumulative_count > rank {
+                return Ok(self.mapping.lower_bound(index).clamp(min, max));
+            }
+        }
+        Ok(self.max_value.unwrap_or(0.0))
+    }
+    
+    /// Get the estimated number of values less than or equal to a given value
+    ///
+    /// This counts values as `DDSketch::rank` does.
+    ///
+    /// # Arguments
+    /// * `value` - The value to compare against
+    pub fn rank(&self, value: f64) -> u64 {
+        match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) if value >= min => {
+                if value >= max {
+                    return self.count();
+                }
+            }
+            _ => return 0,
+        }
+        
+        if value < 0.0 {
+            let key = self.mapping.key(-value);
+            return self.negative.iter()
+                .filter(|&(index, _)| index >= key)
+                .map(|(_, count)| count)
+                .sum();
+        }
+        
+        let mut rank = self.negative.total_count + self.zero_count;
+        if value > 0.0 {
+            let key = self.mapping.key(value);
+            rank += self.positive.iter()
+                .take_while(|&(index, _)| index <= key)
+                .map(|(_, count)| count)
+                .sum::<u64>();
+        }
+        rank
+    }
+    
+    /// Get the estimated fraction of values less than or equal to a given value
+    ///
+    /// # Arguments
+    /// * `value` - The value to compare against
+    ///
+    /// # Returns
+    /// The estimated fraction (between 0 and 1) of values at or below `value`
+    pub fn cdf(&self, value: f64) -> Result<f64> {
+        if self.is_empty() {
+            return Err(DDSketchError::EmptySketch);
+        }
+        
+        Ok(self.rank(value) as f64 / self.count() as f64)
+    }
+    
+    /// Decode the viewed bytes into a sketch
+    pub fn to_sketch(&self) -> Result<DDSketch> {
+        DDSketch::decode(self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_view_matches_sketch() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        for i in 1..=500 {
+            sketch.add(i as f64 * 0.37);
+            sketch.add(-(i as f64) * 1.9);
+        }
+        sketch.add_with_count(0.0, 40);
+        let bytes = sketch.encode();
+        
+        let view = DDSketchView::new(&bytes).unwrap();
+        assert_eq!(view.count(), sketch.count());
+        assert_eq!((view.min(), view.max()), (sketch.min(), sketch.max()));
+        assert_eq!(view.mean(), sketch.mean());
+        for i in 0..=100 {
+            let q = i as f64 / 100.0;
+            assert_eq!(view.get_quantile_value(q).unwrap(), sketch.get_quantile_value(q).unwrap(), "q={}", q);
+        }
+        for value in [-1000.0, -950.0, -3.0, 0.0, 0.5, 100.0, 185.0, 1e6] {
+            assert_eq!(view.rank(value), sketch.rank(value), "value={}", value);
+        }
+        assert_eq!(view.cdf(50.0).unwrap(), sketch.cdf(50.0).unwrap());
+        assert_eq!(view.to_sketch().unwrap(), sketch);
+    }
+    
+    #[test]
+    fn test_view_rejects_invalid_bytes() {
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        sketch.add(7.0);
+        let bytes = sketch.encode();
+        
+        for len in 0..bytes.len() {
+            assert!(DDSketchView::new(&bytes[..len]).is_err());
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(DDSketchView::new(&trailing).unwrap_err(), DDSketchError::InvalidEncoding);
+        
+        let empty = DDSketch::new(0.02).unwrap().encode();
+        let view = DDSketchView::new(&empty).unwrap();
+        assert_eq!(view.get_quantile_value(0.5), Err(DDSketchError::EmptySketch));
+        assert_eq!(view.get_quantile_value(2.0), Err(DDSketchError::InvalidQuantile { got: 2.0 }));
+    }
+}
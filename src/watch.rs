@@ -0,0 +1,338 @@
+//! Quantile threshold watches
+//!
+//! This module evaluates alerting rules such as "p99 above 250ms for 3 consecutive
+//! evaluations" against a sketch, and calls back when a rule starts or stops being
+//! breached.
+
+use crate::{DDSketch, DDSketchError, ExpDecayDDSketch, MergedView, Result, RollingDDSketch};
+use crate::mapping::IndexMapping;
+use crate::store::Store;
+use std::fmt;
+
+/// A sketch-like source of quantile values that a `QuantileWatch` can evaluate
+pub trait QuantileSource {
+    /// Get the value at a given quantile (between 0 and 1)
+    fn quantile_value(&self, quantile: f64) -> Result<f64>;
+}
+
+impl<M: IndexMapping, S: Store> QuantileSource for DDSketch<M, S> {
+    fn quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.get_quantile_value(quantile)
+    }
+}
+
+impl QuantileSource for RollingDDSketch {
+    fn quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.get_quantile_value(quantile)
+    }
+}
+
+impl QuantileSource for ExpDecayDDSketch {
+    fn quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.get_quantile_value(quantile)
+    }
+}
+
+impl<M: IndexMapping, S: Store> QuantileSource for MergedView<'_, M, S> {
+    fn quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.get_quantile_value(quantile)
+    }
+}
+
+/// Which side of its threshold breaches a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// The rule is breached when the value is above the threshold
+    Above,
+    /// The rule is breached when the value is below the threshold
+    Below,
+}
+
+/// How a rule treats an evaluation of an empty sketch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyWindow {
+    /// Skip the evaluation: it neither counts towards nor breaks a streak
+    #[default]
+    Hold,
+    /// Count the evaluation as not breaching the rule
+    Clear,
+}
+
+/// A threshold on one quantile that must be crossed for several evaluations in a row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchRule {
+    quantile: f64,
+    comparison: Comparison,
+    threshold: f64,
+    consecutive: u32,
+    empty_window: EmptyWindow,
+}
+
+impl WatchRule {
+    /// Create a rule breached when a quantile stays above a threshold
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to watch (between 0 and 1)
+    /// * `threshold` - The value the quantile must exceed
+    /// * `consecutive` - The number of evaluations in a row that breach, or stop
+    ///   breaching, the rule before it changes state
+    ///
+    /// # Returns
+    /// The rule, `DDSketchError::InvalidQuantile` if the quantile is out of range, or
+    /// `DDSketchError::InvalidValue` if the threshold is NaN or `consecutive` is zero
+    pub fn above(quantile: f64, threshold: f64, consecutive: u32) -> Result<Self> {
+        WatchRule::new(quantile, Comparison::Above, threshold, consecutive)
+    }
+    
+    /// Create a rule breached when a quantile stays below a threshold
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to watch (between 0 and 1)
+    /// * `threshold` - The value the quantile must fall under
+    /// * `consecutive` - The number of evaluations in a row that breach, or stop
+    ///   breaching, the rule before it changes state
+    ///
+    /// # Returns
+    /// The rule, `DDSketchError::InvalidQuantile` if the quantile is out of range, or
+    /// `DDSketchError::InvalidValue` if the threshold is NaN or `consecutive` is zero
+    pub fn below(quantile: f64, threshold: f64, consecutive: u32) -> Result<Self> {
+        WatchRule::new(quantile, Comparison::Below, threshold, consecutive)
+    }
+    
+    fn new(quantile: f64, comparison: Comparison, threshold: f64, consecutive: u32) -> Result<Self> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        if threshold.is_nan() || consecutive == 0 {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        Ok(WatchRule {
+            quantile,
+            comparison,
+            threshold,
+            consecutive,
+            empty_window: EmptyWindow::default(),
+        })
+    }
+    
+    /// Set how the rule treats evaluations of an empty sketch
+    pub fn with_empty_window(mut self, empty_window: EmptyWindow) -> Self {
+        self.empty_window = empty_window;
+        self
+    }
+    
+    /// Get the watched quantile
+    pub fn quantile(&self) -> f64 {
+        self.quantile
+    }
+    
+    /// Get the side of the threshold that breaches the rule
+    pub fn comparison(&self) -> Comparison {
+        self.comparison
+    }
+    
+    /// Get the threshold
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+    
+    /// Get the number of evaluations in a row needed to change state
+    pub fn consecutive(&self) -> u32 {
+        self.consecutive
+    }
+    
+    fn is_breached_by(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Above => value > self.threshold,
+            Comparison::Below => value < self.threshold,
+        }
+    }
+}
+
+/// Whether a rule started or stopped being breached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEventKind {
+    /// The rule was breached for the required number of evaluations
+    Breached,
+    /// The rule was not breached for the required number of evaluations
+    Cleared,
+}
+
+/// A change in the state of a rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchEvent {
+    /// The rule's position, as returned by `QuantileWatch::add_rule`
+    pub rule: usize,
+    /// The state the rule changed to
+    pub kind: WatchEventKind,
+    /// The value of the quantile at the evaluation that changed the state, or `None`
+    /// if the sketch was empty
+    pub value: Option<f64>,
+}
+
+/// The state of one rule of a watch
+#[derive(Debug, Clone, Copy)]
+struct RuleState {
+    rule: WatchRule,
+    breached: bool,
+    /// The number of evaluations in a row that disagree with `breached`
+    streak: u32,
+}
+
+/// A set of quantile rules evaluated against a sketch, with a callback on state changes
+///
+/// Each call to `evaluate` queries the watched quantiles once. A rule becomes
+/// breached after `consecutive` breaching evaluations in a row and clears after as
+/// many non-breaching ones, so a single outlier window neither raises nor clears it.
+pub struct QuantileWatch<'a> {
+    rules: Vec<RuleState>,
+    callback: Box<dyn FnMut(&WatchEvent) + 'a>,
+}
+
+impl<'a> QuantileWatch<'a> {
+    /// Create a watch with no rules
+    ///
+    /// # Arguments
+    /// * `callback` - Called with every state change, in rule order
+    pub fn new(callback: impl FnMut(&WatchEvent) + 'a) -> Self {
+        QuantileWatch {
+            rules: Vec::new(),
+            callback: Box::new(callback),
+        }
+    }
+    
+    /// Add a rule, initially not breached
+    ///
+    /// # Returns
+    /// The rule's position, which identifies it in events
+    pub fn add_rule(&mut self, rule: WatchRule) -> usize {
+        self.rules.push(RuleState { rule, breached: false, streak: 0 });
+        self.rules.len() - 1
+    }
+    
+    /// Get the rules, in the order they were added
+    pub fn rules(&self) -> impl Iterator<Item = &WatchRule> + '_ {
+        self.rules.iter().map(|state| &state.rule)
+    }
+    
+    /// Check whether a rule is currently breached
+    ///
+    /// # Arguments
+    /// * `rule` - The rule's position
+    pub fn is_breached(&self, rule: usize) -> bool {
+        self.rules.get(rule).is_some_and(|state| state.breached)
+    }
+    
+    /// Evaluate every rule against a sketch
+    ///
+    /// # Arguments
+    /// * `source` - The sketch to query
+    ///
+    /// # Returns
+    /// The state changes caused by this evaluation, which were also passed to the callback
+    pub fn evaluate(&mut self, source: &(impl QuantileSource + ?Sized)) -> Vec<WatchEvent> {
+        let mut events = Vec::new();
+        
+        for (position, state) in self.rules.iter_mut().enumerate() {
+            let value = match source.quantile_value(state.rule.quantile) {
+                Ok(value) => Some(value),
+                Err(_) if state.rule.empty_window == EmptyWindow::Clear => None,
+                Err(_) => continue,
+            };
+            
+            let breaching = value.is_some_and(|value| state.rule.is_breached_by(value));
+            if breaching == state.breached {
+                state.streak = 0;
+                continue;
+            }
+            
+            state.streak += 1;
+            if state.streak >= state.rule.consecutive {
+                state.breached = breaching;
+                state.streak = 0;
+                let kind = if breaching { WatchEventKind::Breached } else { WatchEventKind::Cleared };
+                events.push(WatchEvent { rule: position, kind, value });
+            }
+        }
+        
+        for event in &events {
+            (self.callback)(event);
+        }
+        events
+    }
+    
+    /// Return every rule to its initial, not breached state
+    pub fn reset(&mut self) {
+        for state in &mut self.rules {
+            state.breached = false;
+            state.streak = 0;
+        }
+    }
+}
+
+impl fmt::Debug for QuantileWatch<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuantileWatch")
+            .field("rules", &self.rules)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    
+    #[test]
+    fn test_quantile_watch_consecutive_breaches() {
+        let fired = RefCell::new(Vec::new());
+        let mut watch = QuantileWatch::new(|event: &WatchEvent| fired.borrow_mut().push(event.kind));
+        let rule = watch.add_rule(WatchRule::above(0.99, 250.0, 3).unwrap());
+        
+        let mut slow = DDSketch::new(0.01).unwrap();
+        slow.add_with_count(400.0, 10);
+        let mut fast = DDSketch::new(0.01).unwrap();
+        fast.add_with_count(20.0, 10);
+        
+        // A single fast window breaks the streak
+        for sketch in [&slow, &slow, &fast, &slow, &slow] {
+            assert!(watch.evaluate(sketch).is_empty());
+        }
+        let events = watch.evaluate(&slow);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, WatchEventKind::Breached);
+        assert!((events[0].value.unwrap() - 400.0).abs() <= 4.0);
+        assert!(watch.is_breached(rule));
+        
+        for _ in 0..3 {
+            watch.evaluate(&fast);
+        }
+        assert!(!watch.is_breached(rule));
+        assert_eq!(*fired.borrow(), vec![WatchEventKind::Breached, WatchEventKind::Cleared]);
+    }
+    
+    #[test]
+    fn test_quantile_watch_empty_windows() {
+        let mut watch = QuantileWatch::new(|_: &WatchEvent| {});
+        let held = watch.add_rule(WatchRule::below(0.5, 10.0, 2).unwrap());
+        let cleared = watch.add_rule(WatchRule::below(0.5, 10.0, 2).unwrap().with_empty_window(EmptyWindow::Clear));
+        
+        let mut low = DDSketch::new(0.01).unwrap();
+        low.add(1.0);
+        let empty = DDSketch::new(0.01).unwrap();
+        watch.evaluate(&low);
+        watch.evaluate(&low);
+        assert!(watch.is_breached(held) && watch.is_breached(cleared));
+        
+        watch.evaluate(&empty);
+        watch.evaluate(&empty);
+        assert!(watch.is_breached(held));
+        assert!(!watch.is_breached(cleared));
+        
+        watch.reset();
+        assert!(!watch.is_breached(held));
+        assert_eq!(WatchRule::above(1.5, 1.0, 1), Err(DDSketchError::InvalidQuantile { got: 1.5 }));
+        assert_eq!(WatchRule::above(0.5, 1.0, 0), Err(DDSketchError::InvalidValue));
+    }
+}
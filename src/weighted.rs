@@ -0,0 +1,360 @@
+//! Weighted DDSketch
+//!
+//! This module provides a DDSketch variant whose values carry fractional weights,
+//! for importance-weighted samples or sample-rate corrected counts where rounding
+//! to integer counts would bias the quantiles.
+
+use crate::{DDSketchError, Result};
+use crate::mapping::{IndexMapping, LogarithmicMapping};
+use crate::store::WeightedStore;
+use std::fmt;
+
+/// A DDSketch whose counts are arbitrary positive weights
+///
+/// Quantiles are computed over the total weight: the value at quantile `q` is the
+/// value below which a fraction `q` of the weight lies. With integer weights the
+/// answers match a `DDSketch` holding the same values and counts.
+#[derive(Clone)]
+pub struct WeightedDDSketch {
+    mapping: LogarithmicMapping,
+    store: WeightedStore,
+    zero_weight: f64,
+    negative_store: WeightedStore,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+    sum: f64,
+}
+
+impl WeightedDDSketch {
+    /// Create a new weighted sketch with the given relative accuracy
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    ///
+    /// # Returns
+    /// A new WeightedDDSketch instance
+    pub fn new(relative_accuracy: f64) -> Result<Self> {
+        Ok(WeightedDDSketch {
+            mapping: LogarithmicMapping::new(relative_accuracy)?,
+            store: WeightedStore::new(),
+            zero_weight: 0.0,
+            negative_store: WeightedStore::new(),
+            min_value: None,
+            max_value: None,
+            sum: 0.0,
+        })
+    }
+    
+    /// Add a value with a weight of one
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: f64) {
+        self.add_weighted(value, 1.0);
+    }
+    
+    /// Add a value with a fractional weight
+    ///
+    /// NaN and infinite values, and weights that are not positive and finite, are
+    /// ignored.
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `weight` - The weight of the value, e.g. `1.0 / sample_rate`
+    pub fn add_weighted(&mut self, value: f64, weight: f64) {
+        let _ = self.try_add_weighted(value, weight);
+    }
+    
+    /// Add a value with a fractional weight, reporting invalid values and weights
+    ///
+    /// # Returns
+    /// `DDSketchError::NonFiniteValue` if the value is NaN or infinite, or
+    /// `DDSketchError::InvalidValue` if the weight is not positive and finite
+    pub fn try_add_weighted(&mut self, value: f64, weight: f64) -> Result<()> {
+        if !value.is_finite() {
+            return Err(DDSketchError::NonFiniteValue);
+        }
+        if !(weight > 0.0 && weight.is_finite()) {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        if value == 0.0 {
+            self.zero_weight += weight;
+        } else if value > 0.0 {
+            self.store.add(self.mapping.key(value), weight);
+        } else {
+            self.negative_store.add(self.mapping.key(-value), weight);
+        }
+        
+        // Update min/max
+        self.min_value = Some(self.min_value.map_or(value, |min| min.min(value)));
+        self.max_value = Some(self.max_value.map_or(value, |max| max.max(value)));
+        
+        self.sum += value * weight;
+        
+        Ok(())
+    }
+    
+    /// Get the total weight of values in the sketch
+    pub fn total_weight(&self) -> f64 {
+        self.store.total_weight() + self.zero_weight + self.negative_store.total_weight()
+    }
+    
+    /// Check if the sketch is empty
+    pub fn is_empty(&self) -> bool {
+        self.min_value.is_none()
+    }
+    
+    /// Get the minimum value
+    pub fn min(&self) -> Option<f64> {
+        self.min_value
+    }
+    
+    /// Get the maximum value
+    pub fn max(&self) -> Option<f64> {
+        self.max_value
+    }
+    
+    /// Get the weighted sum of all values
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+    
+    /// Get the weighted mean of all values
+    ///
+    /// # Returns
+    /// The mean, or None if the sketch is empty
+    pub fn mean(&self) -> Option<f64> {
+        (!self.is_empty()).then(|| self.sum / self.total_weight())
+    }
+    
+    /// Get the relative accuracy of the sketch
+    pub fn relative_accuracy(&self) -> f64 {
+        self.mapping.relative_accuracy()
+    }
+    
+    /// Get the value at a given quantile of the total weight
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(DDSketchError::InvalidQuantile { got: quantile });
+        }
+        
+        let (min, max) = match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Err(DDSketchError::EmptySketch),
+        };
+        
+        let rank = quantile * self.total_weight();
+        let mut cumulative_weight = 0.0;
+        
+        let mut negative: Vec<(i32, f64)> = self.negative_store.iter().collect();
+        negative.sort_unstable_by_key(|&(index, _)| std::cmp::Reverse(index));
+        for (index, weight) in negative {
+            cumulative_weight += weight;
+            if cumulative_weight > rank {
+                return Ok((-self.mapping.upper_bound(index)).clamp(min, max));
+            }
+        }
+        
+        cumulative_weight += self.zero_weight;
+        if cumulative_weight > rank {
+            return Ok(0.0);
+        }
+        
+        let mut positive: Vec<(i32, f64)> = self.store.iter().collect();
+        positive.sort_unstable_by_key(|&(index, _)| index);
+        for (index, weight) in positive {
+            cumulative_weight += weight;
+            if cumulative_weight > rank {
+                return Ok(self.mapping.value(index).clamp(min, max));
+            }
+        }
+        
+        Ok(max)
+    }
+    
+    /// Merge another weighted sketch into this one
+    ///
+    /// # Arguments
+    /// * `other` - The other sketch to merge
+    ///
+    /// # Returns
+    /// An error if the sketches have different relative accuracies
+    pub fn merge(&mut self, other: &WeightedDDSketch) -> Result<()> {
+        if (self.mapping.relative_accuracy() - other.mapping.relative_accuracy()).abs() > 1e-10 {
+            return Err(DDSketchError::different_accuracy(
+                self.mapping.relative_accuracy(),
+                other.mapping.relative_accuracy(),
+            ));
+        }
+        
+        self.store.merge(&other.store);
+        self.negative_store.merge(&other.negative_store);
+        self.zero_weight += other.zero_weight;
+        self.sum += other.sum;
+        
+        // Update min/max
+        if let Some(other_min) = other.min_value {
+            self.min_value = Some(self.min_value.map_or(other_min, |min| min.min(other_min)));
+        }
+        
+        if let Some(other_max) = other.max_value {
+            self.max_value = Some(self.max_value.map_or(other_max, |max| max.max(other_max)));
+        }
+        
+        Ok(())
+    }
+    
+    /// Multiply every weight by a factor
+    ///
+    /// Quantiles are unchanged, but values added afterwards count relatively more.
+    /// Bins whose weight falls below `min_weight` are dropped; once every bin is gone
+    /// the sketch is empty. When the bin holding the min or max is dropped, that
+    /// extreme moves to the outermost remaining bin.
+    ///
+    /// # Arguments
+    /// * `factor` - The factor to multiply weights by, between 0 and 1 to fade values out
+    /// * `min_weight` - The weight below which a bin is dropped
+    pub fn scale_weights(&mut self, factor: f64, min_weight: f64) {
+        self.store.scale(factor, min_weight);
+        self.negative_store.scale(factor, min_weight);
+        self.zero_weight *= factor;
+        if self.zero_weight < min_weight {
+            self.zero_weight = 0.0;
+        }
+        self.sum *= factor;
+        
+        if self.total_weight() == 0.0 {
+            self.clear();
+        } else {
+            self.narrow_extremes();
+        }
+    }
+    
+    /// Move extremes whose bins were dropped to the outermost remaining bins
+    fn narrow_extremes(&mut self) {
+        let has_bin = |sketch: &Self, value: f64| {
+            if value > 0.0 {
+                sketch.store.get(sketch.mapping.key(value)) > 0.0
+            } else if value < 0.0 {
+                sketch.negative_store.get(sketch.mapping.key(-value)) > 0.0
+            } else {
+                sketch.zero_weight > 0.0
+            }
+        };
+        let lowest_index = |store: &WeightedStore| store.iter().map(|(index, _)| index).min();
+        let highest_index = |store: &WeightedStore| store.iter().map(|(index, _)| index).max();
+        
+        if self.min_value.is_some_and(|min| !has_bin(self, min)) {
+            self.min_value = highest_index(&self.negative_store)
+                .map(|index| -self.mapping.upper_bound(index))
+                .or((self.zero_weight > 0.0).then_some(0.0))
+                .or(lowest_index(&self.store).map(|index| self.mapping.lower_bound(index)));
+        }
+        if self.max_value.is_some_and(|max| !has_bin(self, max)) {
+            self.max_value = highest_index(&self.store)
+                .map(|index| self.mapping.upper_bound(index))
+                .or((self.zero_weight > 0.0).then_some(0.0))
+                .or(lowest_index(&self.negative_store).map(|index| -self.mapping.lower_bound(index)));
+        }
+    }
+    
+    /// Clear all data from the sketch
+    pub fn clear(&mut self) {
+        self.store.clear();
+        self.negative_store.clear();
+        self.zero_weight = 0.0;
+        self.min_value = None;
+        self.max_value = None;
+        self.sum = 0.0;
+    }
+}
+
+impl fmt::Debug for WeightedDDSketch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WeightedDDSketch")
+            .field("relative_accuracy", &self.mapping.relative_accuracy())
+            .field("total_weight", &self.total_weight())
+            .field("min", &self.min_value)
+            .field("max", &self.max_value)
+            .field("sum", &self.sum)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DDSketch;
+    
+    #[test]
+    fn test_weighted_matches_integer_counts() {
+        let mut weighted = WeightedDDSketch::new(0.02).unwrap();
+        let mut sketch = DDSketch::new(0.02).unwrap();
+        
+        for i in -100..=1000 {
+            let count = (i32::abs(i) % 5) as u64 + 1;
+            weighted.add_weighted(i as f64, count as f64);
+            sketch.add_with_count(i as f64, count);
+        }
+        
+        assert_eq!(weighted.total_weight(), sketch.count() as f64);
+        assert_eq!(weighted.sum(), sketch.sum());
+        for q in [0.0, 0.1, 0.25, 0.5, 0.75, 0.99, 1.0] {
+            assert_eq!(weighted.get_quantile_value(q).unwrap(), sketch.get_quantile_value(q).unwrap());
+        }
+    }
+    
+    #[test]
+    fn test_weighted_fractional_weights() {
+        let mut sketch = WeightedDDSketch::new(0.01).unwrap();
+        assert_eq!(sketch.get_quantile_value(0.5), Err(DDSketchError::EmptySketch));
+        
+        // Values sampled at 10% stand for ten times their weight
+        sketch.add_weighted(1.0, 0.5);
+        sketch.add_weighted(100.0, 1.0 / 0.1);
+        
+        assert!(sketch.try_add_weighted(5.0, 0.0).is_err());
+        assert!(sketch.try_add_weighted(5.0, f64::NAN).is_err());
+        assert_eq!(sketch.try_add_weighted(f64::NAN, 1.0), Err(DDSketchError::NonFiniteValue));
+        assert_eq!(sketch.try_add_weighted(f64::NEG_INFINITY, 1.0), Err(DDSketchError::NonFiniteValue));
+        sketch.add(f64::NAN);
+        sketch.add(f64::INFINITY);
+        assert_eq!(sketch.total_weight(), 10.5);
+        assert_eq!(sketch.sum(), 1000.5);
+        assert_eq!(sketch.max(), Some(100.0));
+        
+        assert_eq!(sketch.get_quantile_value(0.0).unwrap(), 1.0);
+        let median = sketch.get_quantile_value(0.5).unwrap();
+        assert!((median - 100.0).abs() <= 100.0 * 0.01);
+        assert!((sketch.mean().unwrap() - 1000.5 / 10.5).abs() < 1e-9);
+        assert!(sketch.get_quantile_value(1.5).is_err());
+    }
+    
+    #[test]
+    fn test_weighted_merge() {
+        let mut a = WeightedDDSketch::new(0.02).unwrap();
+        let mut b = WeightedDDSketch::new(0.02).unwrap();
+        
+        a.add_weighted(-3.0, 0.25);
+        b.add_weighted(0.0, 0.5);
+        b.add_weighted(7.0, 1.25);
+        
+        a.merge(&b).unwrap();
+        assert_eq!(a.total_weight(), 2.0);
+        assert_eq!(a.min(), Some(-3.0));
+        assert_eq!(a.max(), Some(7.0));
+        assert_eq!(a.get_quantile_value(0.2).unwrap(), 0.0);
+        
+        assert!(a.merge(&WeightedDDSketch::new(0.05).unwrap()).is_err());
+        
+        a.clear();
+        assert!(a.is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+//! Sliding-window sketches
+//!
+//! This module provides a sketch that only answers for recently recorded values,
+//! by keeping a ring of sub-sketches and discarding the oldest one on each tick.
+
+use crate::{DDSketch, DDSketchError, Result};
+use std::time::{Duration, Instant};
+
+/// A sketch over a rolling window of the most recent slots
+///
+/// Values are recorded into the newest of `num_slots` sub-sketches. Each tick drops
+/// the oldest sub-sketch and starts a new one, and queries answer over the union of
+/// the live sub-sketches, so the window covers between `num_slots - 1` and
+/// `num_slots` ticks' worth of values.
+///
+/// Ticks are either triggered manually with `tick`, or derived from the clock when
+/// the sketch is created with `with_slot_duration`: a 60 second window with 1 second
+/// resolution is `with_slot_duration(0.01, 60, Duration::from_secs(1))`.
+#[derive(Debug, Clone)]
+pub struct RollingDDSketch {
+    slots: Vec<DDSketch>,
+    /// The position of the newest slot in `slots`
+    current: usize,
+    /// How long each slot stays the newest, for clock-driven rotation
+    slot_duration: Option<Duration>,
+    /// When the newest slot started
+    slot_start: Instant,
+}
+
+impl RollingDDSketch {
+    /// Create a rolling sketch that rotates on manual ticks
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    /// * `num_slots` - The number of sub-sketches in the window
+    ///
+    /// # Returns
+    /// A new RollingDDSketch, or an error if `num_slots` is zero
+    pub fn new(relative_accuracy: f64, num_slots: usize) -> Result<Self> {
+        if num_slots == 0 {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        let empty = DDSketch::new(relative_accuracy)?;
+        Ok(RollingDDSketch {
+            slots: vec![empty; num_slots],
+            current: 0,
+            slot_duration: None,
+            slot_start: Instant::now(),
+        })
+    }
+    
+    /// Create a rolling sketch that rotates as time passes
+    ///
+    /// # Arguments
+    /// * `relative_accuracy` - The relative accuracy parameter (between 0 and 1)
+    /// * `num_slots` - The number of sub-sketches in the window
+    /// * `slot_duration` - How long each sub-sketch receives values
+    ///
+    /// # Returns
+    /// A new RollingDDSketch, or an error if `num_slots` or `slot_duration` is zero
+    pub fn with_slot_duration(relative_accuracy: f64, num_slots: usize, slot_duration: Duration) -> Result<Self> {
+        if slot_duration.is_zero() {
+            return Err(DDSketchError::InvalidValue);
+        }
+        
+        let mut sketch = RollingDDSketch::new(relative_accuracy, num_slots)?;
+        sketch.slot_duration = Some(slot_duration);
+        Ok(sketch)
+    }
+    
+    /// Get the number of sub-sketches in the window
+    pub fn num_slots(&self) -> usize {
+        self.slots.len()
+    }
+    
+    /// Get the relative accuracy of the sketch
+    pub fn relative_accuracy(&self) -> f64 {
+        self.slots[0].relative_accuracy()
+    }
+    
+    /// Add a value to the newest slot
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    pub fn add(&mut self, value: f64) {
+        self.add_with_count(value, 1);
+    }
+    
+    /// Add a value with a specific count to the newest slot
+    ///
+    /// # Arguments
+    /// * `value` - The value to add
+    /// * `count` - The number of times to add the value
+    pub fn add_with_count(&mut self, value: f64, count: u64) {
+        if self.slot_duration.is_some() {
+            self.advance_to(Instant::now());
+        }
+        self.slots[self.current].add_with_count(value, count);
+    }
+    
+    /// Drop the oldest slot and start a new one
+    pub fn tick(&mut self) {
+        self.current = (self.current + 1) % self.slots.len();
+        self.slots[self.current].clear();
+    }
+    
+    /// Rotate the slots that have expired by `now`
+    ///
+    /// Only meaningful for sketches created with `with_slot_duration`; clock-driven
+    /// sketches call this on every add.
+    pub fn advance_to(&mut self, now: Instant) {
+        let elapsed_slots = self.elapsed_slots(now);
+        
+        for _ in 0..elapsed_slots.min(self.slots.len() as u64) {
+            self.tick();
+        }
+        
+        if let Some(slot_duration) = self.slot_duration {
+            self.slot_start += slot_duration.saturating_mul(elapsed_slots.min(u32::MAX as u64) as u32);
+        }
+    }
+    
+    /// Merge the live slots into a single sketch
+    ///
+    /// For clock-driven sketches, slots that have expired since the last add are
+    /// excluded even though they have not been rotated out yet.
+    pub fn window(&self) -> DDSketch {
+        let expired = self.elapsed_slots(Instant::now()).min(self.slots.len() as u64) as usize;
+        let live = self.slots.len() - expired;
+        
+        // The slot of age `k` sits `k` positions before the newest one
+        let slots = (0..live).map(|age| &self.slots[(self.current + self.slots.len() - age) % self.slots.len()]);
+        
+        let mut window = DDSketch::new(self.relative_accuracy())
+            .expect("relative accuracy was validated on construction");
+        window.merge_all(slots).expect("slots share the same relative accuracy");
+        window
+    }
+    
+    /// Get the total count of values in the window
+    pub fn count(&self) -> u64 {
+        self.window().count()
+    }
+    
+    /// Get the value at a given quantile of the window
+    ///
+    /// # Arguments
+    /// * `quantile` - The quantile to query (between 0 and 1)
+    ///
+    /// # Returns
+    /// The estimated value at the given quantile
+    pub fn get_quantile_value(&self, quantile: f64) -> Result<f64> {
+        self.window().get_quantile_value(quantile)
+    }
+    
+    /// Clear all slots
+    pub fn clear(&mut self) {
+        for slot in &mut self.slots {
+            slot.clear();
+        }
+    }
+    
+    /// Count the whole slot durations between the start of the newest slot and `now`
+    fn elapsed_slots(&self, now: Instant) -> u64 {
+        match self.slot_duration {
+            Some(slot_duration) => {
+                (now.saturating_duration_since(self.slot_start).as_nanos() / slot_duration.as_nanos()) as u64
+            }
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_rolling_manual_ticks() {
+        let mut sketch = RollingDDSketch::new(0.02, 3).unwrap();
+        assert!(RollingDDSketch::new(0.02, 0).is_err());
+        assert!(sketch.get_quantile_value(0.5).is_err());
+        
+        for tick in 0..5 {
+            for i in 1..=100 {
+                sketch.add((tick * 1000 + i) as f64);
+            }
+            sketch.tick();
+        }
+        
+        // Only the two most recent full slots remain next to the fresh one
+        assert_eq!(sketch.count(), 200);
+        let min = sketch.window().min().unwrap();
+        assert_eq!(min, 3001.0);
+        
+        sketch.tick();
+        sketch.tick();
+        assert_eq!(sketch.count(), 0);
+    }
+    
+    #[test]
+    fn test_rolling_clock_driven() {
+        let slot_duration = Duration::from_secs(3600);
+        let mut sketch = RollingDDSketch::with_slot_duration(0.02, 4, slot_duration).unwrap();
+        assert!(RollingDDSketch::with_slot_duration(0.02, 4, Duration::ZERO).is_err());
+        
+        sketch.add(5.0);
+        assert_eq!(sketch.count(), 1);
+        
+        // Two slot durations later the value is still within the four-slot window
+        let start = sketch.slot_start;
+        sketch.advance_to(start + slot_duration * 2);
+        sketch.slots[sketch.current].add(7.0);
+        assert_eq!(sketch.window().max(), Some(7.0));
+        assert_eq!(sketch.window().count(), 2);
+        
+        // Far in the future, everything has rotated out
+        sketch.advance_to(start + slot_duration * 100);
+        assert_eq!(sketch.window().count(), 0);
+        assert_eq!(sketch.slot_start, start + slot_duration * 100);
+    }
+}